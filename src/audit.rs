@@ -0,0 +1,376 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::languages::canonical_name;
+
+// --scan-scanners: look through the hand-written sources of a grammar before
+// compiling them. A flagged grammar is only built once it's confirmed.
+#[derive(Debug, Clone, Default)]
+pub struct ScannerAudit {
+    // by canonical name, what --confirm-scanner lists
+    confirmed: BTreeSet<String>,
+}
+
+impl ScannerAudit {
+    pub fn new<I, S>(confirmed: I) -> ScannerAudit
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        ScannerAudit {
+            confirmed: confirmed
+                .into_iter()
+                .map(|lang| canonical_name(lang.as_ref()))
+                .collect(),
+        }
+    }
+
+    pub fn confirmed(&self, lang: &str) -> bool {
+        self.confirmed.contains(&canonical_name(lang))
+    }
+}
+
+// Things a scanner, which only ever looks at the next characters of the
+// input, has no business doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RedFlag {
+    Network,
+    // running programs, loading libraries, raw syscalls
+    Process,
+    // writing, removing or renaming files; printing to stderr is fine
+    FileWrite,
+    InlineAssembly,
+}
+
+impl fmt::Display for RedFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RedFlag::Network => "network access",
+            RedFlag::Process => "runs programs",
+            RedFlag::FileWrite => "writes files",
+            RedFlag::InlineAssembly => "inline assembly",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    // inside the checkout
+    pub file: String,
+    pub line: usize,
+    pub flag: RedFlag,
+    // the line it's on, shortened
+    pub code: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.file, self.line, self.flag, self.code
+        )
+    }
+}
+
+const NETWORK_HEADERS: &[&str] = &[
+    "sys/socket.h",
+    "netdb.h",
+    "netinet/in.h",
+    "arpa/inet.h",
+    "curl/curl.h",
+    "winsock2.h",
+    "ws2tcpip.h",
+];
+
+// calls that can't be anything else; connect, send and friends are left out,
+// scanners name their own helpers like that
+const NETWORK_CALLS: &[&str] = &[
+    "socket",
+    "getaddrinfo",
+    "gethostbyname",
+    "inet_pton",
+    "curl_easy_init",
+    "curl_easy_perform",
+    "WSAStartup",
+];
+
+const PROCESS_CALLS: &[&str] = &[
+    "system",
+    "popen",
+    "_popen",
+    "execl",
+    "execlp",
+    "execle",
+    "execv",
+    "execvp",
+    "execvpe",
+    "execve",
+    "fork",
+    "vfork",
+    "posix_spawn",
+    "posix_spawnp",
+    "syscall",
+    "dlopen",
+    "CreateProcessA",
+    "CreateProcessW",
+    "ShellExecuteA",
+    "ShellExecuteW",
+    "WinExec",
+    "LoadLibraryA",
+    "LoadLibraryW",
+];
+
+const FILE_CALLS: &[&str] = &[
+    "creat",
+    "unlink",
+    "unlinkat",
+    "rename",
+    "renameat",
+    "truncate",
+    "ftruncate",
+    "chmod",
+    "chown",
+    "symlink",
+    "mkdir",
+    "rmdir",
+    "DeleteFileA",
+    "DeleteFileW",
+];
+
+// fopen and friends count when the mode opens for writing, open when its
+// flags do
+const OPEN_CALLS: &[&str] = &["fopen", "freopen", "fdopen", "_wfopen"];
+const OPEN_WRITE_FLAGS: &[&str] = &["O_WRONLY", "O_RDWR", "O_CREAT", "O_TRUNC", "O_APPEND"];
+const FILE_STREAMS: &[&str] = &["ofstream", "fstream", "wofstream", "wfstream"];
+
+const ASSEMBLY: &[&str] = &["asm", "__asm", "__asm__"];
+
+// Scan the hand-written sources of a grammar: `sources` minus parser.c, and
+// the headers next to them. Unreadable files are skipped, they don't compile
+// either.
+pub fn scan_grammar(sources: &[String], src_dir: &Path, repo_dir: &str) -> Vec<Finding> {
+    let mut files: Vec<String> = sources
+        .iter()
+        .filter(|s| {
+            Path::new(s)
+                .file_name()
+                .is_some_and(|name| name != "parser.c")
+        })
+        .cloned()
+        .collect();
+    if let Ok(entries) = fs::read_dir(src_dir) {
+        let mut headers: Vec<String> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.is_file()
+                    && matches!(
+                        path.extension().and_then(|e| e.to_str()),
+                        Some("h") | Some("hh") | Some("hpp")
+                    )
+            })
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        headers.sort();
+        files.extend(headers);
+    }
+    let mut findings = Vec::new();
+    for file in files {
+        if let Ok(text) = fs::read_to_string(&file) {
+            let relative = Path::new(&file)
+                .strip_prefix(repo_dir)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or(file.clone());
+            findings.extend(scan(&relative, &text));
+        }
+    }
+    findings
+}
+
+// The red flags of one source file
+pub fn scan(file: &str, text: &str) -> Vec<Finding> {
+    let code = strip_comments(text);
+    let mut findings = Vec::new();
+    for ((number, line), original) in code.lines().enumerate().zip(text.lines()) {
+        let mut flag = |flag: RedFlag| {
+            findings.push(Finding {
+                file: file.to_string(),
+                line: number + 1,
+                flag,
+                code: shorten(original.trim()),
+            })
+        };
+        let trimmed = line.trim_start();
+        if let Some(header) = trimmed.strip_prefix('#').and_then(|directive| {
+            directive.trim_start().strip_prefix("include").map(|name| {
+                name.trim()
+                    .trim_matches(|c| c == '"' || c == '<' || c == '>')
+            })
+        }) {
+            if NETWORK_HEADERS.contains(&header) {
+                flag(RedFlag::Network);
+            }
+            continue;
+        }
+        let mut flagged = BTreeSet::new();
+        for (name, args) in identifiers(line) {
+            let called = args.is_some();
+            let found = if called && NETWORK_CALLS.contains(&name) {
+                Some(RedFlag::Network)
+            } else if called && PROCESS_CALLS.contains(&name) {
+                Some(RedFlag::Process)
+            } else if called && FILE_CALLS.contains(&name) {
+                Some(RedFlag::FileWrite)
+            } else if called && name == "remove" {
+                // std::remove(first, last, value) is the algorithm
+                (!args.unwrap_or("").contains(',')).then_some(RedFlag::FileWrite)
+            } else if called && OPEN_CALLS.contains(&name) {
+                writes_mode(args.unwrap_or("")).then_some(RedFlag::FileWrite)
+            } else if called && matches!(name, "open" | "openat" | "_open") {
+                let args = args.unwrap_or("");
+                OPEN_WRITE_FLAGS
+                    .iter()
+                    .any(|f| args.contains(f))
+                    .then_some(RedFlag::FileWrite)
+            } else if FILE_STREAMS.contains(&name) {
+                Some(RedFlag::FileWrite)
+            } else if ASSEMBLY.contains(&name) {
+                Some(RedFlag::InlineAssembly)
+            } else {
+                None
+            };
+            if let Some(found) = found {
+                flagged.insert(found);
+            }
+        }
+        for found in flagged {
+            flag(found);
+        }
+    }
+    findings
+}
+
+// the mode argument of an fopen call, its second; one that isn't a literal
+// could be anything
+fn writes_mode(args: &str) -> bool {
+    match args.split(',').nth(1).map(str::trim) {
+        Some(mode) if mode.starts_with('"') => mode.contains(['w', 'a', '+']),
+        _ => true,
+    }
+}
+
+// The identifiers of a line that aren't members (`x.open(`, `x->open(`), each
+// with what follows it up to the closing parenthesis when it's called
+fn identifiers(line: &str) -> Vec<(&str, Option<&str>)> {
+    let bytes = line.as_bytes();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        // what a string says isn't code
+        if bytes[i] == b'"' || bytes[i] == b'\'' {
+            let quote = bytes[i];
+            i += 1;
+            while i < bytes.len() && bytes[i] != quote {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i += 1;
+            continue;
+        }
+        if !is_ident(bytes[i]) || (i > 0 && is_ident(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && is_ident(bytes[i]) {
+            i += 1;
+        }
+        let name = &line[start..i];
+        let before = line[..start].trim_end();
+        if before.ends_with('.') || before.ends_with("->") || name.as_bytes()[0].is_ascii_digit() {
+            continue;
+        }
+        let after = line[i..].trim_start();
+        let args = after.strip_prefix('(').map(|rest| {
+            let mut depth = 1;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map(|(end, _)| end)
+                .unwrap_or(rest.len());
+            &rest[..end]
+        });
+        // `asm volatile (` has the parenthesis after the qualifier
+        let args = args.or_else(|| {
+            (ASSEMBLY.contains(&name) && after.starts_with(['v', 'g', '_', '{'])).then_some("")
+        });
+        found.push((name, args));
+    }
+    found
+}
+
+// The source with its comments blanked out, line for line. Strings stay, the
+// fopen modes are in them; a comment marker inside one isn't a comment.
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if let Some(quote) = in_string {
+            out.push(c);
+            if c == '\\' {
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+            } else if c == quote || c == '\n' {
+                in_string = None;
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                out.push(' ');
+            }
+            ('"', _) | ('\'', _) => {
+                in_string = Some(c);
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn shorten(code: &str) -> String {
+    const MAX: usize = 100;
+    if code.chars().count() > MAX {
+        format!("{}...", code.chars().take(MAX).collect::<String>())
+    } else {
+        code.to_string()
+    }
+}
@@ -0,0 +1,236 @@
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tree_sitter::Parser;
+
+use crate::config::{entry_extensions, resolve_entry_path};
+use crate::languages::canonical_name;
+use crate::smoke::load_grammar;
+
+// what one `bench` invocation measures and where it keeps its history
+pub struct BenchOptions<'a> {
+    // sample files, or directories searched for them
+    pub samples: &'a [String],
+    // canonical names to benchmark, everything in the config when empty
+    pub languages: &'a [String],
+    // each file is parsed this many times and the fastest parse counts
+    pub iterations: usize,
+    // name of this run in the report, e.g. the flags the grammars were built with
+    pub label: &'a str,
+    pub created: &'a str,
+    // JSON history of every run; the comparison goes next to it as .md
+    pub report: &'a str,
+}
+
+// Parse the samples with every built grammar they belong to, add the run to
+// the report and rewrite the comparison table, which is also returned
+pub fn run_bench(
+    config: &Value,
+    config_path: &str,
+    options: &BenchOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for sample in options.samples {
+        collect_files(Path::new(sample), &mut files).map_err(|e| format!("{}: {}", sample, e))?;
+    }
+    files.sort();
+    files.dedup();
+
+    let mut results = Map::new();
+    let languages = config
+        .get("known_languages")
+        .and_then(Value::as_object)
+        .ok_or("config has no known_languages")?;
+    for (name, entry) in languages {
+        if !options.languages.is_empty() && !options.languages.contains(&canonical_name(name)) {
+            continue;
+        }
+        let samples: Vec<&PathBuf> = files.iter().filter(|f| belongs_to(entry, f)).collect();
+        if samples.is_empty() {
+            log::info!("no samples for {}", name);
+            continue;
+        }
+        let path = match entry.get("path").and_then(Value::as_str) {
+            Some(path) => resolve_entry_path(path, config_path),
+            None => continue,
+        };
+        let language = entry
+            .get("language")
+            .and_then(Value::as_str)
+            .unwrap_or(name);
+        match bench_language(&path, language, &samples, options.iterations) {
+            Ok(result) => {
+                results.insert(name.clone(), result);
+            }
+            Err(e) => log::warn!("failed to benchmark {} : {}", name, e),
+        }
+    }
+    if results.is_empty() {
+        return Err("none of the samples belong to a built grammar".into());
+    }
+
+    let mut report: Value = match fs::read_to_string(options.report) {
+        Ok(text) => serde_json::from_str(&text)?,
+        Err(_) => json!({ "runs": [] }),
+    };
+    let runs = report["runs"]
+        .as_array_mut()
+        .ok_or_else(|| format!("{} has no runs", options.report))?;
+    // rerunning a label replaces its numbers
+    runs.retain(|run| run["label"].as_str() != Some(options.label));
+    runs.push(json!({
+        "label": options.label,
+        "created": options.created,
+        "iterations": options.iterations,
+        "results": results
+    }));
+    fs::write(
+        options.report,
+        serde_json::to_string_pretty(&report)? + "\n",
+    )?;
+
+    let comparison = render_comparison(&report["runs"]);
+    fs::write(Path::new(options.report).with_extension("md"), &comparison)?;
+    Ok(comparison)
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            collect_files(&entry?.path(), files)?;
+        }
+    } else {
+        // fail on samples that were named but don't exist
+        fs::metadata(path)?;
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+// whether the config entry would claim `file`, by file name or extension
+fn belongs_to(entry: &Value, file: &Path) -> bool {
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let by_name = entry
+        .get("filenames")
+        .and_then(Value::as_array)
+        .is_some_and(|names| names.iter().any(|n| n.as_str() == Some(&file_name)));
+    let extension = file.extension().map(|e| e.to_string_lossy().to_string());
+    by_name || extension.is_some_and(|e| entry_extensions(entry).contains(&e))
+}
+
+// fastest of `iterations` parses of every sample, summed over the samples
+fn bench_language(
+    library: &Path,
+    language: &str,
+    samples: &[&PathBuf],
+    iterations: usize,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let grammar = load_grammar(library, language)?;
+    let mut parser = Parser::new();
+    parser
+        .set_language(&grammar.language)
+        .map_err(|e| format!("unusable language: {}", e))?;
+
+    let (mut bytes, mut nodes, mut errors, mut seconds) = (0, 0, 0, 0.0);
+    for sample in samples {
+        let source = fs::read(sample)?;
+        let mut fastest = f64::MAX;
+        let mut tree = None;
+        for _ in 0..iterations.max(1) {
+            let start = Instant::now();
+            let parsed = parser
+                .parse(&source, None)
+                .ok_or_else(|| format!("parsing {} failed", sample.display()))?;
+            fastest = fastest.min(start.elapsed().as_secs_f64());
+            tree = Some(parsed);
+        }
+        let tree = tree.expect("parsed at least once");
+        let root = tree.root_node();
+        if root.has_error() {
+            errors += 1;
+        }
+        bytes += source.len();
+        nodes += root.descendant_count();
+        seconds += fastest;
+    }
+    Ok(json!({
+        "files": samples.len(),
+        "files_with_errors": errors,
+        "bytes": bytes,
+        "nodes": nodes,
+        "seconds": seconds,
+        "mb_per_s": megabytes_per_second(bytes, seconds)
+    }))
+}
+
+fn megabytes_per_second(bytes: usize, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        bytes as f64 / 1_000_000.0 / seconds
+    } else {
+        0.0
+    }
+}
+
+// A markdown table with a MB/s column per run and the change of the latest
+// run against the first one
+fn render_comparison(runs: &Value) -> String {
+    let runs: Vec<&Value> = runs
+        .as_array()
+        .map(|r| r.iter().collect())
+        .unwrap_or_default();
+    let mut languages: Vec<&str> = runs
+        .iter()
+        .filter_map(|run| run["results"].as_object())
+        .flat_map(|results| results.keys().map(String::as_str))
+        .collect();
+    languages.sort();
+    languages.dedup();
+
+    let labels: Vec<&str> = runs
+        .iter()
+        .map(|run| run["label"].as_str().unwrap_or("?"))
+        .collect();
+    let mut table = format!("| language | {} MB/s | nodes |", labels.join(" MB/s | "));
+    if runs.len() > 1 {
+        table.push_str(&format!(" {} vs {} |", labels[labels.len() - 1], labels[0]));
+    }
+    table.push('\n');
+    table.push_str(&"|---".repeat(labels.len() + 2 + usize::from(runs.len() > 1)));
+    table.push_str("|\n");
+
+    for language in languages {
+        let speeds: Vec<Option<f64>> = runs
+            .iter()
+            .map(|run| run["results"][language]["mb_per_s"].as_f64())
+            .collect();
+        let cells: Vec<String> = speeds
+            .iter()
+            .map(|s| {
+                s.map(|s| format!("{:.2}", s))
+                    .unwrap_or_else(|| "-".to_string())
+            })
+            .collect();
+        let nodes = runs
+            .iter()
+            .rev()
+            .find_map(|run| run["results"][language]["nodes"].as_u64())
+            .unwrap_or_default();
+        table.push_str(&format!(
+            "| {} | {} | {} |",
+            language,
+            cells.join(" | "),
+            nodes
+        ));
+        if runs.len() > 1 {
+            match (speeds[0], speeds[speeds.len() - 1]) {
+                (Some(first), Some(last)) if first > 0.0 => {
+                    table.push_str(&format!(" {:+.1}% |", (last / first - 1.0) * 100.0))
+                }
+                _ => table.push_str(" - |"),
+            }
+        }
+        table.push('\n');
+    }
+    table
+}
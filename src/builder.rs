@@ -0,0 +1,725 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::cancel::CancelToken;
+use crate::error::CompileError;
+use crate::limits::{ResourceLimitExceeded, ResourceLimits};
+use crate::package::{library_mode, set_mode};
+use crate::sandbox::{self, Sandbox, DOCKER_IMAGE};
+
+// One compile of a grammar into a shared library. The arguments are the
+// gcc-style ones every backend shares (sources, include dirs, flags and
+// `-o <output>`), relative to `repo_dir`, which the compiler runs in.
+pub struct CompileJob<'a> {
+    pub lang: &'a str,
+    // absolute, like `output`
+    pub repo_dir: &'a Path,
+    pub grammar_dir: &'a Path,
+    pub output: &'a Path,
+    pub args: &'a [String],
+    pub env: &'a [(String, String)],
+    pub sandbox: Option<Sandbox>,
+    pub limits: &'a ResourceLimits,
+    pub cancel: &'a CancelToken,
+}
+
+impl CompileJob<'_> {
+    // where the library is written, the only place a sandbox lets the compiler write to
+    pub fn output_dir(&self) -> &Path {
+        self.output.parent().unwrap_or(Path::new("."))
+    }
+}
+
+// A toolchain that turns a checkout into a library. `--builder` picks one of
+// the implementations below, library users can pass their own in `BuildOptions`.
+pub trait GrammarCompiler: Debug + Send + Sync {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError>;
+
+    // the toolchain as recorded in SBOMs
+    fn describe(&self) -> String;
+
+    // The first line of the C compiler's `--version`, for --min-cc-version.
+    // None when there's no telling, e.g. a compiler somewhere else.
+    fn compiler_version(&self) -> Option<String> {
+        None
+    }
+
+    // whether the compile passes on the flags of the job, which --hide-symbols
+    // and --sanitize need
+    fn takes_flags(&self) -> bool {
+        true
+    }
+}
+
+// Parse `--builder`: `gcc` (the host compiler), `cc` (whatever compiler the cc
+// crate finds, honouring CC and CFLAGS), `docker[:image]` (a toolchain
+// container), `tree-sitter-cli` (upstream's `tree-sitter build`, falling
+// back to gcc) or `ssh:host,...` (gcc on other machines).
+pub fn parse_builder(s: &str) -> Result<Arc<dyn GrammarCompiler>, String> {
+    match s.split_once(':') {
+        None if s == "gcc" => Ok(Arc::new(GccBuilder)),
+        None if s == "cc" => Ok(Arc::new(CcCrateBuilder)),
+        None if s == "docker" => Ok(Arc::new(DockerBuilder {
+            image: DOCKER_IMAGE.to_string(),
+        })),
+        None if s == "tree-sitter-cli" => Ok(Arc::new(TreeSitterCliBuilder)),
+        Some(("docker", image)) if !image.is_empty() => Ok(Arc::new(DockerBuilder {
+            image: image.to_string(),
+        })),
+        Some(("ssh", hosts)) => {
+            let hosts: Vec<String> = hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(str::to_string)
+                .collect();
+            if hosts.is_empty() {
+                return Err("ssh: needs at least one host, like ssh:build1,build2".to_string());
+            }
+            Ok(Arc::new(SshBuilder::new(hosts)))
+        }
+        _ => Err(format!(
+            "unknown builder '{}', expected gcc, cc, docker[:image], tree-sitter-cli or ssh:host,...",
+            s
+        )),
+    }
+}
+
+// the host gcc, wrapped in the sandbox when there is one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GccBuilder;
+
+impl GrammarCompiler for GccBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        let cmd = sandbox::command(
+            job.sandbox,
+            "gcc",
+            job.args,
+            job.env,
+            job.limits,
+            job.repo_dir,
+            job.output_dir(),
+        );
+        run_compiler(job, cmd)
+    }
+
+    fn describe(&self) -> String {
+        tool_version("gcc").unwrap_or_else(|| "gcc".to_string())
+    }
+
+    fn compiler_version(&self) -> Option<String> {
+        tool_version("gcc")
+    }
+}
+
+// The C compiler the cc crate detects for this host, the same one a build
+// script would use: CC, CFLAGS and friends apply, and cc's own flags for the
+// target (-fPIC, -O2, ...) go before ours.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CcCrateBuilder;
+
+impl CcCrateBuilder {
+    fn tool(&self) -> Result<cc::Tool, CompileError> {
+        let target = std::env::var("TARGET").unwrap_or_else(|_| host_triple());
+        let tool = cc::Build::new()
+            .target(&target)
+            .host(&target)
+            .opt_level(2)
+            .debug(false)
+            .cargo_metadata(false)
+            .cargo_warnings(false)
+            .try_get_compiler()
+            .map_err(|e| CompileError::Toolchain(e.to_string()))?;
+        // the arguments are gcc-style
+        if tool.is_like_msvc() {
+            return Err(CompileError::Toolchain(format!(
+                "{} is MSVC, only gcc-like compilers are supported",
+                tool.path().display()
+            )));
+        }
+        Ok(tool)
+    }
+}
+
+impl GrammarCompiler for CcCrateBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        let tool = self.tool()?;
+        let mut args: Vec<String> = tool
+            .args()
+            .iter()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        args.extend(job.args.iter().cloned());
+        let mut env = job.env.to_vec();
+        env.extend(tool.get_envs().map(|(k, v)| {
+            (
+                k.to_string_lossy().to_string(),
+                v.to_string_lossy().to_string(),
+            )
+        }));
+        let cmd = sandbox::command(
+            job.sandbox,
+            &tool.path().to_string_lossy(),
+            &args,
+            &env,
+            job.limits,
+            job.repo_dir,
+            job.output_dir(),
+        );
+        run_compiler(job, cmd)
+    }
+
+    fn describe(&self) -> String {
+        match self.tool() {
+            Ok(tool) => {
+                let path = tool.path().to_string_lossy().to_string();
+                tool_version(&path).unwrap_or(path)
+            }
+            Err(_) => "cc".to_string(),
+        }
+    }
+
+    fn compiler_version(&self) -> Option<String> {
+        tool_version(&self.tool().ok()?.path().to_string_lossy())
+    }
+}
+
+// gcc in a toolchain container, which has no network and a read-only checkout already
+#[derive(Debug, Clone)]
+pub struct DockerBuilder {
+    pub image: String,
+}
+
+impl GrammarCompiler for DockerBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        let cmd = sandbox::docker_command(
+            &self.image,
+            &job.limits.docker_args(),
+            "gcc",
+            job.args,
+            job.env,
+            job.repo_dir,
+            job.output_dir(),
+        );
+        run_compiler(job, cmd)
+    }
+
+    fn describe(&self) -> String {
+        format!("gcc in docker image {}", self.image)
+    }
+
+    fn compiler_version(&self) -> Option<String> {
+        first_line(
+            Command::new("docker")
+                .args(["run", "--rm", "--network", "none", &self.image])
+                .args(["gcc", "--version"]),
+        )
+    }
+}
+
+// `tree-sitter build -o <output> <grammar_dir>` in the sandbox and under the
+// limits, and gcc when the CLI is missing or fails. The CLI picks its own
+// flags, the job's aren't passed on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TreeSitterCliBuilder;
+
+impl GrammarCompiler for TreeSitterCliBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        // the image's own PATH in docker, ours can't be mounted into it
+        let program = match job.sandbox {
+            Some(Sandbox::Docker) => "tree-sitter".to_string(),
+            _ => match sandbox::find_program("tree-sitter") {
+                Some(program) => program.display().to_string(),
+                None => {
+                    fallback_warning(format!(
+                        "tree-sitter isn't on PATH, falling back to gcc for {}",
+                        job.lang
+                    ));
+                    return GccBuilder.compile(job);
+                }
+            },
+        };
+        // the CLI runs inside the checkout, the paths it's given can't be
+        // relative to our working directory
+        let grammar_dir = fs::canonicalize(job.grammar_dir)?;
+        let args = vec![
+            "build".to_string(),
+            "-o".to_string(),
+            job.output.display().to_string(),
+            grammar_dir.display().to_string(),
+        ];
+        let mut cmd = sandbox::command(
+            job.sandbox,
+            &program,
+            &args,
+            job.env,
+            job.limits,
+            job.repo_dir,
+            job.output_dir(),
+        );
+        match job.cancel.output(&mut cmd) {
+            Ok(output) if output.status.success() => return Ok(()),
+            // gcc would only be stopped or killed the same way
+            Err(e) if job.cancel.is_cancelled() => return Err(e.into()),
+            Ok(output) if job.limits.violation(&output).is_some() => {
+                return compiler_result(job, &output)
+            }
+            Ok(output) => fallback_warning(format!(
+                "tree-sitter build failed for {}, falling back to gcc: {}",
+                job.lang,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )),
+            Err(e) => fallback_warning(format!(
+                "failed to run tree-sitter for {}, falling back to gcc: {}",
+                job.lang, e
+            )),
+        }
+        GccBuilder.compile(job)
+    }
+
+    fn describe(&self) -> String {
+        tool_version("tree-sitter").unwrap_or_else(|| "tree-sitter-cli".to_string())
+    }
+
+    // the CLI compiles with what the cc crate finds, the fallback with gcc
+    fn compiler_version(&self) -> Option<String> {
+        CcCrateBuilder.compiler_version()
+    }
+
+    fn takes_flags(&self) -> bool {
+        false
+    }
+}
+
+// a library built by gcc instead is worth knowing about, it's not what
+// --builder asked for
+fn fallback_warning(message: String) {
+    log::warn!("{}", message);
+    eprintln!("Warning: {}", message);
+}
+
+// Experimental: gcc on other machines, anything ssh can log into without a
+// prompt (keys, ~/.ssh/config). Every compile goes to the least busy host:
+// the checkout without .git is copied into a temporary directory there,
+// compiled, and the library fetched back. The compile workers (--threads)
+// are what is shared out, so give as many as the hosts have cores. A host
+// that can't be reached is skipped, and tried last until it works again.
+#[derive(Debug)]
+pub struct SshBuilder {
+    pub hosts: Vec<String>,
+    load: Mutex<Vec<HostLoad>>,
+    version: OnceLock<Option<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HostLoad {
+    running: usize,
+    // unreachable since it last worked
+    failures: usize,
+}
+
+// the host couldn't be used, another one might do
+struct Unreachable(String);
+
+impl SshBuilder {
+    pub fn new(hosts: Vec<String>) -> SshBuilder {
+        SshBuilder {
+            load: Mutex::new(vec![HostLoad::default(); hosts.len()]),
+            hosts,
+            version: OnceLock::new(),
+        }
+    }
+
+    // the least busy working host not in `tried`, counted busy until released
+    fn claim(&self, tried: &[usize]) -> Option<usize> {
+        let mut load = self.load.lock().unwrap();
+        let host = (0..self.hosts.len())
+            .filter(|host| !tried.contains(host))
+            .min_by_key(|host| (load[*host].failures, load[*host].running))?;
+        load[host].running += 1;
+        Some(host)
+    }
+
+    fn release(&self, host: usize, reachable: bool) {
+        let mut load = self.load.lock().unwrap();
+        load[host].running -= 1;
+        if reachable {
+            load[host].failures = 0;
+        } else {
+            load[host].failures += 1;
+        }
+    }
+
+    fn compile_on(
+        &self,
+        host: &str,
+        job: &CompileJob,
+    ) -> Result<Result<(), CompileError>, Unreachable> {
+        let unreachable = |what: &str, output: &Output| {
+            Unreachable(format!(
+                "{} failed ({}): {}",
+                what,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        };
+        let run = |cmd: &mut Command| {
+            job.cancel
+                .output(cmd)
+                .map_err(|e| Unreachable(format!("running ssh failed: {}", e)))
+        };
+
+        let output = run(&mut ssh(
+            host,
+            "d=$(mktemp -d -t parser_scraper.XXXXXX) && mkdir \"$d/src\" \"$d/out\" && echo \"$d\"",
+        ))?;
+        let dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || dir.is_empty() {
+            return Err(unreachable("making a directory", &output));
+        }
+        let result = self.build_in(host, &dir, job, &run, &unreachable);
+        // not through the cancel token, a cancelled compile is cleaned up too
+        let _ = ssh(host, &format!("rm -rf {}", quote(&dir))).output();
+        result
+    }
+
+    fn build_in(
+        &self,
+        host: &str,
+        dir: &str,
+        job: &CompileJob,
+        run: &dyn Fn(&mut Command) -> Result<Output, Unreachable>,
+        unreachable: &dyn Fn(&str, &Output) -> Unreachable,
+    ) -> Result<Result<(), CompileError>, Unreachable> {
+        let src = format!("{}/src", dir);
+        let name = job.output.file_name().unwrap_or_default().to_string_lossy();
+        let output_path = format!("{}/out/{}", dir, name);
+
+        let copy = format!(
+            "tar -C {} --exclude=.git -cf - . | ssh -o BatchMode=yes {} {}",
+            quote(&job.repo_dir.to_string_lossy()),
+            quote(host),
+            quote(&format!("tar -C {} -xf -", quote(&src)))
+        );
+        let output = run(Command::new("sh").arg("-c").arg(copy))?;
+        if !output.status.success() {
+            return Err(unreachable("copying the checkout", &output));
+        }
+
+        // the arguments name the checkout and the output by their local paths
+        let local_output = job.output.to_string_lossy();
+        let local_repo = fs::canonicalize(job.repo_dir).unwrap_or(job.repo_dir.to_path_buf());
+        let local_repo = local_repo.to_string_lossy();
+        let args: Vec<String> = job
+            .args
+            .iter()
+            .map(|arg| {
+                quote(
+                    &arg.replace(&*local_output, &output_path)
+                        .replace(&*local_repo, &src),
+                )
+            })
+            .collect();
+        let env: Vec<String> = job
+            .env
+            .iter()
+            .map(|(name, value)| quote(&format!("{}={}", name, value)))
+            .collect();
+        let script = format!(
+            "cd {} && {}exec env {} gcc {}",
+            quote(&src),
+            job.limits.ulimit_commands(),
+            env.join(" "),
+            args.join(" ")
+        );
+        let output = run(&mut ssh(host, &script))?;
+        // ssh's own failures, the connection dropping and such
+        if output.status.code() == Some(255) {
+            return Err(unreachable("compiling", &output));
+        }
+        if let Err(e) = compiler_result(job, &output) {
+            return Ok(Err(e));
+        }
+
+        let output = run(&mut ssh(host, &format!("cat {}", quote(&output_path))))?;
+        if !output.status.success() {
+            return Err(unreachable("fetching the library", &output));
+        }
+        Ok(fs::write(job.output, &output.stdout)
+            .and_then(|_| set_mode(job.output, library_mode(&local_output)))
+            .map_err(CompileError::from))
+    }
+}
+
+impl GrammarCompiler for SshBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        let mut tried = Vec::new();
+        while let Some(host) = self.claim(&tried) {
+            let result = self.compile_on(&self.hosts[host], job);
+            self.release(host, result.is_ok());
+            match result {
+                Ok(result) => return result,
+                Err(Unreachable(e)) => {
+                    if job.cancel.is_cancelled() {
+                        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, e).into());
+                    }
+                    log::warn!(
+                        "building {} on {}: {}, trying another host",
+                        job.lang,
+                        self.hosts[host],
+                        e
+                    );
+                    tried.push(host);
+                }
+            }
+        }
+        Err(CompileError::Toolchain(format!(
+            "none of the ssh hosts ({}) could build {}",
+            self.hosts.join(", "),
+            job.lang
+        )))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} on ssh:{}",
+            self.compiler_version().as_deref().unwrap_or("gcc"),
+            self.hosts.join(",")
+        )
+    }
+
+    // the first host that answers stands for all of them
+    fn compiler_version(&self) -> Option<String> {
+        self.version
+            .get_or_init(|| {
+                self.hosts
+                    .iter()
+                    .find_map(|host| first_line(&mut ssh(host, "gcc --version")))
+            })
+            .clone()
+    }
+}
+
+// `ssh <host> <script>`, never prompting
+fn ssh(host: &str, script: &str) -> Command {
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-o", "BatchMode=yes", host, script]);
+    cmd
+}
+
+// single quoted for a POSIX shell
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// run a compile and tell a limit being hit apart from the compiler rejecting the sources
+fn run_compiler(job: &CompileJob, mut cmd: Command) -> Result<(), CompileError> {
+    let output = job.cancel.output(&mut cmd)?;
+    compiler_result(job, &output)
+}
+
+fn compiler_result(job: &CompileJob, output: &Output) -> Result<(), CompileError> {
+    if let Some(kind) = job.limits.violation(output) {
+        return Err(ResourceLimitExceeded {
+            lang: job.lang.to_string(),
+            kind,
+        }
+        .into());
+    }
+    if !output.status.success() {
+        return Err(CompileError::Compiler {
+            lang: job.lang.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(())
+}
+
+// the first line of `<program> --version`
+fn tool_version(program: &str) -> Option<String> {
+    first_line(Command::new(program).arg("--version"))
+}
+
+// the first line `cmd` prints, when it succeeds and prints one
+fn first_line(cmd: &mut Command) -> Option<String> {
+    cmd.output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_string())
+        })
+        .filter(|line| !line.is_empty())
+}
+
+// A compiler release, as far as --version tells: `gcc (Debian 12.2.0-14) 12.2.0`,
+// `Ubuntu clang version 18.1.3 (1ubuntu1)`, `cc (GCC) 8.5.0 20210514`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CcVersion {
+    // gcc or clang, what the version numbers are of
+    pub family: String,
+    pub version: Vec<u32>,
+}
+
+impl std::fmt::Display for CcVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let version: Vec<String> = self.version.iter().map(u32::to_string).collect();
+        write!(f, "{} {}", self.family, version.join("."))
+    }
+}
+
+pub fn parse_cc_version(line: &str) -> Option<CcVersion> {
+    let family = if line.to_lowercase().contains("clang") {
+        "clang"
+    } else {
+        "gcc"
+    };
+    let numbers = |word: &str| -> Option<Vec<u32>> {
+        let version: Option<Vec<u32>> = word.split('.').map(|part| part.parse().ok()).collect();
+        version.filter(|v| v.len() >= 2)
+    };
+    let words: Vec<&str> = line.split_whitespace().collect();
+    // clang says `version 18.1.3`, gcc ends on it
+    let version = match words.iter().position(|w| *w == "version") {
+        Some(i) => words.get(i + 1).and_then(|w| numbers(w)),
+        None => words
+            .iter()
+            .filter(|w| !w.starts_with('('))
+            .find_map(|w| numbers(w.trim_end_matches(')'))),
+    }?;
+    Some(CcVersion {
+        family: family.to_string(),
+        version,
+    })
+}
+
+// one --min-cc-version: `9`, `9.4`, or `clang:14` for one family only
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinCcVersion {
+    pub family: Option<String>,
+    pub version: Vec<u32>,
+}
+
+impl std::str::FromStr for MinCcVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (family, version) = match s.split_once(':') {
+            Some((family, version)) => (Some(family.trim().to_lowercase()), version),
+            None => (None, s),
+        };
+        if let Some(family) = family.as_deref().filter(|f| *f != "gcc" && *f != "clang") {
+            return Err(format!(
+                "unknown compiler '{}', expected gcc or clang",
+                family
+            ));
+        }
+        let version: Result<Vec<u32>, _> = version.trim().split('.').map(str::parse).collect();
+        match version {
+            Ok(version) => Ok(MinCcVersion { family, version }),
+            Err(_) => Err(format!("'{}' isn't a version like 9, 9.4 or clang:14", s)),
+        }
+    }
+}
+
+// Whether `line`, a compiler's --version, is at least the minimum of its
+// family. A compiler whose version can't be told fails the check, its builds
+// would be the per-language failures --min-cc-version is there to avoid.
+pub fn check_cc_version(line: Option<&str>, minimums: &[MinCcVersion]) -> Result<(), String> {
+    let Some(line) = line else {
+        return Err(
+            "can't tell which compiler the builder runs, so --min-cc-version can't be checked"
+                .to_string(),
+        );
+    };
+    let Some(found) = parse_cc_version(line) else {
+        return Err(format!(
+            "can't tell the version of `{}` for --min-cc-version",
+            line
+        ));
+    };
+    for minimum in minimums {
+        if minimum
+            .family
+            .as_deref()
+            .is_some_and(|family| family != found.family)
+        {
+            continue;
+        }
+        if found.version < minimum.version {
+            let wanted: Vec<String> = minimum.version.iter().map(u32::to_string).collect();
+            return Err(format!(
+                "{} is older than the {} {} asked for, and old compilers miscompile some scanners. Install a newer one, or pick another with --builder or CC",
+                found,
+                found.family,
+                wanted.join(".")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// the target triple of this binary, close enough for cc to pick the host compiler
+fn host_triple() -> String {
+    let arch = std::env::consts::ARCH;
+    match std::env::consts::OS {
+        "macos" => format!("{}-apple-darwin", arch),
+        "windows" => format!("{}-pc-windows-gnu", arch),
+        "freebsd" => format!("{}-unknown-freebsd", arch),
+        _ => format!("{}-unknown-linux-gnu", arch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(family: &str, version: &[u32]) -> Option<CcVersion> {
+        Some(CcVersion {
+            family: family.to_string(),
+            version: version.to_vec(),
+        })
+    }
+
+    #[test]
+    fn cc_versions() {
+        assert_eq!(
+            parse_cc_version("gcc (GCC) 13.2.1 20231011 (Red Hat 13.2.1-4)"),
+            version("gcc", &[13, 2, 1])
+        );
+        assert_eq!(
+            parse_cc_version("gcc (Debian 12.2.0-14) 12.2.0"),
+            version("gcc", &[12, 2, 0])
+        );
+        assert_eq!(
+            parse_cc_version("clang version 17.0.6"),
+            version("clang", &[17, 0, 6])
+        );
+        assert_eq!(
+            parse_cc_version("Apple clang version 15.0.0 (clang-1500.3.9.4)"),
+            version("clang", &[15, 0, 0])
+        );
+        assert_eq!(parse_cc_version("tcc version"), None);
+        assert_eq!(parse_cc_version("no compiler here"), None);
+    }
+
+    #[test]
+    fn min_cc_versions() {
+        let minimums: Vec<MinCcVersion> = ["9.4", "clang:16"]
+            .iter()
+            .map(|m| m.parse().unwrap())
+            .collect();
+        assert!(check_cc_version(Some("gcc (GCC) 13.2.1"), &minimums).is_ok());
+        assert!(check_cc_version(Some("cc (GCC) 8.5.0 20210514"), &minimums).is_err());
+        assert!(check_cc_version(Some("clang version 17.0.6"), &minimums).is_ok());
+        assert!(check_cc_version(Some("Apple clang version 15.0.0"), &minimums).is_err());
+        assert!(check_cc_version(None, &minimums).is_err());
+        assert!("icc:19".parse::<MinCcVersion>().is_err());
+        assert!("nine".parse::<MinCcVersion>().is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::builder::{CompileJob, GrammarCompiler};
+use crate::error::CompileError;
+use crate::package::{library_mode, set_mode};
+use crate::publish::Remote;
+
+// cached libraries don't change once uploaded, the key covers everything
+// that went into them
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+// --build-cache: before compiling a grammar, look for a library built from
+// the same commit for the same target with the same compiler and flags, and
+// download it instead. What is compiled is uploaded for the next run.
+pub struct CachedBuilder {
+    pub inner: Arc<dyn GrammarCompiler>,
+    pub remote: Box<dyn Remote>,
+    // only download, for runs without credentials to write, like PRs from forks
+    pub read_only: bool,
+}
+
+impl fmt::Debug for CachedBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachedBuilder")
+            .field("inner", &self.inner)
+            .field("remote", &self.remote.describe())
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+impl GrammarCompiler for CachedBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        let Some(key) = build_key(job, &self.inner.describe()) else {
+            return self.inner.compile(job);
+        };
+        let object = format!("builds/{}", key);
+        match self.remote.get(&object) {
+            Ok(Some(library)) => match write_library(job.output, &library) {
+                Ok(()) => {
+                    log::info!("{} came from the build cache ({})", job.lang, key);
+                    return Ok(());
+                }
+                Err(e) => log::warn!("writing the cached {} failed: {}", job.lang, e),
+            },
+            Ok(None) => log::debug!("{} isn't in the build cache ({})", job.lang, key),
+            Err(e) => log::warn!(
+                "looking up {} in the build cache failed: {}, compiling it",
+                job.lang,
+                e
+            ),
+        }
+
+        self.inner.compile(job)?;
+        if self.read_only {
+            return Ok(());
+        }
+        // a cache that can't be written to doesn't fail the build
+        match fs::read(job.output)
+            .map_err(|e| e.into())
+            .and_then(|library| self.remote.put(&object, &library, IMMUTABLE))
+        {
+            Ok(()) => log::info!("uploaded {} to the build cache ({})", job.lang, key),
+            Err(e) => log::warn!("uploading {} to the build cache failed: {}", job.lang, e),
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    fn compiler_version(&self) -> Option<String> {
+        self.inner.compiler_version()
+    }
+
+    fn takes_flags(&self) -> bool {
+        self.inner.takes_flags()
+    }
+}
+
+// The sha256 of the grammar's commit, the target, the compiler and the
+// arguments and environment it is given. The checkout and output paths are
+// left out, they differ between machines but not what is built. None for a
+// checkout with local changes, which the commit doesn't describe.
+fn build_key(job: &CompileJob, compiler: &str) -> Option<String> {
+    let commit = git(job.repo_dir, &["rev-parse", "HEAD"])?;
+    let changes = git(job.repo_dir, &["status", "--porcelain"])?;
+    if !changes.is_empty() {
+        log::info!(
+            "{} has local changes, not using the build cache for it",
+            job.lang
+        );
+        return None;
+    }
+
+    let repo_dir = fs::canonicalize(job.repo_dir).unwrap_or(job.repo_dir.to_path_buf());
+    let normalize = |s: &str| {
+        s.replace(&*job.output.to_string_lossy(), "<output>")
+            .replace(&*repo_dir.to_string_lossy(), "<repo>")
+            .replace(&*job.repo_dir.to_string_lossy(), "<repo>")
+    };
+    let mut hasher = Sha256::new();
+    let mut add = |field: &str, value: &str| {
+        hasher.update(field.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    };
+    add("commit", &commit);
+    add(
+        "grammar",
+        &job.grammar_dir
+            .strip_prefix(job.repo_dir)
+            .unwrap_or(job.grammar_dir)
+            .to_string_lossy(),
+    );
+    add(
+        "target",
+        &std::env::var("TARGET")
+            .unwrap_or_else(|_| format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)),
+    );
+    add("compiler", compiler);
+    for arg in job.args {
+        add("arg", &normalize(arg));
+    }
+    let mut env = job.env.to_vec();
+    env.sort();
+    for (name, value) in env {
+        add("env", &format!("{}={}", name, normalize(&value)));
+    }
+    Some(
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    )
+}
+
+fn write_library(output: &Path, library: &[u8]) -> std::io::Result<()> {
+    fs::write(output, library)?;
+    set_mode(output, library_mode(&output.to_string_lossy()))
+}
+
+// trimmed stdout, None when git fails
+fn git(repo_dir: &Path, args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::limits::ResourceLimits;
+    use crate::publish::tests::MemoryRemote;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // a checkout with one commit, at <root>/<name>
+    fn checkout(root: &Path, name: &str) -> PathBuf {
+        let repo = root.join(name);
+        fs::create_dir_all(repo.join("src")).unwrap();
+        fs::write(repo.join("src/parser.c"), "int x;").unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .env("GIT_AUTHOR_DATE", "2026-01-01T00:00:00Z")
+                .env("GIT_COMMITTER_DATE", "2026-01-01T00:00:00Z")
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&[
+            "commit",
+            "-q",
+            "-m",
+            "grammar",
+            "--date",
+            "2026-01-01T00:00:00",
+        ]);
+        repo
+    }
+
+    fn key(repo: &Path, args: &[&str], compiler: &str) -> Option<String> {
+        let output = repo.join("out/libjson.so");
+        let args: Vec<String> = args
+            .iter()
+            .map(|a| a.replace("<out>", &output.to_string_lossy()))
+            .collect();
+        let limits = ResourceLimits::default();
+        let cancel = CancelToken::new();
+        build_key(
+            &CompileJob {
+                lang: "json",
+                repo_dir: repo,
+                grammar_dir: repo,
+                output: &output,
+                args: &args,
+                env: &[],
+                sandbox: None,
+                limits: &limits,
+                cancel: &cancel,
+            },
+            compiler,
+        )
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn build_keys() {
+        let root = scratch("keys");
+        let repo = checkout(&root, "a");
+        let args = ["-shared", "-o", "<out>", "src/parser.c"];
+        let first = key(&repo, &args, "gcc 13").unwrap();
+        assert_eq!(key(&repo, &args, "gcc 13").as_ref(), Some(&first));
+        assert_ne!(key(&repo, &args, "gcc 14").as_ref(), Some(&first));
+        assert_ne!(
+            key(
+                &repo,
+                &["-shared", "-O3", "-o", "<out>", "src/parser.c"],
+                "gcc 13"
+            )
+            .as_ref(),
+            Some(&first)
+        );
+
+        // the same commit somewhere else builds the same library
+        let elsewhere = checkout(&root, "b");
+        assert_eq!(key(&elsewhere, &args, "gcc 13").as_ref(), Some(&first));
+
+        // local changes aren't what the commit describes
+        fs::write(repo.join("src/parser.c"), "int y;").unwrap();
+        assert_eq!(key(&repo, &args, "gcc 13"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // writes a library, counting the compiles
+    #[derive(Debug, Default)]
+    struct Compiler {
+        compiles: AtomicUsize,
+    }
+
+    impl GrammarCompiler for Compiler {
+        fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+            self.compiles.fetch_add(1, Ordering::SeqCst);
+            fs::write(job.output, b"compiled")?;
+            Ok(())
+        }
+
+        fn describe(&self) -> String {
+            "gcc 13".to_string()
+        }
+    }
+
+    fn compile(builder: &CachedBuilder, repo: &Path) -> Vec<u8> {
+        let output = repo.join("libjson.so");
+        let limits = ResourceLimits::default();
+        let cancel = CancelToken::new();
+        builder
+            .compile(&CompileJob {
+                lang: "json",
+                repo_dir: repo,
+                grammar_dir: repo,
+                output: &output,
+                args: &[],
+                env: &[],
+                sandbox: None,
+                limits: &limits,
+                cancel: &cancel,
+            })
+            .unwrap();
+        fs::read(&output).unwrap()
+    }
+
+    #[test]
+    fn compiles_once_then_downloads() {
+        let root = scratch("builder");
+        let inner = Arc::new(Compiler::default());
+        let builder = CachedBuilder {
+            inner: inner.clone(),
+            remote: Box::new(MemoryRemote::default()),
+            read_only: false,
+        };
+        assert_eq!(compile(&builder, &checkout(&root, "a")), b"compiled");
+        assert_eq!(compile(&builder, &checkout(&root, "b")), b"compiled");
+        assert_eq!(inner.compiles.load(Ordering::SeqCst), 1);
+
+        let read_only = CachedBuilder {
+            inner: inner.clone(),
+            remote: Box::new(MemoryRemote::default()),
+            read_only: true,
+        };
+        compile(&read_only, &checkout(&root, "c"));
+        compile(&read_only, &checkout(&root, "d"));
+        assert_eq!(inner.compiles.load(Ordering::SeqCst), 3);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often waits check for a cancellation
+const POLL: Duration = Duration::from_millis(50);
+
+// how long a cancelled process gets to exit on SIGTERM before it's killed,
+// enough for `docker run` to stop its container
+const GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    // the first reason given wins
+    reason: Mutex<Option<String>>,
+    parent: Option<CancelToken>,
+}
+
+// Asks every stage of a run to stop: Ctrl-C, a deadline, --fail-fast. Work is
+// stopped cooperatively, queued tasks check the token before starting and
+// child processes run through `output` are killed, so whatever is on the
+// stack unwinds as usual. Clones share the token, a `child` is cancelled
+// along with its parent but not the other way round.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn child(&self) -> CancelToken {
+        CancelToken {
+            inner: Arc::new(Inner {
+                parent: Some(self.clone()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn cancel(&self, reason: impl Into<String>) {
+        let mut current = self.inner.reason.lock().unwrap();
+        if current.is_none() {
+            *current = Some(reason.into());
+        }
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+            || self.inner.parent.as_ref().is_some_and(|p| p.is_cancelled())
+    }
+
+    // why, once cancelled
+    pub fn reason(&self) -> Option<String> {
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            return self.inner.reason.lock().unwrap().clone();
+        }
+        self.inner.parent.as_ref().and_then(|p| p.reason())
+    }
+
+    // Cancel once `after` has passed. The timer goes away with the last clone
+    // of the token, so a run finishing early leaves nothing behind.
+    pub fn cancel_after(&self, after: Duration) {
+        let token = Arc::downgrade(&self.inner);
+        let deadline = Instant::now() + after;
+        thread::spawn(move || loop {
+            let Some(inner) = Weak::upgrade(&token) else {
+                return;
+            };
+            let token = CancelToken { inner };
+            if token.is_cancelled() {
+                return;
+            }
+            if Instant::now() >= deadline {
+                token.cancel(format!("the deadline of {}s passed", after.as_secs()));
+                return;
+            }
+            drop(token);
+            thread::sleep(POLL);
+        });
+    }
+
+    // Sleep for `duration`, or less when cancelled meanwhile. false when it
+    // was cut short.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while Instant::now() < until {
+            if self.is_cancelled() {
+                return false;
+            }
+            thread::sleep(POLL.min(until - Instant::now()));
+        }
+        !self.is_cancelled()
+    }
+
+    // resolves once cancelled, for the async stages to select on
+    pub async fn cancelled(&self) {
+        while !self.is_cancelled() {
+            tokio::time::sleep(POLL).await;
+        }
+    }
+
+    // `cmd.output()`, but the process and everything it started is killed
+    // when the token is cancelled, returning an Interrupted error
+    pub fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        if self.is_cancelled() {
+            return Err(self.error());
+        }
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // its own group, so cc1 and ld go down with gcc
+            cmd.process_group(0);
+        }
+        let mut child = cmd.spawn()?;
+        // read on threads, a full pipe would block the child forever
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if self.is_cancelled() {
+                terminate(&mut child);
+                return Err(self.error());
+            }
+            thread::sleep(POLL);
+        };
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    // the same for the clone stage, which runs on the async runtime
+    pub async fn output_async(&self, cmd: &mut tokio::process::Command) -> io::Result<Output> {
+        if self.is_cancelled() {
+            return Err(self.error());
+        }
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        cmd.process_group(0);
+        let child = cmd.spawn()?;
+        let pid = child.id();
+        tokio::select! {
+            output = child.wait_with_output() => output,
+            _ = self.cancelled() => {
+                // dropping the child kills it, this gets the rest of its group
+                #[cfg(unix)]
+                if let Some(pid) = pid {
+                    // SAFETY: only sends a signal
+                    unsafe {
+                        libc::kill(-(pid as i32), libc::SIGKILL);
+                    }
+                }
+                #[cfg(not(unix))]
+                let _ = pid;
+                Err(self.error())
+            }
+        }
+    }
+
+    fn error(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!(
+                "cancelled: {}",
+                self.reason()
+                    .unwrap_or_else(|| "no reason given".to_string())
+            ),
+        )
+    }
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+// SIGTERM to the process group, then SIGKILL if it's still around after GRACE
+fn terminate(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        let group = -(child.id() as i32);
+        // SAFETY: only sends signals
+        unsafe {
+            libc::kill(group, libc::SIGTERM);
+        }
+        let until = Instant::now() + GRACE;
+        while Instant::now() < until {
+            if let Ok(Some(_)) = child.try_wait() {
+                break;
+            }
+            thread::sleep(POLL);
+        }
+        unsafe {
+            libc::kill(group, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
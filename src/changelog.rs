@@ -0,0 +1,123 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::config::LanguageArtifact;
+use crate::languages::canonical_name;
+use crate::store::LastBuild;
+
+// the upstream version and commit of one build, either may be unknown
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Release {
+    pub version: Option<String>,
+    pub commit: Option<String>,
+}
+
+impl Release {
+    fn of_entry(entry: &Value) -> Release {
+        Release {
+            version: entry
+                .get("version")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            commit: entry
+                .get("commit")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        }
+    }
+}
+
+impl fmt::Display for Release {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.version, short(&self.commit)) {
+            (Some(version), Some(commit)) => write!(f, "{} ({})", version, commit),
+            (Some(version), None) => f.write_str(version),
+            (None, Some(commit)) => f.write_str(&commit),
+            (None, None) => f.write_str("unknown"),
+        }
+    }
+}
+
+// What happened to one config entry since the builds the store had before the run
+#[derive(Debug, Clone)]
+pub enum Change {
+    Added(String, Release),
+    Updated(String, Release, Release),
+    // the source doesn't list its language anymore
+    Removed(String, Release),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added(name, release) => write!(f, "+ {} {}", name, release),
+            Change::Updated(name, before, after) => {
+                // only the half that moved, the version is often left alone
+                let (before, after) = if before.version == after.version {
+                    (
+                        short(&before.commit).unwrap_or_else(|| "unknown".to_string()),
+                        short(&after.commit).unwrap_or_else(|| "unknown".to_string()),
+                    )
+                } else {
+                    (before.to_string(), after.to_string())
+                };
+                write!(f, "~ {} {} -> {}", name, before, after)
+            }
+            Change::Removed(name, release) => write!(f, "- {} {}", name, release),
+        }
+    }
+}
+
+// The changes between `previous`, the last builds from the store as the run
+// started, and the `artifacts` of the run. Entries whose language the run
+// didn't get to (left out with -l, failed) aren't removed unless the source
+// stopped listing them.
+pub fn changes(
+    previous: &[LastBuild],
+    artifacts: &[LanguageArtifact],
+    listed: &BTreeSet<String>,
+) -> Vec<Change> {
+    let before: BTreeMap<&str, &LastBuild> =
+        previous.iter().map(|b| (b.name.as_str(), b)).collect();
+    let mut changes = Vec::new();
+    let mut seen = BTreeSet::new();
+    let mut sorted: Vec<&LanguageArtifact> = artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    for artifact in sorted {
+        seen.insert(artifact.name.as_str());
+        let after = Release::of_entry(&artifact.entry);
+        match before.get(artifact.name.as_str()) {
+            None => changes.push(Change::Added(artifact.name.clone(), after)),
+            Some(build) => {
+                let before = Release {
+                    version: build
+                        .entry
+                        .as_ref()
+                        .and_then(|e| e.get("version"))
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    commit: build.commit.clone(),
+                };
+                if before != after {
+                    changes.push(Change::Updated(artifact.name.clone(), before, after));
+                }
+            }
+        }
+    }
+    for (name, build) in before {
+        if !seen.contains(name) && !listed.contains(&canonical_name(&build.language)) {
+            let release = build
+                .entry
+                .as_ref()
+                .map(Release::of_entry)
+                .unwrap_or_default();
+            changes.push(Change::Removed(name.to_string(), release));
+        }
+    }
+    changes
+}
+
+fn short(commit: &Option<String>) -> Option<String> {
+    commit.as_ref().map(|c| c[..c.len().min(7)].to_string())
+}
@@ -0,0 +1,98 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const SHA256SUMS: &str = "SHA256SUMS";
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// Hash the given artifacts and record them in <output>/SHA256SUMS, in the
+// format `sha256sum -c` reads from inside the output directory. Lines for
+// artifacts built by earlier runs are kept while the file is still there.
+// Returns the hash of each artifact keyed by the path it was given as.
+pub fn write_sha256sums(
+    output_dir: &str,
+    artifacts: &[String],
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let output_dir = Path::new(output_dir);
+    let sums_path = output_dir.join(SHA256SUMS);
+
+    // relative path -> hash
+    let mut sums: BTreeMap<String, String> = BTreeMap::new();
+    if let Ok(existing) = fs::read_to_string(&sums_path) {
+        for line in existing.lines() {
+            if let Some((hash, name)) = line.split_once("  ") {
+                if output_dir.join(name).exists() {
+                    sums.insert(name.to_string(), hash.to_string());
+                }
+            }
+        }
+    }
+
+    let mut hashes = BTreeMap::new();
+    for artifact in artifacts {
+        let hash = sha256_file(Path::new(artifact))?;
+        let relative = Path::new(artifact)
+            .strip_prefix(output_dir)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| artifact.clone());
+        sums.insert(relative, hash.clone());
+        hashes.insert(artifact.clone(), hash);
+    }
+
+    let content: String = sums
+        .iter()
+        .map(|(name, hash)| format!("{}  {}\n", hash, name))
+        .collect();
+    fs::write(&sums_path, content)?;
+    Ok(hashes)
+}
+
+// Re-hash every file listed in <output>/SHA256SUMS, one line per mismatch or missing file
+pub fn verify_sha256sums(output_dir: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output_dir = Path::new(output_dir);
+    let sums_path = output_dir.join(SHA256SUMS);
+    let content = fs::read_to_string(&sums_path)
+        .map_err(|e| format!("can't read {}: {}", sums_path.display(), e))?;
+
+    let mut problems = Vec::new();
+    for line in content.lines() {
+        let (expected, name) = match line.split_once("  ") {
+            Some(parts) => parts,
+            None => {
+                problems.push(format!("malformed line in {}: {}", SHA256SUMS, line));
+                continue;
+            }
+        };
+        match sha256_file(&output_dir.join(name)) {
+            Ok(actual) if actual == expected => {}
+            Ok(_) => problems.push(format!("{}: checksum mismatch", name)),
+            Err(e) => problems.push(format!("{}: {}", name, e)),
+        }
+    }
+    Ok(problems)
+}
+
+// the files SHA256SUMS lists, as paths under the output directory
+pub fn listed_files(output_dir: &str) -> Vec<PathBuf> {
+    let output_dir = Path::new(output_dir);
+    fs::read_to_string(output_dir.join(SHA256SUMS))
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| line.split_once("  "))
+                .map(|(_, name)| output_dir.join(name))
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,89 @@
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::config::LanguageArtifact;
+use crate::emit::{EmitContext, Emitter};
+use crate::error::EmitError;
+use crate::pkgconfig;
+
+pub const CONFIG_FILE: &str = "TreeSitterGrammarsConfig.cmake";
+
+// <dir>/cmake/TreeSitterGrammarsConfig.cmake with an imported target
+// ts::<lang> per built grammar, for `find_package(TreeSitterGrammars)` with
+// the output directory on CMAKE_PREFIX_PATH. Its headers go where
+// --emit pkg-config puts them, <dir>/include/tree_sitter/.
+pub fn write_cmake_config(
+    dir: &str,
+    artifacts: &[LanguageArtifact],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let dir = Path::new(dir);
+    let cmake_dir = dir.join("cmake");
+    let include_dir = dir.join("include").join("tree_sitter");
+    fs::create_dir_all(&cmake_dir)?;
+    fs::create_dir_all(&include_dir)?;
+
+    let mut sorted: Vec<&LanguageArtifact> = artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut targets = String::new();
+    let mut names = Vec::new();
+    for artifact in sorted {
+        let Some(library) = artifact.entry.get("path").and_then(Value::as_str) else {
+            continue;
+        };
+        // relative to the output directory the config is in, unless outside it
+        let location = match Path::new(library).strip_prefix(dir) {
+            Ok(relative) => format!("${{_TS_GRAMMARS_PREFIX}}/{}", relative.display()),
+            Err(_) => fs::canonicalize(library)?.display().to_string(),
+        };
+        pkgconfig::write_header(&include_dir, artifact)?;
+        let target = format!("ts::{}", artifact.name);
+        // the libraries have no SONAME, linked by path the full path would end
+        // up in DT_NEEDED
+        targets.push_str(&format!(
+            "if(NOT TARGET {target})\n  \
+               add_library({target} SHARED IMPORTED)\n  \
+               set_target_properties({target} PROPERTIES\n    \
+                 IMPORTED_LOCATION \"{location}\"\n    \
+                 IMPORTED_NO_SONAME TRUE\n    \
+                 INTERFACE_INCLUDE_DIRECTORIES \"${{_TS_GRAMMARS_PREFIX}}/include\")\n\
+             endif()\n\
+             \n"
+        ));
+        names.push(artifact.name.clone());
+    }
+    let config = format!(
+        "# @generated by parser_scraper, do not edit\n\
+         # find_package(TreeSitterGrammars) with this output directory on CMAKE_PREFIX_PATH,\n\
+         # then target_link_libraries(app PRIVATE ts::rust), #include <tree_sitter/tree-sitter-rust.h>\n\
+         get_filename_component(_TS_GRAMMARS_PREFIX \"${{CMAKE_CURRENT_LIST_DIR}}/..\" ABSOLUTE)\n\
+         \n\
+         set(TreeSitterGrammars_LANGUAGES {})\n\
+         \n\
+         {}\
+         unset(_TS_GRAMMARS_PREFIX)\n",
+        names.join(" "),
+        targets
+    );
+    fs::write(cmake_dir.join(CONFIG_FILE), config)?;
+    Ok(())
+}
+
+// --emit cmake, into `dir`
+pub struct CmakeConfig {
+    pub dir: String,
+}
+
+impl Emitter for CmakeConfig {
+    fn destination(&self) -> String {
+        Path::new(&self.dir)
+            .join("cmake")
+            .join(CONFIG_FILE)
+            .display()
+            .to_string()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        write_cmake_config(&self.dir, artifacts).map_err(EmitError::write(self.destination()))
+    }
+}
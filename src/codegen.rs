@@ -0,0 +1,385 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{entry_extensions, LanguageArtifact, PathStyle};
+use crate::emit::{EmitContext, Emitter};
+use crate::error::EmitError;
+use crate::languages::checked_symbol_name;
+
+// Render the built grammars as Rust source meant to be `include!`d: a struct
+// definition and a static slice with one element per language, sorted by name.
+pub fn render_rust_table(
+    artifacts: &[LanguageArtifact],
+    path_style: Option<PathStyle>,
+    destination: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut sorted: Vec<&LanguageArtifact> = artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut source = String::from(
+        "// @generated by parser_scraper, do not edit\n\
+         \n\
+         pub struct KnownLanguage {\n    \
+             pub name: &'static str,\n    \
+             pub symbol: &'static str,\n    \
+             pub extensions: &'static [&'static str],\n    \
+             pub path: &'static str,\n\
+         }\n\
+         \n\
+         pub static KNOWN_LANGUAGES: &[KnownLanguage] = &[\n",
+    );
+    for artifact in sorted {
+        let language = artifact
+            .entry
+            .get("language")
+            .and_then(Value::as_str)
+            .unwrap_or(&artifact.name);
+        let mut path = artifact
+            .entry
+            .get("path")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        if let Some(style) = path_style {
+            path = style.apply(&path, destination)?;
+        }
+        let extensions: Vec<String> = entry_extensions(&artifact.entry)
+            .iter()
+            .map(|e| format!("{:?}", e))
+            .collect();
+        source.push_str(&format!(
+            "    KnownLanguage {{\n        \
+                 name: {:?},\n        \
+                 symbol: {:?},\n        \
+                 extensions: &[{}],\n        \
+                 path: {:?},\n    \
+             }},\n",
+            artifact.name,
+            checked_symbol_name(language)?,
+            extensions.join(", "),
+            path
+        ));
+    }
+    source.push_str("];\n");
+    Ok(source)
+}
+
+pub fn write_rust_table(
+    path: &str,
+    artifacts: &[LanguageArtifact],
+    path_style: Option<PathStyle>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    fs::write(path, render_rust_table(artifacts, path_style, path)?)?;
+    Ok(())
+}
+
+// Generate a cargo crate wrapping the built libraries: extern declarations for
+// every entry point, a `language(name)` lookup, and a build.rs that links the
+// libraries from where they were built.
+pub fn write_wrapper_crate(
+    crate_dir: &str,
+    artifacts: &[LanguageArtifact],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut sorted: Vec<&LanguageArtifact> = artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let crate_name = Path::new(crate_dir)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty() && n != "." && n != "..")
+        .unwrap_or_else(|| "tree-sitter-grammars".to_string());
+
+    // (name, symbol) for the lookup, plus the libraries to link, each once
+    let mut languages: Vec<(String, String)> = Vec::new();
+    let mut libraries: BTreeSet<PathBuf> = BTreeSet::new();
+    for artifact in sorted {
+        let language = artifact
+            .entry
+            .get("language")
+            .and_then(Value::as_str)
+            .unwrap_or(&artifact.name);
+        languages.push((artifact.name.clone(), checked_symbol_name(language)?));
+        if let Some(path) = artifact.entry.get("path").and_then(Value::as_str) {
+            // the crate is built from elsewhere, so link by absolute path
+            libraries.insert(fs::canonicalize(path)?);
+        }
+    }
+
+    let mut symbols: Vec<&str> = languages.iter().map(|(_, s)| s.as_str()).collect();
+    symbols.sort();
+    symbols.dedup();
+
+    let mut lib_rs = String::from(
+        "// @generated by parser_scraper, do not edit\n\
+         use tree_sitter::Language;\n\
+         \n\
+         extern \"C\" {\n",
+    );
+    for symbol in &symbols {
+        lib_rs.push_str(&format!("    fn {}() -> Language;\n", symbol));
+    }
+    lib_rs.push_str("}\n\n// names accepted by `language`\npub const LANGUAGES: &[&str] = &[\n");
+    for (name, _) in &languages {
+        lib_rs.push_str(&format!("    {:?},\n", name));
+    }
+    lib_rs.push_str(
+        "];\n\
+         \n\
+         pub fn language(name: &str) -> Option<Language> {\n    \
+             // SAFETY: every symbol is a grammar entry point exported by the linked libraries\n    \
+             unsafe {\n        \
+                 match name {\n",
+    );
+    for (name, symbol) in &languages {
+        lib_rs.push_str(&format!("            {:?} => Some({}()),\n", name, symbol));
+    }
+    lib_rs.push_str("            _ => None,\n        }\n    }\n}\n");
+
+    let mut build_rs = String::from("// @generated by parser_scraper, do not edit\nfn main() {\n");
+    let dirs: BTreeSet<&Path> = libraries.iter().filter_map(|l| l.parent()).collect();
+    // quoted, and not as the format string, paths can have quotes and braces
+    for dir in dirs {
+        build_rs.push_str(&format!(
+            "    println!(\"{{}}\", {:?});\n",
+            format!("cargo:rustc-link-search=native={}", dir.display())
+        ));
+    }
+    for library in &libraries {
+        if let Some(file_name) = library.file_name() {
+            // verbatim, since library names don't always follow lib<name>.so
+            build_rs.push_str(&format!(
+                "    println!(\"{{}}\", {:?});\n",
+                format!(
+                    "cargo:rustc-link-lib=dylib:+verbatim={}",
+                    file_name.to_string_lossy()
+                )
+            ));
+        }
+    }
+    build_rs.push_str("}\n");
+
+    let cargo_toml = format!(
+        "[package]\n\
+         name = \"{}\"\n\
+         version = \"0.1.0\"\n\
+         edition = \"2021\"\n\
+         build = \"build.rs\"\n\
+         \n\
+         [dependencies]\n\
+         tree-sitter = \"0.24\"\n",
+        crate_name
+    );
+
+    let crate_dir = Path::new(crate_dir);
+    fs::create_dir_all(crate_dir.join("src"))?;
+    fs::write(crate_dir.join("Cargo.toml"), cargo_toml)?;
+    fs::write(crate_dir.join("build.rs"), build_rs)?;
+    fs::write(crate_dir.join("src").join("lib.rs"), lib_rs)?;
+    Ok(())
+}
+
+// Write parsers.h declaring every grammar entry point and parsers.c with a
+// name -> entry point registry, for C/C++ consumers linking the grammars in.
+pub fn write_c_registry(
+    dir: &str,
+    artifacts: &[LanguageArtifact],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut languages: Vec<(String, String)> = artifacts
+        .iter()
+        .map(|artifact| {
+            let language = artifact
+                .entry
+                .get("language")
+                .and_then(Value::as_str)
+                .unwrap_or(&artifact.name);
+            Ok((artifact.name.clone(), checked_symbol_name(language)?))
+        })
+        .collect::<Result<_, String>>()?;
+    languages.sort();
+    let mut symbols: Vec<&str> = languages.iter().map(|(_, s)| s.as_str()).collect();
+    symbols.sort();
+    symbols.dedup();
+
+    let mut header = String::from(
+        "/* @generated by parser_scraper, do not edit */\n\
+         #ifndef PARSER_SCRAPER_PARSERS_H\n\
+         #define PARSER_SCRAPER_PARSERS_H\n\
+         \n\
+         #include <stddef.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {\n\
+         #endif\n\
+         \n\
+         typedef struct TSLanguage TSLanguage;\n\
+         \n",
+    );
+    for symbol in &symbols {
+        header.push_str(&format!("const TSLanguage *{}(void);\n", symbol));
+    }
+    header.push_str(
+        "\n\
+         typedef struct {\n    \
+             const char *name;\n    \
+             const TSLanguage *(*language)(void);\n\
+         } parser_scraper_language;\n\
+         \n\
+         /* every built grammar by name, terminated by a { NULL, NULL } entry */\n\
+         extern const parser_scraper_language parser_scraper_languages[];\n\
+         extern const size_t parser_scraper_language_count;\n\
+         \n\
+         /* the grammar called `name`, or NULL */\n\
+         const TSLanguage *parser_scraper_find(const char *name);\n\
+         \n\
+         #ifdef __cplusplus\n\
+         }\n\
+         #endif\n\
+         \n\
+         #endif\n",
+    );
+
+    let mut source = String::from(
+        "/* @generated by parser_scraper, do not edit */\n\
+         #include <string.h>\n\
+         \n\
+         #include \"parsers.h\"\n\
+         \n\
+         const parser_scraper_language parser_scraper_languages[] = {\n",
+    );
+    for (name, symbol) in &languages {
+        source.push_str(&format!("    {{ {}, {} }},\n", c_string(name), symbol));
+    }
+    source.push_str(&format!(
+        "    {{ NULL, NULL }},\n\
+         }};\n\
+         \n\
+         const size_t parser_scraper_language_count = {};\n\
+         \n\
+         const TSLanguage *parser_scraper_find(const char *name) {{\n    \
+             for (size_t i = 0; i < parser_scraper_language_count; i++) {{\n        \
+                 if (strcmp(parser_scraper_languages[i].name, name) == 0) {{\n            \
+                     return parser_scraper_languages[i].language();\n        \
+                 }}\n    \
+             }}\n    \
+             return NULL;\n\
+         }}\n",
+        languages.len()
+    ));
+
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("parsers.h"), header)?;
+    fs::write(dir.join("parsers.c"), source)?;
+    Ok(())
+}
+
+// `s` as a C string literal, anything but printable ASCII as octal escapes.
+// Always three digits, so a digit after one isn't read as part of it.
+fn c_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'\\' => literal.push_str("\\\\"),
+            b'"' => literal.push_str("\\\""),
+            // no trigraphs either
+            b'?' => literal.push_str("\\?"),
+            0x20..=0x7e => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+// --emit rust
+pub struct RustTable {
+    pub path: String,
+    pub path_style: Option<PathStyle>,
+}
+
+impl Emitter for RustTable {
+    fn destination(&self) -> String {
+        self.path.clone()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        write_rust_table(&self.path, artifacts, self.path_style)
+            .map_err(EmitError::write(&self.path))
+    }
+}
+
+// --emit crate
+pub struct WrapperCrate {
+    pub dir: String,
+}
+
+impl Emitter for WrapperCrate {
+    fn destination(&self) -> String {
+        self.dir.clone()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        write_wrapper_crate(&self.dir, artifacts).map_err(EmitError::write(&self.dir))
+    }
+}
+
+// --emit c, parsers.h and parsers.c in `dir`
+pub struct CRegistry {
+    pub dir: String,
+}
+
+impl Emitter for CRegistry {
+    fn destination(&self) -> String {
+        Path::new(&self.dir).join("parsers.h").display().to_string()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        write_c_registry(&self.dir, artifacts).map_err(EmitError::write(self.destination()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn build_rs_quotes_library_paths() {
+        let root = std::env::temp_dir().join(format!("codegen-{}", std::process::id()));
+        let lib_dir = root.join("we\"ird {dir}\\");
+        fs::create_dir_all(&lib_dir).unwrap();
+        let library = lib_dir.join("libtree-sitter-rust.so");
+        fs::write(&library, b"").unwrap();
+        let artifact = LanguageArtifact {
+            name: "rust".to_string(),
+            repo_url: String::new(),
+            scope: None,
+            license: None,
+            license_files: Vec::new(),
+            corpus: None,
+            test_files: Vec::new(),
+            tree_diff: None,
+            entry: json!({ "language": "rust", "path": library }),
+        };
+        let crate_dir = root.join("grammars");
+        write_wrapper_crate(&crate_dir.to_string_lossy(), &[artifact]).unwrap();
+        let build_rs = fs::read_to_string(crate_dir.join("build.rs")).unwrap();
+        let search = format!(
+            "cargo:rustc-link-search=native={}",
+            fs::canonicalize(&lib_dir).unwrap().display()
+        );
+        assert!(build_rs.contains(&format!("println!(\"{{}}\", {:?});", search)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn c_string_escapes() {
+        assert_eq!(c_string("rust"), "\"rust\"");
+        assert_eq!(c_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(c_string("??="), "\"\\?\\?=\"");
+        assert_eq!(c_string("é1"), "\"\\303\\2511\"");
+        assert_eq!(c_string("a\nb\0"), "\"a\\012b\\000\"");
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::Value;
+
+use crate::config::LanguageArtifact;
+
+// what --zstd compresses with unless told otherwise, zstd's own default is 3
+pub const ZSTD_LEVEL: i32 = 19;
+
+// <library>.zst, next to it
+pub fn zstd_path(library: &Path) -> PathBuf {
+    let mut path = library.as_os_str().to_owned();
+    path.push(".zst");
+    PathBuf::from(path)
+}
+
+// Compress `library` to <library>.zst with the zstd CLI, keeping the library
+pub fn write_zstd(library: &Path, level: i32) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let compressed = zstd_path(library);
+    let output = Command::new("zstd")
+        .arg("-q")
+        .arg("-f")
+        .arg(format!("-{}", level))
+        // levels past 19 need --ultra
+        .args((level > 19).then_some("--ultra"))
+        .arg("-o")
+        .arg(&compressed)
+        .arg(library)
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => "zstd isn't installed".to_string(),
+            _ => format!("failed to run zstd: {}", e),
+        })?;
+    if !output.status.success() {
+        return Err(format!(
+            "zstd failed for {}: {}",
+            library.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(compressed)
+}
+
+// the size of one library on disk, and of its .zst when it has one
+#[derive(Debug, Clone)]
+pub struct LibrarySize {
+    pub path: String,
+    // the languages the library is the parser of, usually one
+    pub languages: Vec<String>,
+    pub size: u64,
+    pub compressed: Option<u64>,
+}
+
+// The libraries of `artifacts` with their sizes, biggest first. A .zst only
+// counts with `compressed`, one an earlier run left may be of another build.
+pub fn library_sizes(artifacts: &[LanguageArtifact], compressed: bool) -> Vec<LibrarySize> {
+    let mut languages: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for artifact in artifacts {
+        if let Some(path) = artifact.entry.get("path").and_then(Value::as_str) {
+            languages
+                .entry(path)
+                .or_default()
+                .push(artifact.name.clone());
+        }
+    }
+    let mut sizes: Vec<LibrarySize> = languages
+        .into_iter()
+        .filter_map(|(path, languages)| {
+            let size = fs::metadata(path).ok()?.len();
+            let compressed = compressed
+                .then(|| fs::metadata(zstd_path(Path::new(path))).ok())
+                .flatten()
+                .map(|m| m.len());
+            Some(LibrarySize {
+                path: path.to_string(),
+                languages,
+                size,
+                compressed,
+            })
+        })
+        .collect();
+    sizes.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    sizes
+}
+
+// How many of the biggest `sizes` make up half of their total, at least one
+pub fn biggest_half(sizes: &[LibrarySize]) -> usize {
+    let total: u64 = sizes.iter().map(|s| s.size).sum();
+    let mut sum = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        sum += size.size;
+        if sum * 2 >= total {
+            return i + 1;
+        }
+    }
+    sizes.len()
+}
@@ -0,0 +1,994 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::corpus::CorpusReport;
+use crate::emit::{EmitContext, Emitter};
+use crate::error::BuildError;
+use crate::error::{ConfigError, EmitError};
+use crate::grammar::find_file;
+use crate::languages::{
+    aliases_for, checked_symbol_name, is_filename, known_extensions, known_filenames,
+    known_shebangs, normalize_name, safe_name, DEFAULT_EXTENSION_PRIORITY,
+};
+use crate::regression::TreeDiff;
+use crate::smoke::TestFileParse;
+
+// on-disk format of the config file, all of them share the same schema
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    // pick the format from the config file's extension, json when unknown
+    pub fn from_path(path: &str) -> ConfigFormat {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    pub fn parse(&self, content: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::from_str(content)?,
+            ConfigFormat::Toml => toml::from_str(content)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(content)?,
+        })
+    }
+
+    pub fn render(&self, document: &Value) -> Result<String, Box<dyn std::error::Error>> {
+        let mut rendered = match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(document)?,
+            // TOML has no null, so unset fields are left out
+            ConfigFormat::Toml => toml::to_string_pretty(&without_nulls(document))?,
+            ConfigFormat::Yaml => serde_yaml::to_string(document)?,
+        };
+        if !rendered.ends_with('\n') {
+            rendered.push('\n');
+        }
+        Ok(rendered)
+    }
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .filter(|v| !v.is_null())
+                .map(without_nulls)
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+// how artifact paths are written into entries, as given on the command line when unset
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PathStyle {
+    Absolute,
+    // relative to the directory holding the config file
+    RelativeToConfig,
+}
+
+impl PathStyle {
+    pub fn apply(&self, path: &str, config_path: &str) -> std::io::Result<String> {
+        let absolute = absolute_path(Path::new(path))?;
+        let styled = match self {
+            PathStyle::Absolute => absolute,
+            PathStyle::RelativeToConfig => {
+                let config_dir = Path::new(config_path)
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                relative_path(&absolute_path(config_dir)?, &absolute)
+            }
+        };
+        Ok(styled.to_string_lossy().to_string())
+    }
+}
+
+// Anchored at the working directory and without . and .. components, but
+// symlinks are kept: a path through --versioned's latest link has to keep
+// going through it
+fn absolute_path(path: &Path) -> std::io::Result<PathBuf> {
+    let mut absolute = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            Component::CurDir => {}
+            other => absolute.push(other.as_os_str()),
+        }
+    }
+    Ok(absolute)
+}
+
+// `to` expressed relative to the directory `from`, both absolute
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component.as_os_str());
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+// the maps of an entry holding file paths next to `path`, name -> path
+pub const FILE_MAPS: &[&str] = &["queries", "grammar_files"];
+
+// rewrite the artifact, query and grammar file paths of an entry for the
+// config it goes into
+fn restyle_paths(entry: &mut Value, style: PathStyle, config_path: &str) -> std::io::Result<()> {
+    if let Some(path) = entry.get("path").and_then(Value::as_str) {
+        entry["path"] = json!(style.apply(path, config_path)?);
+    }
+    for map in FILE_MAPS {
+        if let Some(files) = entry.get_mut(*map).and_then(Value::as_object_mut) {
+            for file in files.values_mut() {
+                if let Some(path) = file.as_str() {
+                    *file = json!(style.apply(path, config_path)?);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Version of the config layout written by this tool. Bump it together with a
+// new step in `migrate` whenever the shape of entries changes.
+//   1: unversioned, single `extension` string per language
+//   2: `extensions` array, `schema_version` at the top level
+pub const SCHEMA_VERSION: u64 = 2;
+
+// Upgrade a config document read from disk to SCHEMA_VERSION in place.
+// Configs written by a newer tool are refused rather than guessed at.
+pub fn migrate(document: &mut Value) -> Result<(), Box<dyn std::error::Error>> {
+    let version = document
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1);
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "config uses schema version {}, this version of parser_scraper only understands up to {}",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+
+    if version < 2 {
+        if let Some(languages) = document
+            .get_mut("known_languages")
+            .and_then(Value::as_object_mut)
+        {
+            for entry in languages.values_mut().filter_map(Value::as_object_mut) {
+                if let Some(Value::String(extension)) = entry.remove("extension") {
+                    let extensions: Vec<String> = if extension.is_empty() {
+                        Vec::new()
+                    } else {
+                        vec![extension]
+                    };
+                    entry
+                        .entry("extensions")
+                        .or_insert_with(|| json!(extensions));
+                }
+            }
+        }
+        log::info!("migrated config from schema version {} to 2", version);
+    }
+
+    document["schema_version"] = json!(SCHEMA_VERSION);
+    Ok(())
+}
+
+// facts about a finished build recorded in its config entry
+pub struct BuildMetadata {
+    pub abi_version: Option<u32>,
+    pub commit: Option<String>,
+    // the newest tag the commit has, the version when the manifest has none
+    pub tag: Option<String>,
+    pub built_at: String,
+    // copied query files by name, e.g. "highlights" -> ".../highlights.scm"
+    pub queries: BTreeMap<String, String>,
+    // copied node-types.json and grammar.json, "node_types" and "grammar"
+    pub grammar_files: BTreeMap<String, String>,
+    pub license_files: Vec<String>,
+    pub detected_license: Option<String>,
+}
+
+pub fn extract_comment_types(node_types: Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    // Ensure that the data is an array
+    if let Value::Array(items) = node_types {
+        // Filter items where the "type" field contains the substring "comment"
+        let comment_types: Vec<String> = items
+            .iter()
+            .filter_map(|item| {
+                if let Some(Value::String(type_value)) = item.get("type") {
+                    if type_value.contains("comment") {
+                        Some(type_value.clone()) // Clone the string value
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Ok(comment_types)
+    } else {
+        // If the JSON is not an array, return an error
+        Err("Expected JSON array at root".into())
+    }
+}
+
+// One built language as collected by the main thread: its native config entry
+// plus the upstream details other output formats need.
+#[derive(Clone)]
+pub struct LanguageArtifact {
+    pub name: String,
+    pub repo_url: String,
+    pub scope: Option<String>,
+    // SPDX expression the grammar declares, or the one recognized from its license file
+    pub license: Option<String>,
+    // upstream license files copied into the output
+    pub license_files: Vec<String>,
+    // results of --run-corpus against the built library
+    pub corpus: Option<CorpusReport>,
+    // how the --test-files for the language parsed
+    pub test_files: Vec<TestFileParse>,
+    // --diff-trees result against the library this build replaced
+    pub tree_diff: Option<TreeDiff>,
+    pub entry: Value,
+}
+
+// Build the known_languages entries for one checkout. Nothing is written here,
+// entries are handed back to the main thread which writes the config once.
+// A language no entry point or file can be named after fails the grammar
+// with a BuildError. Each entry is named by the language's safe_name.
+pub fn create_config_entry(
+    lang: &str,
+    repo_dir: &str,
+    repo_url: &str,
+    shared_object_path: &str,
+    metadata: &BuildMetadata,
+    single_extension: bool,
+) -> Result<Vec<LanguageArtifact>, Box<dyn std::error::Error>> {
+    let grammar_manifest = read_grammar_manifest(repo_dir, lang)?;
+
+    // read the node-types.json from the target repo
+
+    let json_path = find_file(repo_dir, "node-types.json")?;
+    let mut file = File::open(json_path)?;
+    let mut file_content = String::new();
+    file.read_to_string(&mut file_content)?;
+
+    let node_types_json: Value = serde_json::from_str(&file_content)?;
+
+    let comment_types = extract_comment_types(node_types_json)?;
+
+    let version = grammar_manifest
+        .version
+        .as_deref()
+        .or(metadata.tag.as_deref().map(tag_version));
+
+    let mut entries = Vec::new();
+    for grammar in &grammar_manifest.grammars {
+        if let Some(name) = grammar.get("name").and_then(Value::as_str) {
+            let safe = checked_symbol_name(name)
+                .and_then(|_| safe_name(name))
+                .map_err(|reason| BuildError::InvalidLanguage {
+                    lang: lang.to_string(),
+                    reason,
+                })?;
+            let file_types: Vec<&str> = grammar
+                .get("file-types")
+                .and_then(Value::as_array)
+                .map(|arr| arr.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default(); // Default to empty if no extension found
+            let (filenames, extensions): (Vec<&str>, Vec<&str>) =
+                file_types.into_iter().partition(|t| is_filename(t));
+
+            // whole file names and interpreters, from the grammar plus the built-in tables
+            let mut filenames: Vec<String> = filenames.into_iter().map(str::to_string).collect();
+            for known in known_filenames(name) {
+                if !filenames.contains(&known) {
+                    filenames.push(known);
+                }
+            }
+            let shebangs = known_shebangs(name);
+
+            let mut entry = json!({
+                "language": name,
+                "path": shared_object_path,
+                "comment_types": comment_types,
+                "abi_version": metadata.abi_version,
+                "commit": metadata.commit,
+                "version": version,
+                "built_at": metadata.built_at,
+                "queries": metadata.queries
+            });
+            // language detection hints, only written when the grammar declares them
+            for (key, field) in [
+                ("injection-regex", "injection_regex"),
+                ("first-line-regex", "first_line_regex"),
+                ("content-regex", "content_regex"),
+            ] {
+                if let Some(regex) = grammar.get(key).and_then(Value::as_str) {
+                    entry[field] = json!(regex);
+                }
+            }
+            if !metadata.grammar_files.is_empty() {
+                entry["grammar_files"] = json!(metadata.grammar_files);
+            }
+            entry["aliases"] = json!(aliases_for(name));
+            entry["filenames"] = json!(filenames);
+            entry["shebangs"] = json!(shebangs);
+            if grammar.get("inferred").and_then(Value::as_bool) == Some(true) {
+                entry["inferred"] = json!(true);
+            }
+            // still the v2 array, a v2 file has only the one shape
+            let extensions: Vec<&str> = if single_extension {
+                extensions.into_iter().take(1).collect()
+            } else {
+                extensions
+            };
+            entry["extensions"] = json!(extensions);
+
+            entries.push(LanguageArtifact {
+                name: safe,
+                repo_url: repo_url.to_string(),
+                scope: grammar
+                    .get("scope")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                license: grammar_manifest
+                    .license
+                    .clone()
+                    .or_else(|| metadata.detected_license.clone()),
+                license_files: metadata.license_files.clone(),
+                corpus: None,
+                test_files: Vec::new(),
+                tree_diff: None,
+                entry,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+// Read and migrate the config file, or start a fresh document if there is none
+pub fn load_config(config_path: &str, format: ConfigFormat) -> Result<Value, ConfigError> {
+    if let Ok(mut output_file) = File::open(config_path) {
+        let mut output_file_content = String::new();
+        output_file
+            .read_to_string(&mut output_file_content)
+            .map_err(|e| ConfigError::io(config_path, e))?;
+        let mut existing_json = format
+            .parse(&output_file_content)
+            .map_err(|e| ConfigError::invalid(config_path, e))?;
+        if !existing_json.is_object() {
+            return Err(ConfigError::invalid(
+                config_path,
+                "does not contain a top-level table",
+            ));
+        }
+        migrate(&mut existing_json).map_err(|e| ConfigError::invalid(config_path, e))?;
+        Ok(existing_json)
+    } else {
+        Ok(json!({ "schema_version": SCHEMA_VERSION })) // Start fresh if the output file doesn't exist
+    }
+}
+
+// an extension claimed by more than one built language
+pub struct ExtensionCollision {
+    pub extension: String,
+    pub languages: Vec<String>,
+    pub winner: String,
+    pub rule: PriorityRule,
+}
+
+// what decided the winner of a collision
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PriorityRule {
+    // the manifest's extension_priority
+    Configured,
+    // DEFAULT_EXTENSION_PRIORITY
+    Default,
+    // neither lists it, the name first in the alphabet
+    Alphabetical,
+}
+
+impl PriorityRule {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            PriorityRule::Configured => "per the manifest's extension_priority",
+            PriorityRule::Default => "per the built-in priority",
+            PriorityRule::Alphabetical => "alphabetically, no priority lists it",
+        }
+    }
+}
+
+// Find extensions claimed by several artifacts and keep each one only on the
+// highest priority language: first the configured priority list, then the
+// built-in default, then alphabetical.
+pub fn resolve_extension_collisions(
+    artifacts: &mut [LanguageArtifact],
+    priority: &[String],
+) -> Vec<ExtensionCollision> {
+    let rank = |name: &str| -> (usize, usize, String) {
+        let configured = priority
+            .iter()
+            .position(|p| p == name)
+            .unwrap_or(usize::MAX);
+        let default = DEFAULT_EXTENSION_PRIORITY
+            .iter()
+            .position(|p| *p == name)
+            .unwrap_or(usize::MAX);
+        (configured, default, name.to_string())
+    };
+
+    let mut claims: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for artifact in artifacts.iter() {
+        for extension in entry_extensions(&artifact.entry) {
+            let claimants = claims.entry(extension).or_default();
+            if !claimants.contains(&artifact.name) {
+                claimants.push(artifact.name.clone());
+            }
+        }
+    }
+
+    let mut collisions = Vec::new();
+    for (extension, mut languages) in claims {
+        if languages.len() < 2 {
+            continue;
+        }
+        languages.sort_by_key(|name| rank(name));
+        let winner = languages[0].clone();
+        let rule = match rank(&winner) {
+            (configured, _, _) if configured != usize::MAX => PriorityRule::Configured,
+            (_, default, _) if default != usize::MAX => PriorityRule::Default,
+            _ => PriorityRule::Alphabetical,
+        };
+        for artifact in artifacts.iter_mut().filter(|a| a.name != winner) {
+            remove_extension(&mut artifact.entry, &extension);
+        }
+        collisions.push(ExtensionCollision {
+            extension,
+            languages,
+            winner,
+            rule,
+        });
+    }
+    collisions
+}
+
+// Extensions more than one entry of a config claims, with those entries.
+// Resolving the collisions of a run leaves none among what it built, these
+// are with entries an earlier run left in the config.
+pub fn extension_overlaps(config: &Value) -> BTreeMap<String, Vec<String>> {
+    let mut claims: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Some(entries) = config.get("known_languages").and_then(Value::as_object) {
+        for (name, entry) in entries {
+            for extension in entry_extensions(entry) {
+                claims.entry(extension).or_default().push(name.clone());
+            }
+        }
+    }
+    claims.retain(|_, names| names.len() > 1);
+    claims
+}
+
+// Where an entry's library is: relative paths are tried from the working
+// directory, then from the config's directory
+pub fn resolve_entry_path(path: &str, config_path: &str) -> PathBuf {
+    if Path::new(path).exists() {
+        return Path::new(path).to_path_buf();
+    }
+    let config_dir = Path::new(config_path).parent().unwrap_or(Path::new("."));
+    config_dir.join(path)
+}
+
+// extensions of an entry in either config shape
+pub fn entry_extensions(entry: &Value) -> Vec<String> {
+    if let Some(extensions) = entry.get("extensions").and_then(Value::as_array) {
+        return extensions
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect();
+    }
+    entry
+        .get("extension")
+        .and_then(Value::as_str)
+        .filter(|e| !e.is_empty())
+        .map(|e| vec![e.to_string()])
+        .unwrap_or_default()
+}
+
+fn remove_extension(entry: &mut Value, extension: &str) {
+    if let Some(extensions) = entry.get_mut("extensions").and_then(Value::as_array_mut) {
+        extensions.retain(|e| e.as_str() != Some(extension));
+    }
+    if entry.get("extension").and_then(Value::as_str) == Some(extension) {
+        entry["extension"] = json!("");
+    }
+}
+
+// grammar descriptions in tree-sitter.json's shape, wherever they came from
+struct GrammarManifest {
+    grammars: Vec<Value>,
+    version: Option<String>,
+    license: Option<String>,
+}
+
+// Read grammar metadata from tree-sitter.json, falling back to the `tree-sitter`
+// key of package.json for older repos, and finally to an entry inferred from
+// the language name so a successful build always gets a config entry.
+fn read_grammar_manifest(
+    repo_dir: &str,
+    lang: &str,
+) -> Result<GrammarManifest, Box<dyn std::error::Error>> {
+    // read the tree-sitter.json from the target repo
+    if let Ok(json_path) = find_file(repo_dir, "tree-sitter.json") {
+        let mut file = File::open(json_path)?;
+        let mut file_content = String::new();
+        file.read_to_string(&mut file_content)?;
+
+        let tree_sitter_json: Value = serde_json::from_str(&file_content)?;
+        let grammars = tree_sitter_json
+            .get("grammars")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        let metadata = tree_sitter_json.get("metadata");
+        let version = metadata
+            .and_then(|m| m.get("version"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let license = metadata
+            .and_then(|m| m.get("license"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        return Ok(GrammarManifest {
+            grammars,
+            version,
+            license,
+        });
+    }
+
+    let package_json_path = Path::new(repo_dir).join("package.json");
+    if let Ok(file_content) = fs::read_to_string(&package_json_path) {
+        let package_json: Value = serde_json::from_str(&file_content)?;
+        if let Some(configs) = package_json.get("tree-sitter").and_then(Value::as_array) {
+            let package_name = package_json
+                .get("name")
+                .and_then(Value::as_str)
+                .map(|n| n.rsplit('/').next().unwrap_or(n))
+                .map(|n| n.trim_start_matches("tree-sitter-").replace('-', "_"));
+            let grammars = configs
+                .iter()
+                .filter_map(Value::as_object)
+                .map(|config| {
+                    let mut grammar = config.clone();
+                    let name = package_config_name(config, configs.len(), package_name.as_deref())
+                        .unwrap_or_else(|| lang.to_string());
+                    grammar.insert("name".to_string(), json!(name));
+                    Value::Object(grammar)
+                })
+                .collect();
+            let version = package_json
+                .get("version")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            let license = package_json
+                .get("license")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            return Ok(GrammarManifest {
+                grammars,
+                version,
+                license,
+            });
+        }
+    }
+
+    // nothing to go on but the name, guess the rest from the built-in table
+    let name = normalize_name(lang);
+    let extensions = known_extensions(&name);
+    log::warn!(
+        "no grammar metadata in {}, inferring an entry for {} with extensions {:?}",
+        repo_dir,
+        name,
+        extensions
+    );
+    // a package.json without a tree-sitter section still has the version
+    let version = fs::read_to_string(&package_json_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+        .and_then(|package_json| {
+            package_json
+                .get("version")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        });
+    Ok(GrammarManifest {
+        grammars: vec![json!({ "name": name, "file-types": extensions, "inferred": true })],
+        version,
+        license: None,
+    })
+}
+
+// v1.2.3 -> 1.2.3, other tags as they are
+fn tag_version(tag: &str) -> &str {
+    match tag.strip_prefix('v') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+        _ => tag,
+    }
+}
+
+// package.json entries carry no name: a lone entry is the package itself, in
+// multi-grammar packages the entry's `path` (or scope) tells them apart
+fn package_config_name(
+    config: &Map<String, Value>,
+    count: usize,
+    package_name: Option<&str>,
+) -> Option<String> {
+    if count == 1 {
+        if let Some(name) = package_name {
+            return Some(name.to_string());
+        }
+    }
+    if let Some(path) = config.get("path").and_then(Value::as_str) {
+        let last = Path::new(path).file_name()?.to_string_lossy().to_string();
+        return Some(last.replace('-', "_"));
+    }
+    config
+        .get("scope")
+        .and_then(Value::as_str)
+        .and_then(|scope| scope.rsplit('.').next())
+        .map(str::to_string)
+        .or_else(|| package_name.map(str::to_string))
+}
+
+// Write every entry as its own `<name>.json` in the fragments directory, for
+// configs kept in dotfiles where one file per language merges more cleanly.
+pub fn write_fragments(
+    fragments_dir: &str,
+    artifacts: &[LanguageArtifact],
+    path_style: Option<PathStyle>,
+    config_path: &str,
+) -> Result<(), ConfigError> {
+    fs::create_dir_all(fragments_dir).map_err(|e| ConfigError::io(fragments_dir, e))?;
+    for artifact in artifacts {
+        let mut entry = artifact.entry.clone();
+        if let Some(style) = path_style {
+            restyle_paths(&mut entry, style, config_path)
+                .map_err(|e| ConfigError::io(config_path, e))?;
+        }
+        // the name could have come from any config file
+        let name = safe_name(&artifact.name).map_err(|e| ConfigError::invalid(fragments_dir, e))?;
+        let path = Path::new(fragments_dir).join(format!("{}.json", name));
+        let rendered = ConfigFormat::Json
+            .render(&entry)
+            .map_err(|e| ConfigError::invalid(&path, e))?;
+        fs::write(&path, rendered).map_err(|e| ConfigError::io(&path, e))?;
+    }
+    Ok(())
+}
+
+// Read back the fragments of a directory, named after their file stem
+pub fn read_fragments(fragments_dir: &str) -> Result<Vec<LanguageArtifact>, ConfigError> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(fragments_dir)
+        .map_err(|e| ConfigError::io(fragments_dir, e))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect();
+    paths.sort();
+
+    let mut artifacts = Vec::new();
+    for path in paths {
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let content = fs::read_to_string(&path).map_err(|e| ConfigError::io(&path, e))?;
+        let entry: Value =
+            serde_json::from_str(&content).map_err(|e| ConfigError::invalid(&path, e))?;
+        if !entry.is_object() {
+            return Err(ConfigError::invalid(&path, "does not contain an object"));
+        }
+        artifacts.push(LanguageArtifact {
+            name,
+            repo_url: String::new(),
+            scope: None,
+            license: None,
+            license_files: Vec::new(),
+            corpus: None,
+            test_files: Vec::new(),
+            tree_diff: None,
+            entry,
+        });
+    }
+    Ok(artifacts)
+}
+
+// Merge the collected entries into the config file in one go. The new file is
+// written next to the old one and renamed over it, so readers never see a
+// half-written config.
+pub fn write_config(
+    config_path: &str,
+    format: ConfigFormat,
+    artifacts: &[LanguageArtifact],
+    path_style: Option<PathStyle>,
+) -> Result<(), ConfigError> {
+    let output_json = merged_config(config_path, format, artifacts, path_style)?;
+    // serde_json's Map is a BTreeMap, so keys come out sorted and pretty
+    // printing keeps successive runs diffable
+    let rendered = format
+        .render(&output_json)
+        .map_err(|e| ConfigError::invalid(config_path, e))?;
+
+    let tmp_path = format!("{}.tmp", config_path);
+    let write_tmp = || -> std::io::Result<()> {
+        let mut output_file = File::create(&tmp_path)?;
+        output_file.write_all(rendered.as_bytes())?;
+        output_file.sync_all()
+    };
+    write_tmp().map_err(|e| ConfigError::io(&tmp_path, e))?;
+    fs::rename(&tmp_path, Path::new(config_path)).map_err(|e| ConfigError::io(config_path, e))?;
+
+    Ok(())
+}
+
+// the config as write_config would leave it
+pub fn merged_config(
+    config_path: &str,
+    format: ConfigFormat,
+    artifacts: &[LanguageArtifact],
+    path_style: Option<PathStyle>,
+) -> Result<Value, ConfigError> {
+    // Everything outside known_languages belongs to the user and is kept as is.
+    let mut output_json = load_config(config_path, format)?;
+
+    let mut known_languages = output_json
+        .get("known_languages")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+
+    for artifact in artifacts {
+        let mut entry = artifact.entry.clone();
+        if let Some(style) = path_style {
+            restyle_paths(&mut entry, style, config_path)
+                .map_err(|e| ConfigError::io(config_path, e))?;
+        }
+        // Add or update the entry in known_languages, keeping fields we don't write
+        let merged = match (known_languages.remove(&artifact.name), entry) {
+            (Some(Value::Object(mut existing)), Value::Object(new)) => {
+                // only one of the two extension shapes may survive
+                if new.contains_key("extensions") {
+                    existing.remove("extension");
+                } else if new.contains_key("extension") {
+                    existing.remove("extensions");
+                }
+                existing.extend(new);
+                Value::Object(existing)
+            }
+            (_, entry) => entry,
+        };
+        known_languages.insert(artifact.name.clone(), merged);
+    }
+
+    output_json["known_languages"] = Value::Object(known_languages);
+    Ok(output_json)
+}
+
+// what changes between two configs, entry by entry
+pub enum EntryChange {
+    Added(String),
+    // the fields that differ, with their old and new values
+    Updated(String, Vec<(String, Option<Value>, Option<Value>)>),
+    Removed(String),
+}
+
+pub fn config_changes(old: &Value, new: &Value) -> Vec<EntryChange> {
+    let entries = |config: &Value| {
+        config
+            .get("known_languages")
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default()
+    };
+    let (old, new) = (entries(old), entries(new));
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let mut changes = Vec::new();
+    for name in names {
+        match (old.get(name), new.get(name)) {
+            (None, Some(_)) => changes.push(EntryChange::Added(name.clone())),
+            (Some(_), None) => changes.push(EntryChange::Removed(name.clone())),
+            (Some(before), Some(after)) if before != after => {
+                let fields = |entry: &Value| entry.as_object().cloned().unwrap_or_default();
+                let (before, after) = (fields(before), fields(after));
+                let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                let differing = keys
+                    .into_iter()
+                    .filter(|key| before.get(*key) != after.get(*key))
+                    .map(|key| {
+                        (
+                            key.clone(),
+                            before.get(key).cloned(),
+                            after.get(key).cloned(),
+                        )
+                    })
+                    .collect();
+                changes.push(EntryChange::Updated(name.clone(), differing));
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+// --emit native: the entries merged into the config file
+pub struct NativeConfig {
+    pub path: String,
+    pub format: ConfigFormat,
+    pub path_style: Option<PathStyle>,
+}
+
+impl Emitter for NativeConfig {
+    fn destination(&self) -> String {
+        self.path.clone()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        Ok(write_config(
+            &self.path,
+            self.format,
+            artifacts,
+            self.path_style,
+        )?)
+    }
+}
+
+// --emit fragments: one file per entry, paths styled relative to `config_path`
+pub struct Fragments {
+    pub dir: String,
+    pub config_path: String,
+    pub path_style: Option<PathStyle>,
+}
+
+impl Emitter for Fragments {
+    fn destination(&self) -> String {
+        self.dir.clone()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        Ok(write_fragments(
+            &self.dir,
+            artifacts,
+            self.path_style,
+            &self.config_path,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_v1_extension_strings() {
+        let mut document = json!({
+            "known_languages": {
+                "rust": { "extension": "rs", "path": "rust.so" },
+                "json": { "extension": "" },
+            },
+            "user_key": true,
+        });
+        migrate(&mut document).unwrap();
+        assert_eq!(document["schema_version"], json!(SCHEMA_VERSION));
+        let rust = &document["known_languages"]["rust"];
+        assert_eq!(rust["extensions"], json!(["rs"]));
+        assert!(rust.get("extension").is_none());
+        assert_eq!(document["known_languages"]["json"]["extensions"], json!([]));
+        assert_eq!(document["user_key"], json!(true));
+    }
+
+    #[test]
+    fn keeps_v2_as_is() {
+        let mut document = json!({
+            "schema_version": 2,
+            "known_languages": { "rust": { "extensions": ["rs"] } },
+        });
+        let before = document.clone();
+        migrate(&mut document).unwrap();
+        assert_eq!(document, before);
+    }
+
+    #[test]
+    fn refuses_newer_schemas() {
+        let mut document = json!({ "schema_version": SCHEMA_VERSION + 1 });
+        assert!(migrate(&mut document).is_err());
+    }
+
+    fn artifact(name: &str, extensions: &[&str]) -> LanguageArtifact {
+        LanguageArtifact {
+            name: name.to_string(),
+            repo_url: String::new(),
+            scope: None,
+            license: None,
+            license_files: Vec::new(),
+            corpus: None,
+            test_files: Vec::new(),
+            tree_diff: None,
+            entry: json!({ "extensions": extensions }),
+        }
+    }
+
+    #[test]
+    fn extension_collisions_by_priority() {
+        let mut artifacts = vec![
+            artifact("objc", &["h", "m"]),
+            artifact("cpp", &["h", "cpp"]),
+            artifact("c", &["h", "c"]),
+            artifact("zig", &["x"]),
+            artifact("odin", &["x"]),
+        ];
+        let collisions = resolve_extension_collisions(&mut artifacts, &[]);
+        assert_eq!(collisions.len(), 2);
+        assert_eq!(collisions[0].extension, "h");
+        assert_eq!(collisions[0].winner, "c");
+        assert_eq!(collisions[0].rule, PriorityRule::Default);
+        assert_eq!(collisions[0].languages, vec!["c", "cpp", "objc"]);
+        assert_eq!(collisions[1].winner, "odin");
+        assert_eq!(collisions[1].rule, PriorityRule::Alphabetical);
+        assert_eq!(artifacts[0].entry["extensions"], json!(["m"]));
+        assert_eq!(artifacts[1].entry["extensions"], json!(["cpp"]));
+        assert_eq!(artifacts[2].entry["extensions"], json!(["h", "c"]));
+        assert_eq!(artifacts[3].entry["extensions"], json!([]));
+    }
+
+    #[test]
+    fn configured_priority_wins() {
+        let mut artifacts = vec![artifact("c", &["h"]), artifact("cpp", &["h"])];
+        let collisions = resolve_extension_collisions(&mut artifacts, &["cpp".to_string()]);
+        assert_eq!(collisions[0].winner, "cpp");
+        assert_eq!(collisions[0].rule, PriorityRule::Configured);
+        assert_eq!(artifacts[0].entry["extensions"], json!([]));
+        assert!(resolve_extension_collisions(&mut artifacts, &[]).is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tree_sitter::Parser;
+
+use crate::smoke::load_grammar;
+
+// pass/fail counts of one grammar's corpus against the built library
+#[derive(Debug, Default, Clone)]
+pub struct CorpusReport {
+    pub passed: usize,
+    pub skipped: usize,
+    // "<file>: <test name>" of every failing test
+    pub failures: Vec<String>,
+}
+
+// one test out of a corpus file
+struct CorpusTest {
+    name: String,
+    attributes: Vec<String>,
+    source: String,
+    expected: String,
+}
+
+// Where the grammar keeps its corpus: test/corpus/ next to grammar.js, the
+// older corpus/ layout, or the top of the repo for multi-grammar checkouts.
+pub fn find_corpus(grammar_dir: &Path, repo_dir: &Path) -> Option<PathBuf> {
+    [
+        grammar_dir.join("test").join("corpus"),
+        grammar_dir.join("corpus"),
+        repo_dir.join("test").join("corpus"),
+    ]
+    .into_iter()
+    .find(|dir| dir.is_dir())
+}
+
+// Parse every test in `corpus_dir` with the built library and compare the
+// trees with the expected ones, the way `tree-sitter test` does
+pub fn run_corpus(
+    library: &Path,
+    language: &str,
+    corpus_dir: &Path,
+) -> Result<CorpusReport, Box<dyn std::error::Error>> {
+    let grammar = load_grammar(library, language)?;
+    let mut parser = Parser::new();
+    parser
+        .set_language(&grammar.language)
+        .map_err(|e| format!("unusable language: {}", e))?;
+
+    let mut files = Vec::new();
+    collect_files(corpus_dir, &mut files)?;
+    files.sort();
+
+    let mut report = CorpusReport::default();
+    for file in files {
+        let text = fs::read_to_string(&file)?;
+        let file_name = file
+            .strip_prefix(corpus_dir)
+            .unwrap_or(&file)
+            .to_string_lossy()
+            .to_string();
+        for test in parse_corpus(&text) {
+            if should_skip(&test, language) {
+                report.skipped += 1;
+                continue;
+            }
+            let tree = match parser.parse(&test.source, None) {
+                Some(tree) => tree,
+                None => {
+                    report
+                        .failures
+                        .push(format!("{}: {}", file_name, test.name));
+                    continue;
+                }
+            };
+            let root = tree.root_node();
+            let passed = if test.attributes.iter().any(|a| a == ":error") {
+                root.has_error()
+            } else {
+                let expected = normalize_sexp(&test.expected);
+                let mut actual = normalize_sexp(&root.to_sexp());
+                // field names are only compared when the test spells them out
+                if !has_fields(&expected) {
+                    actual = strip_fields(&actual);
+                }
+                actual == expected
+            };
+            if passed {
+                report.passed += 1;
+            } else {
+                log::debug!(
+                    "corpus test {} / {} failed, expected {} got {}",
+                    file_name,
+                    test.name,
+                    normalize_sexp(&test.expected),
+                    root.to_sexp()
+                );
+                report
+                    .failures
+                    .push(format!("{}: {}", file_name, test.name));
+            }
+        }
+    }
+    Ok(report)
+}
+
+// "<file>: <test name>" and the source of every test in a corpus
+pub fn corpus_inputs(
+    corpus_dir: &Path,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    collect_files(corpus_dir, &mut files)?;
+    files.sort();
+    let mut inputs = Vec::new();
+    for file in files {
+        let text = fs::read_to_string(&file)?;
+        let file_name = file
+            .strip_prefix(corpus_dir)
+            .unwrap_or(&file)
+            .to_string_lossy()
+            .to_string();
+        for test in parse_corpus(&text) {
+            inputs.push((format!("{}: {}", file_name, test.name), test.source));
+        }
+    }
+    Ok(inputs)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "txt") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// `:skip`, `:cst` and tests for another platform or another grammar of the
+// same repo are counted as skipped
+fn should_skip(test: &CorpusTest, language: &str) -> bool {
+    test.attributes.iter().any(|attribute| {
+        if attribute == ":skip" || attribute == ":cst" {
+            return true;
+        }
+        if let Some(platform) = attribute
+            .strip_prefix(":platform(")
+            .and_then(|a| a.strip_suffix(')'))
+        {
+            return platform != std::env::consts::OS;
+        }
+        if let Some(other) = attribute
+            .strip_prefix(":language(")
+            .and_then(|a| a.strip_suffix(')'))
+        {
+            return other.replace('-', "_") != language.replace('-', "_");
+        }
+        false
+    })
+}
+
+// a line of at least three `rule` characters, returning what follows them
+fn rule_suffix(line: &str, rule: char) -> Option<&str> {
+    let line = line.trim_end();
+    let suffix = line.trim_start_matches(rule);
+    let length = line.len() - suffix.len();
+    if length >= 3 && !suffix.contains(char::is_whitespace) {
+        Some(suffix)
+    } else {
+        None
+    }
+}
+
+// A test header starting at line `i`: a `=` rule, the name and attribute
+// lines, and a closing rule with the same suffix. Returns the suffix and the
+// index of the closing rule.
+fn header_at<'a>(lines: &[&'a str], i: usize) -> Option<(&'a str, usize)> {
+    let suffix = rule_suffix(lines[i], '=')?;
+    let closing = (i + 1..lines.len()).find(|&j| rule_suffix(lines[j], '=').is_some())?;
+    if closing > i + 1 && rule_suffix(lines[closing], '=') == Some(suffix) {
+        Some((suffix, closing))
+    } else {
+        None
+    }
+}
+
+// Split a corpus file into its tests:
+//
+// ==================
+// test name
+// :attributes
+// ==================
+// source
+// ---
+// (expected tree)
+//
+// Rules may carry a suffix (`===|||`) so sources can contain plain rules, and
+// the last divider wins so sources can contain `---` too.
+fn parse_corpus(text: &str) -> Vec<CorpusTest> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut tests = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (suffix, closing) = match header_at(&lines, i) {
+            Some(header) => header,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let (attributes, names): (Vec<&str>, Vec<&str>) = lines[i + 1..closing]
+            .iter()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .partition(|l| l.starts_with(':'));
+
+        let body_end = (closing + 1..lines.len())
+            .find(|&j| header_at(&lines, j).is_some())
+            .unwrap_or(lines.len());
+        let body = &lines[closing + 1..body_end];
+        if let Some(divider) = body
+            .iter()
+            .rposition(|l| rule_suffix(l, '-') == Some(suffix))
+        {
+            let expected: Vec<&str> = body[divider + 1..]
+                .iter()
+                .filter(|l| !l.trim_start().starts_with(';'))
+                .copied()
+                .collect();
+            tests.push(CorpusTest {
+                name: names.join(" "),
+                attributes: attributes.iter().map(|a| a.to_string()).collect(),
+                source: body[..divider].join("\n"),
+                expected: expected.join("\n"),
+            });
+        }
+        i = body_end;
+    }
+    tests
+}
+
+// one space between tokens and none inside parentheses
+fn normalize_sexp(sexp: &str) -> String {
+    sexp.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("( ", "(")
+        .replace(" )", ")")
+}
+
+fn is_field(token: &str) -> bool {
+    token.strip_suffix(':').is_some_and(|name| {
+        !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+fn has_fields(sexp: &str) -> bool {
+    sexp.split(' ').any(is_field)
+}
+
+fn strip_fields(sexp: &str) -> String {
+    sexp.split(' ')
+        .filter(|token| !is_field(token))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,209 @@
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Response, Server};
+
+use crate::metrics::Metrics;
+
+// Parse a duration like `90s`, `30m`, `24h`, `1d` or `1h30m`. A bare number
+// is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration '{}', expected e.g. 90s, 30m, 24h or 1d12h",
+            s
+        )
+    };
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = number.parse().map_err(|_| invalid())?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|seconds| seconds.checked_add(total))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() || s.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+// how long to wait between cycles
+pub struct Schedule {
+    pub interval: Duration,
+    // up to this much is added to every wait, so many daemons started at once
+    // don't all hit the remotes at the same moment
+    pub jitter: Duration,
+}
+
+impl Schedule {
+    pub fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.interval;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        self.interval + Duration::from_millis(random % self.jitter.as_millis().max(1) as u64)
+    }
+}
+
+// what a cycle did
+pub struct CycleReport {
+    pub artifacts: usize,
+    pub failed: usize,
+    // how many failed for each "phase/category"
+    pub failures: BTreeMap<String, usize>,
+    // kept because their upstream didn't move
+    pub reused: usize,
+}
+
+#[derive(Default)]
+struct HealthState {
+    cycles: u64,
+    running: bool,
+    last_started: Option<String>,
+    last_finished: Option<String>,
+    // None while the last cycle was fine
+    last_error: Option<String>,
+    last_report: Option<Value>,
+    next_cycle: Option<String>,
+}
+
+// What the health endpoint reports, updated by the daemon loop
+#[derive(Default)]
+pub struct Health {
+    state: Mutex<HealthState>,
+}
+
+impl Health {
+    pub fn cycle_started(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cycles += 1;
+        state.running = true;
+        state.last_started = Some(now());
+        state.next_cycle = None;
+    }
+
+    pub fn cycle_finished(&self, result: &Result<CycleReport, String>, next: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        state.last_finished = Some(now());
+        match result {
+            Ok(report) => {
+                state.last_error = None;
+                state.last_report = Some(json!({
+                    "artifacts": report.artifacts,
+                    "failed": report.failed,
+                    "failures": report.failures,
+                    "reused": report.reused,
+                }));
+            }
+            Err(e) => state.last_error = Some(e.clone()),
+        }
+        state.next_cycle = chrono::Duration::from_std(next).ok().map(|next| {
+            (chrono::Utc::now() + next).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        });
+    }
+
+    // healthy until a cycle fails as a whole, failed grammars alone don't count
+    fn to_json(&self) -> (bool, Value) {
+        let state = self.state.lock().unwrap();
+        let healthy = state.last_error.is_none();
+        (
+            healthy,
+            json!({
+                "status": if healthy { "ok" } else { "failing" },
+                "cycles": state.cycles,
+                "running": state.running,
+                "last_started": state.last_started,
+                "last_finished": state.last_finished,
+                "last_error": state.last_error,
+                "last_cycle": state.last_report,
+                "next_cycle": state.next_cycle,
+            }),
+        )
+    }
+}
+
+// Answer `GET /health` on `listen` from a thread of its own: 200 while the
+// last cycle went through, 503 when it failed. `GET /metrics` is for
+// Prometheus to scrape.
+pub fn serve_health(
+    listen: &str,
+    health: Arc<Health>,
+    metrics: Arc<Metrics>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = Server::http(listen)?;
+    log::info!("health endpoint on {}", listen);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let path = request.url().split('?').next().unwrap_or("").to_string();
+            let (response, content_type) = match path.as_str() {
+                "/health" => {
+                    let (healthy, body) = health.to_json();
+                    (
+                        Response::from_string(body.to_string() + "\n")
+                            .with_status_code(if healthy { 200 } else { 503 }),
+                        "application/json",
+                    )
+                }
+                "/metrics" => (
+                    Response::from_string(metrics.render()),
+                    "text/plain; version=0.0.4",
+                ),
+                _ => (
+                    Response::from_string("{\"error\":\"not found\"}\n").with_status_code(404),
+                    "application/json",
+                ),
+            };
+            let response = response.with_header(
+                Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap(),
+            );
+            if let Err(e) = request.respond(response) {
+                log::warn!("failed to respond to {} : {}", path, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("24h"), Ok(Duration::from_secs(24 * 3600)));
+        assert_eq!(parse_duration("1d12h"), Ok(Duration::from_secs(36 * 3600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        for bad in ["", "h", "1w", "1h30", "-5m", "1.5h", "999999999999999999d"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// where --split-debug puts the debug info, inside the output directory
+pub const DEBUG_DIR: &str = "debug";
+
+// <output>/debug/<library path inside the output>.debug
+pub fn debug_path(library: &Path, output_dir: &Path) -> PathBuf {
+    let relative = library.strip_prefix(output_dir).unwrap_or(library);
+    let mut name = output_dir.join(DEBUG_DIR).join(relative).into_os_string();
+    name.push(".debug");
+    PathBuf::from(name)
+}
+
+// Move the debug info of `library` into its file under debug/ and leave the
+// library stripped, with a gnu-debuglink to it. None for a library without
+// any, built without -g or already split by an earlier run.
+pub fn split(
+    library: &Path,
+    output_dir: &Path,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if !has_debug_info(library)? {
+        return Ok(None);
+    }
+    let debug = debug_path(library, output_dir);
+    if let Some(parent) = debug.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    run(Command::new("objcopy")
+        .arg("--only-keep-debug")
+        .arg(library)
+        .arg(&debug))?;
+    run(Command::new("objcopy")
+        .args(["--strip-debug", "--strip-unneeded"])
+        .arg(library))?;
+    run(Command::new("objcopy")
+        .arg(format!("--add-gnu-debuglink={}", debug.display()))
+        .arg(library))?;
+    Ok(Some(debug))
+}
+
+fn has_debug_info(library: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let output = Command::new("readelf")
+        .args(["-S", "--wide"])
+        .arg(library)
+        .output()
+        .map_err(|e| format!("failed to run readelf: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "readelf failed for {}: {}",
+            library.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).contains(".debug_info"))
+}
+
+fn run(cmd: &mut Command) -> Result<(), Box<dyn std::error::Error>> {
+    let output = cmd
+        .output()
+        .map_err(|e| format!("failed to run {:?}: {}", cmd.get_program(), e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{:?} failed: {}",
+            cmd.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::languages::own_name;
+
+// a checkout never measured before, about what a typical grammar repo takes
+const UNMEASURED_CHECKOUT: u64 = 20 * 1024 * 1024;
+
+// Parse a size like `500M`, `20G` or `1.5T`, in powers of 1024. A bare
+// number is bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size '{}', expected e.g. 500M, 20G or 1T", s);
+    let trimmed = s.trim().trim_end_matches(['B', 'b']).trim_end_matches('i');
+    let (number, unit) = match trimmed.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(at) => trimmed.split_at(at),
+        None => (trimmed, ""),
+    };
+    let scale: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(invalid());
+    }
+    Ok((number * scale as f64) as u64)
+}
+
+// 1.2 GiB, 340 MiB, ...
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 || size >= 10.0 {
+        format!("{:.0} {}", size, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+// what everything below `path` takes on disk, like du, symlinks not followed
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let own = allocated(&metadata);
+    if !metadata.is_dir() {
+        return own;
+    }
+    own + fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| dir_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+// a git checkout is lots of small files, each taking at least a block
+#[cfg(unix)]
+fn allocated(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+// free space on the filesystem `path` is or would be created on
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|dir| dir.exists())?;
+    statvfs_available(existing)
+}
+
+#[cfg(unix)]
+fn statvfs_available(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: a valid C string and a statvfs to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn statvfs_available(_path: &Path) -> Option<u64> {
+    None
+}
+
+// what a checkout never measured is counted as: the median of the measured
+// ones, or a typical grammar repo
+pub fn guess(sizes: &HashMap<String, u64>) -> u64 {
+    let mut known: Vec<u64> = sizes.values().copied().collect();
+    known.sort_unstable();
+    known
+        .get(known.len() / 2)
+        .copied()
+        .unwrap_or(UNMEASURED_CHECKOUT)
+}
+
+// what the checkouts of a run are expected to take
+pub struct Estimate {
+    pub bytes: u64,
+    // the languages without a measurement, counted at a guess
+    pub unmeasured: usize,
+}
+
+// from the last measured size of each language
+pub fn estimate(languages: &[String], sizes: &HashMap<String, u64>) -> Estimate {
+    let guess = guess(sizes);
+    let mut estimate = Estimate {
+        bytes: 0,
+        unmeasured: 0,
+    };
+    for language in languages {
+        match sizes.get(&own_name(language)) {
+            Some(bytes) => estimate.bytes += bytes,
+            None => {
+                estimate.bytes += guess;
+                estimate.unmeasured += 1;
+            }
+        }
+    }
+    estimate
+}
+
+// --max-disk: the checkouts of a run against a budget. A clone waits until
+// its checkout is expected to fit, and checkouts of grammars that are
+// through are removed, oldest first, to make room. A checkout too big even
+// on its own is the end of the run.
+pub struct DiskBudget {
+    pub limit: u64,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    used: u64,
+    checkouts: HashMap<String, u64>,
+    // through the build, their checkouts can go, in the order they got through
+    finished: Vec<String>,
+    // what the checkouts are expected to take before they are measured
+    expected: HashMap<String, u64>,
+    guess: u64,
+}
+
+// what the budget wants done
+pub struct Reclaim {
+    // the languages whose checkouts to remove
+    pub remove: Vec<String>,
+    // a checkout over the budget all by itself, how big it is
+    pub too_big: Option<u64>,
+}
+
+impl DiskBudget {
+    pub fn new(limit: u64) -> DiskBudget {
+        DiskBudget {
+            limit,
+            state: Mutex::new(BudgetState {
+                guess: UNMEASURED_CHECKOUT,
+                ..BudgetState::default()
+            }),
+        }
+    }
+
+    // the sizes `estimate` went by, for the clones to reserve
+    pub fn expect(&self, sizes: &HashMap<String, u64>, guess: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.expected = sizes.clone();
+        state.guess = guess;
+    }
+
+    // Make room for a clone of `language`. Ok once it's reserved, Err while
+    // it has to wait for a grammar to get through. Both can come with
+    // checkouts to remove.
+    pub fn reserve(&self, language: &str) -> Result<Reclaim, Reclaim> {
+        let mut state = self.state.lock().unwrap();
+        let expected = state
+            .expected
+            .get(&own_name(language))
+            .copied()
+            .unwrap_or(state.guess);
+        let remove = self.make_room(&mut state, expected);
+        let reclaim = Reclaim {
+            remove,
+            too_big: None,
+        };
+        // with nothing in flight there's nothing to wait for
+        let in_flight = state.checkouts.len() - state.finished.len();
+        if state.used + expected > self.limit && in_flight > 0 {
+            return Err(reclaim);
+        }
+        state.used += expected;
+        state.checkouts.insert(language.to_string(), expected);
+        Ok(reclaim)
+    }
+
+    // the clone of `language` is done and takes `bytes`
+    pub fn cloned(&self, language: &str, bytes: u64) -> Reclaim {
+        let mut state = self.state.lock().unwrap();
+        let reserved = state
+            .checkouts
+            .insert(language.to_string(), bytes)
+            .unwrap_or(0);
+        state.used = state.used + bytes - reserved;
+        let remove = self.make_room(&mut state, 0);
+        Reclaim {
+            remove,
+            too_big: (bytes > self.limit).then_some(bytes),
+        }
+    }
+
+    // `language` got through, built or not, and needs its checkout no more
+    pub fn finished(&self, language: &str) -> Reclaim {
+        let mut state = self.state.lock().unwrap();
+        if state.checkouts.contains_key(language) && !state.finished.iter().any(|l| l == language) {
+            state.finished.push(language.to_string());
+        }
+        let remove = self.make_room(&mut state, 0);
+        Reclaim {
+            remove,
+            too_big: None,
+        }
+    }
+
+    // the finished checkouts to remove for `more` bytes to fit
+    fn make_room(&self, state: &mut BudgetState, more: u64) -> Vec<String> {
+        let mut remove = Vec::new();
+        while state.used + more > self.limit && !state.finished.is_empty() {
+            let language = state.finished.remove(0);
+            if let Some(bytes) = state.checkouts.remove(&language) {
+                state.used -= bytes;
+                remove.push(language);
+            }
+        }
+        remove
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("20G"), Ok(20 << 30));
+        assert_eq!(parse_size("20GiB"), Ok(20 << 30));
+        assert_eq!(parse_size("20gb"), Ok(20 << 30));
+        assert_eq!(parse_size("1.5T"), Ok(3 << 39));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        for bad in ["", "G", "20X", "-1G", "inf", "NaN", "1.2.3M"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn formatted_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(340 << 20), "340 MiB");
+    }
+}
@@ -0,0 +1,23 @@
+use std::collections::BTreeMap;
+
+use crate::config::LanguageArtifact;
+use crate::error::EmitError;
+
+// what outputs may need about the run besides the artifacts
+pub struct EmitContext<'a> {
+    // sha256 of each library keyed by the path in its entry, empty when
+    // SHA256SUMS wasn't written
+    pub hashes: &'a BTreeMap<String, String>,
+    // the toolchain, as the builder describes it
+    pub compiler: String,
+}
+
+// One downstream format written from the collected artifacts: the config,
+// a Helix languages.toml, a Rust table, ... A new format is an implementation
+// of this next to its renderer, nothing in the build needs to know about it.
+pub trait Emitter: Send + Sync {
+    // the file or directory written, named by events and errors
+    fn destination(&self) -> String;
+
+    fn emit(&self, artifacts: &[LanguageArtifact], context: &EmitContext) -> Result<(), EmitError>;
+}
@@ -0,0 +1,215 @@
+use std::io;
+use thiserror::Error;
+
+use crate::failure::Phase;
+use crate::hooks::HookStage;
+use crate::limits::ResourceLimitExceeded;
+
+// fetching a parser list, from any source
+#[derive(Debug, Error)]
+pub enum ScrapeError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    // the list was fetched but isn't in the expected shape
+    #[error("{origin}: {message}")]
+    Invalid { origin: String, message: String },
+    // for sources outside this crate
+    #[error(transparent)]
+    Other(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+// getting a grammar's checkout
+#[derive(Debug, Error)]
+pub enum CloneError {
+    #[error("running git failed: {0}")]
+    Git(#[from] io::Error),
+    #[error("Failed to clone {repo_url}: {stderr}")]
+    Failed { repo_url: String, stderr: String },
+    #[error("can't check out {rev} of {repo_url}: {stderr}")]
+    Revision {
+        repo_url: String,
+        rev: String,
+        stderr: String,
+    },
+    #[error(transparent)]
+    Hook(#[from] HookError),
+}
+
+// a manifest hook around the clone or build
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error("running the {stage} hook failed: {source}")]
+    Spawn {
+        stage: HookStage,
+        #[source]
+        source: io::Error,
+    },
+    #[error("the {stage} hook `{command}` failed ({status}): {stderr}")]
+    Failed {
+        stage: HookStage,
+        command: String,
+        status: String,
+        stderr: String,
+    },
+}
+
+// one compiler invocation
+#[derive(Debug, Error)]
+pub enum CompileError {
+    // more flags won't help with these
+    #[error(transparent)]
+    ResourceLimit(#[from] ResourceLimitExceeded),
+    // the compiler ran and rejected the sources
+    #[error("Failed to build grammar for {lang}: {stderr}")]
+    Compiler { lang: String, stderr: String },
+    // the builder couldn't find or use a compiler
+    #[error("no usable compiler: {0}")]
+    Toolchain(String),
+    #[error("can't export only the entry points: {0}")]
+    ExportList(String),
+    // objcopy for --split-debug
+    #[error("can't split the debug info: {0}")]
+    SplitDebug(String),
+    #[error("running the compiler failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+// everything between cloning a grammar and handing back its config entries
+#[derive(Debug, Error)]
+pub enum BuildError {
+    #[error(transparent)]
+    Clone(#[from] CloneError),
+    #[error("{0}")]
+    MissingParser(#[source] io::Error),
+    #[error("{lang} {reason}")]
+    AbiMismatch { lang: String, reason: String },
+    #[error("{lang} has external tokens but no scanner: {parser_c} calls {function} and neither {scanner_c} nor {scanner_cc} exists")]
+    MissingScanner {
+        lang: String,
+        parser_c: String,
+        function: String,
+        scanner_c: String,
+        scanner_cc: String,
+    },
+    #[error("the scanner of {lang} ({scanners}) doesn't define {missing}")]
+    UndefinedScannerFunctions {
+        lang: String,
+        scanners: String,
+        missing: String,
+    },
+    #[error("the scanner of {lang} was flagged and isn't confirmed: {findings}")]
+    FlaggedScanner { lang: String, findings: String },
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error("Build of {0} is not reproducible")]
+    NotReproducible(String),
+    #[error("the build panicked: {message}")]
+    Panicked { phase: Phase, message: String },
+    #[error("Smoke test failed for {lang}: {reason}")]
+    SmokeTest { lang: String, reason: String },
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    // grammar.json names a language that can't be pasted into paths or sources
+    #[error("{lang} can't be configured: {reason}")]
+    InvalidLanguage { lang: String, reason: String },
+    // --only-config for a checkout whose library isn't there
+    #[error("{lang} has a checkout but no library at {path}, build it first")]
+    NotBuilt { lang: String, path: String },
+    // reading the checkout or writing next to the artifact
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+// reading or writing a config file, or its fragments
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    // there is a file, but not one this version can use
+    #[error("{path}: {message}")]
+    Invalid { path: String, message: String },
+}
+
+impl ConfigError {
+    pub(crate) fn io(path: impl AsRef<std::path::Path>, source: io::Error) -> Self {
+        ConfigError::Io {
+            path: path.as_ref().display().to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn invalid(path: impl AsRef<std::path::Path>, message: impl ToString) -> Self {
+        ConfigError::Invalid {
+            path: path.as_ref().display().to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+// writing one of the outputs of a run
+#[derive(Debug, Error)]
+pub enum EmitError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("{destination}: {source}")]
+    Write {
+        destination: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl EmitError {
+    pub(crate) fn write(
+        destination: impl ToString,
+    ) -> impl FnOnce(Box<dyn std::error::Error + Send + Sync>) -> Self {
+        let destination = destination.to_string();
+        move |source| EmitError::Write {
+            destination,
+            source,
+        }
+    }
+}
+
+// reading or writing the state database
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("state database {path}: {source}")]
+    Sqlite {
+        path: String,
+        #[source]
+        source: rusqlite::Error,
+    },
+    #[error(
+        "state database {path} is at schema version {version}, written by a newer parser_scraper"
+    )]
+    NewerSchema { path: String, version: i32 },
+}
+
+// what stops a whole pipeline run, failed builds alone don't
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("fetching parsers from {origin} failed: {source}")]
+    Scrape {
+        origin: String,
+        #[source]
+        source: ScrapeError,
+    },
+    #[error("writing an output failed: {0}")]
+    Emit(#[from] EmitError),
+    #[error("starting the async runtime failed: {0}")]
+    Runtime(#[source] io::Error),
+    // the run stopped short and wrote nothing, see Pipeline::cancel
+    #[error("the run was cancelled, {unfinished} grammars left unbuilt: {reason}")]
+    Cancelled { reason: String, unfinished: usize },
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::error::{BuildError, CloneError, CompileError, HookError, PipelineError, ScrapeError};
+use crate::hooks::HookStage;
+use crate::limits::LimitKind;
+
+// Where a grammar's build went wrong
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    // what the sources list about it, e.g. a repo that is gone
+    ScrapeMetadata,
+    Clone,
+    // the revision asked for
+    Checkout,
+    // the generated parser.c, missing or for another ABI
+    Generate,
+    Compile,
+    Link,
+    // the checks that load the library
+    Verify,
+    // writing the config and the other outputs
+    Config,
+}
+
+// Who can do something about it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    // the remote, DNS, a proxy, worth retrying
+    Network,
+    // this machine: the compiler, git, the disk, the hooks, parser_scraper itself
+    Toolchain,
+    // the grammar's repo, for its maintainers
+    UpstreamBug,
+    // a limit on how long it may take
+    Timeout,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::ScrapeMetadata => "scrape-metadata",
+            Phase::Clone => "clone",
+            Phase::Checkout => "checkout",
+            Phase::Generate => "generate",
+            Phase::Compile => "compile",
+            Phase::Link => "link",
+            Phase::Verify => "verify",
+            Phase::Config => "config",
+        }
+    }
+}
+
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Network => "network",
+            Category::Toolchain => "toolchain",
+            Category::UpstreamBug => "upstream-bug",
+            Category::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Failure {
+    pub phase: Phase,
+    pub category: Category,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {}", self.phase, self.category)
+    }
+}
+
+fn failure(phase: Phase, category: Category) -> Failure {
+    Failure { phase, category }
+}
+
+// Tag a failed build, from the error alone. What the compiler and git print
+// decides between the categories they can fail for.
+pub fn classify(error: &BuildError) -> Failure {
+    match error {
+        BuildError::Clone(e) => classify_clone(e),
+        BuildError::MissingParser(_) | BuildError::AbiMismatch { .. } => {
+            failure(Phase::Generate, Category::UpstreamBug)
+        }
+        BuildError::MissingScanner { .. } | BuildError::UndefinedScannerFunctions { .. } => {
+            failure(Phase::Link, Category::UpstreamBug)
+        }
+        // the grammar's code, until someone has read it
+        BuildError::FlaggedScanner { .. } => failure(Phase::Compile, Category::UpstreamBug),
+        BuildError::Compile(e) => classify_compile(e),
+        BuildError::NotReproducible(_) => failure(Phase::Verify, Category::Toolchain),
+        BuildError::Panicked { phase, .. } => failure(*phase, Category::Toolchain),
+        BuildError::SmokeTest { reason, .. } => {
+            if timed_out(reason) {
+                failure(Phase::Verify, Category::Timeout)
+            } else {
+                failure(Phase::Verify, Category::UpstreamBug)
+            }
+        }
+        BuildError::Hook(e) => classify_hook(e),
+        BuildError::InvalidLanguage { .. } => failure(Phase::Config, Category::UpstreamBug),
+        BuildError::NotBuilt { .. } => failure(Phase::Config, Category::Toolchain),
+        BuildError::Io(_) => failure(Phase::Compile, Category::Toolchain),
+    }
+}
+
+// what stopped a whole run, for the runs that stopped
+pub fn classify_run(error: &PipelineError) -> Option<Failure> {
+    match error {
+        PipelineError::Scrape { source, .. } => Some(failure(
+            Phase::ScrapeMetadata,
+            match source {
+                ScrapeError::Request(e) if e.is_timeout() => Category::Timeout,
+                ScrapeError::Request(_) => Category::Network,
+                // the page or registry changed its format
+                ScrapeError::Invalid { .. } => Category::UpstreamBug,
+                ScrapeError::Io { .. } | ScrapeError::Other(_) => Category::Toolchain,
+            },
+        )),
+        PipelineError::Emit(_) => Some(failure(Phase::Config, Category::Toolchain)),
+        PipelineError::Runtime(_) | PipelineError::Cancelled { .. } => None,
+    }
+}
+
+fn classify_clone(error: &CloneError) -> Failure {
+    match error {
+        CloneError::Git(_) => failure(Phase::Clone, Category::Toolchain),
+        CloneError::Failed { stderr, .. } => {
+            let stderr = stderr.to_lowercase();
+            // the listing points at something that isn't there (any more)
+            let gone = [
+                "repository not found",
+                "not found",
+                "does not appear to be a git repository",
+                "does not exist",
+            ];
+            if timed_out(&stderr) {
+                failure(Phase::Clone, Category::Timeout)
+            } else if gone.iter().any(|m| stderr.contains(m)) {
+                failure(Phase::ScrapeMetadata, Category::UpstreamBug)
+            } else {
+                failure(Phase::Clone, Category::Network)
+            }
+        }
+        CloneError::Revision { .. } => failure(Phase::Checkout, Category::UpstreamBug),
+        CloneError::Hook(e) => classify_hook(e),
+    }
+}
+
+fn classify_compile(error: &CompileError) -> Failure {
+    match error {
+        CompileError::ResourceLimit(e) => match e.kind {
+            LimitKind::CpuTime => failure(Phase::Compile, Category::Timeout),
+            LimitKind::Memory => failure(Phase::Compile, Category::Toolchain),
+        },
+        CompileError::Compiler { stderr, .. } => {
+            let stderr = stderr.to_lowercase();
+            let linking = [
+                "undefined reference",
+                "collect2",
+                "ld:",
+                "ld returned",
+                "linker",
+            ];
+            let toolchain = [
+                "unrecognized command-line option",
+                "unrecognized command line option",
+                "unknown argument",
+                "cannot find -l",
+                "internal compiler error",
+            ];
+            let phase = if linking.iter().any(|m| stderr.contains(m)) {
+                Phase::Link
+            } else {
+                Phase::Compile
+            };
+            if toolchain.iter().any(|m| stderr.contains(m)) {
+                failure(phase, Category::Toolchain)
+            } else {
+                failure(phase, Category::UpstreamBug)
+            }
+        }
+        CompileError::Toolchain(_) | CompileError::Io(_) => {
+            failure(Phase::Compile, Category::Toolchain)
+        }
+        CompileError::ExportList(_) | CompileError::SplitDebug(_) => {
+            failure(Phase::Link, Category::Toolchain)
+        }
+    }
+}
+
+fn classify_hook(error: &HookError) -> Failure {
+    let (HookError::Spawn { stage, .. } | HookError::Failed { stage, .. }) = error;
+    let phase = match stage {
+        HookStage::PreClone | HookStage::PostClone => Phase::Clone,
+        HookStage::PreBuild | HookStage::PostBuild => Phase::Compile,
+    };
+    failure(phase, Category::Toolchain)
+}
+
+fn timed_out(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("timed out") || message.contains("timeout")
+}
+
+// The languages of every failure, under the phase and category they failed for
+pub fn summarize<'a>(
+    failed: impl IntoIterator<Item = (&'a str, &'a BuildError)>,
+) -> BTreeMap<Failure, Vec<&'a str>> {
+    let mut summary: BTreeMap<Failure, Vec<&str>> = BTreeMap::new();
+    for (lang, error) in failed {
+        summary.entry(classify(error)).or_default().push(lang);
+    }
+    summary
+}
@@ -0,0 +1,139 @@
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+use crate::config::resolve_entry_path;
+use crate::languages::checked_symbol_name;
+
+// flags for --sanitize builds, the fuzz targets run against these
+pub fn sanitizer_flags(sanitizers: &str) -> Vec<String> {
+    vec![
+        format!("-fsanitize={}", sanitizers),
+        "-fno-omit-frame-pointer".to_string(),
+        "-fno-sanitize-recover=all".to_string(),
+        "-g".to_string(),
+    ]
+}
+
+// The fuzz target: parse the input, then cut a chunk out of it and reparse
+// incrementally, which runs the external scanner's serialize/deserialize too
+const TARGET_TEMPLATE: &str = r#"// @generated by parser_scraper, do not edit
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+use tree_sitter::{InputEdit, Language, Parser, Point};
+use tree_sitter_language::LanguageFn;
+
+const LIBRARY: &str = {library:?};
+const SYMBOL: &[u8] = b"{symbol}";
+
+fn language() -> &'static Language {
+    static GRAMMAR: OnceLock<(libloading::Library, Language)> = OnceLock::new();
+    &GRAMMAR
+        .get_or_init(|| unsafe {
+            let library = libloading::Library::new(LIBRARY).expect("grammar library loads");
+            let entry_point: libloading::Symbol<unsafe extern "C" fn() -> *const ()> =
+                library.get(SYMBOL).expect("grammar exports its entry point");
+            let language = Language::new(LanguageFn::from_raw(*entry_point));
+            (library, language)
+        })
+        .1
+}
+
+fn point_at(source: &[u8], offset: usize) -> Point {
+    let before = &source[..offset];
+    let row = before.iter().filter(|&&b| b == b'\n').count();
+    let column = offset - before.iter().rposition(|&b| b == b'\n').map_or(0, |p| p + 1);
+    Point { row, column }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = Parser::new();
+    parser.set_language(language()).unwrap();
+    let Some(mut tree) = parser.parse(data, None) else {
+        return;
+    };
+    let _ = tree.root_node().to_sexp();
+
+    // drop the second quarter of the input and reparse from the old tree
+    let start = data.len() / 4;
+    let end = data.len() / 2;
+    let mut edited = data[..start].to_vec();
+    edited.extend_from_slice(&data[end..]);
+    tree.edit(&InputEdit {
+        start_byte: start,
+        old_end_byte: end,
+        new_end_byte: start,
+        start_position: point_at(data, start),
+        old_end_position: point_at(data, end),
+        new_end_position: point_at(&edited, start),
+    });
+    if let Some(reparsed) = parser.parse(&edited, Some(&tree)) {
+        let _ = reparsed.root_node().to_sexp();
+    }
+});
+"#;
+
+// Write a cargo-fuzz project with one libFuzzer target per language in
+// `languages`, each loading the built library it points at
+pub fn write_fuzz_harness(
+    dest: &str,
+    languages: &Map<String, Value>,
+    config_path: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let targets_dir = Path::new(dest).join("fuzz_targets");
+    fs::create_dir_all(&targets_dir)?;
+
+    let mut cargo_toml = String::from(
+        "# @generated by parser_scraper, do not edit\n\
+         [package]\n\
+         name = \"tree-sitter-grammars-fuzz\"\n\
+         version = \"0.0.0\"\n\
+         publish = false\n\
+         edition = \"2021\"\n\
+         \n\
+         [package.metadata]\n\
+         cargo-fuzz = true\n\
+         \n\
+         [dependencies]\n\
+         libfuzzer-sys = \"0.4\"\n\
+         libloading = \"0.8\"\n\
+         tree-sitter = \"0.24\"\n\
+         tree-sitter-language = \"0.1\"\n\
+         \n\
+         # not part of any enclosing workspace\n\
+         [workspace]\n",
+    );
+    let mut count = 0;
+    for (name, entry) in languages {
+        let path = match entry.get("path").and_then(Value::as_str) {
+            Some(path) => path,
+            None => continue,
+        };
+        // the harness is built and run from elsewhere, so load by absolute path
+        let library = fs::canonicalize(resolve_entry_path(path, config_path))
+            .map_err(|e| format!("library {} for {}: {}", path, name, e))?;
+        let language = entry
+            .get("language")
+            .and_then(Value::as_str)
+            .unwrap_or(name);
+        // target names end up as file and binary names
+        let target: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+
+        let source = TARGET_TEMPLATE
+            .replace("{library:?}", &format!("{:?}", library.to_string_lossy()))
+            .replace("{symbol}", &checked_symbol_name(language)?);
+        fs::write(targets_dir.join(format!("{}.rs", target)), source)?;
+        cargo_toml.push_str(&format!(
+            "\n[[bin]]\nname = \"{0}\"\npath = \"fuzz_targets/{0}.rs\"\ntest = false\ndoc = false\nbench = false\n",
+            target
+        ));
+        count += 1;
+    }
+    fs::write(Path::new(dest).join("Cargo.toml"), cargo_toml)?;
+    Ok(count)
+}
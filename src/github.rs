@@ -0,0 +1,174 @@
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use crate::changelog::Change;
+use crate::failure;
+use crate::pipeline::PipelineReport;
+
+// GitHub Actions sets GITHUB_ACTIONS=true for every step it runs
+pub fn active() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+// A `::error title=...::message` workflow command, shown on the run and on the
+// PR. Newlines and the other characters the runner would cut the message at
+// are escaped.
+pub fn annotation(level: Level, title: &str, message: &str) -> String {
+    let level = match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+    };
+    format!(
+        "::{} title={}::{}",
+        level,
+        escape_property(title),
+        escape_data(message)
+    )
+}
+
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(property: &str) -> String {
+    escape_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+// The annotations of a run: an error per failed grammar, a warning for the
+// ones the trust policy refused and every extension claimed twice
+pub fn annotations(report: &PipelineReport) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (lang, error) in &report.failed {
+        let failure = failure::classify(error);
+        lines.push(annotation(
+            Level::Error,
+            &format!("{} failed ({}/{})", lang, failure.phase, failure.category),
+            error.to_string().trim_end(),
+        ));
+    }
+    for (lang, repo_url) in &report.untrusted {
+        lines.push(annotation(
+            Level::Warning,
+            &format!("{} refused", lang),
+            &format!("{} isn't from a trusted owner", repo_url),
+        ));
+    }
+    for collision in &report.extension_collisions {
+        lines.push(annotation(
+            Level::Warning,
+            &format!("extension {} claimed twice", collision.extension),
+            &format!(
+                "{} claim it, {} keeps it {}",
+                collision.languages.join(", "),
+                collision.winner,
+                collision.rule.describe()
+            ),
+        ));
+    }
+    lines
+}
+
+// The Markdown for $GITHUB_STEP_SUMMARY: a row per grammar of the run, built,
+// kept or failed, and what changed since the last builds
+pub fn step_summary(report: &PipelineReport, changes: &[Change]) -> String {
+    let mut markdown = format!(
+        "### parser_scraper: {} built, {} failed\n\n",
+        report.artifacts.len(),
+        report.failed.len()
+    );
+    markdown.push_str(&format!("Compiler: `{}`\n\n", report.compiler));
+    markdown.push_str("| Grammar | Result | Version | Commit | Details |\n");
+    markdown.push_str("| --- | --- | --- | --- | --- |\n");
+    let mut rows: Vec<(String, String)> = Vec::new();
+    for artifact in &report.artifacts {
+        let field = |key: &str| {
+            artifact
+                .entry
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string()
+        };
+        let commit = field("commit");
+        let result = if report.reused.contains(&artifact.name) {
+            "kept"
+        } else {
+            "built"
+        };
+        rows.push((
+            artifact.name.clone(),
+            format!(
+                "| {} | :white_check_mark: {} | {} | {} | |",
+                cell(&artifact.name),
+                result,
+                cell(&field("version")),
+                cell(&commit[..commit.len().min(7)])
+            ),
+        ));
+    }
+    for (lang, error) in &report.failed {
+        let failure = failure::classify(error);
+        // the first line, the log has the rest
+        let message = error.to_string();
+        let first = message.lines().next().unwrap_or("");
+        rows.push((
+            lang.clone(),
+            format!(
+                "| {} | :x: {}/{} | | | {} |",
+                cell(lang),
+                failure.phase,
+                failure.category,
+                cell(&shorten(first))
+            ),
+        ));
+    }
+    rows.sort();
+    for (_, row) in rows {
+        markdown.push_str(&row);
+        markdown.push('\n');
+    }
+    if !changes.is_empty() {
+        markdown.push_str("\n#### Changes since the last builds\n\n```\n");
+        for change in changes {
+            markdown.push_str(&format!("{}\n", change));
+        }
+        markdown.push_str("```\n");
+    }
+    // apart from what the next step appends
+    markdown.push('\n');
+    markdown
+}
+
+// appended, the file collects every step's summary of the job
+pub fn append_step_summary(markdown: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+        return Ok(false);
+    };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(markdown.as_bytes())?;
+    Ok(true)
+}
+
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn shorten(text: &str) -> String {
+    const MAX: usize = 200;
+    if text.chars().count() > MAX {
+        format!("{}...", text.chars().take(MAX).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,1510 @@
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::audit::{self, ScannerAudit};
+use crate::builder::{CompileJob, GccBuilder, GrammarCompiler};
+use crate::cancel::CancelToken;
+use crate::config::{create_config_entry, BuildMetadata, LanguageArtifact};
+use crate::error::{BuildError, CloneError, CompileError};
+use crate::hooks::{self, HookEnv, HookStage, Hooks};
+use crate::layout::{self, Layout};
+use crate::limits::ResourceLimits;
+use crate::sandbox::Sandbox;
+use crate::{corpus, debuginfo, fuzz, languages, licenses, regression, smoke, symbols};
+
+// per build settings, shared read-only by every build
+#[derive(Clone)]
+pub struct BuildOptions {
+    pub output_dir: String,
+    pub source_destination: String,
+    pub name_template: String,
+    pub layout: Layout,
+    pub copy_queries: bool,
+    // node-types.json and grammar.json into <output>/<lang>/
+    pub copy_grammar_files: bool,
+    pub copy_licenses: bool,
+    pub check_reproducible: bool,
+    pub smoke_test: bool,
+    pub run_corpus: bool,
+    pub test_files: Option<PathBuf>,
+    // where --diff-trees writes its details, when enabled
+    pub tree_diffs: Option<PathBuf>,
+    pub runtime_abi: Option<u32>,
+    pub warn_abi_mismatch: bool,
+    pub hide_symbols: bool,
+    // compile with -g and move the debug info out of the library, see debuginfo.rs
+    pub split_debug: bool,
+    // name the library by its content, see layout::name_by_content
+    pub content_hash: bool,
+    pub sanitize: Option<String>,
+    pub builder: Arc<dyn GrammarCompiler>,
+    pub sandbox: Option<Sandbox>,
+    // --scan-scanners, when enabled
+    pub scanner_audit: Option<ScannerAudit>,
+    pub limits: ResourceLimits,
+    pub single_extension: bool,
+    // earlier versions of a rebuilt library kept next to it as <library>.1 ...
+    pub keep_previous: usize,
+    pub manifest: Manifest,
+    // stops the clones, compiles and hooks of every build sharing the options
+    pub cancel: CancelToken,
+}
+
+// the CLI's defaults: the host gcc, flat output in ./shared_libs/, queries and
+// licenses copied, no extra checks
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            output_dir: "./shared_libs/".to_string(),
+            source_destination: "./shared_libs_src/".to_string(),
+            name_template: "{prefix}{lang}{ext}".to_string(),
+            layout: Layout::Flat,
+            copy_queries: true,
+            copy_grammar_files: false,
+            copy_licenses: true,
+            check_reproducible: false,
+            smoke_test: false,
+            run_corpus: false,
+            test_files: None,
+            tree_diffs: None,
+            runtime_abi: None,
+            warn_abi_mismatch: false,
+            hide_symbols: false,
+            split_debug: false,
+            content_hash: false,
+            sanitize: None,
+            builder: Arc::new(GccBuilder),
+            sandbox: None,
+            scanner_audit: None,
+            limits: ResourceLimits::default(),
+            single_extension: false,
+            keep_previous: 0,
+            manifest: Manifest::default(),
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
+impl BuildOptions {
+    // The sha256 of the options that change what is compiled for `lang`: the
+    // toolchain, its flags and the language's own (build_flags). --incremental only reuses
+    // a build of the same hash.
+    pub fn options_hash(&self, lang: &str) -> String {
+        let mut hasher = Sha256::new();
+        let mut add = |field: &str, value: &str| {
+            hasher.update(field.as_bytes());
+            hasher.update(b"=");
+            hasher.update(value.as_bytes());
+            hasher.update(b"\n");
+        };
+        add("builder", &self.builder.describe());
+        add(
+            "compiler",
+            self.builder.compiler_version().as_deref().unwrap_or(""),
+        );
+        add("sanitize", self.sanitize.as_deref().unwrap_or(""));
+        add("hide_symbols", &self.hide_symbols.to_string());
+        add("split_debug", &self.split_debug.to_string());
+        add("content_hash", &self.content_hash.to_string());
+        let flags = build_flags(&self.manifest, lang);
+        for flag in &flags.cflags {
+            add("cflag", flag);
+        }
+        for flag in &flags.ldflags {
+            add("ldflag", flag);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+// everything that goes into compiling one grammar checkout
+struct GrammarSources {
+    repo_dir: String,
+    // the directory holding grammar.js and src/
+    grammar_dir: PathBuf,
+    sources: Vec<String>,
+    include_dirs: Vec<PathBuf>,
+}
+
+// user supplied tool settings, read from --manifest
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Manifest {
+    pub languages: HashMap<String, LanguageOverrides>,
+    // languages in the order they win contested extensions
+    pub extension_priority: Vec<String>,
+    // extra parsers for --source manifest, name -> repository
+    pub parsers: BTreeMap<String, String>,
+    // run around every grammar's clone and build, see hooks.rs
+    pub hooks: Hooks,
+    // --preset bundles of languages, on top of the built-in ones
+    pub presets: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct LanguageOverrides {
+    pub flags: BuildFlags,
+    // snippet parsed by --smoke-test instead of the built-in one
+    pub sample: Option<String>,
+    // replace the global hooks of the same stage
+    pub hooks: Hooks,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct BuildFlags {
+    // passed before the sources
+    pub cflags: Vec<String>,
+    // passed after the sources, so libraries like -lm resolve
+    pub ldflags: Vec<String>,
+}
+
+impl BuildFlags {
+    // append any flags from `other` that aren't already present
+    pub fn merged_with(&self, other: &BuildFlags) -> BuildFlags {
+        let mut merged = self.clone();
+        for flag in &other.cflags {
+            if !merged.cflags.contains(flag) {
+                merged.cflags.push(flag.clone());
+            }
+        }
+        for flag in &other.ldflags {
+            if !merged.ldflags.contains(flag) {
+                merged.ldflags.push(flag.clone());
+            }
+        }
+        merged
+    }
+}
+
+// flags some scanners need to compile or link: (language, cflags, ldflags).
+// These use tree_sitter/array.h, which is C99 and won't build with a gcc
+// that defaults to gnu89. The manifest's flags for a language replace its row.
+const BUILD_QUIRKS: &[(&str, &[&str], &[&str])] = &[
+    ("haskell", &["-std=c11"], &[]),
+    ("markdown", &["-std=c11"], &[]),
+    ("markdown_inline", &["-std=c11"], &[]),
+    ("python", &["-std=c11"], &[]),
+];
+
+// what a compile the table doesn't help gets retried with, once
+const RETRY_CFLAGS: &[&str] = &["-std=c11", "-D_POSIX_C_SOURCE=200809L"];
+const RETRY_LDFLAGS: &[&str] = &["-lm"];
+
+fn owned(flags: &[&str]) -> Vec<String> {
+    flags.iter().map(|f| f.to_string()).collect()
+}
+
+// The flags `lang` is compiled with first: the manifest's, or else its row of
+// the quirks table
+fn build_flags(manifest: &Manifest, lang: &str) -> BuildFlags {
+    let configured = manifest.languages.get(lang).map(|o| &o.flags);
+    if let Some(flags) = configured.filter(|f| !f.cflags.is_empty() || !f.ldflags.is_empty()) {
+        return flags.clone();
+    }
+    BUILD_QUIRKS
+        .iter()
+        .find(|(name, _, _)| *name == lang)
+        .map(|(_, cflags, ldflags)| BuildFlags {
+            cflags: owned(cflags),
+            ldflags: owned(ldflags),
+        })
+        .unwrap_or_default()
+}
+
+fn retry_flags() -> BuildFlags {
+    BuildFlags {
+        cflags: owned(RETRY_CFLAGS),
+        ldflags: owned(RETRY_LDFLAGS),
+    }
+}
+
+pub fn load_manifest(path: &str) -> Result<Manifest, Box<dyn std::error::Error>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Clones and builds grammars with one set of options. Builds don't share any
+// state, so one builder can serve every build thread.
+pub struct GrammarBuilder {
+    options: BuildOptions,
+}
+
+impl GrammarBuilder {
+    pub fn new(options: BuildOptions) -> Self {
+        GrammarBuilder { options }
+    }
+
+    pub fn options(&self) -> &BuildOptions {
+        &self.options
+    }
+
+    // Clone `repo_url` into the source destination and build the grammar for
+    // `lang`, returning its config entries. `status` gets a line for every step.
+    pub fn build(
+        &self,
+        lang: &str,
+        repo_url: &str,
+        status: &dyn Fn(String),
+    ) -> Result<Vec<LanguageArtifact>, BuildError> {
+        status(format!("Cloning {}", repo_url));
+        let checkout = self.checkout(lang, repo_url);
+        if let Some(command) = self.hook(lang, HookStage::PreClone) {
+            hooks::run_hook(
+                &command,
+                HookStage::PreClone,
+                &self.hook_env(&checkout, None),
+            )
+            .map_err(CloneError::Hook)?;
+        }
+        let output = self
+            .options
+            .cancel
+            .output(Command::new("git").args(["clone", "--", repo_url, &checkout.repo_dir]))
+            .map_err(CloneError::Git)?;
+        clone_result(repo_url, &output)?;
+        if let Some(command) = self.hook(lang, HookStage::PostClone) {
+            hooks::run_hook(
+                &command,
+                HookStage::PostClone,
+                &self.hook_env(&checkout, None),
+            )
+            .map_err(CloneError::Hook)?;
+        }
+        let built = self.compile(&checkout, status)?;
+        self.verify(built, status)
+    }
+
+    // The clone stage of `build`, without blocking a thread so many of them can
+    // wait on the network at once
+    pub async fn clone_async(&self, lang: &str, repo_url: &str) -> Result<Checkout, CloneError> {
+        self.clone_at_async(lang, repo_url, None).await
+    }
+
+    // the same, checked out at `rev` (a branch, tag or commit) when there is one
+    pub async fn clone_at_async(
+        &self,
+        lang: &str,
+        repo_url: &str,
+        rev: Option<&str>,
+    ) -> Result<Checkout, CloneError> {
+        let mut checkout = self.checkout(lang, repo_url);
+        checkout.rev = rev.map(str::to_string);
+        if let Some(command) = self.hook(lang, HookStage::PreClone) {
+            let env = self.hook_env(&checkout, None);
+            hooks::run_hook_async(&command, HookStage::PreClone, &env).await?;
+        }
+        let cancel = &self.options.cancel;
+        let output = cancel
+            .output_async(tokio::process::Command::new("git").args([
+                "clone",
+                "--",
+                repo_url,
+                &checkout.repo_dir,
+            ]))
+            .await?;
+        clone_result(repo_url, &output)?;
+        if let Some(rev) = rev {
+            checkout_revision(&checkout.repo_dir, repo_url, rev, cancel).await?;
+        }
+        if let Some(command) = self.hook(lang, HookStage::PostClone) {
+            let env = self.hook_env(&checkout, None);
+            hooks::run_hook_async(&command, HookStage::PostClone, &env).await?;
+        }
+        Ok(checkout)
+    }
+
+    // The compile stage: the library, its queries and licenses, and the config
+    // entries for it
+    pub fn compile(
+        &self,
+        checkout: &Checkout,
+        status: &dyn Fn(String),
+    ) -> Result<BuiltGrammar, BuildError> {
+        compile_checkout(checkout, status, &self.options)
+    }
+
+    // The config entries of a checkout whose library is already built, see
+    // describe_checkout
+    pub fn describe(
+        &self,
+        checkout: &Checkout,
+        status: &dyn Fn(String),
+    ) -> Result<Vec<LanguageArtifact>, BuildError> {
+        describe_checkout(checkout, status, &self.options)
+    }
+
+    // The checkouts earlier runs left in the source destination, by language
+    // name. The repository is the checkout's origin.
+    pub fn existing_checkouts(&self) -> io::Result<Vec<Checkout>> {
+        let mut checkouts = Vec::new();
+        for entry in fs::read_dir(&self.options.source_destination)? {
+            let path = entry?.path();
+            let Some(lang) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("tree-sitter-"))
+            else {
+                continue;
+            };
+            if !path.join(".git").exists() {
+                continue;
+            }
+            let mut checkout = self.checkout(lang, "");
+            checkout.repo_url = git_origin(&checkout.repo_dir).unwrap_or_default();
+            checkouts.push(checkout);
+        }
+        checkouts.sort_by(|a, b| a.lang.cmp(&b.lang));
+        Ok(checkouts)
+    }
+
+    // The verify stage: the checks that load the library (smoke test, test
+    // files, corpus, tree diffs), returning the entries with their results
+    pub fn verify(
+        &self,
+        built: BuiltGrammar,
+        status: &dyn Fn(String),
+    ) -> Result<Vec<LanguageArtifact>, BuildError> {
+        verify_build(built, status, &self.options)
+    }
+
+    // Remove the checkout of a build that was cancelled halfway, so the next
+    // run can clone it again
+    pub fn discard(&self, lang: &str) {
+        let repo_dir = self.checkout(lang, "").repo_dir;
+        if Path::new(&repo_dir).exists() {
+            if let Err(e) = fs::remove_dir_all(&repo_dir) {
+                log::warn!("failed to remove {} : {}", repo_dir, e);
+            }
+        }
+    }
+
+    fn hook(&self, lang: &str, stage: HookStage) -> Option<String> {
+        hooks::hook_for(&self.options.manifest, lang, stage)
+    }
+
+    fn hook_env<'a>(&'a self, checkout: &'a Checkout, artifact: Option<&'a str>) -> HookEnv<'a> {
+        HookEnv {
+            lang: &checkout.lang,
+            repo_url: &checkout.repo_url,
+            repo_dir: &checkout.repo_dir,
+            output_dir: &self.options.output_dir,
+            artifact,
+            cancel: &self.options.cancel,
+        }
+    }
+
+    fn checkout(&self, lang: &str, repo_url: &str) -> Checkout {
+        Checkout {
+            lang: lang.to_string(),
+            repo_url: repo_url.to_string(),
+            repo_dir: format!("{}tree-sitter-{}", self.options.source_destination, lang),
+            rev: None,
+        }
+    }
+}
+
+// a cloned grammar, waiting to be compiled
+#[derive(Debug, Clone)]
+pub struct Checkout {
+    pub lang: String,
+    pub repo_url: String,
+    pub repo_dir: String,
+    // what was checked out instead of the default branch
+    pub rev: Option<String>,
+}
+
+// a compiled grammar, waiting for the checks that load it
+pub struct BuiltGrammar {
+    pub lang: String,
+    pub repo_dir: String,
+    // the directory above src/, where test/corpus lives
+    pub grammar_dir: PathBuf,
+    pub library: String,
+    // the library of an earlier run, when --diff-trees kept one
+    pub previous_library: PathBuf,
+    pub artifacts: Vec<LanguageArtifact>,
+}
+
+// Detach the checkout at `rev`, resolved to a commit first so it can't be
+// taken for a path or, starting with a dash, an option
+async fn checkout_revision(
+    repo_dir: &str,
+    repo_url: &str,
+    rev: &str,
+    cancel: &CancelToken,
+) -> Result<(), CloneError> {
+    let failed = |stderr: &[u8]| CloneError::Revision {
+        repo_url: repo_url.to_string(),
+        rev: rev.to_string(),
+        stderr: String::from_utf8_lossy(stderr).trim().to_string(),
+    };
+    if rev.starts_with('-') {
+        return Err(failed(b"not a revision"));
+    }
+    // branches other than the default one only exist as origin/<branch> after a clone
+    let mut commit = None;
+    for candidate in [rev.to_string(), format!("origin/{}", rev)] {
+        let resolved = cancel
+            .output_async(
+                tokio::process::Command::new("git")
+                    .args(["-C", repo_dir, "rev-parse", "--verify", "--quiet"])
+                    .arg(format!("{}^{{commit}}", candidate)),
+            )
+            .await?;
+        if resolved.status.success() {
+            commit = Some(String::from_utf8_lossy(&resolved.stdout).trim().to_string());
+            break;
+        }
+    }
+    let Some(commit) = commit else {
+        return Err(failed(b"no such branch, tag or commit"));
+    };
+    let output = cancel
+        .output_async(
+            tokio::process::Command::new("git")
+                .args(["-C", repo_dir, "checkout", "--quiet", "--detach", &commit]),
+        )
+        .await?;
+    if !output.status.success() {
+        return Err(failed(&output.stderr));
+    }
+    Ok(())
+}
+
+fn clone_result(repo_url: &str, output: &std::process::Output) -> Result<(), CloneError> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(CloneError::Failed {
+            repo_url: repo_url.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+// Build the grammar for a given language from its checkout
+fn compile_checkout(
+    checkout: &Checkout,
+    status: &dyn Fn(String),
+    build_options: &BuildOptions,
+) -> Result<BuiltGrammar, BuildError> {
+    let lang = checkout.lang.as_str();
+    let repo_url = checkout.repo_url.as_str();
+    let repo_dir = checkout.repo_dir.clone();
+    let hook_env = |artifact| HookEnv {
+        lang,
+        repo_url,
+        repo_dir: &checkout.repo_dir,
+        output_dir: &build_options.output_dir,
+        artifact,
+        cancel: &build_options.cancel,
+    };
+    if let Some(command) = hooks::hook_for(&build_options.manifest, lang, HookStage::PreBuild) {
+        status(format!("Running the pre_build hook of {}", lang));
+        hooks::run_hook(&command, HookStage::PreBuild, &hook_env(None))?;
+    }
+    status(format!("Cloned {}. Searching for parser.c", lang));
+
+    // Search for parser.c in the cloned directory
+    let parser_c_path = find_file(&repo_dir, "parser.c").map_err(BuildError::MissingParser)?;
+
+    // no point building what the target runtime won't load
+    if let Some(runtime_abi) = build_options.runtime_abi {
+        match read_abi_version(&parser_c_path) {
+            Some(abi) => {
+                if let Err(reason) = check_runtime_abi(abi, runtime_abi) {
+                    if !build_options.warn_abi_mismatch {
+                        return Err(BuildError::AbiMismatch {
+                            lang: lang.to_string(),
+                            reason,
+                        });
+                    }
+                    log::warn!("{} {}, keeping it anyway", lang, reason);
+                }
+            }
+            None => log::warn!("can't tell which ABI {} was generated for", lang),
+        }
+    }
+
+    // the directory holding parser.c is the grammar's src dir, everything in it
+    // (split scanners, C++ scanners) is part of the build
+    let src_dir = Path::new(&parser_c_path)
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from(&repo_dir));
+    let external_files = read_external_files(&repo_dir);
+    let mut sources = collect_sources(&src_dir, &repo_dir, &external_files)?;
+    // scanner.c is optional, and normally already sits next to parser.c
+    let has_scanner = sources.iter().any(|s| {
+        Path::new(s)
+            .file_stem()
+            .map(|stem| stem == "scanner")
+            .unwrap_or(false)
+    });
+    if !has_scanner {
+        if let Ok(scanner_c) = find_file(&repo_dir, "scanner.c") {
+            sources.push(scanner_c);
+        }
+    }
+    let scanner_sources: Vec<String> = sources
+        .iter()
+        .filter(|s| {
+            Path::new(s)
+                .file_stem()
+                .is_some_and(|stem| stem == "scanner")
+        })
+        .map(|s| relative_to(s, &repo_dir))
+        .collect();
+
+    // a grammar with external tokens links against its scanner, without one the
+    // library only fails later with undefined tree_sitter_*_external_scanner_* symbols
+    let scanner_functions =
+        symbols::external_scanner_references(&fs::read_to_string(&parser_c_path)?);
+    if !scanner_functions.is_empty() && scanner_sources.is_empty() {
+        return Err(BuildError::MissingScanner {
+            lang: lang.to_string(),
+            parser_c: relative_to(&parser_c_path, &repo_dir),
+            function: scanner_functions.iter().next().unwrap().clone(),
+            scanner_c: relative_to(&src_dir.join("scanner.c").to_string_lossy(), &repo_dir),
+            scanner_cc: relative_to(&src_dir.join("scanner.cc").to_string_lossy(), &repo_dir),
+        });
+    }
+
+    // the hand-written sources are what a grammar's repo can slip anything into
+    if let Some(audit) = &build_options.scanner_audit {
+        let findings = audit::scan_grammar(&sources, &src_dir, &repo_dir);
+        for finding in &findings {
+            log::warn!("flagged in the scanner of {}: {}", lang, finding);
+        }
+        if !findings.is_empty() {
+            if !audit.confirmed(lang) {
+                let flagged: Vec<String> = findings
+                    .iter()
+                    .map(|f| format!("{}:{} ({})", f.file, f.line, f.flag))
+                    .collect();
+                return Err(BuildError::FlaggedScanner {
+                    lang: lang.to_string(),
+                    findings: flagged.join(", "),
+                });
+            }
+            log::warn!(
+                "building {} as confirmed although its scanner was flagged",
+                lang
+            );
+        }
+    }
+
+    status(format!("Building grammar for {}", lang));
+    let output_dir = &build_options.output_dir;
+    let output_path = layout::artifact_path(
+        build_options.layout,
+        output_dir,
+        &build_options.name_template,
+        lang,
+    );
+    if let Some(parent) = Path::new(&output_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // headers shipped next to external files (e.g. a shared common/ dir)
+    let mut include_dirs: Vec<PathBuf> = vec![src_dir.clone()];
+    for file in &external_files {
+        if let Some(parent) = Path::new(&repo_dir).join(file).parent() {
+            if parent.is_dir() && !include_dirs.contains(&parent.to_path_buf()) {
+                include_dirs.push(parent.to_path_buf());
+            }
+        }
+    }
+
+    let grammar = GrammarSources {
+        repo_dir: repo_dir.clone(),
+        grammar_dir: src_dir
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from(&repo_dir)),
+        sources,
+        include_dirs,
+    };
+
+    let mut flags = build_flags(&build_options.manifest, lang);
+
+    // the library an earlier run left here, kept to compare trees with
+    let previous_build = Path::new(&repo_dir).join(".parser_scraper-previous");
+    if build_options.tree_diffs.is_some() && Path::new(&output_path).is_file() {
+        fs::copy(&output_path, &previous_build)?;
+    }
+    layout::rotate_library(Path::new(&output_path), build_options.keep_previous)?;
+    // the link --content-hash left, the compiler would write through it into
+    // a library that must never change
+    if Path::new(&output_path).is_symlink() {
+        fs::remove_file(&output_path)?;
+    }
+
+    if let Err(e) = compile_grammar(lang, &grammar, &flags, &output_path, build_options) {
+        // only a compiler that rejected the sources might accept them with
+        // other flags, a resource limit or a missing compiler won't change
+        if !matches!(e, CompileError::Compiler { .. }) {
+            return Err(e.into());
+        }
+        // retry once with the known quirk flags before giving up
+        let with_quirks = flags.merged_with(&retry_flags());
+        if with_quirks == flags {
+            return Err(e.into());
+        }
+        status(format!("Retrying {} with quirk flags", lang));
+        log::info!("retrying {} with quirk flags {:?}", lang, with_quirks);
+        compile_grammar(lang, &grammar, &with_quirks, &output_path, build_options)?;
+        flags = with_quirks;
+    }
+
+    if !scanner_functions.is_empty() {
+        if let Ok(undefined) = symbols::undefined_symbols(&output_path) {
+            let missing: Vec<&str> = scanner_functions
+                .iter()
+                .filter(|f| undefined.contains(*f))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                return Err(BuildError::UndefinedScannerFunctions {
+                    lang: lang.to_string(),
+                    scanners: scanner_sources.join(", "),
+                    missing: missing.join(", "),
+                });
+            }
+        }
+    }
+
+    if build_options.check_reproducible {
+        status(format!("Rebuilding {} to check reproducibility", lang));
+        let check_path = format!("{}.repro-check", output_path);
+        compile_grammar(lang, &grammar, &flags, &check_path, build_options)?;
+        let identical = fs::read(&output_path)? == fs::read(&check_path)?;
+        fs::remove_file(&check_path)?;
+        if !identical {
+            return Err(BuildError::NotReproducible(lang.to_string()));
+        }
+    }
+
+    // before anything hashes or records the library, it's what ships
+    if build_options.split_debug {
+        status(format!("Splitting the debug info of {}", lang));
+        let debug = debuginfo::split(Path::new(&output_path), Path::new(output_dir))
+            .map_err(|e| CompileError::SplitDebug(e.to_string()))?;
+        if let Some(debug) = debug {
+            log::info!("split the debug info of {} into {}", lang, debug.display());
+        }
+    }
+
+    if let Some(command) = hooks::hook_for(&build_options.manifest, lang, HookStage::PostBuild) {
+        status(format!("Running the post_build hook of {}", lang));
+        hooks::run_hook(
+            &command,
+            HookStage::PostBuild,
+            &hook_env(Some(&output_path)),
+        )?;
+    }
+
+    // last, the hash is of what ships
+    let output_path = if build_options.content_hash {
+        let hashed = layout::name_by_content(Path::new(&output_path))?;
+        log::info!("{} is {}", output_path, hashed.display());
+        hashed.to_string_lossy().to_string()
+    } else {
+        output_path
+    };
+
+    let entries = describe_grammar(
+        checkout,
+        &grammar.grammar_dir,
+        &parser_c_path,
+        &output_path,
+        build_timestamp(),
+        status,
+        build_options,
+    )?;
+
+    Ok(BuiltGrammar {
+        lang: lang.to_string(),
+        repo_dir,
+        grammar_dir: grammar.grammar_dir,
+        library: output_path,
+        previous_library: previous_build,
+        artifacts: entries,
+    })
+}
+
+// The queries, grammar files and licenses next to the library at
+// `output_path`, and the config entries for it. Only a language that can't be
+// named fails, an entry that can't be created is logged and left out.
+fn describe_grammar(
+    checkout: &Checkout,
+    grammar_dir: &Path,
+    parser_c_path: &str,
+    output_path: &str,
+    built_at: String,
+    status: &dyn Fn(String),
+    build_options: &BuildOptions,
+) -> Result<Vec<LanguageArtifact>, BuildError> {
+    let lang = checkout.lang.as_str();
+    let repo_dir = checkout.repo_dir.as_str();
+    let mut queries = BTreeMap::new();
+    if build_options.copy_queries {
+        status(format!("Copying queries for {}", lang));
+        let dest = layout::queries_dir(&build_options.output_dir, lang);
+        match layout::copy_queries(grammar_dir, Path::new(repo_dir), &dest) {
+            Ok(copied) => queries = copied,
+            Err(e) => log::warn!("failed to copy queries for {} : {}", lang, e),
+        }
+    }
+
+    let mut grammar_files = BTreeMap::new();
+    if build_options.copy_grammar_files {
+        let dest = layout::grammar_files_dir(&build_options.output_dir, lang);
+        match layout::copy_grammar_files(grammar_dir, &dest) {
+            Ok(copied) => grammar_files = copied,
+            Err(e) => log::warn!("failed to copy node-types.json of {} : {}", lang, e),
+        }
+        if grammar_files.is_empty() {
+            log::warn!("{} has no src/node-types.json or src/grammar.json", lang);
+        }
+    }
+
+    let mut license_files = Vec::new();
+    if build_options.copy_licenses {
+        let dest = licenses::licenses_dir(&build_options.output_dir, lang);
+        match licenses::copy_licenses(grammar_dir, Path::new(repo_dir), &dest) {
+            Ok(copied) => license_files = copied,
+            Err(e) => log::warn!("failed to copy licenses for {} : {}", lang, e),
+        }
+        if license_files.is_empty() {
+            log::warn!("no license file found for {}", lang);
+        }
+    }
+
+    let metadata = BuildMetadata {
+        abi_version: read_abi_version(parser_c_path),
+        commit: git_head_commit(repo_dir),
+        tag: git_latest_tag(repo_dir),
+        built_at,
+        queries,
+        grammar_files,
+        detected_license: licenses::identify_files(&license_files).map(str::to_string),
+        license_files,
+    };
+
+    match create_config_entry(
+        lang,
+        repo_dir,
+        &checkout.repo_url,
+        output_path,
+        &metadata,
+        build_options.single_extension,
+    ) {
+        Ok(entries) => Ok(entries),
+        Err(e) => match e.downcast::<BuildError>() {
+            Ok(e) => Err(*e),
+            Err(e) => {
+                log::error!("failed to create config entry for {} : {}", lang, e);
+                Ok(Vec::new())
+            }
+        },
+    }
+}
+
+// --only-config: the config entries of a checkout and the library an earlier
+// run built from it, without compiling anything. built_at is when the library
+// was last written.
+fn describe_checkout(
+    checkout: &Checkout,
+    status: &dyn Fn(String),
+    build_options: &BuildOptions,
+) -> Result<Vec<LanguageArtifact>, BuildError> {
+    let lang = checkout.lang.as_str();
+    let parser_c_path =
+        find_file(&checkout.repo_dir, "parser.c").map_err(BuildError::MissingParser)?;
+    let grammar_dir = Path::new(&parser_c_path)
+        .parent()
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(&checkout.repo_dir));
+    let output_path = layout::artifact_path(
+        build_options.layout,
+        &build_options.output_dir,
+        &build_options.name_template,
+        lang,
+    );
+    if !Path::new(&output_path).is_file() {
+        return Err(BuildError::NotBuilt {
+            lang: lang.to_string(),
+            path: output_path,
+        });
+    }
+    let output_path = if build_options.content_hash {
+        layout::name_by_content(Path::new(&output_path))?
+            .to_string_lossy()
+            .to_string()
+    } else {
+        output_path
+    };
+    let modified = fs::metadata(&output_path)?.modified()?;
+    let built_at = chrono::DateTime::<chrono::Utc>::from(modified)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    status(format!("Describing {}", lang));
+    describe_grammar(
+        checkout,
+        &grammar_dir,
+        &parser_c_path,
+        &output_path,
+        built_at,
+        status,
+        build_options,
+    )
+}
+
+// Run the checks that load the built library
+fn verify_build(
+    built: BuiltGrammar,
+    status: &dyn Fn(String),
+    build_options: &BuildOptions,
+) -> Result<Vec<LanguageArtifact>, BuildError> {
+    let BuiltGrammar {
+        lang,
+        repo_dir,
+        grammar_dir,
+        library: output_path,
+        previous_library: previous_build,
+        artifacts: mut entries,
+    } = built;
+    let lang = lang.as_str();
+
+    if build_options.smoke_test {
+        status(format!("Smoke testing {}", lang));
+        let custom_sample = build_options
+            .manifest
+            .languages
+            .get(lang)
+            .and_then(|o| o.sample.as_deref());
+        for entry in &entries {
+            let language = entry
+                .entry
+                .get("language")
+                .and_then(Value::as_str)
+                .unwrap_or(&entry.name);
+            let sample = custom_sample.unwrap_or_else(|| languages::sample_for(&entry.name));
+            smoke::smoke_test(Path::new(&output_path), language, sample).map_err(|e| {
+                BuildError::SmokeTest {
+                    lang: entry.name.clone(),
+                    reason: e.to_string(),
+                }
+            })?;
+        }
+    }
+
+    if let Some(test_dir) = &build_options.test_files {
+        status(format!("Parsing the test files of {}", lang));
+        for entry in entries.iter_mut() {
+            let language = entry
+                .entry
+                .get("language")
+                .and_then(Value::as_str)
+                .unwrap_or(&entry.name)
+                .to_string();
+            for file in test_files_for(test_dir, &entry.name) {
+                match smoke::parse_test_file(Path::new(&output_path), &language, &file) {
+                    Ok(parse) => {
+                        if let Some((line, column)) = parse.first_error {
+                            log::warn!(
+                                "{} has {} errors when parsed as {}, the first at {}:{}",
+                                parse.file,
+                                parse.errors,
+                                entry.name,
+                                line,
+                                column
+                            );
+                        }
+                        entry.test_files.push(parse);
+                    }
+                    Err(e) => log::warn!("failed to parse {} : {}", file.display(), e),
+                }
+            }
+        }
+    }
+
+    if build_options.run_corpus {
+        match corpus::find_corpus(&grammar_dir, Path::new(&repo_dir)) {
+            Some(corpus_dir) => {
+                status(format!("Running the test corpus of {}", lang));
+                // the corpus belongs to the grammar the checkout is named for
+                let position = entries.iter().position(|e| e.name == lang).unwrap_or(0);
+                if let Some(entry) = entries.get_mut(position) {
+                    let language = entry
+                        .entry
+                        .get("language")
+                        .and_then(Value::as_str)
+                        .unwrap_or(&entry.name)
+                        .to_string();
+                    match corpus::run_corpus(Path::new(&output_path), &language, &corpus_dir) {
+                        Ok(report) => {
+                            for failure in &report.failures {
+                                log::warn!("corpus test failed for {} : {}", lang, failure);
+                            }
+                            entry.corpus = Some(report);
+                        }
+                        Err(e) => log::warn!("failed to run the corpus for {} : {}", lang, e),
+                    }
+                }
+            }
+            None => log::info!("no test corpus found for {}", lang),
+        }
+    }
+
+    if let Some(diff_dir) = &build_options.tree_diffs {
+        if previous_build.is_file() {
+            status(format!(
+                "Comparing trees with the previous build of {}",
+                lang
+            ));
+            let position = entries.iter().position(|e| e.name == lang).unwrap_or(0);
+            if let Some(entry) = entries.get_mut(position) {
+                let language = entry
+                    .entry
+                    .get("language")
+                    .and_then(Value::as_str)
+                    .unwrap_or(&entry.name)
+                    .to_string();
+                let mut inputs: Vec<(String, Vec<u8>)> = Vec::new();
+                if let Some(corpus_dir) = corpus::find_corpus(&grammar_dir, Path::new(&repo_dir)) {
+                    match corpus::corpus_inputs(&corpus_dir) {
+                        Ok(tests) => inputs.extend(
+                            tests
+                                .into_iter()
+                                .map(|(name, source)| (name, source.into_bytes())),
+                        ),
+                        Err(e) => log::warn!("failed to read the corpus of {} : {}", lang, e),
+                    }
+                }
+                if let Some(test_dir) = &build_options.test_files {
+                    for file in test_files_for(test_dir, &entry.name) {
+                        inputs.push((file.to_string_lossy().to_string(), fs::read(&file)?));
+                    }
+                }
+                let report = diff_dir.join(format!("{}.diff", entry.name));
+                match regression::diff_trees(
+                    &previous_build,
+                    Path::new(&output_path),
+                    &language,
+                    &inputs,
+                    &report,
+                ) {
+                    Ok(diff) => {
+                        if !diff.changed.is_empty() {
+                            log::warn!(
+                                "{} of {} trees of {} changed, see {}",
+                                diff.changed.len(),
+                                diff.compared,
+                                lang,
+                                report.display()
+                            );
+                        }
+                        entry.tree_diff = Some(diff);
+                    }
+                    Err(e) => log::warn!("failed to compare trees for {} : {}", lang, e),
+                }
+            }
+        } else {
+            log::info!("no previous build of {} to compare trees with", lang);
+        }
+    }
+    status(format!("Built grammar for {}", lang));
+    Ok(entries)
+}
+
+// Build the grammar with the configured builder. The compiler runs inside the
+// checkout with relative paths so no absolute paths from this machine end up
+// in the artifact.
+fn compile_grammar(
+    lang: &str,
+    grammar: &GrammarSources,
+    flags: &BuildFlags,
+    output_path: &str,
+    build_options: &BuildOptions,
+) -> Result<(), CompileError> {
+    let repo_path = Path::new(&grammar.repo_dir);
+    let relative = |p: &Path| -> String {
+        p.strip_prefix(repo_path)
+            .unwrap_or(p)
+            .to_string_lossy()
+            .to_string()
+    };
+    let absolute_output = std::env::current_dir()?.join(output_path);
+    let absolute_repo = fs::canonicalize(repo_path)?;
+
+    let mut gcc_args: Vec<String> = vec![
+        "-shared".to_string(),
+        "-fPIC".to_string(),
+        format!("-ffile-prefix-map={}=.", absolute_repo.display()),
+        format!("-fdebug-prefix-map={}=.", absolute_repo.display()),
+        "-o".to_string(),
+        absolute_output.to_string_lossy().to_string(),
+    ];
+    if build_options.split_debug {
+        gcc_args.push("-g".to_string());
+    }
+
+    for dir in &grammar.include_dirs {
+        gcc_args.push(format!("-I{}", relative(dir)));
+    }
+    gcc_args.extend(flags.cflags.iter().cloned());
+    let sanitizer_flags = build_options
+        .sanitize
+        .as_deref()
+        .map(fuzz::sanitizer_flags)
+        .unwrap_or_default();
+    gcc_args.extend(sanitizer_flags.iter().cloned());
+
+    let mut export_ldflags = Vec::new();
+    if build_options.hide_symbols {
+        let parser_c = grammar
+            .sources
+            .iter()
+            .find(|s| Path::new(s).file_name().is_some_and(|n| n == "parser.c"))
+            .ok_or_else(|| CompileError::ExportList("no parser.c to find them in".to_string()))?;
+        let (cflags, ldflags) = symbols::export_only_entry_points(repo_path, Path::new(parser_c))
+            .map_err(|e| CompileError::ExportList(e.to_string()))?;
+        gcc_args.extend(cflags);
+        export_ldflags = ldflags;
+    }
+
+    let mut relative_sources: Vec<String> = grammar
+        .sources
+        .iter()
+        .map(|s| relative(Path::new(s)))
+        .collect();
+    relative_sources.sort();
+    gcc_args.extend(relative_sources);
+
+    // C++ scanners need the C++ runtime linked in
+    if grammar.sources.iter().any(|s| is_cpp_source(Path::new(s))) {
+        gcc_args.push("-lstdc++".to_string());
+    }
+    gcc_args.extend(flags.ldflags.iter().cloned());
+    gcc_args.extend(export_ldflags);
+    // the sanitizer runtimes have to be linked in as well
+    gcc_args.extend(sanitizer_flags);
+
+    // pin __DATE__/__TIME__ to SOURCE_DATE_EPOCH, or the commit time of the checkout
+    let mut env = Vec::new();
+    if let Some(epoch) = source_date_epoch(&grammar.repo_dir) {
+        env.push(("SOURCE_DATE_EPOCH".to_string(), epoch));
+    }
+
+    build_options.builder.compile(&CompileJob {
+        lang,
+        repo_dir: &absolute_repo,
+        grammar_dir: &grammar.grammar_dir,
+        output: &absolute_output,
+        args: &gcc_args,
+        env: &env,
+        sandbox: build_options.sandbox,
+        limits: &build_options.limits,
+        cancel: &build_options.cancel,
+    })
+}
+
+fn source_date_epoch(repo_dir: &str) -> Option<String> {
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        return Some(epoch);
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .arg("log")
+        .arg("-1")
+        .arg("--format=%ct")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let epoch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!epoch.is_empty()).then_some(epoch)
+}
+
+// the ABI a generated parser targets, from `#define LANGUAGE_VERSION 14`
+fn read_abi_version(parser_c_path: &str) -> Option<u32> {
+    let content = fs::read_to_string(parser_c_path).ok()?;
+    content.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("#define LANGUAGE_VERSION")
+            .and_then(|v| v.trim().parse().ok())
+    })
+}
+
+// oldest grammar ABI any current tree-sitter runtime still loads
+const MIN_COMPATIBLE_ABI: u32 = 13;
+
+// whether a runtime built for ABI `runtime_abi` can load a grammar generated for `abi`
+fn check_runtime_abi(abi: u32, runtime_abi: u32) -> Result<(), String> {
+    if abi > runtime_abi {
+        Err(format!(
+            "was generated for ABI {}, newer than the runtime's {}",
+            abi, runtime_abi
+        ))
+    } else if abi < MIN_COMPATIBLE_ABI.min(runtime_abi) {
+        Err(format!(
+            "was generated for ABI {}, older than the runtime supports",
+            abi
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn git_head_commit(repo_dir: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn git_origin(repo_dir: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["-C", repo_dir, "remote", "get-url", "origin"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// the newest tag reachable from HEAD, None for a repo without tags
+fn git_latest_tag(repo_dir: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["-C", repo_dir, "describe", "--tags", "--abbrev=0", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string()).filter(|t| !t.is_empty())
+}
+
+// The commit the default branch of `repo_url` is at, asked without cloning.
+// None when the remote can't be reached.
+pub async fn remote_head_commit(repo_url: &str) -> Option<String> {
+    if repo_url.starts_with('-') {
+        return None;
+    }
+    let output = tokio::process::Command::new("git")
+        .args(["ls-remote", repo_url, "HEAD"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+}
+
+// ISO8601 build time, SOURCE_DATE_EPOCH wins so reproducible runs write the same config
+pub fn build_timestamp() -> String {
+    build_time().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+pub fn build_time() -> chrono::DateTime<chrono::Utc> {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now)
+}
+
+// read the `external-files` entries of every grammar in tree-sitter.json, if present
+fn read_external_files(repo_dir: &str) -> Vec<String> {
+    let json_path = Path::new(repo_dir).join("tree-sitter.json");
+    let tree_sitter_json: Value = match fs::read_to_string(json_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+    {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+
+    let mut files = Vec::new();
+    if let Some(grammars) = tree_sitter_json.get("grammars").and_then(Value::as_array) {
+        for grammar in grammars {
+            if let Some(external) = grammar.get("external-files").and_then(Value::as_array) {
+                for file in external.iter().filter_map(Value::as_str) {
+                    if !files.iter().any(|f| f == file) {
+                        files.push(file.to_string());
+                    }
+                }
+            }
+        }
+    }
+    files
+}
+
+fn is_c_source(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("c"))
+}
+
+// `path` inside the checkout, for messages
+fn relative_to(path: &str, repo_dir: &str) -> String {
+    Path::new(path)
+        .strip_prefix(repo_dir)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+fn is_cpp_source(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("cc") | Some("cpp") | Some("cxx")
+    )
+}
+
+// gather every C/C++ source in the grammar's src dir plus any C/C++ external files,
+// leaving out files that other sources pull in via `#include "foo.c"`
+fn collect_sources(
+    src_dir: &Path,
+    repo_dir: &str,
+    external_files: &[String],
+) -> std::io::Result<Vec<String>> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(src_dir)? {
+        let path = entry?.path();
+        if path.is_file() && (is_c_source(&path) || is_cpp_source(&path)) {
+            candidates.push(path);
+        }
+    }
+    for file in external_files {
+        let path = Path::new(repo_dir).join(file);
+        if path.is_file()
+            && (is_c_source(&path) || is_cpp_source(&path))
+            && !candidates.contains(&path)
+        {
+            candidates.push(path);
+        }
+    }
+
+    let mut included: HashSet<String> = HashSet::new();
+    for path in &candidates {
+        if let Ok(content) = fs::read_to_string(path) {
+            for line in content.lines() {
+                let line = line.trim_start();
+                if let Some(rest) = line.strip_prefix("#include") {
+                    let name = rest
+                        .trim()
+                        .trim_matches(|c| c == '"' || c == '<' || c == '>');
+                    if let Some(file_name) = Path::new(name).file_name() {
+                        included.insert(file_name.to_string_lossy().to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut sources: Vec<String> = candidates
+        .into_iter()
+        .filter(|p| {
+            let name = p
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            name == "parser.c" || !included.contains(&name)
+        })
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    sources.sort();
+    Ok(sources)
+}
+
+// directories that never hold the grammar sources we are looking for, and can
+// contain decoy copies (bindings, vendored node packages) or be huge (.git)
+const IGNORED_DIRS: &[&str] = &[".git", "node_modules", "bindings"];
+
+pub fn find_file(dir: &str, filename: &str) -> std::io::Result<String> {
+    find_files(dir, filename)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("File {} not found in {}", filename, dir),
+            )
+        })
+}
+
+// find every file called `filename` under `dir`, ordered so that files directly
+// inside a `src/` directory come first, then shallower paths, then alphabetically
+fn find_files(dir: &str, filename: &str) -> std::io::Result<Vec<String>> {
+    let mut found = Vec::new();
+    walk_for_file(Path::new(dir), filename, &mut found)?;
+
+    found.sort_by_key(|path| {
+        let in_src = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .map(|n| n == "src")
+            .unwrap_or(false);
+        (!in_src, path.components().count(), path.clone())
+    });
+
+    Ok(found
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect())
+}
+
+fn walk_for_file(dir: &Path, filename: &str, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // the entry itself, not what a link points to: a checkout's links can
+        // point anywhere on the machine, or back up into a loop
+        let file_type = entry.file_type()?;
+
+        if file_type.is_file() && path.file_name().unwrap_or_default() == filename {
+            found.push(path);
+        } else if file_type.is_dir() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if IGNORED_DIRS.contains(&name.as_ref()) {
+                continue;
+            }
+            // Recursive search in the subdirectory, one that can't be read
+            // doesn't stop the search
+            if let Err(e) = walk_for_file(&path, filename, found) {
+                log::warn!("skipping {} : {}", path.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// the <lang>.<ext> files in `dir` belonging to `language`
+fn test_files_for(dir: &Path, language: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_stem().is_some_and(|stem| {
+                languages::canonical_name(&stem.to_string_lossy())
+                    == languages::canonical_name(language)
+            })
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn file_search_stays_in_the_checkout() {
+        let root = std::env::temp_dir().join(format!("walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("node_modules/tree-sitter-c/src")).unwrap();
+        fs::write(root.join("src/parser.c"), "").unwrap();
+        fs::write(root.join("node_modules/tree-sitter-c/src/parser.c"), "").unwrap();
+        let outside = std::env::temp_dir().join(format!("walk-outside-{}", std::process::id()));
+        fs::create_dir_all(outside.join("src")).unwrap();
+        fs::write(outside.join("src/parser.c"), "").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("elsewhere")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("src/loop")).unwrap();
+        std::os::unix::fs::symlink(outside.join("src/parser.c"), root.join("parser.c")).unwrap();
+
+        let found = find_files(&root.to_string_lossy(), "parser.c").unwrap();
+        assert_eq!(found, vec![root.join("src/parser.c").to_string_lossy()]);
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn quirks_unless_the_manifest_says_otherwise() {
+        let mut manifest = Manifest::default();
+        assert_eq!(build_flags(&manifest, "python").cflags, vec!["-std=c11"]);
+        assert_eq!(build_flags(&manifest, "json"), BuildFlags::default());
+
+        let flags = BuildFlags {
+            cflags: vec!["-std=gnu99".to_string()],
+            ldflags: Vec::new(),
+        };
+        manifest.languages.insert(
+            "python".to_string(),
+            LanguageOverrides {
+                flags: flags.clone(),
+                ..Default::default()
+            },
+        );
+        // a sample or hooks alone keep the quirks
+        manifest.languages.insert(
+            "haskell".to_string(),
+            LanguageOverrides {
+                sample: Some("main = pure ()".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(build_flags(&manifest, "python"), flags);
+        assert_eq!(build_flags(&manifest, "haskell").cflags, vec!["-std=c11"]);
+    }
+
+    #[test]
+    fn options_hash_follows_the_options() {
+        let options = BuildOptions::default();
+        assert_eq!(options.options_hash("c"), options.options_hash("c"));
+
+        let mut sanitized = options.clone();
+        sanitized.sanitize = Some("address".to_string());
+        assert_ne!(options.options_hash("c"), sanitized.options_hash("c"));
+
+        let mut flagged = options.clone();
+        flagged.manifest.languages.insert(
+            "c".to_string(),
+            LanguageOverrides {
+                flags: BuildFlags {
+                    cflags: vec!["-O3".to_string()],
+                    ldflags: Vec::new(),
+                },
+                ..Default::default()
+            },
+        );
+        assert_ne!(options.options_hash("c"), flagged.options_hash("c"));
+        assert_eq!(options.options_hash("cpp"), flagged.options_hash("cpp"));
+    }
+}
@@ -0,0 +1,98 @@
+use serde_json::Value;
+use std::fs;
+
+use crate::config::{entry_extensions, LanguageArtifact};
+use crate::emit::{EmitContext, Emitter};
+use crate::error::EmitError;
+
+// Render the collected grammars as a Helix languages.toml fragment: one
+// [[language]] block pointing at a [[grammar]] of the same name, whose source
+// is the upstream repository pinned to the commit that was built.
+pub fn render_languages_toml(
+    artifacts: &[LanguageArtifact],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut sorted: Vec<&LanguageArtifact> = artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut languages = Vec::new();
+    let mut grammars = Vec::new();
+    for artifact in sorted {
+        let mut language = toml::Table::new();
+        language.insert("name".into(), artifact.name.clone().into());
+        let scope = artifact
+            .scope
+            .clone()
+            .unwrap_or_else(|| format!("source.{}", artifact.name));
+        language.insert("scope".into(), scope.into());
+        let mut file_types: Vec<toml::Value> = entry_extensions(&artifact.entry)
+            .into_iter()
+            .map(toml::Value::from)
+            .collect();
+        // whole file names are globs to helix
+        for filename in string_list(&artifact.entry, "filenames") {
+            let mut glob = toml::Table::new();
+            glob.insert("glob".into(), filename.into());
+            file_types.push(toml::Value::Table(glob));
+        }
+        language.insert("file-types".into(), file_types.into());
+        let shebangs = string_list(&artifact.entry, "shebangs");
+        if !shebangs.is_empty() {
+            language.insert("shebangs".into(), shebangs.into());
+        }
+        language.insert("roots".into(), toml::Value::Array(Vec::new()));
+        language.insert("grammar".into(), artifact.name.clone().into());
+        languages.push(toml::Value::Table(language));
+
+        let mut source = toml::Table::new();
+        source.insert("git".into(), artifact.repo_url.clone().into());
+        if let Some(commit) = artifact.entry.get("commit").and_then(Value::as_str) {
+            source.insert("rev".into(), commit.into());
+        }
+        let mut grammar = toml::Table::new();
+        grammar.insert("name".into(), artifact.name.clone().into());
+        grammar.insert("source".into(), toml::Value::Table(source));
+        grammars.push(toml::Value::Table(grammar));
+    }
+
+    let mut document = toml::Table::new();
+    document.insert("language".into(), toml::Value::Array(languages));
+    document.insert("grammar".into(), toml::Value::Array(grammars));
+    Ok(toml::to_string_pretty(&document)?)
+}
+
+fn string_list(entry: &Value, key: &str) -> Vec<String> {
+    entry
+        .get(key)
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn write_languages_toml(
+    path: &str,
+    artifacts: &[LanguageArtifact],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    fs::write(path, render_languages_toml(artifacts)?)?;
+    Ok(())
+}
+
+// --emit helix
+pub struct HelixLanguages {
+    pub path: String,
+}
+
+impl Emitter for HelixLanguages {
+    fn destination(&self) -> String {
+        self.path.clone()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        write_languages_toml(&self.path, artifacts).map_err(EmitError::write(&self.path))
+    }
+}
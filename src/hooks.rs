@@ -0,0 +1,152 @@
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use crate::cancel::CancelToken;
+use crate::error::HookError;
+use crate::grammar::Manifest;
+
+// Shell commands run around a grammar's clone and build, from the manifest's
+// top-level `hooks` or a language's own. They run with `sh -c` and get
+// $LANG, $REPO_URL, $REPO_DIR and $OUTPUT_DIR, plus $ARTIFACT in post_build.
+// A hook exiting non-zero fails the grammar.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Hooks {
+    // in the current directory, before the clone; $REPO_DIR doesn't exist yet
+    pub pre_clone: Option<String>,
+    // the rest run inside the checkout
+    pub post_clone: Option<String>,
+    // before parser.c is looked for, e.g. to apply a patch
+    pub pre_build: Option<String>,
+    // once the library is built and checked, before its entry is written
+    pub post_build: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookStage {
+    PreClone,
+    PostClone,
+    PreBuild,
+    PostBuild,
+}
+
+impl fmt::Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            HookStage::PreClone => "pre_clone",
+            HookStage::PostClone => "post_clone",
+            HookStage::PreBuild => "pre_build",
+            HookStage::PostBuild => "post_build",
+        })
+    }
+}
+
+impl Hooks {
+    fn get(&self, stage: HookStage) -> Option<&String> {
+        match stage {
+            HookStage::PreClone => self.pre_clone.as_ref(),
+            HookStage::PostClone => self.post_clone.as_ref(),
+            HookStage::PreBuild => self.pre_build.as_ref(),
+            HookStage::PostBuild => self.post_build.as_ref(),
+        }
+    }
+}
+
+// what a hook is told about the grammar
+pub struct HookEnv<'a> {
+    pub lang: &'a str,
+    pub repo_url: &'a str,
+    pub repo_dir: &'a str,
+    pub output_dir: &'a str,
+    // the built library, post_build only
+    pub artifact: Option<&'a str>,
+    // kills the hook when the run is cancelled
+    pub cancel: &'a CancelToken,
+}
+
+// A language's hook replaces the global one for the same stage, an empty
+// command turns the global one off for that language.
+pub fn hook_for(manifest: &Manifest, lang: &str, stage: HookStage) -> Option<String> {
+    manifest
+        .languages
+        .get(lang)
+        .and_then(|o| o.hooks.get(stage))
+        .or_else(|| manifest.hooks.get(stage))
+        .filter(|command| !command.trim().is_empty())
+        .cloned()
+}
+
+// `sh -c <command>` with the hook environment. $LANG takes the place of the
+// locale variable of the same name for the hook.
+// Paths are made absolute, since most hooks run in the checkout rather than
+// the directory they are relative to.
+pub fn hook_command(command: &str, stage: HookStage, env: &HookEnv) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .env("LANG", env.lang)
+        .env("REPO_URL", env.repo_url)
+        .env("REPO_DIR", absolute(env.repo_dir))
+        .env("OUTPUT_DIR", absolute(env.output_dir))
+        .env("HOOK", stage.to_string());
+    if let Some(artifact) = env.artifact {
+        cmd.env("ARTIFACT", absolute(artifact));
+    }
+    if stage != HookStage::PreClone && Path::new(env.repo_dir).is_dir() {
+        cmd.current_dir(env.repo_dir);
+    }
+    cmd
+}
+
+pub fn run_hook(command: &str, stage: HookStage, env: &HookEnv) -> Result<(), HookError> {
+    let output = env
+        .cancel
+        .output(&mut hook_command(command, stage, env))
+        .map_err(|source| HookError::Spawn { stage, source })?;
+    hook_result(command, stage, env.lang, &output)
+}
+
+// the same for the clone stage, which runs on the async runtime
+pub async fn run_hook_async(
+    command: &str,
+    stage: HookStage,
+    env: &HookEnv<'_>,
+) -> Result<(), HookError> {
+    let output = env
+        .cancel
+        .output_async(&mut tokio::process::Command::from(hook_command(
+            command, stage, env,
+        )))
+        .await
+        .map_err(|source| HookError::Spawn { stage, source })?;
+    hook_result(command, stage, env.lang, &output)
+}
+
+fn hook_result(
+    command: &str,
+    stage: HookStage,
+    lang: &str,
+    output: &Output,
+) -> Result<(), HookError> {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        log::info!("{} hook of {} : {}", stage, lang, stdout.trim());
+    }
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(HookError::Failed {
+        stage,
+        command: command.to_string(),
+        status: output.status.to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    })
+}
+
+fn absolute(path: &str) -> String {
+    std::path::absolute(path)
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
@@ -0,0 +1,410 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{load_config, resolve_entry_path, ConfigFormat, FILE_MAPS};
+use crate::package::{library_mode, set_mode};
+
+// what an install put where, kept in the library directory for the next
+// install and for uninstall
+const MANIFEST: &str = ".installed.json";
+
+// Where `install` puts the libraries, their queries and the config
+pub struct InstallDirs {
+    pub lib: PathBuf,
+    pub queries: PathBuf,
+    pub config: PathBuf,
+}
+
+impl InstallDirs {
+    // ~/.local/lib/parser_scraper plus the XDG data and config directories,
+    // None without HOME
+    pub fn user() -> Option<InstallDirs> {
+        let home = PathBuf::from(std::env::var_os("HOME")?);
+        // the spec says relative values are to be ignored
+        let xdg = |var: &str, fallback: &str| {
+            std::env::var_os(var)
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.join(fallback))
+        };
+        Some(InstallDirs {
+            lib: home.join(".local/lib/parser_scraper"),
+            queries: xdg("XDG_DATA_HOME", ".local/share").join("parser_scraper/queries"),
+            config: xdg("XDG_CONFIG_HOME", ".config").join("parser_scraper"),
+        })
+    }
+
+    // <prefix>/lib, <prefix>/share and <prefix>/etc, like make install
+    pub fn prefix(prefix: &Path) -> io::Result<InstallDirs> {
+        // the installed config points here, so it can't be relative
+        let prefix = std::path::absolute(prefix)?;
+        Ok(InstallDirs {
+            lib: prefix.join("lib/parser_scraper"),
+            queries: prefix.join("share/parser_scraper/queries"),
+            config: prefix.join("etc/parser_scraper"),
+        })
+    }
+
+    fn manifest(&self) -> PathBuf {
+        self.lib.join(MANIFEST)
+    }
+}
+
+pub struct Installed {
+    pub languages: usize,
+    pub files: usize,
+    // left over from an earlier install, e.g. of languages no longer in the config
+    pub removed: usize,
+    pub config: PathBuf,
+}
+
+// Copy (or symlink) the libraries, queries and grammar files of every entry in the config
+// into `dirs`, and write a copy of the config pointing at them. Files an
+// earlier install put there are replaced, anything else only with `force`.
+pub fn install(
+    config_path: &str,
+    format: ConfigFormat,
+    dirs: &InstallDirs,
+    symlink: bool,
+    force: bool,
+) -> Result<Installed, Box<dyn Error>> {
+    let mut config = load_config(config_path, format)?;
+    let previous = read_manifest(&dirs.manifest())?;
+    let entries = match config
+        .get_mut("known_languages")
+        .and_then(Value::as_object_mut)
+    {
+        Some(entries) if !entries.is_empty() => entries,
+        _ => return Err(format!("{} has no languages to install", config_path).into()),
+    };
+
+    let mut installed = BTreeSet::new();
+    // dest -> source of what this install placed, the grammars of one repo
+    // (markdown and markdown_inline, typescript and tsx) share a library
+    let mut placed = BTreeMap::new();
+    let mut languages = 0;
+    for (name, entry) in entries.iter_mut() {
+        let Some(path) = entry.get("path").and_then(Value::as_str) else {
+            continue;
+        };
+        let source = resolve_entry_path(path, config_path);
+        if !source.is_file() {
+            log::warn!("not installing {}, {} is missing", name, source.display());
+            continue;
+        }
+        let dest = dirs.lib.join(source.file_name().unwrap_or_default());
+        if first_placement(&mut placed, &source, &dest)? {
+            place(&source, &dest, symlink, force, &previous)?;
+        }
+        entry["path"] = json!(dest.to_string_lossy());
+        installed.insert(dest);
+
+        // node-types.json and grammar.json go with the queries, the
+        // language's data next to the library
+        for map in FILE_MAPS {
+            let Some(files) = entry.get_mut(*map).and_then(Value::as_object_mut) else {
+                continue;
+            };
+            for file in files.values_mut() {
+                let Some(path) = file.as_str() else {
+                    continue;
+                };
+                let source = resolve_entry_path(path, config_path);
+                let dest = dirs
+                    .queries
+                    .join(name)
+                    .join(source.file_name().unwrap_or_default());
+                place(&source, &dest, symlink, force, &previous)?;
+                *file = json!(dest.to_string_lossy());
+                installed.insert(dest);
+            }
+        }
+        languages += 1;
+    }
+
+    // always a copy, its paths are the installed ones
+    let config_dest = dirs
+        .config
+        .join(Path::new(config_path).file_name().unwrap_or_default());
+    check_overwrite(&config_dest, force, &previous)?;
+    let rendered = format.render(&config)?;
+    write_through_partial(&config_dest, |partial| {
+        fs::write(partial, &rendered)?;
+        set_mode(partial, 0o644)
+    })?;
+    installed.insert(config_dest.clone());
+
+    let stale: Vec<PathBuf> = previous.difference(&installed).cloned().collect();
+    let removed = remove_files(&stale)?;
+    prune(dirs, &stale);
+    write_manifest(&dirs.manifest(), &installed)?;
+
+    Ok(Installed {
+        languages,
+        files: installed.len(),
+        removed,
+        config: config_dest,
+    })
+}
+
+// Remove everything the last install into `dirs` put there, and the
+// directories it leaves empty. Returns how many files went.
+pub fn uninstall(dirs: &InstallDirs) -> Result<usize, Box<dyn Error>> {
+    let manifest = dirs.manifest();
+    let files: Vec<PathBuf> = read_manifest(&manifest)?.into_iter().collect();
+    let removed = remove_files(&files)?;
+    if manifest.exists() {
+        fs::remove_file(&manifest).map_err(|e| io_error(&manifest, e))?;
+    }
+    prune(dirs, &files);
+    Ok(removed)
+}
+
+// Whether `dest` is still to be placed by this install. Placed already from
+// the same source it's done, from another one the two would overwrite each other.
+fn first_placement(
+    placed: &mut BTreeMap<PathBuf, PathBuf>,
+    source: &Path,
+    dest: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    let source = fs::canonicalize(source).map_err(|e| io_error(source, e))?;
+    match placed.get(dest) {
+        Some(earlier) if *earlier == source => Ok(false),
+        Some(earlier) => Err(format!(
+            "both {} and {} would be installed as {}",
+            earlier.display(),
+            source.display(),
+            dest.display()
+        )
+        .into()),
+        None => {
+            placed.insert(dest.to_path_buf(), source);
+            Ok(true)
+        }
+    }
+}
+
+// A file that is there and wasn't installed by us belongs to someone else
+fn check_overwrite(
+    dest: &Path,
+    force: bool,
+    previous: &BTreeSet<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    // symlink_metadata, a dangling link is still in the way
+    if !force && !previous.contains(dest) && fs::symlink_metadata(dest).is_ok() {
+        return Err(format!(
+            "{} already exists and wasn't installed by parser_scraper, pass --force to replace it",
+            dest.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn place(
+    source: &Path,
+    dest: &Path,
+    symlink: bool,
+    force: bool,
+    previous: &BTreeSet<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    check_overwrite(dest, force, previous)?;
+    if symlink {
+        let target = fs::canonicalize(source).map_err(|e| io_error(source, e))?;
+        write_through_partial(dest, |partial| {
+            #[cfg(unix)]
+            return std::os::unix::fs::symlink(&target, partial);
+            #[cfg(windows)]
+            return std::os::windows::fs::symlink_file(&target, partial);
+        })
+    } else {
+        write_through_partial(dest, |partial| {
+            fs::copy(source, partial)?;
+            set_mode(partial, library_mode(&dest.to_string_lossy()))
+        })
+    }
+}
+
+// Write `dest` as <dest>.partial and rename it over the old one, so a
+// program that has the old library loaded keeps its mapping instead of
+// having the file rewritten under it
+fn write_through_partial(
+    dest: &Path,
+    write: impl FnOnce(&Path) -> io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|e| io_error(parent, e))?;
+    }
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let _ = fs::remove_file(&partial);
+    write(&partial).map_err(|e| io_error(dest, e))?;
+    fs::rename(&partial, dest).map_err(|e| io_error(dest, e))?;
+    Ok(())
+}
+
+fn remove_files(files: &[PathBuf]) -> Result<usize, Box<dyn Error>> {
+    let mut removed = 0;
+    for file in files {
+        match fs::remove_file(file) {
+            Ok(()) => removed += 1,
+            // removed by hand since, that's fine
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error(file, e)),
+        }
+    }
+    Ok(removed)
+}
+
+// Drop the directories left empty by removing `files`, deepest first.
+// remove_dir refuses anything with files in it, whoever they belong to.
+fn prune(dirs: &InstallDirs, files: &[PathBuf]) {
+    let mut candidates: BTreeSet<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.parent().map(Path::to_path_buf))
+        .collect();
+    candidates.extend([dirs.lib.clone(), dirs.queries.clone(), dirs.config.clone()]);
+    // share/parser_scraper, above the queries
+    candidates.extend(dirs.queries.parent().map(Path::to_path_buf));
+    let mut candidates: Vec<PathBuf> = candidates.into_iter().collect();
+    candidates.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in candidates {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(e) => return Err(io_error(path, e)),
+    };
+    let manifest: Value =
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(manifest
+        .get("files")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn write_manifest(path: &Path, files: &BTreeSet<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let files: Vec<_> = files.iter().map(|file| file.to_string_lossy()).collect();
+    let rendered = serde_json::to_string_pretty(&json!({ "files": files }))?;
+    write_through_partial(path, |partial| fs::write(partial, rendered))
+}
+
+// a permission problem most likely means a system prefix without sudo
+fn io_error(path: &Path, e: io::Error) -> Box<dyn Error> {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        format!(
+            "{}: permission denied, pick a --prefix you can write to",
+            path.display()
+        )
+        .into()
+    } else {
+        format!("{}: {}", path.display(), e).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("install-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("lib")).unwrap();
+        root
+    }
+
+    fn write_config(root: &Path, entries: Value) -> String {
+        let config = root.join("config.json");
+        fs::write(&config, json!({ "known_languages": entries }).to_string()).unwrap();
+        config.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn installs_a_library_shared_by_two_grammars() {
+        let root = scratch("shared");
+        let library = root.join("lib/markdown.so");
+        fs::write(&library, b"a library").unwrap();
+        let config = write_config(
+            &root,
+            json!({
+                "markdown": { "path": library },
+                "markdown_inline": { "path": library },
+            }),
+        );
+        let dirs = InstallDirs::prefix(&root.join("p")).unwrap();
+
+        let installed = install(&config, ConfigFormat::Json, &dirs, false, false).unwrap();
+        assert_eq!(installed.languages, 2);
+        let dest = dirs.lib.join("markdown.so");
+        assert!(dest.is_file());
+        let written = load_config(&installed.config.to_string_lossy(), ConfigFormat::Json).unwrap();
+        for name in ["markdown", "markdown_inline"] {
+            assert_eq!(
+                written["known_languages"][name]["path"],
+                json!(dest.to_string_lossy())
+            );
+        }
+
+        // installing again replaces what the first install put there
+        install(&config, ConfigFormat::Json, &dirs, false, false).unwrap();
+        assert_eq!(uninstall(&dirs).unwrap(), 2);
+        assert!(!dest.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refuses_two_libraries_of_one_name() {
+        let root = scratch("clash");
+        fs::create_dir_all(root.join("other")).unwrap();
+        fs::write(root.join("lib/json.so"), b"one").unwrap();
+        fs::write(root.join("other/json.so"), b"another").unwrap();
+        let config = write_config(
+            &root,
+            json!({
+                "json": { "path": root.join("lib/json.so") },
+                "json5": { "path": root.join("other/json.so") },
+            }),
+        );
+        let dirs = InstallDirs::prefix(&root.join("p")).unwrap();
+        match install(&config, ConfigFormat::Json, &dirs, false, false) {
+            Err(e) => assert!(e.to_string().contains("would be installed as"), "{}", e),
+            Ok(_) => panic!("installed two libraries as one"),
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn leaves_files_it_did_not_install() {
+        let root = scratch("foreign");
+        fs::write(root.join("lib/json.so"), b"a library").unwrap();
+        let config = write_config(
+            &root,
+            json!({ "json": { "path": root.join("lib/json.so") } }),
+        );
+        let dirs = InstallDirs::prefix(&root.join("p")).unwrap();
+        fs::create_dir_all(&dirs.lib).unwrap();
+        fs::write(dirs.lib.join("json.so"), b"someone else's").unwrap();
+
+        assert!(install(&config, ConfigFormat::Json, &dirs, false, false).is_err());
+        assert_eq!(
+            fs::read(dirs.lib.join("json.so")).unwrap(),
+            b"someone else's"
+        );
+        install(&config, ConfigFormat::Json, &dirs, false, true).unwrap();
+        assert_eq!(fs::read(dirs.lib.join("json.so")).unwrap(), b"a library");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
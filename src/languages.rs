@@ -0,0 +1,364 @@
+// Built-in knowledge about common languages, used when a grammar repo
+// doesn't describe itself.
+
+use std::collections::BTreeMap;
+
+// usual file extensions per (normalized) language name
+const EXTENSIONS: &[(&str, &[&str])] = &[
+    ("bash", &["sh", "bash"]),
+    ("c", &["c", "h"]),
+    ("c_sharp", &["cs"]),
+    ("clojure", &["clj", "cljs", "cljc", "edn"]),
+    ("cmake", &["cmake"]),
+    ("cpp", &["cc", "cpp", "cxx", "hpp", "hxx", "hh"]),
+    ("css", &["css"]),
+    ("dart", &["dart"]),
+    ("dockerfile", &["dockerfile"]),
+    ("elixir", &["ex", "exs"]),
+    ("elm", &["elm"]),
+    ("erlang", &["erl", "hrl"]),
+    ("fish", &["fish"]),
+    ("go", &["go"]),
+    ("graphql", &["graphql", "gql"]),
+    ("haskell", &["hs"]),
+    ("hcl", &["hcl", "tf"]),
+    ("html", &["html", "htm"]),
+    ("java", &["java"]),
+    ("javascript", &["js", "mjs", "cjs", "jsx"]),
+    ("json", &["json"]),
+    ("julia", &["jl"]),
+    ("kotlin", &["kt", "kts"]),
+    ("latex", &["tex"]),
+    ("lua", &["lua"]),
+    ("make", &["mk"]),
+    ("markdown", &["md", "markdown"]),
+    ("nix", &["nix"]),
+    ("objc", &["m"]),
+    ("ocaml", &["ml", "mli"]),
+    ("perl", &["pl", "pm"]),
+    ("php", &["php"]),
+    ("python", &["py", "pyi"]),
+    ("r", &["r"]),
+    ("ruby", &["rb"]),
+    ("rust", &["rs"]),
+    ("scala", &["scala", "sc"]),
+    ("scss", &["scss"]),
+    ("sql", &["sql"]),
+    ("swift", &["swift"]),
+    ("toml", &["toml"]),
+    ("tsx", &["tsx"]),
+    ("typescript", &["ts", "mts", "cts"]),
+    ("vim", &["vim"]),
+    ("vue", &["vue"]),
+    ("yaml", &["yaml", "yml"]),
+    ("zig", &["zig"]),
+];
+
+// whole file names that identify a language regardless of extension
+const FILENAMES: &[(&str, &[&str])] = &[
+    (
+        "bash",
+        &[".bashrc", ".bash_profile", ".profile", "PKGBUILD"],
+    ),
+    ("cmake", &["CMakeLists.txt"]),
+    ("dockerfile", &["Dockerfile", "Containerfile"]),
+    ("git_config", &[".gitconfig", ".gitmodules"]),
+    ("make", &["Makefile", "GNUmakefile", "makefile"]),
+    ("nix", &["flake.lock"]),
+    ("python", &["SConstruct", "SConscript"]),
+    ("ruby", &["Gemfile", "Rakefile", "Vagrantfile"]),
+    ("toml", &["Cargo.lock", "Pipfile"]),
+];
+
+// interpreters named on a `#!` line, as in `#!/usr/bin/env python3`
+const SHEBANGS: &[(&str, &[&str])] = &[
+    ("bash", &["bash", "sh", "zsh", "dash", "ksh"]),
+    ("elixir", &["elixir"]),
+    ("fish", &["fish"]),
+    ("javascript", &["node", "nodejs", "deno"]),
+    ("julia", &["julia"]),
+    ("lua", &["lua", "luajit"]),
+    ("perl", &["perl"]),
+    ("php", &["php"]),
+    ("python", &["python", "python2", "python3"]),
+    ("r", &["Rscript"]),
+    ("ruby", &["ruby"]),
+    ("typescript", &["ts-node", "tsx"]),
+];
+
+// tiny valid programs for the post-build smoke test
+const SAMPLES: &[(&str, &str)] = &[
+    ("bash", "echo hello\n"),
+    ("c", "int main(void) { return 0; }\n"),
+    ("c_sharp", "class A { }\n"),
+    ("cpp", "int main() { return 0; }\n"),
+    ("css", "a { color: red; }\n"),
+    ("go", "package main\n"),
+    ("haskell", "main = pure ()\n"),
+    ("html", "<p>hello</p>\n"),
+    ("java", "class A { }\n"),
+    ("javascript", "let a = 1;\n"),
+    ("json", "{\"a\": [1, 2]}\n"),
+    ("lua", "local a = 1\n"),
+    ("markdown", "# hello\n"),
+    ("python", "a = 1\n"),
+    ("ruby", "a = 1\n"),
+    ("rust", "fn main() {}\n"),
+    ("toml", "a = 1\n"),
+    ("typescript", "let a: number = 1;\n"),
+    ("yaml", "a: 1\n"),
+];
+
+// other names people use for a language: (canonical name, aliases)
+const ALIASES: &[(&str, &[&str])] = &[
+    ("bash", &["shell", "sh", "zsh"]),
+    ("c_sharp", &["csharp", "cs", "c#"]),
+    ("commonlisp", &["common_lisp", "lisp", "cl"]),
+    ("cpp", &["c++", "cxx", "cplusplus"]),
+    ("dockerfile", &["docker"]),
+    ("elixir", &["ex"]),
+    ("erlang", &["erl"]),
+    ("go", &["golang"]),
+    ("haskell", &["hs"]),
+    ("javascript", &["js", "node"]),
+    ("kotlin", &["kt"]),
+    ("latex", &["tex"]),
+    ("make", &["makefile"]),
+    ("markdown", &["md"]),
+    ("objc", &["objective_c", "objective-c", "objectivec"]),
+    ("ocaml", &["ml"]),
+    ("python", &["py", "python3"]),
+    ("ruby", &["rb"]),
+    ("rust", &["rs"]),
+    ("typescript", &["ts"]),
+    ("vim", &["vimscript", "viml"]),
+    ("yaml", &["yml"]),
+];
+
+// --preset bundles, the manifest's `presets` add to and replace these
+pub const PRESETS: &[(&str, &[&str])] = &[
+    (
+        "web",
+        &["html", "css", "javascript", "typescript", "tsx", "json"],
+    ),
+    ("systems", &["c", "cpp", "rust", "go", "zig"]),
+    ("data", &["json", "yaml", "toml", "xml", "csv"]),
+    ("scripting", &["python", "ruby", "lua", "bash", "perl"]),
+];
+
+// who keeps a contested extension when no priority is configured, earlier wins
+pub const DEFAULT_EXTENSION_PRIORITY: &[&str] = &[
+    "c",
+    "cpp",
+    "objc",
+    "javascript",
+    "typescript",
+    "python",
+    "perl",
+    "matlab",
+];
+
+// Turn a scraped or repo-derived name into the identifier form grammars use:
+// lowercase, no `tree-sitter-` prefix, separators as underscores.
+pub fn normalize_name(name: &str) -> String {
+    let lower = name.trim().to_lowercase();
+    let stripped = lower.strip_prefix("tree-sitter-").unwrap_or(&lower);
+    stripped
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>()
+        .trim_matches('_')
+        .to_string()
+}
+
+pub fn known_extensions(name: &str) -> Vec<String> {
+    lookup(EXTENSIONS, name)
+}
+
+// built-in smoke test sample, empty when there is none
+pub fn sample_for(name: &str) -> &'static str {
+    let canonical = canonical_name(name);
+    SAMPLES
+        .iter()
+        .find(|(lang, _)| *lang == canonical)
+        .map(|(_, sample)| *sample)
+        .unwrap_or("")
+}
+
+pub fn known_filenames(name: &str) -> Vec<String> {
+    lookup(FILENAMES, &canonical_name(name))
+}
+
+pub fn known_shebangs(name: &str) -> Vec<String> {
+    lookup(SHEBANGS, &canonical_name(name))
+}
+
+fn lookup(table: &[(&str, &[&str])], name: &str) -> Vec<String> {
+    table
+        .iter()
+        .find(|(lang, _)| *lang == name)
+        .map(|(_, values)| values.iter().map(|v| v.to_string()).collect())
+        .unwrap_or_default()
+}
+
+// tree-sitter.json's file-types mixes extensions with whole file names; the
+// latter are capitalized (Makefile) or contain a dot (CMakeLists.txt, .bashrc)
+pub fn is_filename(file_type: &str) -> bool {
+    file_type.contains('.') || file_type.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+// Resolve any common name of a language to its canonical grammar name.
+// Names containing punctuation (c++, c#) are looked up before normalizing,
+// since normalizing alone would collapse them onto other languages.
+pub fn canonical_name(name: &str) -> String {
+    let lower = name.trim().to_lowercase();
+    let normalized = normalize_name(name);
+    for candidate in [&lower, &normalized] {
+        if let Some((canonical, _)) = ALIASES.iter().find(|(canonical, aliases)| {
+            canonical == candidate || aliases.contains(&candidate.as_str())
+        }) {
+            return canonical.to_string();
+        }
+    }
+    normalized
+}
+
+// longer than any grammar's name, short enough for every file system
+const MAX_NAME_LEN: usize = 64;
+
+// The name a grammar goes by on its own, where canonical_name would fold it
+// into another language: zsh, ml and node are grammars of their own, not
+// bash, ocaml and javascript. Only the aliases spelled with symbols are
+// resolved, c++ and c# would come out as c.
+pub fn own_name(name: &str) -> String {
+    let lower = name.trim().to_lowercase();
+    if lower.contains(['+', '#']) {
+        if let Some((canonical, _)) = ALIASES
+            .iter()
+            .find(|(_, aliases)| aliases.contains(&lower.as_str()))
+        {
+            return canonical.to_string();
+        }
+    }
+    normalize_name(name)
+}
+
+// The name a scraped language goes by in directory and file names, git
+// arguments, config entries and the store: its own_name, ASCII letters,
+// digits and underscores only. The scraped one is for showing.
+pub fn safe_name(name: &str) -> Result<String, String> {
+    let safe = own_name(name);
+    if safe.is_empty() {
+        return Err(format!("{:?} has no letters or digits to name it by", name));
+    }
+    if safe.len() > MAX_NAME_LEN {
+        return Err(format!(
+            "{:?} is longer than {} characters",
+            name, MAX_NAME_LEN
+        ));
+    }
+    Ok(safe)
+}
+
+// the C entry point a grammar's library exports
+pub fn symbol_name(language: &str) -> String {
+    format!("tree_sitter_{}", language.replace('-', "_"))
+}
+
+// symbol_name, for the generated sources it's pasted into: the language comes
+// from upstream's grammar.json, which could name anything
+pub fn checked_symbol_name(language: &str) -> Result<String, String> {
+    let symbol = symbol_name(language);
+    let mut chars = symbol.chars();
+    let identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !identifier {
+        return Err(format!(
+            "the language {:?} doesn't make a C identifier of its entry point {:?}",
+            language, symbol
+        ));
+    }
+    Ok(symbol)
+}
+
+// every other name `name` is known by, including its canonical name
+pub fn aliases_for(name: &str) -> Vec<String> {
+    let canonical = canonical_name(name);
+    let mut aliases: Vec<String> = ALIASES
+        .iter()
+        .find(|(lang, _)| *lang == canonical)
+        .map(|(_, aliases)| aliases.iter().map(|a| a.to_string()).collect())
+        .unwrap_or_default();
+    aliases.insert(0, canonical);
+    aliases.retain(|a| a != name);
+    aliases
+}
+
+// A language selection as --languages-file and `-l -` read it: one name per
+// line, `#` to the end of a line is a comment. Commas and spaces separate
+// names too, so `-l` lists can be piped in as they are.
+pub fn parse_language_list(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// The languages of a preset, the manifest's own before the built-in ones.
+// Err lists the presets there are.
+pub fn preset(name: &str, custom: &BTreeMap<String, Vec<String>>) -> Result<Vec<String>, String> {
+    if let Some(languages) = custom.get(name) {
+        return Ok(languages.clone());
+    }
+    if let Some((_, languages)) = PRESETS.iter().find(|(preset, _)| *preset == name) {
+        return Ok(languages.iter().map(|l| l.to_string()).collect());
+    }
+    let mut known: Vec<&str> = PRESETS.iter().map(|(preset, _)| *preset).collect();
+    known.extend(custom.keys().map(String::as_str));
+    known.sort();
+    known.dedup();
+    Err(format!(
+        "unknown preset '{}', there are {}",
+        name,
+        known.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_names_are_identifiers() {
+        assert_eq!(
+            checked_symbol_name("c_sharp"),
+            Ok("tree_sitter_c_sharp".to_string())
+        );
+        assert_eq!(
+            checked_symbol_name("embedded-template"),
+            Ok("tree_sitter_embedded_template".to_string())
+        );
+        assert!(checked_symbol_name("x(void); system(\"id\")").is_err());
+        assert!(checked_symbol_name("../evil").is_err());
+        assert!(checked_symbol_name("c\n#include").is_err());
+    }
+
+    #[test]
+    fn safe_names() {
+        assert_eq!(safe_name("C++"), Ok("cpp".to_string()));
+        assert_eq!(safe_name("C#"), Ok("c_sharp".to_string()));
+        assert_eq!(safe_name("tree-sitter-Rust"), Ok("rust".to_string()));
+        // other grammars, whatever canonical_name makes of them
+        assert_eq!(safe_name("zsh"), Ok("zsh".to_string()));
+        assert_eq!(safe_name("ml"), Ok("ml".to_string()));
+        assert_eq!(safe_name("node"), Ok("node".to_string()));
+        assert_eq!(canonical_name("zsh"), "bash");
+        assert_eq!(safe_name("../../etc/passwd"), Ok("etc_passwd".to_string()));
+        assert!(safe_name("..").is_err());
+        assert!(safe_name(&"a".repeat(65)).is_err());
+    }
+}
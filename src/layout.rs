@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::checksums;
+
+// how many hex digits of the sha256 go into a --content-hash name
+pub const CONTENT_HASH_LEN: usize = 12;
+
+// How artifacts are named and arranged under the output directory
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    // <output>/<name template>
+    Flat,
+    // nvim runtimepath: <output>/parser/<lang>.so and <output>/queries/<lang>/
+    Nvim,
+    // Emacs 29 treesit: <output>/libtree-sitter-<lang>.so
+    Emacs,
+}
+
+// where the shared object for `lang` goes
+pub fn artifact_path(layout: Layout, output_dir: &str, name_template: &str, lang: &str) -> String {
+    let path = match layout {
+        Layout::Flat => Path::new(output_dir).join(render_artifact_name(name_template, lang)),
+        // nvim expects .so on every platform and no lib prefix
+        Layout::Nvim => Path::new(output_dir)
+            .join("parser")
+            .join(format!("{}.so", lang)),
+        Layout::Emacs => Path::new(output_dir).join(render_artifact_name(
+            "{prefix}tree-sitter-{lang}{ext}",
+            lang,
+        )),
+    };
+    path.to_string_lossy().to_string()
+}
+
+// libjson.so -> libjson-<hash>.so, the hash going before the extension
+pub fn content_hashed_path(library: &Path, hash: &str) -> PathBuf {
+    let hash = &hash[..hash.len().min(CONTENT_HASH_LEN)];
+    let stem = library
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match library.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, hash, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, hash),
+    };
+    library.with_file_name(name)
+}
+
+// The other way around: libjson-<hash>.so -> libjson.so, for a library that
+// got its name by its content
+pub fn stable_path(library: &Path) -> PathBuf {
+    let stem = library
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let Some((name, hash)) = stem.rsplit_once('-') else {
+        return library.to_path_buf();
+    };
+    if hash.len() != CONTENT_HASH_LEN || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return library.to_path_buf();
+    }
+    // a grammar may as well be called tree-sitter-cafe0123beef, the hash has
+    // to be the library's
+    if !checksums::sha256_file(library).is_ok_and(|sha256| sha256.starts_with(hash)) {
+        return library.to_path_buf();
+    }
+    let name = match library.extension() {
+        Some(ext) => format!("{}.{}", name, ext.to_string_lossy()),
+        None => name.to_string(),
+    };
+    library.with_file_name(name)
+}
+
+// --content-hash: move `library` to its content hashed name and leave a
+// symlink to it in its place, for people and tools going by the usual name.
+// A hashed name that already exists has that content, it's kept as it is.
+pub fn name_by_content(library: &Path) -> io::Result<PathBuf> {
+    let hash = checksums::sha256_file(library)?;
+    let hashed = content_hashed_path(library, &hash);
+    if !hashed.is_file() {
+        if library.is_symlink() {
+            fs::copy(library, &hashed)?;
+        } else {
+            fs::rename(library, &hashed)?;
+        }
+    }
+    // a new link renamed over the library, which is never missing
+    let name = hashed.file_name().unwrap_or_default();
+    let mut partial = library.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let _ = fs::remove_file(&partial);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(name, &partial)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(name, &partial)?;
+    fs::rename(&partial, library)?;
+    Ok(hashed)
+}
+
+// where the query files for `lang` go, <output>/queries/<lang>/ for every layout
+pub fn queries_dir(output_dir: &str, lang: &str) -> PathBuf {
+    Path::new(output_dir).join("queries").join(lang)
+}
+
+// where node-types.json and grammar.json of `lang` go, <output>/<lang>/
+pub fn grammar_files_dir(output_dir: &str, lang: &str) -> PathBuf {
+    Path::new(output_dir).join(lang)
+}
+
+// fill in the artifact name template, defaults follow the host platform's
+// shared library conventions
+pub fn render_artifact_name(template: &str, lang: &str) -> String {
+    let (prefix, ext) = if cfg!(target_os = "windows") {
+        ("", ".dll")
+    } else if cfg!(target_os = "macos") {
+        ("lib", ".dylib")
+    } else {
+        ("lib", ".so")
+    };
+    let target = format!("-{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+
+    template
+        .replace("{prefix}", prefix)
+        .replace("{lang}", lang)
+        .replace("{target}", &target)
+        .replace("{ext}", ext)
+}
+
+// Copy the `*.scm` files of a grammar's queries/ directory into `dest`,
+// returning the copied paths keyed by query name (highlights, injections, ...)
+pub fn copy_queries(
+    grammar_dir: &Path,
+    repo_dir: &Path,
+    dest: &Path,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    // multi-grammar repos keep queries next to each grammar, others at the root
+    let source = [grammar_dir.join("queries"), repo_dir.join("queries")]
+        .into_iter()
+        .find(|dir| dir.is_dir());
+    let source = match source {
+        Some(dir) => dir,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    fs::create_dir_all(dest)?;
+    let mut copied = BTreeMap::new();
+    for entry in fs::read_dir(&source)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().map(|e| e == "scm").unwrap_or(false) {
+            let target = dest.join(path.file_name().unwrap_or_default());
+            fs::copy(&path, &target)?;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            copied.insert(name.to_string(), target.to_string_lossy().to_string());
+        }
+    }
+    Ok(copied)
+}
+
+// Copy src/node-types.json and src/grammar.json of a grammar into `dest`,
+// returning the copied paths keyed node_types and grammar. Older grammars only
+// generated node-types.json, what's missing is left out.
+pub fn copy_grammar_files(
+    grammar_dir: &Path,
+    dest: &Path,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let mut copied = BTreeMap::new();
+    for (key, file) in [
+        ("node_types", "node-types.json"),
+        ("grammar", "grammar.json"),
+    ] {
+        let source = grammar_dir.join("src").join(file);
+        if !source.is_file() {
+            continue;
+        }
+        fs::create_dir_all(dest)?;
+        let target = dest.join(file);
+        fs::copy(&source, &target)?;
+        copied.insert(key.to_string(), target.to_string_lossy().to_string());
+    }
+    Ok(copied)
+}
+
+// default directory Emacs searches for grammar libraries
+pub fn emacs_grammar_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".emacs.d").join("tree-sitter"))
+}
+
+// Copy built libraries into an Emacs grammar directory, returning how many were installed
+pub fn install_into(
+    dest: &Path,
+    artifacts: &[String],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    fs::create_dir_all(dest)?;
+    let mut installed = 0;
+    for artifact in artifacts {
+        let source = Path::new(artifact);
+        if let Some(name) = source.file_name() {
+            fs::copy(source, dest.join(name))?;
+            installed += 1;
+        }
+    }
+    Ok(installed)
+}
+
+// Keep up to `keep` earlier versions of a library about to be rebuilt, as
+// <library>.1 (the newest) to <library>.<keep>, dropping the oldest
+pub fn rotate_library(library: &Path, keep: usize) -> std::io::Result<()> {
+    if keep == 0 || !library.is_file() {
+        return Ok(());
+    }
+    let version = |n: usize| {
+        let mut name = library.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    for n in (1..keep).rev() {
+        if version(n).is_file() {
+            fs::rename(version(n), version(n + 1))?;
+        }
+    }
+    // copied rather than moved, a failed build leaves the current one in place
+    fs::copy(library, version(1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hashed_names() {
+        let hash = "0123456789abcdef0123456789abcdef";
+        assert_eq!(
+            content_hashed_path(Path::new("out/libjson.so"), hash),
+            PathBuf::from("out/libjson-0123456789ab.so")
+        );
+        assert_eq!(
+            content_hashed_path(Path::new("out/json"), hash),
+            PathBuf::from("out/json-0123456789ab")
+        );
+    }
+
+    #[test]
+    fn stable_names_need_the_content_hash() {
+        let root = std::env::temp_dir().join(format!("layout-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        let library = root.join("libjson.so");
+        fs::write(&library, b"a library").unwrap();
+        let hash = checksums::sha256_file(&library).unwrap();
+        let hashed = content_hashed_path(&library, &hash);
+        fs::rename(&library, &hashed).unwrap();
+        assert_eq!(stable_path(&hashed), library);
+
+        // shaped like a hash, but not this library's
+        let lookalike = root.join("libtree-sitter-cafe0123beef.so");
+        fs::write(&lookalike, b"another library").unwrap();
+        assert_eq!(stable_path(&lookalike), lookalike);
+
+        assert_eq!(
+            stable_path(Path::new("libc-sharp.so")),
+            PathBuf::from("libc-sharp.so")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+// Scrape the tree-sitter wiki's parser list, build the grammars as shared
+// libraries and write configs for them. The parser_scraper binary is a thin
+// CLI over this.
+
+pub mod audit;
+pub mod bench;
+pub mod builder;
+pub mod cache;
+pub mod cancel;
+pub mod changelog;
+pub mod checksums;
+pub mod cmake;
+pub mod codegen;
+pub mod compress;
+pub mod config;
+pub mod corpus;
+pub mod daemon;
+pub mod debuginfo;
+pub mod disk;
+pub mod emit;
+pub mod error;
+pub mod failure;
+pub mod fuzz;
+pub mod github;
+pub mod grammar;
+pub mod helix;
+pub mod hooks;
+pub mod install;
+pub mod languages;
+pub mod layout;
+pub mod licenses;
+pub mod limits;
+pub mod metrics;
+pub mod npm;
+pub mod package;
+pub mod pipeline;
+pub mod pkgconfig;
+pub mod prebuilt;
+pub mod progress;
+pub mod publish;
+pub mod regression;
+pub mod sandbox;
+pub mod sbom;
+pub mod schedule;
+pub mod scrape;
+pub mod serve;
+pub mod service;
+pub mod signing;
+pub mod skiplist;
+pub mod smoke;
+pub mod sources;
+pub mod store;
+pub mod symbols;
+pub mod trust;
+pub mod validate;
+pub mod versions;
+pub mod watch;
+
+pub use config::{
+    load_config, read_fragments, resolve_extension_collisions, write_config, write_fragments,
+    ConfigFormat, LanguageArtifact, PathStyle,
+};
+pub use emit::{EmitContext, Emitter};
+pub use error::{
+    BuildError, CloneError, CompileError, ConfigError, EmitError, HookError, PipelineError,
+    ScrapeError, StoreError,
+};
+pub use grammar::{
+    build_time, build_timestamp, find_file, load_manifest, BuildFlags, BuildOptions,
+    GrammarBuilder, LanguageOverrides, Manifest,
+};
+pub use pipeline::{Event, Pipeline, PipelineReport};
+pub use progress::ProgressSink;
+pub use scrape::scrape_parsers;
+pub use sources::{
+    GithubSearchSource, ManifestSource, NvimTreesitterSource, ParserEntry, ParserSource, WikiSource,
+};
+pub use store::Store;
@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::LanguageArtifact;
+
+// where the license files for `lang` go
+pub fn licenses_dir(output_dir: &str, lang: &str) -> PathBuf {
+    Path::new(output_dir).join("licenses").join(lang)
+}
+
+fn is_license_file(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    ["LICENSE", "LICENCE", "COPYING", "UNLICENSE", "NOTICE"]
+        .iter()
+        .any(|prefix| upper.starts_with(prefix))
+}
+
+// Copy the license files at the top of the grammar checkout (and of the
+// grammar's own directory in multi-grammar repos) into `dest`
+pub fn copy_licenses(
+    grammar_dir: &Path,
+    repo_dir: &Path,
+    dest: &Path,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut sources = BTreeSet::new();
+    for dir in [repo_dir, grammar_dir] {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.is_file() && is_license_file(&name) {
+                sources.insert(path);
+            }
+        }
+    }
+    if sources.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    fs::create_dir_all(dest)?;
+    let mut copied = Vec::new();
+    for source in sources {
+        let target = dest.join(source.file_name().unwrap_or_default());
+        fs::copy(&source, &target)?;
+        copied.push(target.to_string_lossy().to_string());
+    }
+    Ok(copied)
+}
+
+// Guess the SPDX identifier of a license text from the phrases the common
+// licenses are recognized by. Anything unusual is left for a human.
+pub fn identify(text: &str) -> Option<&'static str> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let has = |phrase: &str| text.contains(phrase);
+
+    if has("Apache License") && has("Version 2.0") {
+        Some("Apache-2.0")
+    } else if has("Mozilla Public License Version 2.0") || has("Mozilla Public License, v. 2.0") {
+        Some("MPL-2.0")
+    } else if has("GNU LESSER GENERAL PUBLIC LICENSE") {
+        if has("Version 3") {
+            Some("LGPL-3.0")
+        } else {
+            Some("LGPL-2.1")
+        }
+    } else if has("GNU GENERAL PUBLIC LICENSE") {
+        if has("Version 3") {
+            Some("GPL-3.0")
+        } else {
+            Some("GPL-2.0")
+        }
+    } else if has("This is free and unencumbered software released into the public domain") {
+        Some("Unlicense")
+    } else if has("CC0 1.0") {
+        Some("CC0-1.0")
+    } else if has("Permission is hereby granted, free of charge") || has("MIT License") {
+        Some("MIT")
+    } else if has("Permission to use, copy, modify, and/or distribute") {
+        Some("ISC")
+    } else if has("Redistribution and use in source and binary forms") {
+        if has("Neither the name") {
+            Some("BSD-3-Clause")
+        } else {
+            Some("BSD-2-Clause")
+        }
+    } else {
+        None
+    }
+}
+
+// the license of a checkout according to its copied license files
+pub fn identify_files(files: &[String]) -> Option<&'static str> {
+    files
+        .iter()
+        .filter_map(|f| fs::read_to_string(f).ok())
+        .find_map(|text| identify(&text))
+}
+
+// Write <output>/NOTICE, attributing every built grammar and reproducing the
+// license texts that were copied for it
+pub fn write_notice(
+    output_dir: &str,
+    artifacts: &[LanguageArtifact],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sorted: Vec<&LanguageArtifact> = artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let rule = "=".repeat(72);
+    let mut notice = String::from(
+        "This bundle contains tree-sitter grammars built from the following upstream\n\
+         projects. Their licenses are reproduced below.\n",
+    );
+    // grammars from one repository share their license files, print them once
+    let mut printed: BTreeMap<&[String], &str> = BTreeMap::new();
+    for artifact in sorted {
+        notice.push_str(&format!("\n{}\n{}\n", rule, artifact.name));
+        let commit = artifact.entry.get("commit").and_then(|c| c.as_str());
+        match commit {
+            Some(commit) => {
+                notice.push_str(&format!("  source:  {} @ {}\n", artifact.repo_url, commit))
+            }
+            None => notice.push_str(&format!("  source:  {}\n", artifact.repo_url)),
+        }
+        notice.push_str(&format!(
+            "  license: {}\n",
+            artifact.license.as_deref().unwrap_or("unknown")
+        ));
+        if artifact.license_files.is_empty() {
+            notice.push_str("  no license file found upstream\n");
+            continue;
+        }
+        if let Some(first) = printed.get(artifact.license_files.as_slice()) {
+            notice.push_str(&format!("  license text as for {}\n", first));
+            continue;
+        }
+        printed.insert(&artifact.license_files, &artifact.name);
+        for file in &artifact.license_files {
+            let relative = Path::new(file)
+                .strip_prefix(output_dir)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| file.clone());
+            notice.push_str(&format!("\n--- {} ---\n\n", relative));
+            let text = fs::read_to_string(file)?;
+            notice.push_str(text.trim_end());
+            notice.push('\n');
+        }
+    }
+
+    fs::write(Path::new(output_dir).join("NOTICE"), notice)?;
+    Ok(())
+}
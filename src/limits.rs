@@ -0,0 +1,259 @@
+use std::fmt;
+use std::process::{Command, Output};
+
+// Per compile task resource limits, applied to the compiler process as rlimits
+// (or the equivalent container flags for the docker builder).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceLimits {
+    pub memory_mb: Option<u64>,
+    pub cpu_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitKind {
+    Memory,
+    CpuTime,
+}
+
+const DOCKER_PIDS_LIMIT: u32 = 256;
+
+// returned instead of the compiler output when a build hit one of the limits
+#[derive(Debug)]
+pub struct ResourceLimitExceeded {
+    pub lang: String,
+    pub kind: LimitKind,
+}
+
+impl fmt::Display for ResourceLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = match self.kind {
+            LimitKind::Memory => "memory",
+            LimitKind::CpuTime => "cpu time",
+        };
+        write!(
+            f,
+            "Resource limit exceeded building {}: {} limit hit",
+            self.lang, limit
+        )
+    }
+}
+
+impl std::error::Error for ResourceLimitExceeded {}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_mb.is_none() && self.cpu_seconds.is_none()
+    }
+
+    // set the limits on a locally spawned process, inherited by gcc's cc1/ld children
+    pub fn apply(&self, cmd: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            let limits = *self;
+            // SAFETY: only async-signal-safe setrlimit calls between fork and exec
+            unsafe {
+                cmd.pre_exec(move || {
+                    if let Some(mb) = limits.memory_mb {
+                        let bytes = (mb * 1024 * 1024) as libc::rlim_t;
+                        set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
+                    }
+                    if let Some(secs) = limits.cpu_seconds {
+                        // soft limit delivers SIGXCPU, the hard one a second later SIGKILL
+                        set_rlimit(
+                            libc::RLIMIT_CPU,
+                            secs as libc::rlim_t,
+                            (secs + 1) as libc::rlim_t,
+                        )?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    // `docker run` arguments enforcing the same limits on the container. No
+    // swap on top of the memory, and a cap on processes whatever the limits,
+    // gcc only needs a few.
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = vec![format!("--pids-limit={}", DOCKER_PIDS_LIMIT)];
+        if let Some(mb) = self.memory_mb {
+            args.push(format!("--memory={}m", mb));
+            args.push(format!("--memory-swap={}m", mb));
+        }
+        if let Some(secs) = self.cpu_seconds {
+            args.push(format!("--ulimit=cpu={}:{}", secs, secs + 1));
+        }
+        args
+    }
+
+    // nsjail arguments setting the same rlimits in the jail, the others left
+    // as they are outside it instead of nsjail's defaults
+    pub fn nsjail_args(&self) -> Vec<String> {
+        if self.is_empty() {
+            return vec!["--disable_rlimits".to_string()];
+        }
+        let mut args = Vec::new();
+        let memory = self.memory_mb.map(|mb| mb.to_string());
+        let cpu = self.cpu_seconds.map(|secs| secs.to_string());
+        for (flag, value) in [
+            ("--rlimit_as", memory),
+            ("--rlimit_cpu", cpu),
+            ("--rlimit_fsize", None),
+            ("--rlimit_nofile", None),
+            ("--rlimit_nproc", None),
+            ("--rlimit_stack", None),
+        ] {
+            args.push(flag.to_string());
+            args.push(value.unwrap_or_else(|| "soft".to_string()));
+        }
+        args
+    }
+
+    // `ulimit` commands setting the same limits in a remote shell, for the ssh builder
+    pub fn ulimit_commands(&self) -> String {
+        let mut commands = String::new();
+        if let Some(mb) = self.memory_mb {
+            commands.push_str(&format!("ulimit -v {} && ", mb * 1024));
+        }
+        if let Some(secs) = self.cpu_seconds {
+            commands.push_str(&format!("ulimit -t {} && ", secs));
+        }
+        commands
+    }
+
+    // decide whether a failed compile was caused by one of the limits
+    pub fn violation(&self, output: &Output) -> Option<LimitKind> {
+        if self.is_empty() || output.status.success() {
+            return None;
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+
+        if self.cpu_seconds.is_some() {
+            // or the exit status a shell makes of it, over ssh
+            let cpu_signal =
+                signal_of(output) == Some(SIGXCPU) || output.status.code() == Some(128 + SIGXCPU);
+            if cpu_signal || stderr.contains("cpu time limit exceeded") {
+                return Some(LimitKind::CpuTime);
+            }
+        }
+        if self.memory_mb.is_some() {
+            // what `docker run` exits with when the container was OOMKilled
+            let oom_killed = output.status.code() == Some(137);
+            if oom_killed
+                || stderr.contains("out of memory")
+                || stderr.contains("virtual memory exhausted")
+                || stderr.contains("cannot allocate memory")
+                // cc1 usually dies on a failed mmap rather than reporting it
+                || stderr.contains("segmentation fault signal terminated program")
+                || stderr.contains("killed signal terminated program")
+            {
+                return Some(LimitKind::Memory);
+            }
+        }
+        // nsjail exits with 128 + the signal of the process it ran
+        let killed =
+            signal_of(output) == Some(SIGKILL) || output.status.code() == Some(128 + SIGKILL);
+        if self.cpu_seconds.is_some() && killed {
+            return Some(LimitKind::CpuTime);
+        }
+        None
+    }
+}
+
+// --nice/--background: lower the CPU priority of this process to `niceness`
+// and with `idle_io` its IO priority to idle, so it only gets the disk when
+// nothing else wants it. Every git, compiler and hook it runs after inherits
+// them. On Linux both are per thread, so this has to happen before any are
+// started.
+pub fn lower_priority(niceness: i32, idle_io: bool) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        // SAFETY: plain syscall on the calling process
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, niceness) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if idle_io {
+            set_idle_io()?;
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (niceness, idle_io);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "lowering the priority isn't supported on this platform",
+        ))
+    }
+}
+
+// what `ionice -c 3` does
+#[cfg(target_os = "linux")]
+fn set_idle_io() -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    // SAFETY: plain syscall on the calling process
+    let set = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    if set != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// the CPU priority is all there is, a lower one gets less IO from most
+// schedulers as well
+#[cfg(all(unix, not(target_os = "linux")))]
+fn set_idle_io() -> std::io::Result<()> {
+    log::info!("no idle IO priority on this platform, only the CPU priority is lowered");
+    Ok(())
+}
+
+#[cfg(unix)]
+const SIGXCPU: i32 = libc::SIGXCPU;
+#[cfg(unix)]
+const SIGKILL: i32 = libc::SIGKILL;
+#[cfg(not(unix))]
+const SIGXCPU: i32 = -1;
+#[cfg(not(unix))]
+const SIGKILL: i32 = -1;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn set_rlimit(resource: Resource, soft: libc::rlim_t, hard: libc::rlim_t) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft,
+        rlim_max: hard,
+    };
+    // SAFETY: plain syscall on a valid, initialized struct
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn signal_of(output: &Output) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    output.status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal_of(_output: &Output) -> Option<i32> {
+    None
+}
@@ -1,39 +1,606 @@
-use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use clap::{Parser, Subcommand};
 use log::LevelFilter;
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
-use reqwest::blocking::Client;
-use scraper::{Html, Selector};
-use serde_json::{json, Map, Value};
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::{fs, thread, time::Duration};
-use threadpool::ThreadPool;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, time::Duration};
+
+use parser_scraper::audit::ScannerAudit;
+use parser_scraper::builder::{self, GrammarCompiler, MinCcVersion};
+use parser_scraper::cache::CachedBuilder;
+use parser_scraper::cancel::CancelToken;
+use parser_scraper::config::{config_changes, extension_overlaps, merged_config, EntryChange};
+use parser_scraper::config::{Fragments, NativeConfig};
+use parser_scraper::install::{self, InstallDirs};
+use parser_scraper::layout::{self, Layout};
+use parser_scraper::limits::{self, ResourceLimits};
+use parser_scraper::metrics::Metrics;
+use parser_scraper::prebuilt::PrebuiltBuilder;
+use parser_scraper::progress::{self, ProgressSink};
+use parser_scraper::sandbox::Sandbox;
+use parser_scraper::sbom::{self, SbomFormat};
+use parser_scraper::service::{BuildQueue, BuildService};
+use parser_scraper::signing::{self, Signer};
+use parser_scraper::trust::TrustPolicy;
+use parser_scraper::{
+    bench, changelog, checksums, cmake, codegen, compress, corpus, daemon, debuginfo, disk,
+    failure, fuzz, github, helix, languages, licenses, package, pipeline, pkgconfig, publish,
+    regression, schedule, serve, skiplist, smoke, sources, store, symbols, validate, versions,
+    watch,
+};
+use parser_scraper::{
+    build_time, build_timestamp, load_config, load_manifest, read_fragments, write_config,
+    BuildError, BuildOptions, ConfigFormat, EmitContext, EmitError, Emitter, LanguageArtifact,
+    ParserSource, PathStyle, Pipeline, PipelineError, Store,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     // config file path
-    #[arg(short, long, default_value = "./shared_libs/")]
+    #[arg(short, long, default_value = "./shared_libs/", global = true)]
     output: String,
 
     #[arg(short, long, default_value = "./shared_libs_src/")]
     source_destination: String,
 
-    #[arg(short, long, default_value = "./config.json")]
+    #[arg(short, long, default_value = "./config.json", global = true)]
     config_destination: String,
 
-    // target dir/file
-    #[arg(short, long, default_value = "10")]
-    threads: usize,
+    // config file format, inferred from the config destination's extension by default
+    #[arg(long, value_enum, global = true)]
+    config_format: Option<ConfigFormat>,
+
+    // where --emit fragments writes one <lang>.json per language, and merge-config reads them
+    #[arg(long, default_value = "./config.d/", global = true)]
+    fragments_dir: String,
+
+    // how library paths are written into the config: absolute or relative-to-config
+    #[arg(long, value_enum)]
+    path_style: Option<PathStyle>,
+
+    // outputs to generate from the built grammars
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "native")]
+    emit: Vec<Emit>,
+
+    // show what changes in the config and ask before writing it
+    #[arg(long, conflicts_with = "daemon")]
+    confirm: bool,
+
+    // where --emit helix writes its languages.toml fragment
+    #[arg(long, default_value = "./languages.toml")]
+    helix_destination: String,
+
+    // where --emit rust writes its table, meant to be include!d
+    #[arg(long, default_value = "./known_languages.rs")]
+    rust_destination: String,
+
+    // where --emit crate generates the wrapper crate, named after the directory
+    #[arg(long, default_value = "./tree-sitter-grammars/")]
+    crate_destination: String,
+
+    #[arg(long, default_value = "./sbom.spdx.json")]
+    spdx_destination: String,
+
+    #[arg(long, default_value = "./sbom.cdx.json")]
+    cyclonedx_destination: String,
+
+    // how many grammars compile at once, one per CPU when not given
+    #[arg(short, long)]
+    threads: Option<usize>,
+
+    // how many clones run at once, independent of the compile threads
+    #[arg(long, default_value_t = pipeline::NETWORK_JOBS)]
+    network_jobs: usize,
+
+    // how many built grammars are smoke tested, corpus tested etc. at once
+    #[arg(long, default_value = "4")]
+    verify_jobs: usize,
 
+    // the languages to build, comma separated. `-` reads them from stdin like --languages-file
     #[arg(short, long, value_delimiter = ',', required = false)]
     languages: Vec<String>,
+
+    // more languages to build, one per line with `#` comments, `-` for stdin
+    #[arg(long, value_name = "PATH")]
+    languages_file: Option<String>,
+
+    // named bundles of languages to build: web, systems, data, scripting or the manifest's presets
+    #[arg(long, value_delimiter = ',')]
+    preset: Vec<String>,
+
+    // the languages of -l and --languages-file, without the presets'
+    #[arg(skip)]
+    requested: Vec<String>,
+
+    // grammars to leave out of every run: a name per line, then optionally until=YYYY-MM-DD and a
+    // reason. it's fine for it not to exist
+    #[arg(long, value_name = "PATH", default_value = skiplist::SKIPLIST)]
+    skiplist: String,
+
+    // build what the skiplist lists too
+    #[arg(long)]
+    no_skiplist: bool,
+
+    // only clone repositories of these owners, e.g. tree-sitter,tree-sitter-grammars. a bare name is
+    // a github.com owner, gitlab.com/someone one elsewhere
+    #[arg(long, value_delimiter = ',')]
+    trusted_orgs: Vec<String>,
+
+    // more trusted owners from a file, separated by commas or whitespace, `#` comments
+    #[arg(long, value_name = "PATH")]
+    trust_policy: Option<String>,
+
+    // build the repositories of other owners too, with a warning
+    #[arg(long)]
+    allow_untrusted: bool,
+
+    // which grammars are built first: requested (those of -l and --languages-file, in that order),
+    // smallest (checkouts of the last runs) or duration (longest builds of the last runs first)
+    #[arg(long, value_enum, default_value = "requested")]
+    order: schedule::Order,
+
+    // where to find parsers: wiki, manifest, nvim-treesitter or github; earlier ones win on duplicates
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "wiki")]
+    source: Vec<SourceKind>,
+
+    // parsers.lua for --source nvim-treesitter, a URL or a local file
+    #[arg(long, default_value = sources::NVIM_TREESITTER_PARSERS_URL)]
+    nvim_registry: String,
+
+    // repository search for --source github, GITHUB_TOKEN is used when set
+    #[arg(long, default_value = sources::GITHUB_SEARCH_QUERY)]
+    github_query: String,
+
+    // artifact file name, supports {prefix}, {lang}, {target} and {ext}
+    #[arg(long, default_value = "{prefix}{lang}{ext}")]
+    name_template: String,
+
+    // arrangement of the output directory: flat, nvim or emacs
+    #[arg(long, value_enum, default_value = "flat")]
+    layout: Layout,
+
+    // don't copy each grammar's query files into <output>/queries/<lang>/
+    #[arg(long)]
+    no_queries: bool,
+
+    // also copy each grammar's src/node-types.json and src/grammar.json into <output>/<lang>/,
+    // for typed AST generators
+    #[arg(long)]
+    copy_node_types: bool,
+
+    // don't copy upstream license files into <output>/licenses/<lang>/ or write <output>/NOTICE
+    #[arg(long)]
+    no_licenses: bool,
+
+    // copy the built libraries into an Emacs grammar dir, ~/.emacs.d/tree-sitter/ by default
+    #[arg(long, num_args = 0..=1, default_missing_value = "")]
+    install_emacs: Option<String>,
+
+    // build every grammar twice and fail if the artifacts differ
+    #[arg(long)]
+    check_reproducible: bool,
+
+    // load every built library and parse a sample with it, failing the language if that doesn't work
+    #[arg(long)]
+    smoke_test: bool,
+
+    // directory of <lang>.<ext> files each built grammar parses, reporting any ERROR nodes
+    #[arg(long)]
+    test_files: Option<String>,
+
+    // when rebuilding over an existing library, parse the corpus and --test-files with the old and
+    // the new build and report which trees changed
+    #[arg(long)]
+    diff_trees: bool,
+
+    // directory for the old and new trees of every changed input, one file per language
+    #[arg(long, default_value = "./tree-diffs/")]
+    tree_diff_destination: String,
+
+    // run each grammar's test/corpus against the built library and report pass/fail counts
+    #[arg(long)]
+    run_corpus: bool,
+
+    // build with -fvisibility=hidden and an export list holding only the entry points, so
+    // scanner helpers can't collide when many grammars are loaded into one process
+    #[arg(long)]
+    hide_symbols: bool,
+
+    // compile with debug info and move it out of the libraries into <output>/debug/<library>.debug,
+    // leaving them stripped with a gnu-debuglink to it
+    #[arg(long)]
+    split_debug: bool,
+
+    // name the libraries lib<lang>-<hash>.so after the sha256 of their content, for immutable
+    // caching, with lib<lang>.so a symlink to the newest build; the config points at the hashed name
+    #[arg(long)]
+    content_hash: bool,
+
+    // build instrumented with these sanitizers, to run the fuzz-harness targets against
+    #[arg(long, num_args = 0..=1, default_missing_value = "address,undefined")]
+    sanitize: Option<String>,
+
+    // highest grammar ABI (TREE_SITTER_LANGUAGE_VERSION) the consuming tree-sitter runtime loads,
+    // grammars outside what it supports fail
+    #[arg(long)]
+    runtime_abi: Option<u32>,
+
+    // only warn about grammars --runtime-abi rejects and keep them in the config
+    #[arg(long, requires = "runtime_abi")]
+    warn_abi_mismatch: bool,
+
+    // toolchain used to compile grammars: gcc, cc, docker[:image], tree-sitter-cli or
+    // ssh:host,... (experimental, gcc on other machines)
+    #[arg(long, default_value = "gcc", value_parser = builder::parse_builder)]
+    builder: Arc<dyn GrammarCompiler>,
+
+    // refuse to start when the builder's C compiler is older than this: 9, 9.4, or per compiler
+    // like gcc:9,clang:14
+    #[arg(long, value_delimiter = ',')]
+    min_cc_version: Vec<MinCcVersion>,
+
+    // download the library a grammar's GitHub release ships for this target, checksum verified,
+    // instead of compiling it. grammars without one are compiled by --builder
+    #[arg(long)]
+    prefer_prebuilt: bool,
+
+    // s3:// or http(s):// store of built libraries keyed by commit, target, compiler and flags.
+    // looked up before compiling, what is compiled is uploaded
+    #[arg(long, value_name = "URL")]
+    build_cache: Option<String>,
+
+    // only download from --build-cache, never upload
+    #[arg(long, requires = "build_cache")]
+    build_cache_read_only: bool,
+
+    // run the compiler isolated from the network and the rest of the filesystem
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "bwrap")]
+    sandbox: Option<Sandbox>,
+
+    // look through the hand-written scanner sources for network calls, running
+    // programs, file writes and inline assembly before compiling them
+    #[arg(long)]
+    scan_scanners: bool,
+
+    // build these grammars although --scan-scanners flagged them, once their
+    // scanners are read
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "LANGUAGES",
+        requires = "scan_scanners"
+    )]
+    confirm_scanner: Vec<String>,
+
+    // per compile task address-space limit in MiB
+    #[arg(long)]
+    max_memory: Option<u64>,
+
+    // per compile task cpu time limit in seconds
+    #[arg(long)]
+    max_cpu_time: Option<u64>,
+
+    // run at a lower CPU priority, this niceness (10 when not given), and so does everything it
+    // starts: git, the compilers, hooks
+    #[arg(long, value_name = "NICENESS", num_args = 0..=1, default_missing_value = "10", value_parser = clap::value_parser!(i32).range(0..=19))]
+    nice: Option<i32>,
+
+    // the lowest CPU priority and idle IO priority, for builds that shouldn't be noticed
+    #[arg(long, conflicts_with = "nice")]
+    background: bool,
+
+    // also record each library's SHA256SUMS hash in its config entry
+    #[arg(long)]
+    embed_checksums: bool,
+
+    // also write every library compressed with zstd next to it, <library>.zst, at this level
+    // (19 when not given, up to 22). needs the zstd command
+    #[arg(long, value_name = "LEVEL", num_args = 0..=1, default_missing_value = "19", value_parser = clap::value_parser!(i32).range(1..=22))]
+    zstd: Option<i32>,
+
+    // list every library with its size at the end of the run, biggest first (--zstd does too)
+    #[arg(long)]
+    sizes: bool,
+
+    // sign SHA256SUMS and every built library with this key (gpg key id, or minisign secret key file)
+    #[arg(long)]
+    sign_key: Option<String>,
+
+    #[arg(long, value_enum, default_value = "gpg")]
+    signer: Signer,
+
+    // only the first file-type in each entry's `extensions`
+    #[arg(long)]
+    single_extension: bool,
+
+    // tool settings (per-language overrides), ignored if missing
+    #[arg(short, long, default_value = "./parser_scraper.json")]
+    manifest: String,
+
+    // SQLite database every run and build is recorded in, read by stats, outdated, --resume and
+    // --incremental
+    #[arg(long, default_value = store::STATE_DB, global = true)]
+    state_db: String,
+
+    // carry on with the last run if it was interrupted, keeping the grammars it had already built
+    #[arg(long)]
+    resume: bool,
+
+    // keep the last build of every grammar whose upstream hasn't moved since, rather than
+    // cloning and building it again
+    #[arg(long)]
+    incremental: bool,
+
+    // write the config and other outputs again from the checkouts in --source-destination and the
+    // libraries already in --output, without scraping, cloning or compiling anything
+    #[arg(long, conflicts_with_all = ["resume", "incremental", "versioned", "daemon"])]
+    only_config: bool,
+
+    // keep this many earlier versions of every rebuilt library, as <library>.1 and up.
+    // 3 with --daemon, 0 otherwise
+    #[arg(long)]
+    keep_previous: Option<usize>,
+
+    // keep every run's outputs in <output>/<timestamp>/ and point <output>/latest, which the config
+    // goes through, at the newest run that went through
+    #[arg(long, global = true)]
+    versioned: bool,
+
+    // how many --versioned runs to keep, the one latest points at always stays
+    #[arg(long, default_value_t = 5)]
+    keep_versions: usize,
+
+    // run again every --interval until killed, rebuilding only the grammars that changed upstream
+    #[arg(long)]
+    daemon: bool,
+
+    // time between --daemon runs, e.g. 30m, 24h or 1d
+    #[arg(long, default_value = "24h", value_parser = daemon::parse_duration)]
+    interval: Duration,
+
+    // up to this much longer between runs, picked at random every time
+    #[arg(long, default_value = "5m", value_parser = daemon::parse_duration)]
+    jitter: Duration,
+
+    // with --daemon, answer GET /health on this address with how the last run went, and
+    // GET /metrics with the Prometheus metrics of every run so far
+    #[arg(long, requires = "daemon")]
+    health_listen: Option<String>,
+
+    // stop at the first grammar that fails, killing the builds in flight and writing nothing
+    #[arg(long)]
+    fail_fast: bool,
+
+    // how much the run's checkouts may take (500M, 20G, ...). over it, the checkouts of grammars that
+    // are through are removed, and the run stops when that isn't enough
+    #[arg(long, value_name = "SIZE", value_parser = disk::parse_size)]
+    max_disk: Option<u64>,
+
+    // give up on a run that takes longer than this, e.g. 30m. Like --fail-fast it writes
+    // nothing, --resume carries on with it
+    #[arg(long, value_parser = daemon::parse_duration)]
+    deadline: Option<Duration>,
+
+    // how to show the progress of the builds: terminal (the default), json (one event per line on
+    // stdout) or none
+    #[arg(long, value_enum)]
+    progress: Option<ProgressOutput>,
+
+    // write Prometheus metrics of the run (of every run so far with --daemon) to this file after
+    // each one, for node_exporter's textfile collector
+    #[arg(long)]
+    metrics_file: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ProgressOutput {
+    #[cfg(feature = "terminal")]
+    Terminal,
+    Json,
+    None,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Assemble the fragments in --fragments-dir into the config file
+    MergeConfig,
+    /// Check the libraries in --output against SHA256SUMS and their signatures
+    Audit {
+        // minisign public key file, needed to check minisign signatures
+        #[arg(long)]
+        verify_key: Option<String>,
+    },
+    /// Bundle --output and the config into a release archive
+    Package {
+        #[arg(long, value_enum, default_value = "tar-gz")]
+        format: package::PackageFormat,
+
+        // archive name without extension, supports {version}, {date} and {target}
+        #[arg(long, default_value = "tree-sitter-grammars-{version}-{target}")]
+        bundle_name: String,
+
+        // filled in for {version}, the build date (YYYYMMDD) when not given
+        #[arg(long)]
+        package_version: Option<String>,
+
+        // directory the archive is written to
+        #[arg(long, default_value = ".")]
+        dest: String,
+
+        // deb/rpm package name, installed to /usr/lib/<name>/
+        #[arg(long, default_value = "tree-sitter-grammars")]
+        package_name: String,
+
+        // deb/rpm maintainer field
+        #[arg(long, default_value = "parser_scraper <root@localhost>")]
+        maintainer: String,
+    },
+    /// Check every entry in the config points at a loadable library for this platform
+    Verify,
+    /// Upload the libraries, queries, SHA256SUMS and the config to S3 or any HTTP server taking PUTs
+    Publish {
+        // s3://bucket/prefix, through the aws CLI and its credentials, or an http(s):// base URL.
+        // PUBLISH_TOKEN is sent to the latter as a bearer token
+        #[arg(long)]
+        to: String,
+    },
+    /// Copy the built libraries, queries and config into ~/.local/lib, the XDG data and config directories, or --prefix
+    Install {
+        // lib/, share/ and etc/ below this instead, like /usr/local
+        #[arg(long)]
+        prefix: Option<String>,
+        // symlinks to the build outputs instead of copies, the config is still a copy
+        #[arg(long)]
+        symlink: bool,
+        // replace files that are in the way even when an earlier install didn't put them there
+        #[arg(long)]
+        force: bool,
+    },
+    /// Point --output's latest back at the previous known-good --versioned run, and put back its config
+    Rollback {
+        // this version instead of the previous one
+        #[arg(long)]
+        to: Option<String>,
+        // only show the known-good versions, newest first
+        #[arg(long)]
+        list: bool,
+    },
+    /// Remove what install put in place
+    Uninstall {
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    /// Write a cargo-fuzz project with a libFuzzer target per grammar in the config
+    FuzzHarness {
+        // directory the project is written to
+        #[arg(long, default_value = "./fuzz")]
+        dest: String,
+    },
+    /// Parse sample files with every built grammar and compare throughput across runs
+    Bench {
+        // sample files, or directories to search for them, matched to grammars by extension
+        #[arg(required = true)]
+        samples: Vec<String>,
+
+        // name of this run in the report, the current time when not given
+        #[arg(long)]
+        label: Option<String>,
+
+        // parses per file, the fastest one counts
+        #[arg(long, default_value_t = 5)]
+        iterations: usize,
+
+        // JSON history of runs, the comparison table is written next to it as .md
+        #[arg(long, default_value = "./bench.json")]
+        report: String,
+    },
+    /// Serve the built grammars and the config over HTTP, for other machines to pull
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        // the config of libraries built for another target, as <os-arch>=<config path>, repeatable.
+        // --config-destination is served as this machine's target
+        #[arg(long = "target")]
+        targets: Vec<String>,
+
+        // requests handled at once
+        #[arg(long, default_value_t = 4)]
+        workers: usize,
+
+        // accept POST /builds, building with the same flags and manifest as a normal run.
+        // anyone who can reach the server can then make it clone and build
+        #[arg(long)]
+        allow_builds: bool,
+    },
+    /// Show how the builds of every language went across runs, from --state-db
+    Stats,
+    /// List the built grammars whose upstream has moved on since their last build
+    Outdated,
+    /// Show how far each grammar of a run got, the newest run by default
+    Jobs {
+        #[arg(long)]
+        run: Option<i64>,
+    },
+    /// Rebuild a local grammar and smoke test it whenever its sources change
+    Watch {
+        // the grammar's directory, with grammar.js and src/
+        dir: String,
+
+        // language name, the directory's name without tree-sitter- by default
+        #[arg(long)]
+        name: Option<String>,
+
+        // how long the sources have to be quiet before rebuilding, in milliseconds
+        #[arg(long, default_value_t = 300)]
+        debounce: u64,
+
+        // run `npm ci` (`npm install` without a lockfile) in the grammar before
+        // generating when grammar.js requires packages that aren't installed
+        #[arg(long)]
+        allow_npm: bool,
+    },
+    /// Inspect a config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Check a config against the schema, its libraries and for conflicting entries
+    Validate {
+        // defaults to --config-destination
+        file: Option<String>,
+    },
+    /// Print the JSON Schema configs are checked against
+    Schema,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum SourceKind {
+    // the tree-sitter wiki's list of parsers
+    Wiki,
+    // the `parsers` of --manifest
+    Manifest,
+    // nvim-treesitter's parsers.lua at --nvim-registry
+    NvimTreesitter,
+    // GitHub repositories matching --github-query
+    Github,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Emit {
+    // the config file at --config-destination
+    Native,
+    // a Helix languages.toml fragment at --helix-destination
+    Helix,
+    // one config entry per language in --fragments-dir
+    Fragments,
+    // a Rust source file with a static table of the built grammars at --rust-destination
+    Rust,
+    // a cargo crate wrapping the built grammars at --crate-destination
+    Crate,
+    // parsers.h and a parsers.c registry next to the libraries in --output
+    C,
+    // a tree-sitter-<lang>.pc per grammar in --output/pkgconfig/, with its header
+    PkgConfig,
+    // TreeSitterGrammarsConfig.cmake with a ts::<lang> target per grammar in --output/cmake/
+    Cmake,
+    // an SPDX 2.3 bill of materials for the run at --spdx-destination
+    Spdx,
+    // a CycloneDX 1.5 bill of materials for the run at --cyclonedx-destination
+    Cyclonedx,
 }
 
 fn main() {
@@ -53,312 +620,1766 @@ fn main() {
     log4rs::init_config(config).unwrap();
 
     // --------------------------------------------------------------------------
-    let url = "https://github.com/tree-sitter/tree-sitter/wiki/List-of-parsers";
-
-    let args = Args::parse();
-    let max_threads = args.threads;
-    let output_dir = Arc::new(Mutex::new(args.output));
-    let source_destination = Arc::new(Mutex::new(args.source_destination));
-    let config_destination = Arc::new(Mutex::new(args.config_destination));
-    let languages = args.languages;
-    let pool = ThreadPool::new(max_threads); // Thread pool with fixed size
-    let target_parsers: HashSet<&str> = languages.iter().map(|s| s.as_str()).collect();
-
-    // Step 1: Scrape the list of parsers
-    let raw_parsers = match scrape_parsers(url) {
-        Ok(rp) => rp,
+    let mut args = Args::parse();
+    // before any threads, they inherit it
+    let priority = match (args.background, args.nice) {
+        (true, _) => Some((19, true)),
+        (false, Some(niceness)) => Some((niceness, false)),
+        (false, None) => None,
+    };
+    if let Some((niceness, idle_io)) = priority {
+        if let Err(e) = limits::lower_priority(niceness, idle_io) {
+            log::warn!("failed to lower the priority : {}", e);
+            eprintln!("Warning: can't lower the priority: {}", e);
+        }
+    }
+    (args.languages, args.requested) = selected_languages(&args);
+    let config_format = args
+        .config_format
+        .unwrap_or_else(|| ConfigFormat::from_path(&args.config_destination));
+    let config_destination = args.config_destination.clone();
+    match &args.command {
+        Some(Commands::Config {
+            action: ConfigCommand::Validate { file },
+        }) => {
+            let path = file.as_deref().unwrap_or(&config_destination);
+            let format = args
+                .config_format
+                .unwrap_or_else(|| ConfigFormat::from_path(path));
+            validate_config_file(path, format);
+            return;
+        }
+        Some(Commands::Config {
+            action: ConfigCommand::Schema,
+        }) => {
+            print!("{}", validate::CONFIG_SCHEMA);
+            return;
+        }
+        Some(Commands::Verify) => {
+            verify_config_file(&config_destination, config_format);
+            return;
+        }
+        Some(Commands::Publish { to }) => {
+            let output = current_output(&args);
+            let published = publish::remote(to).and_then(|remote| {
+                publish::publish(&output, &config_destination, config_format, remote.as_ref())
+                    .map(|published| (remote.describe(), published))
+            });
+            match published {
+                Ok((remote, published)) => println!(
+                    "Uploaded {} objects to {} ({} already there), config at {}/{}",
+                    published.uploaded, remote, published.present, remote, published.config_key
+                ),
+                Err(e) => {
+                    eprintln!("Error publishing: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Install {
+            prefix,
+            symlink,
+            force,
+        }) => {
+            let dirs = install_dirs(prefix.as_deref());
+            match install::install(&config_destination, config_format, &dirs, *symlink, *force) {
+                Ok(installed) => {
+                    println!(
+                        "Installed {} grammars ({} files) into {}, config at {}",
+                        installed.languages,
+                        installed.files,
+                        dirs.lib.display(),
+                        installed.config.display()
+                    );
+                    if installed.removed > 0 {
+                        println!(
+                            "Removed {} files left over from an earlier install",
+                            installed.removed
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error installing: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Rollback { to, list }) => {
+            let store = open_store(&args.state_db);
+            if *list {
+                print_versions(&store, Path::new(&args.output));
+                return;
+            }
+            match versions::rollback(
+                Path::new(&args.output),
+                &store,
+                to.as_deref(),
+                &config_destination,
+            ) {
+                Ok(rollback) => {
+                    println!(
+                        "{} now points at {} of {} (was {})",
+                        versions::LATEST,
+                        rollback.to.name,
+                        rollback.to.promoted_at,
+                        rollback.from.as_deref().unwrap_or("nothing")
+                    );
+                    if rollback.config_restored {
+                        println!("Restored {} as that run wrote it", config_destination);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error rolling back: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Uninstall { prefix }) => {
+            let dirs = install_dirs(prefix.as_deref());
+            match install::uninstall(&dirs) {
+                Ok(0) => println!("Nothing installed in {}", dirs.lib.display()),
+                Ok(removed) => println!("Removed {} installed files", removed),
+                Err(e) => {
+                    eprintln!("Error uninstalling: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Serve {
+            listen,
+            targets,
+            workers,
+            allow_builds,
+        }) => {
+            let mut served = vec![serve::ServedTarget {
+                target: serve::host_target(),
+                config_path: config_destination.clone(),
+                format: config_format,
+            }];
+            for target in targets {
+                let Some((name, path)) = target.split_once('=') else {
+                    eprintln!("Error: --target {} isn't <os-arch>=<config path>", target);
+                    std::process::exit(1);
+                };
+                served.push(serve::ServedTarget {
+                    target: name.to_string(),
+                    config_path: path.to_string(),
+                    format: ConfigFormat::from_path(path),
+                });
+            }
+            let builds = allow_builds.then(|| {
+                let options = build_options(&args);
+                check_compiler(&args, &options);
+                BuildQueue::start(BuildService {
+                    options,
+                    source: Arc::new(parser_sources(&args)),
+                    config_path: config_destination.clone(),
+                    config_format,
+                    path_style: args.path_style,
+                    threads: args.threads.unwrap_or_else(pipeline::default_threads),
+                    network_jobs: args.network_jobs,
+                    verify_jobs: args.verify_jobs,
+                    trust: trust_policy(&args),
+                    store: Some(open_store(&args.state_db)),
+                })
+            });
+            println!("Serving grammars on http://{}", listen);
+            if let Err(e) = serve::serve(listen, served, *workers, builds) {
+                eprintln!("Error serving on {}: {}", listen, e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Stats) => {
+            print_stats(&open_store(&args.state_db), &args.languages);
+            return;
+        }
+        Some(Commands::Outdated) => {
+            print_outdated(
+                &open_store(&args.state_db),
+                &args.languages,
+                args.network_jobs,
+            );
+            return;
+        }
+        Some(Commands::Jobs { run }) => {
+            print_jobs(&open_store(&args.state_db), *run);
+            return;
+        }
+        Some(Commands::Watch {
+            dir,
+            name,
+            debounce,
+            allow_npm,
+        }) => {
+            let mut options = build_options(&args);
+            check_compiler(&args, &options);
+            // the point of watching is seeing the grammar load
+            options.smoke_test = options.sanitize.is_none();
+            let watch = watch::WatchOptions {
+                dir: PathBuf::from(dir),
+                lang: name
+                    .clone()
+                    .unwrap_or_else(|| watch::lang_of_dir(Path::new(dir))),
+                debounce: Duration::from_millis(*debounce),
+                emitters: vec![Box::new(NativeConfig {
+                    path: config_destination.clone(),
+                    format: config_format,
+                    path_style: args.path_style,
+                })],
+                allow_npm: *allow_npm,
+            };
+            if let Err(e) = watch::watch(&watch, options) {
+                eprintln!("Error watching {}: {}", dir, e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Commands::Audit { verify_key }) => {
+            audit(&current_output(&args), verify_key.as_deref());
+            return;
+        }
+        Some(Commands::Package {
+            format,
+            bundle_name,
+            package_version,
+            dest,
+            package_name,
+            maintainer,
+        }) => {
+            let built = build_time();
+            let version = package_version
+                .clone()
+                .unwrap_or_else(|| built.format("%Y%m%d").to_string());
+            let spec = package::PackageSpec {
+                format: *format,
+                name: &package::render_bundle_name(bundle_name, &version, built),
+                package_name,
+                version: &version,
+                maintainer,
+                dest_dir: dest,
+                built,
+            };
+            let output = current_output(&args);
+            match package::write_package(&output, &config_destination, config_format, &spec) {
+                Ok(path) => println!("Wrote {}", path.display()),
+                Err(e) => {
+                    eprintln!("Error packaging {}: {}", output, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::FuzzHarness { dest }) => {
+            let config = match load_config(&config_destination, config_format) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Error reading config: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let wanted: HashSet<String> = args
+                .languages
+                .iter()
+                .map(|l| languages::canonical_name(l))
+                .collect();
+            let languages: serde_json::Map<String, Value> = config
+                .get("known_languages")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter(|(name, _)| {
+                    wanted.is_empty() || wanted.contains(&languages::canonical_name(name))
+                })
+                .map(|(name, entry)| (name.clone(), entry.clone()))
+                .collect();
+            match fuzz::write_fuzz_harness(dest, &languages, &config_destination) {
+                Ok(count) => println!("Wrote {} fuzz targets to {}", count, dest),
+                Err(e) => {
+                    eprintln!("Error writing fuzz harness {}: {}", dest, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Commands::Bench {
+            samples,
+            label,
+            iterations,
+            report,
+        }) => {
+            let config = match load_config(&config_destination, config_format) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Error reading config: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let languages: Vec<String> = args
+                .languages
+                .iter()
+                .map(|l| languages::canonical_name(l))
+                .collect();
+            let created = build_timestamp();
+            let options = bench::BenchOptions {
+                samples,
+                languages: &languages,
+                iterations: *iterations,
+                label: label.as_deref().unwrap_or(&created),
+                created: &created,
+                report,
+            };
+            match bench::run_bench(&config, &config_destination, &options) {
+                Ok(comparison) => print!("{}", comparison),
+                Err(e) => {
+                    eprintln!("Error benchmarking: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+    // refuse configs we can't write back before spending time on builds
+    if let Err(e) = load_config(&config_destination, config_format) {
+        eprintln!("Error reading config: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(Commands::MergeConfig) = args.command {
+        merge_config(&args.fragments_dir, &config_destination, config_format);
+        return;
+    }
+    let mut options = build_options(&args);
+    options.cancel = cancel_on_interrupt();
+    if !matches!(args.progress, Some(ProgressOutput::None)) {
+        print_banner(&args, &options);
+    }
+    check_compiler(&args, &options);
+    if args.daemon {
+        run_daemon(&args, options);
+        return;
+    }
+    let metrics = Arc::new(Metrics::default());
+    if let Err(e) = build(&args, options, &open_store(&args.state_db), &metrics) {
+        match failure::classify_run(&e) {
+            Some(failure) => eprintln!("Error ({}): {}", failure, e),
+            None => eprintln!("Error: {}", e),
+        }
+        std::process::exit(1);
+    }
+}
+
+// --skiplist, read again for every run so the daemon picks up edits. Exits
+// on a list it can't read.
+fn load_skiplist(args: &Args) -> skiplist::Skiplist {
+    if args.no_skiplist {
+        return skiplist::Skiplist::default();
+    }
+    match skiplist::Skiplist::load(&args.skiplist, chrono::Utc::now().date_naive()) {
+        Ok(skiplist) => skiplist,
+        Err(e) => {
+            eprintln!("Error reading the skiplist {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// --trusted-orgs and --trust-policy together, exits on a policy file it can't
+// read
+fn trust_policy(args: &Args) -> TrustPolicy {
+    let mut policy = TrustPolicy::new(&args.trusted_orgs);
+    if let Some(path) = &args.trust_policy {
+        match TrustPolicy::load(path) {
+            Ok(file) => policy.extend(file),
+            Err(e) => {
+                eprintln!("Error reading the trust policy {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    policy.warn_only = args.allow_untrusted;
+    policy
+}
+
+// --min-cc-version, before anything is cloned: one clear error rather than
+// every grammar failing some other way
+fn check_compiler(args: &Args, options: &BuildOptions) {
+    if args.min_cc_version.is_empty() {
+        return;
+    }
+    let version = options.builder.compiler_version();
+    match builder::check_cc_version(version.as_deref(), &args.min_cc_version) {
+        Ok(()) => log::info!(
+            "{} meets --min-cc-version",
+            version.as_deref().unwrap_or("the compiler")
+        ),
         Err(e) => {
-            eprintln!("Error scraping parsers: {}", e);
+            eprintln!("Error with --builder {}: {}", options.builder.describe(), e);
             std::process::exit(1);
         }
+    }
+}
+
+// what the run is going to do at once, on stderr so it stays out of --progress json
+fn print_banner(args: &Args, options: &BuildOptions) {
+    let threads = args.threads.unwrap_or_else(pipeline::default_threads);
+    eprintln!(
+        "parser_scraper {}, compile threads: {}{}, clones: {}, verify jobs: {}, builder: {}",
+        env!("CARGO_PKG_VERSION"),
+        threads,
+        if args.threads.is_none() {
+            " (one per CPU)"
+        } else {
+            ""
+        },
+        args.network_jobs,
+        args.verify_jobs,
+        options.builder.describe()
+    );
+}
+
+// One run over the parsers of the sources: the progress bars, then the outputs
+// and summaries. Only a failed fetch or output ends it with an error, like for
+// the pipeline.
+fn build(
+    args: &Args,
+    mut options: BuildOptions,
+    store: &Arc<Store>,
+    metrics: &Arc<Metrics>,
+) -> Result<daemon::CycleReport, PipelineError> {
+    let config_format = args
+        .config_format
+        .unwrap_or_else(|| ConfigFormat::from_path(&args.config_destination));
+    let config_destination = args.config_destination.clone();
+
+    // --deadline is per run, Ctrl-C ends them all
+    let cancel = options.cancel.child();
+    if let Some(deadline) = args.deadline {
+        cancel.cancel_after(deadline);
+    }
+    // --versioned: everything the run writes goes into a directory of its own
+    let output_root = PathBuf::from(&args.output);
+    let version = if args.versioned {
+        let version = versions::create_version(&output_root).map_err(|e| EmitError::Write {
+            destination: args.output.clone(),
+            source: Box::new(e),
+        })?;
+        options.output_dir = format!("{}/", version.display());
+        Some(version)
+    } else {
+        None
     };
-    let parsers: Vec<(String, String)>;
-    if target_parsers.len() > 0 {
-        parsers = raw_parsers
-            .into_iter()
-            .filter(|(lang, _)| target_parsers.contains(lang.as_str()))
-            .collect();
+    let mut pipeline = Pipeline::builder()
+        .source(parser_sources(args))
+        .languages(&args.languages)
+        .threads(args.threads.unwrap_or_else(pipeline::default_threads))
+        .network_jobs(args.network_jobs)
+        .verify_jobs(args.verify_jobs)
+        .order(args.order)
+        .requested(&args.requested)
+        .skiplist(load_skiplist(args))
+        .trust(trust_policy(args))
+        .options(options)
+        .store(Arc::clone(store))
+        .metrics(Arc::clone(metrics))
+        .cancel(cancel)
+        .fail_fast(args.fail_fast)
+        .resume(args.resume)
+        .incremental(args.incremental || args.daemon)
+        .only_config(args.only_config)
+        .progress(progress_sink(args.progress));
+    if let Some(max_disk) = args.max_disk {
+        pipeline = pipeline.max_disk(max_disk);
+    }
+    let pipeline = pipeline.build();
+    // what the last runs built, for what changed since; nothing does with --only-config
+    let previous = if args.only_config {
+        Vec::new()
+    } else {
+        store.last_builds().unwrap_or_else(|e| {
+            log::warn!("failed to read the earlier builds: {}", e);
+            Vec::new()
+        })
+    };
+    let report = pipeline.run();
+    // written even when the run failed, the counters of what it got through still count
+    if let Some(path) = &args.metrics_file {
+        if let Err(e) = metrics.write_textfile(path) {
+            log::error!("failed to write {} : {}", path, e);
+            eprintln!("Error writing {}: {}", path, e);
+        }
+    }
+    let report = report?;
+    let build_options = pipeline.options();
+    if !report.reused.is_empty() {
+        println!(
+            "Kept the earlier builds of {} languages: {}",
+            report.reused.len(),
+            report.reused.join(", ")
+        );
+    }
+    // nothing to compare the first run with
+    let changes = if previous.is_empty() {
+        Vec::new()
     } else {
-        parsers = raw_parsers.into_iter().collect();
-    }
-
-    let total_parsers = parsers.len();
-    let completed = Arc::new(Mutex::new(0)); // Shared counter for progress
-    let failed = Arc::new(Mutex::new(0));
-    // Step 2: Set up multi-progress bar
-    let multi_progress = Arc::new(MultiProgress::new());
-    let overall_progress = multi_progress.add(ProgressBar::new(total_parsers as u64));
-    overall_progress.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {pos}/{len} completed {msg}")
-            .unwrap(),
+        changelog::changes(&previous, &report.artifacts, &report.listed)
+    };
+    if !previous.is_empty() {
+        if changes.is_empty() {
+            println!("No grammar changed since the last builds");
+        } else {
+            println!("Changes since the last builds:");
+            for change in &changes {
+                println!("  {}", change);
+            }
+        }
+    }
+    let triage = failure::summarize(
+        report
+            .failed
+            .iter()
+            .map(|(lang, error)| (lang.as_str(), &**error)),
     );
-
-    // Submit tasks to the thread pool
-    for (lang, repo_url) in parsers {
-        let completed = Arc::clone(&completed);
-        let failed = Arc::clone(&failed);
-        let multi_progress = Arc::clone(&multi_progress);
-        let overall_progress = overall_progress.clone();
-        let output = Arc::clone(&output_dir);
-        let source_dest = Arc::clone(&source_destination);
-        let config_dest = Arc::clone(&config_destination);
-        pool.execute(move || {
-            // Create a progress bar only when the task starts
-            let pb = multi_progress.add(ProgressBar::new_spinner());
-            pb.set_style(
-                ProgressStyle::default_spinner()
-                    .template("{spinner:.green}[{elapsed_precise}] {msg}")
-                    .unwrap(),
+    if !triage.is_empty() {
+        // as the source named them when that differs
+        let display = |lang: &str| match report.display_names.get(lang) {
+            Some(name) if name != lang => format!("{} ({})", name, lang),
+            _ => lang.to_string(),
+        };
+        println!("Failed builds by phase/category:");
+        for (failure, languages) in &triage {
+            let languages: Vec<String> = languages.iter().map(|lang| display(lang)).collect();
+            println!(
+                "  {}/{}: {}",
+                failure.phase,
+                failure.category,
+                languages.join(", ")
             );
-            pb.set_message(format!("Cloning {}", lang));
-            let pb_clone = pb.clone();
-            let spinner_thread = thread::spawn(move || {
-                while !pb_clone.is_finished() {
-                    pb_clone.tick();
-                    thread::sleep(Duration::from_millis(100));
-                }
-            });
-
-            // Execute the task
-            if let Err(e) = clone_and_build(&lang, &repo_url, &pb, output, source_dest, config_dest)
-            {
-                pb.finish_with_message(format!("Failed for {}: {}", lang, e));
-                log::warn!("failed for {} : {}", lang, e);
-                let mut failed_lock = failed.lock().unwrap();
-                *failed_lock += 1;
+        }
+    }
+    // a dispatcher going by extension would silently pick one of them
+    // (the pipeline logged them already)
+    for collision in &report.extension_collisions {
+        eprintln!(
+            "Warning: extension {} is claimed by {}, {} keeps it {}",
+            collision.extension,
+            collision.languages.join(", "),
+            collision.winner,
+            collision.rule.describe()
+        );
+    }
+    let flagged: Vec<&str> = report
+        .failed
+        .iter()
+        .filter(|(_, error)| matches!(**error, BuildError::FlaggedScanner { .. }))
+        .map(|(lang, _)| lang.as_str())
+        .collect();
+    if !flagged.is_empty() {
+        println!(
+            "Flagged scanners: {}. Read them and pass --confirm-scanner {} to build them anyway.",
+            flagged.join(", "),
+            flagged.join(",")
+        );
+    }
+    if !report.skiplisted.is_empty() {
+        let skipped: Vec<String> = report
+            .skiplisted
+            .iter()
+            .map(|entry| match &entry.reason {
+                Some(reason) => format!("{} ({})", entry.language, reason),
+                None => entry.language.clone(),
+            })
+            .collect();
+        println!("Skipped (skiplist): {}", skipped.join(", "));
+    }
+    if !report.untrusted.is_empty() {
+        let refused: Vec<String> = report
+            .untrusted
+            .iter()
+            .map(|(lang, repo_url)| format!("{} ({})", lang, repo_url))
+            .collect();
+        println!(
+            "Refused (untrusted owner): {}. Add the owners to --trusted-orgs or pass --allow-untrusted to build them.",
+            refused.join(", ")
+        );
+    }
+    if github::active() {
+        for annotation in github::annotations(&report) {
+            println!("{}", annotation);
+        }
+        if let Err(e) = github::append_step_summary(&github::step_summary(&report, &changes)) {
+            log::error!("failed to write the step summary: {}", e);
+            eprintln!("Error writing $GITHUB_STEP_SUMMARY: {}", e);
+        }
+    }
+    let cycle = daemon::CycleReport {
+        artifacts: report.artifacts.len(),
+        failed: report.failed.len(),
+        failures: triage
+            .iter()
+            .map(|(failure, languages)| {
+                (
+                    format!("{}/{}", failure.phase, failure.category),
+                    languages.len(),
+                )
+            })
+            .collect(),
+        reused: report.reused.len(),
+    };
+    let mut artifacts = report.artifacts;
+    if let Some(version) = &version {
+        // a version that can't be made whole never becomes latest
+        let brought = versions::complete(&output_root, version, &mut artifacts).map_err(|e| {
+            EmitError::Write {
+                destination: version.display().to_string(),
+                source: Box::new(e),
+            }
+        })?;
+        log::info!(
+            "brought {} files over from earlier versions into {}",
+            brought,
+            version.display()
+        );
+    }
+    let symbol_collisions = symbols::find_collisions(&artifact_paths(&artifacts));
+    for (symbol, libraries) in &symbol_collisions {
+        log::warn!("symbol {} is exported by {}", symbol, libraries.join(", "));
+    }
+    if !symbol_collisions.is_empty() && !args.hide_symbols {
+        eprintln!(
+            "{} symbols are exported by more than one library, see the log. Rebuild with --hide-symbols to export only the entry points.",
+            symbol_collisions.len()
+        );
+    }
+    let mut checksummed = artifact_paths(&artifacts);
+    if args.split_debug {
+        // kept builds had theirs split by the run that built them
+        for library in artifact_paths(&artifacts) {
+            // split before the library got its hashed name
+            let library = if args.content_hash {
+                layout::stable_path(Path::new(&library))
             } else {
-                pb.finish_with_message(format!("Done with {}", lang));
-                log::info!("Done with {}", lang);
+                PathBuf::from(library)
+            };
+            let debug = debuginfo::debug_path(&library, Path::new(&build_options.output_dir));
+            if debug.is_file() {
+                checksummed.push(debug.to_string_lossy().to_string());
             }
-
-            spinner_thread.join().unwrap();
-            // Clean up the progress bar
-            multi_progress.remove(&pb);
-
-            // Update overall progress
-            let mut completed_lock = completed.lock().unwrap();
-            *completed_lock += 1;
-            let failed_count = failed.lock().unwrap();
-            overall_progress.set_message(format!("{} failed", *failed_count));
-            overall_progress.inc(1);
-        });
-    }
-
-    // Wait for all tasks to finish
-    pool.join();
-    let failed_count = failed.lock().unwrap();
-    overall_progress.finish_with_message(format!("All tasks completed. {} failed.", failed_count));
-}
-
-// Scrape parsers from the Tree-sitter wiki
-fn scrape_parsers(url: &str) -> Result<HashSet<(String, String)>, Box<dyn std::error::Error>> {
-    let client = Client::new();
-    let res = client.get(url).send()?.text()?;
-
-    let document = Html::parse_document(&res);
-    let container_selector = Selector::parse("div.markdown-body li").unwrap();
-    let link_selector = Selector::parse("a").unwrap();
-
-    let mut parsers = HashSet::new();
-    for li_element in document.select(&container_selector) {
-        if let Some(a_element) = li_element.select(&link_selector).next() {
-            if let Some(href) = a_element.value().attr("href") {
-                parsers.insert((
-                    a_element.text().next().unwrap().to_string(),
-                    href.to_string(),
-                ));
+        }
+    }
+    let mut compressed = false;
+    if let Some(level) = args.zstd {
+        compressed = true;
+        let libraries = checksummed.clone();
+        for library in libraries {
+            match compress::write_zstd(Path::new(&library), level) {
+                Ok(compressed) => checksummed.push(compressed.to_string_lossy().to_string()),
+                Err(e) => {
+                    // the same for the rest of them
+                    log::error!("failed to compress {} : {}", library, e);
+                    eprintln!("Error compressing the libraries: {}", e);
+                    compressed = false;
+                    break;
+                }
+            }
+        }
+    }
+    if args.sizes || args.zstd.is_some() {
+        print_sizes(&compress::library_sizes(&artifacts, compressed));
+    }
+    let hashes = match checksums::write_sha256sums(&build_options.output_dir, &checksummed) {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            log::error!("failed to write {} : {}", checksums::SHA256SUMS, e);
+            eprintln!("Error writing {}: {}", checksums::SHA256SUMS, e);
+            BTreeMap::new()
+        }
+    };
+    if args.embed_checksums {
+        for artifact in artifacts.iter_mut() {
+            let path = artifact.entry.get("path").and_then(Value::as_str);
+            if let Some(hash) = path.and_then(|p| hashes.get(p)) {
+                artifact.entry["sha256"] = Value::String(hash.clone());
+            }
+        }
+    }
+    if build_options.copy_licenses && !artifacts.is_empty() {
+        if let Err(e) = licenses::write_notice(&build_options.output_dir, &artifacts) {
+            log::error!("failed to write NOTICE : {}", e);
+            eprintln!("Error writing NOTICE: {}", e);
+        }
+    }
+    if let Some(key) = &args.sign_key {
+        let mut to_sign: Vec<PathBuf> = artifact_paths(&artifacts)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        to_sign.push(Path::new(&build_options.output_dir).join(checksums::SHA256SUMS));
+        // all of them tried, but a release asked to be signed doesn't go out
+        // with any signature missing
+        let mut unsigned = None;
+        for path in to_sign {
+            match args.signer.sign(key, &path) {
+                Ok(signature) => log::info!("signed {} -> {}", path.display(), signature.display()),
+                Err(e) => {
+                    log::error!("{}", e);
+                    eprintln!("Error signing: {}", e);
+                    unsigned.get_or_insert((path, e.to_string()));
+                }
             }
         }
+        if let Some((path, error)) = unsigned {
+            return Err(PipelineError::Emit(EmitError::Write {
+                destination: args.signer.signature_path(&path).display().to_string(),
+                source: error.into(),
+            }));
+        }
+    }
+    // A --versioned run only becomes latest once the config describing it is
+    // written, and confirmed with --confirm. The config points through latest
+    // already, everything emitted after it does once latest has moved.
+    let (latest_artifacts, latest_hashes) = match &version {
+        Some(_) => {
+            let mut through = artifacts.clone();
+            versions::through_latest(&output_root, &mut through);
+            let hashes = hashes
+                .iter()
+                .map(|(path, hash)| (versions::latest_path(&output_root, path), hash.clone()))
+                .collect();
+            (through, hashes)
+        }
+        None => (Vec::new(), BTreeMap::new()),
+    };
+    let compiler = build_options.builder.describe();
+    let config_written = if args.emit.contains(&Emit::Native) {
+        let (artifacts, hashes) = match &version {
+            Some(_) => (&latest_artifacts, &latest_hashes),
+            None => (&artifacts, &hashes),
+        };
+        emit_config(
+            args,
+            &config_destination,
+            config_format,
+            artifacts,
+            &EmitContext {
+                hashes,
+                compiler: compiler.clone(),
+            },
+        )
+    } else {
+        true
+    };
+    let mut hashes = hashes;
+    let mut latest = false;
+    if let Some(version) = &version {
+        if config_written {
+            versions::point_latest(&output_root, version).map_err(|e| EmitError::Write {
+                destination: output_root.join(versions::LATEST).display().to_string(),
+                source: Box::new(e),
+            })?;
+            println!("{} now points at {}", versions::LATEST, version.display());
+            latest = true;
+            match versions::prune(&output_root, args.keep_versions) {
+                Ok(removed) => {
+                    for old in removed {
+                        log::info!("removed the old version {}", old.display());
+                    }
+                }
+                Err(e) => log::warn!("failed to remove old versions : {}", e),
+            }
+            artifacts = latest_artifacts;
+            hashes = latest_hashes;
+        } else {
+            println!(
+                "{} still points at the version before {}, the config wasn't written",
+                versions::LATEST,
+                version.display()
+            );
+        }
+    }
+    let context = EmitContext {
+        hashes: &hashes,
+        compiler,
+    };
+    for emit in args.emit.iter().filter(|emit| **emit != Emit::Native) {
+        let emitter = emitter_for(
+            *emit,
+            args,
+            &config_destination,
+            config_format,
+            &build_options.output_dir,
+        );
+        if let Err(e) = emitter.emit(&artifacts, &context) {
+            log::error!("failed to write {} : {}", emitter.destination(), e);
+            eprintln!("Error writing {}", e);
+        }
+    }
+    // known-good from now on, with the config it was written with, for rollback
+    if let Some(name) = version
+        .as_deref()
+        .filter(|_| latest)
+        .and_then(Path::file_name)
+    {
+        let config = fs::read_to_string(&config_destination).ok();
+        let run = store.latest_run().ok().flatten().map(|(run, _)| run);
+        let recorded = versions::output_key(&output_root)
+            .map_err(|e| e.to_string())
+            .and_then(|output| {
+                store
+                    .record_version(run, &output, &name.to_string_lossy(), config.as_deref())
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = recorded {
+            log::warn!(
+                "failed to record the version {} : {}",
+                name.to_string_lossy(),
+                e
+            );
+        }
+    }
+    if let Some(dest) = &args.install_emacs {
+        let dest = if dest.is_empty() {
+            layout::emacs_grammar_dir()
+        } else {
+            Some(PathBuf::from(dest))
+        };
+        // by the names emacs looks for, the links
+        let libraries: Vec<String> = artifact_paths(&artifacts)
+            .into_iter()
+            .map(|library| {
+                if args.content_hash {
+                    layout::stable_path(Path::new(&library))
+                        .to_string_lossy()
+                        .to_string()
+                } else {
+                    library
+                }
+            })
+            .collect();
+        match dest {
+            Some(dest) => match layout::install_into(&dest, &libraries) {
+                Ok(count) => log::info!("installed {} grammars into {}", count, dest.display()),
+                Err(e) => {
+                    log::error!("failed to install into {} : {}", dest.display(), e);
+                    eprintln!("Error installing into {}: {}", dest.display(), e);
+                }
+            },
+            None => eprintln!("Error installing for emacs: HOME is not set"),
+        }
     }
 
-    Ok(parsers)
-}
+    // catch entries downstream tools would choke on now rather than at load time
+    let entries: serde_json::Map<String, Value> = artifacts
+        .iter()
+        .map(|a| (a.name.clone(), a.entry.clone()))
+        .collect();
+    for problem in validate::verify_entries(&entries, &config_destination) {
+        log::error!("verification failed: {}", problem);
+        eprintln!("Verification failed: {}", problem);
+    }
 
-// Clone and build the grammar for a given language
-fn clone_and_build(
-    lang: &str,
-    repo_url: &str,
-    pb: &ProgressBar,
-    output_dir: Arc<Mutex<String>>,
-    source_destination: Arc<Mutex<String>>,
-    config_path: Arc<Mutex<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    pb.set_message(format!("Cloning {}", repo_url));
+    if build_options.test_files.is_some() {
+        print_test_file_summary(&artifacts);
+    }
+    if build_options.run_corpus {
+        print_corpus_summary(&artifacts);
+    }
+    if let Some(diff_dir) = &build_options.tree_diffs {
+        print_tree_diff_summary(&artifacts, diff_dir);
+    }
+    Ok(cycle)
+}
 
-    let source_destination = source_destination.lock().unwrap();
-    // Clone the repository
-    let clone_output = Command::new("git")
-        .arg("clone")
-        .arg(repo_url)
-        .arg(format!("{}tree-sitter-{}", source_destination, lang))
-        .output()?;
+// the emitter writing one --emit output
+fn emitter_for(
+    emit: Emit,
+    args: &Args,
+    config_destination: &str,
+    config_format: ConfigFormat,
+    output_dir: &str,
+) -> Box<dyn Emitter> {
+    match emit {
+        Emit::Native => Box::new(NativeConfig {
+            path: config_destination.to_string(),
+            format: config_format,
+            path_style: args.path_style,
+        }),
+        Emit::Helix => Box::new(helix::HelixLanguages {
+            path: args.helix_destination.clone(),
+        }),
+        Emit::Fragments => Box::new(Fragments {
+            dir: args.fragments_dir.clone(),
+            config_path: config_destination.to_string(),
+            path_style: args.path_style,
+        }),
+        Emit::Rust => Box::new(codegen::RustTable {
+            path: args.rust_destination.clone(),
+            path_style: args.path_style,
+        }),
+        Emit::Crate => Box::new(codegen::WrapperCrate {
+            dir: args.crate_destination.clone(),
+        }),
+        Emit::C => Box::new(codegen::CRegistry {
+            dir: output_dir.to_string(),
+        }),
+        Emit::PkgConfig => Box::new(pkgconfig::PkgConfig {
+            dir: output_dir.to_string(),
+        }),
+        Emit::Cmake => Box::new(cmake::CmakeConfig {
+            dir: output_dir.to_string(),
+        }),
+        Emit::Spdx => Box::new(sbom::Sbom {
+            path: args.spdx_destination.clone(),
+            format: SbomFormat::Spdx,
+        }),
+        Emit::Cyclonedx => Box::new(sbom::Sbom {
+            path: args.cyclonedx_destination.clone(),
+            format: SbomFormat::Cyclonedx,
+        }),
+    }
+}
 
-    if !clone_output.status.success() {
-        return Err(format!(
-            "Failed to clone {}: {}",
-            repo_url,
-            String::from_utf8_lossy(&clone_output.stderr)
+// --emit native, after --confirm. false when it wasn't written, declined or failed.
+fn emit_config(
+    args: &Args,
+    config_destination: &str,
+    config_format: ConfigFormat,
+    artifacts: &[LanguageArtifact],
+    context: &EmitContext,
+) -> bool {
+    if args.confirm
+        && !confirm_config(
+            config_destination,
+            config_format,
+            artifacts,
+            args.path_style,
         )
-        .into());
-    }
-
-    let repo_dir = format!("{}tree-sitter-{}", source_destination, lang);
-    pb.set_message(format!("Cloned {}. Searching for parser.c", lang));
-
-    // Search for parser.c in the cloned directory
-    let parser_c_path = find_file(&repo_dir, "parser.c")?;
-    let scanner_c_path = find_file(&repo_dir, "scanner.c").ok(); // scanner.c is optional
-    pb.set_message(format!("Building grammar for {}", lang));
-    let output_dir = output_dir.lock().unwrap();
-    let output_path = format!("{}lib{}.so", *output_dir, lang);
-    // Build the grammar using GCC
-    let mut gcc_cmd = Command::new("gcc");
-    gcc_cmd
-        .arg("-shared")
-        .arg("-fPIC")
-        .arg("-o")
-        .arg(output_path.clone())
-        .arg(parser_c_path);
-
-    if let Some(scanner_c) = scanner_c_path {
-        gcc_cmd.arg(scanner_c);
-    }
-
-    let gcc_output = gcc_cmd.output()?;
-    if !gcc_output.status.success() {
-        return Err(format!(
-            "Failed to build grammar for {}: {}",
-            lang,
-            String::from_utf8_lossy(&gcc_output.stderr)
-        )
-        .into());
+    {
+        println!("Left {} unchanged", config_destination);
+        return false;
+    }
+    let emitter = NativeConfig {
+        path: config_destination.to_string(),
+        format: config_format,
+        path_style: args.path_style,
+    };
+    if let Err(e) = emitter.emit(artifacts, context) {
+        log::error!("failed to write {} : {}", emitter.destination(), e);
+        eprintln!("Error writing {}", e);
+        return false;
     }
+    warn_extension_overlaps(config_destination, config_format);
+    true
+}
 
-    let config_path = config_path.lock().unwrap();
+// The run's own collisions are resolved, but entries earlier runs left in the
+// config can still share an extension with what was built
+fn warn_extension_overlaps(path: &str, format: ConfigFormat) {
+    let config = match load_config(path, format) {
+        Ok(config) => config,
+        Err(e) => {
+            log::warn!("can't check {} for shared extensions : {}", path, e);
+            return;
+        }
+    };
+    for (extension, languages) in extension_overlaps(&config) {
+        log::warn!(
+            "extension {} is claimed by {} in {}",
+            extension,
+            languages.join(", "),
+            path
+        );
+        eprintln!(
+            "Warning: extension {} is claimed by {} in {}, build them together to let extension_priority pick one",
+            extension,
+            languages.join(", "),
+            path
+        );
+    }
+}
 
-    match create_config_entry(&repo_dir, &config_path, &output_path) {
-        Ok(()) => (),
+// --confirm: print the entries the run adds, changes and removes, then ask.
+// Nothing to change needs no asking.
+fn confirm_config(
+    path: &str,
+    format: ConfigFormat,
+    artifacts: &[LanguageArtifact],
+    path_style: Option<PathStyle>,
+) -> bool {
+    let (old, new) = match load_config(path, format)
+        .and_then(|old| Ok((old, merged_config(path, format, artifacts, path_style)?)))
+    {
+        Ok(configs) => configs,
         Err(e) => {
-            log::error!("failed to create config entry for {} : {}", lang, e);
+            eprintln!("Error reading {}: {}", path, e);
+            return false;
+        }
+    };
+    let changes = config_changes(&old, &new);
+    if changes.is_empty() {
+        println!("{} is up to date", path);
+        return true;
+    }
+
+    let color = std::io::stdout().is_terminal();
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text
         }
     };
-    pb.set_message(format!("Built grammar for {}", lang));
-    Ok(())
+    let show = |value: &Option<Value>| match value {
+        Some(value) => value.to_string(),
+        None => "(none)".to_string(),
+    };
+    println!("Changes to {}:", path);
+    for change in &changes {
+        match change {
+            EntryChange::Added(name) => println!("{}", paint("32", format!("+ {}", name))),
+            EntryChange::Removed(name) => println!("{}", paint("31", format!("- {}", name))),
+            EntryChange::Updated(name, fields) => {
+                println!("{}", paint("33", format!("~ {}", name)));
+                for (field, before, after) in fields {
+                    println!(
+                        "    {}: {} -> {}",
+                        field,
+                        paint("31", show(before)),
+                        paint("32", show(after))
+                    );
+                }
+            }
+        }
+    }
+
+    print!("Write them? [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    // no answer, e.g. stdin closed, is a no
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
 }
 
+// --daemon: a run every --interval (plus up to --jitter), until killed. Runs
+// are incremental, so a cycle only rebuilds what changed upstream, and the
+// libraries it replaces are rotated rather than lost.
+fn run_daemon(args: &Args, options: BuildOptions) {
+    let store = open_store(&args.state_db);
+    let health = Arc::new(daemon::Health::default());
+    let metrics = Arc::new(Metrics::default());
+    if let Some(listen) = &args.health_listen {
+        if let Err(e) = daemon::serve_health(listen, Arc::clone(&health), Arc::clone(&metrics)) {
+            eprintln!("Error serving health on {}: {}", listen, e);
+            std::process::exit(1);
+        }
+    }
+    let schedule = daemon::Schedule {
+        interval: args.interval,
+        jitter: args.jitter,
+    };
+    for cycle in 1.. {
+        log::info!("cycle {} started", cycle);
+        health.cycle_started();
+        // a fresh checkout every time, the clone refuses an existing one
+        let mut cycle_options = options.clone();
+        cycle_options.source_destination =
+            format!("{}cycle-{}/", options.source_destination, cycle);
+        let source_dir = cycle_options.source_destination.clone();
+        let result = build(args, cycle_options, &store, &metrics).map_err(|e| e.to_string());
+        if Path::new(&source_dir).exists() {
+            if let Err(e) = fs::remove_dir_all(&source_dir) {
+                log::warn!("failed to remove {} : {}", source_dir, e);
+            }
+        }
+        if options.cancel.is_cancelled() {
+            log::info!("cycle {} interrupted, stopping", cycle);
+            return;
+        }
+        let delay = schedule.next_delay();
+        match &result {
+            Ok(report) => log::info!(
+                "cycle {} finished: {} artifacts, {} failed, {} unchanged; next in {}s",
+                cycle,
+                report.artifacts,
+                report.failed,
+                report.reused,
+                delay.as_secs()
+            ),
+            Err(e) => {
+                log::error!(
+                    "cycle {} failed : {}; next in {}s",
+                    cycle,
+                    e,
+                    delay.as_secs()
+                );
+                eprintln!("Error: {}", e);
+            }
+        }
+        health.cycle_finished(&result, delay);
+        if !options.cancel.sleep(delay) {
+            log::info!("interrupted, stopping");
+            return;
+        }
+    }
+}
 
-fn extract_comment_types(node_types: Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+// The first Ctrl-C cancels the run, killing the compilers and git clones in
+// flight, the second exits right away
+fn cancel_on_interrupt() -> CancelToken {
+    let cancel = CancelToken::new();
+    let handler = cancel.clone();
+    let result = ctrlc::set_handler(move || {
+        if handler.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("Stopping, Ctrl-C again to quit right away");
+        handler.cancel("interrupted");
+    });
+    if let Err(e) = result {
+        log::warn!("can't handle Ctrl-C : {}", e);
+    }
+    cancel
+}
 
-    // Ensure that the data is an array
-    if let Value::Array(items) = node_types {
-        // Filter items where the "type" field contains the substring "comment"
-        let comment_types: Vec<String> = items
-            .iter()
-            .filter_map(|item| {
-                if let Some(Value::String(type_value)) = item.get("type") {
-                    if type_value.contains("comment") {
-                        Some(type_value.clone()) // Clone the string value
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+// progress bars unless --progress says otherwise, or the terminal feature is off
+fn progress_sink(output: Option<ProgressOutput>) -> Arc<dyn ProgressSink> {
+    match output {
+        #[cfg(feature = "terminal")]
+        None | Some(ProgressOutput::Terminal) => Arc::new(progress::Terminal::default()),
+        #[cfg(not(feature = "terminal"))]
+        None => Arc::new(progress::NoProgress),
+        Some(ProgressOutput::Json) => Arc::new(progress::JsonLines::new(std::io::stdout())),
+        Some(ProgressOutput::None) => Arc::new(progress::NoProgress),
+    }
+}
+
+// the state database, exits if it can't be opened
+fn open_store(path: &str) -> Arc<Store> {
+    match Store::open(path) {
+        Ok(store) => Arc::new(store),
+        Err(e) => {
+            eprintln!("Error opening {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// -l, --languages-file and --preset together, in order and without repeats,
+// and the ones of them that were named rather than part of a preset. stdin is
+// read once however often `-` is given.
+fn selected_languages(args: &Args) -> (Vec<String>, Vec<String>) {
+    let mut stdin = None;
+    let mut read = |source: &str| -> Vec<String> {
+        let text = if source == "-" {
+            stdin
+                .get_or_insert_with(|| std::io::read_to_string(std::io::stdin()))
+                .as_ref()
+                .map(|text| text.clone())
+                .map_err(|e| e.to_string())
+        } else {
+            fs::read_to_string(source).map_err(|e| e.to_string())
+        };
+        match text {
+            Ok(text) => languages::parse_language_list(&text),
+            Err(e) => {
+                let name = if source == "-" { "stdin" } else { source };
+                eprintln!("Error reading the languages from {}: {}", name, e);
+                std::process::exit(1);
+            }
+        }
+    };
+    let mut selected = Vec::new();
+    for name in &args.languages {
+        if name == "-" {
+            selected.extend(read("-"));
+        } else {
+            selected.push(name.clone());
+        }
+    }
+    if let Some(file) = &args.languages_file {
+        selected.extend(read(file));
+    }
+    let mut seen = HashSet::new();
+    let named: Vec<String> = selected
+        .iter()
+        .filter(|name| seen.insert(name.to_string()))
+        .cloned()
+        .collect();
+    if !args.preset.is_empty() {
+        let presets = match load_manifest(&args.manifest) {
+            Ok(manifest) => manifest.presets,
+            Err(e) => {
+                eprintln!("Error reading manifest {}: {}", args.manifest, e);
+                std::process::exit(1);
+            }
+        };
+        for name in &args.preset {
+            match languages::preset(name, &presets) {
+                Ok(languages) => selected.extend(languages),
+                Err(e) => {
+                    eprintln!("Error: --preset: {}", e);
+                    std::process::exit(1);
                 }
-            })
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    selected.retain(|name| seen.insert(name.clone()));
+    (selected, named)
+}
+
+// the libraries of the run, biggest first, and which of them are half of it all
+fn print_sizes(sizes: &[compress::LibrarySize]) {
+    if sizes.is_empty() {
+        return;
+    }
+    let total: u64 = sizes.iter().map(|s| s.size).sum();
+    let compressed: Option<u64> = sizes.iter().map(|s| s.compressed).sum();
+    println!("{:<24} {:>10} {:>10}", "language", "size", "zstd");
+    for size in sizes {
+        println!(
+            "{:<24} {:>10} {:>10}",
+            size.languages.join(", "),
+            disk::format_size(size.size),
+            size.compressed.map(disk::format_size).unwrap_or("-".into())
+        );
+    }
+    match compressed {
+        Some(compressed) => println!(
+            "{} libraries, {} ({} compressed)",
+            sizes.len(),
+            disk::format_size(total),
+            disk::format_size(compressed)
+        ),
+        None => println!("{} libraries, {}", sizes.len(), disk::format_size(total)),
+    }
+    let biggest = compress::biggest_half(sizes);
+    if sizes.len() > 1 {
+        let names: Vec<String> = sizes[..biggest]
+            .iter()
+            .map(|s| s.languages.join(", "))
             .collect();
+        println!(
+            "Half of it is {} of {}: {}",
+            biggest,
+            sizes.len(),
+            names.join(", ")
+        );
+    }
+}
 
-        Ok(comment_types)
-    } else {
-        // If the JSON is not an array, return an error
-        Err("Expected JSON array at root".into())
+// one line per language that was ever built, then the run counts
+fn print_stats(store: &Store, languages: &[String]) {
+    let stats = match store.language_stats() {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Error reading {}", e);
+            std::process::exit(1);
+        }
+    };
+    let wanted: HashSet<String> = languages
+        .iter()
+        .map(|l| languages::canonical_name(l))
+        .collect();
+    println!(
+        "{:<24} {:>5} {:>7}  {:<8} {:<8} {:>9}  last attempt",
+        "language", "runs", "failed", "last", "commit", "avg time"
+    );
+    for s in stats
+        .iter()
+        .filter(|s| wanted.is_empty() || wanted.contains(&s.language))
+    {
+        let commit = s.last_commit.as_deref().unwrap_or("-");
+        println!(
+            "{:<24} {:>5} {:>7}  {:<8} {:<8} {:>8.1}s  {}",
+            s.language,
+            s.attempts,
+            s.failures,
+            s.last_outcome,
+            &commit[..commit.len().min(7)],
+            s.average_duration.as_secs_f64(),
+            s.last_attempt
+        );
+    }
+    match store.run_counts() {
+        Ok((runs, finished)) => println!(
+            "{} runs recorded in {}, {} of them interrupted",
+            runs,
+            store.path(),
+            runs - finished
+        ),
+        Err(e) => eprintln!("Error reading {}", e),
     }
 }
 
+fn print_outdated(store: &Store, languages: &[String], jobs: usize) {
+    let wanted: HashSet<String> = languages
+        .iter()
+        .map(|l| languages::canonical_name(l))
+        .collect();
+    let builds: Vec<store::LastBuild> = match store.last_builds() {
+        Ok(builds) => builds
+            .into_iter()
+            .filter(|b| wanted.is_empty() || wanted.contains(&b.language))
+            .collect(),
+        Err(e) => {
+            eprintln!("Error reading {}", e);
+            std::process::exit(1);
+        }
+    };
+    let total = builds.len();
+    let outdated = match store::find_outdated(builds, jobs) {
+        Ok(outdated) => outdated,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let short = |commit: Option<&str>| commit.map(|c| c[..c.len().min(7)].to_string());
+    for o in &outdated {
+        let built = short(o.build.commit.as_deref()).unwrap_or_else(|| "-".to_string());
+        match short(o.head.as_deref()) {
+            Some(head) => println!(
+                "{:<24} {} -> {}  {}",
+                o.build.name, built, head, o.build.repo_url
+            ),
+            None => println!(
+                "{:<24} {} -> ?        can't reach {}",
+                o.build.name, built, o.build.repo_url
+            ),
+        }
+    }
+    let unreachable = outdated.iter().filter(|o| o.head.is_none()).count();
+    println!(
+        "{} of {} built grammars are outdated, {} couldn't be checked",
+        outdated.len() - unreachable,
+        total,
+        unreachable
+    );
+}
 
-fn create_config_entry(
-    repo_dir: &str,
-    config_path: &str,
-    shared_object_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // read the tree-sitter.json from the target repo
-    let json_path = find_file(repo_dir, "tree-sitter.json")?;
-    let mut file = File::open(json_path)?;
-    let mut file_content = String::new();
-    file.read_to_string(&mut file_content)?;
+fn print_jobs(store: &Store, run: Option<i64>) {
+    let run = match run {
+        Some(run) => Ok(Some((run, true))),
+        None => store.latest_run(),
+    };
+    let jobs = run.and_then(|run| match run {
+        Some((run, _)) => store.jobs(run).map(|jobs| (run, jobs)).map(Some),
+        None => Ok(None),
+    });
+    let (run, jobs) = match jobs {
+        Ok(Some(jobs)) => jobs,
+        Ok(None) => {
+            println!("No runs recorded in {}", store.path());
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error reading {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for job in &jobs {
+        *counts.entry(&job.state).or_default() += 1;
+        println!(
+            "{:<24} {:<16} {:<9} {}  {}",
+            job.language,
+            job.target,
+            job.state,
+            job.updated_at,
+            job.error
+                .as_deref()
+                .and_then(|e| e.lines().next())
+                .unwrap_or("")
+        );
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(state, count)| format!("{} {}", count, state))
+        .collect();
+    println!("run {}: {} jobs, {}", run, jobs.len(), counts.join(", "));
+}
 
-    let tree_sitter_json: Value = serde_json::from_str(&file_content)?;
+// rollback --list: the known-good versions, the one latest points at marked
+fn print_versions(store: &Store, root: &Path) {
+    let good = versions::output_key(root)
+        .map_err(|e| format!("{}: {}", root.display(), e))
+        .and_then(|output| store.good_versions(&output).map_err(|e| e.to_string()));
+    let good = match good {
+        Ok(good) => good,
+        Err(e) => {
+            eprintln!("Error reading versions: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if good.is_empty() {
+        println!("No --versioned runs of {} recorded", root.display());
+        return;
+    }
+    let current = versions::current_name(root);
+    for version in &good {
+        let marker = if Some(&version.name) == current.as_ref() {
+            "*"
+        } else {
+            " "
+        };
+        let gone = if root.join(&version.name).is_dir() {
+            ""
+        } else {
+            " (removed)"
+        };
+        println!(
+            "{} {:<20} {}{}",
+            marker, version.name, version.promoted_at, gone
+        );
+    }
+}
+
+// the sources of --source, in the order they were given
+fn parser_sources(args: &Args) -> Vec<Box<dyn ParserSource>> {
+    args.source
+        .iter()
+        .map(|kind| -> Box<dyn ParserSource> {
+            match kind {
+                SourceKind::Wiki => Box::new(sources::WikiSource::default()),
+                SourceKind::Manifest => Box::new(sources::ManifestSource {
+                    path: args.manifest.clone(),
+                }),
+                SourceKind::NvimTreesitter => Box::new(sources::NvimTreesitterSource {
+                    url: args.nvim_registry.clone(),
+                }),
+                SourceKind::Github => Box::new(sources::GithubSearchSource {
+                    query: args.github_query.clone(),
+                    ..Default::default()
+                }),
+            }
+        })
+        .collect()
+}
+
+// the build settings of the flags and the manifest, exits if the manifest can't be read
+fn build_options(args: &Args) -> BuildOptions {
+    let manifest = match load_manifest(&args.manifest) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Error reading manifest {}: {}", args.manifest, e);
+            std::process::exit(1);
+        }
+    };
+    // an ASan library aborts any process without the ASan runtime that loads it
+    let in_process_checks = args.sanitize.is_none();
+    if !in_process_checks
+        && (args.smoke_test || args.run_corpus || args.test_files.is_some() || args.diff_trees)
+    {
+        eprintln!("--sanitize builds can't be loaded by parser_scraper, skipping --smoke-test, --test-files, --run-corpus and --diff-trees");
+    }
+    // a release's library was built however its authors built it
+    let prefer_prebuilt = args.prefer_prebuilt && in_process_checks && !args.hide_symbols;
+    if args.prefer_prebuilt && !prefer_prebuilt {
+        eprintln!(
+            "--prefer-prebuilt can't honour --sanitize or --hide-symbols, compiling everything"
+        );
+    }
+    if !args.builder.takes_flags() && (args.hide_symbols || args.sanitize.is_some()) {
+        eprintln!(
+            "--builder {} compiles with its own flags, it can't do --hide-symbols or --sanitize",
+            args.builder.describe()
+        );
+        std::process::exit(1);
+    }
+    let mut builder = Arc::clone(&args.builder);
+    if prefer_prebuilt {
+        builder = Arc::new(PrebuiltBuilder::new(builder));
+    }
+    if let Some(url) = &args.build_cache {
+        // a second build that comes from the cache too proves nothing
+        if args.check_reproducible {
+            eprintln!("--check-reproducible compiles everything, not using --build-cache");
+        } else {
+            match publish::remote(url) {
+                Ok(remote) => {
+                    builder = Arc::new(CachedBuilder {
+                        inner: builder,
+                        remote,
+                        read_only: args.build_cache_read_only,
+                    })
+                }
+                Err(e) => {
+                    eprintln!("Error: --build-cache: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    BuildOptions {
+        output_dir: args.output.clone(),
+        source_destination: args.source_destination.clone(),
+        name_template: args.name_template.clone(),
+        layout: args.layout,
+        copy_queries: !args.no_queries,
+        copy_grammar_files: args.copy_node_types,
+        copy_licenses: !args.no_licenses,
+        check_reproducible: args.check_reproducible,
+        smoke_test: args.smoke_test && in_process_checks,
+        run_corpus: args.run_corpus && in_process_checks,
+        test_files: args
+            .test_files
+            .as_ref()
+            .filter(|_| in_process_checks)
+            .map(PathBuf::from),
+        tree_diffs: (args.diff_trees && in_process_checks)
+            .then(|| PathBuf::from(&args.tree_diff_destination)),
+        runtime_abi: args.runtime_abi,
+        warn_abi_mismatch: args.warn_abi_mismatch,
+        hide_symbols: args.hide_symbols,
+        split_debug: args.split_debug,
+        content_hash: args.content_hash,
+        sanitize: args.sanitize.clone(),
+        builder,
+        sandbox: args.sandbox,
+        scanner_audit: args
+            .scan_scanners
+            .then(|| ScannerAudit::new(&args.confirm_scanner)),
+        limits: ResourceLimits {
+            memory_mb: args.max_memory,
+            cpu_seconds: args.max_cpu_time,
+        },
+        single_extension: args.single_extension,
+        keep_previous: args
+            .keep_previous
+            .unwrap_or(if args.daemon { 3 } else { 0 }),
+        manifest,
+        cancel: CancelToken::new(),
+    }
+}
 
-    // read the node-types.json from the target repo
+// one line per --test-files file: clean, or how many errors and where
+fn print_test_file_summary(artifacts: &[LanguageArtifact]) {
+    let mut parses: Vec<(&str, &smoke::TestFileParse)> = artifacts
+        .iter()
+        .flat_map(|a| a.test_files.iter().map(|p| (a.name.as_str(), p)))
+        .collect();
+    parses.sort_by(|a, b| (a.0, &a.1.file).cmp(&(b.0, &b.1.file)));
+    if parses.is_empty() {
+        println!("No test files matched a built grammar");
+        return;
+    }
+    for (name, parse) in parses {
+        match parse.first_error {
+            Some((line, column)) => println!(
+                "{}: {} has {} ERROR/MISSING nodes, the first at {}:{}",
+                name, parse.file, parse.errors, line, column
+            ),
+            None => println!("{}: {} parsed without errors", name, parse.file),
+        }
+    }
+}
 
-    let json_path = find_file(repo_dir, "node-types.json")?;
-    let mut file = File::open(json_path)?;
-    let mut file_content = String::new();
-    file.read_to_string(&mut file_content)?;
+// one line per grammar that replaced an earlier build, with the inputs whose tree changed
+fn print_tree_diff_summary(artifacts: &[LanguageArtifact], diff_dir: &Path) {
+    let mut diffs: Vec<(&str, &regression::TreeDiff)> = artifacts
+        .iter()
+        .filter_map(|a| a.tree_diff.as_ref().map(|d| (a.name.as_str(), d)))
+        .collect();
+    diffs.sort_by_key(|(name, _)| *name);
+    if diffs.is_empty() {
+        println!("No earlier builds to compare trees with");
+        return;
+    }
+    for (name, diff) in diffs {
+        if diff.changed.is_empty() {
+            println!("{}: all {} trees unchanged", name, diff.compared);
+            continue;
+        }
+        println!(
+            "{}: {} of {} trees changed, see {}",
+            name,
+            diff.changed.len(),
+            diff.compared,
+            diff_dir.join(format!("{}.diff", name)).display()
+        );
+        for changed in &diff.changed {
+            println!("  changed: {}", changed);
+        }
+    }
+}
 
-    let node_types_json: Value = serde_json::from_str(&file_content)?;
+// one line of corpus results per grammar that had a corpus
+fn print_corpus_summary(artifacts: &[LanguageArtifact]) {
+    let mut reports: Vec<(&str, &corpus::CorpusReport)> = artifacts
+        .iter()
+        .filter_map(|a| a.corpus.as_ref().map(|r| (a.name.as_str(), r)))
+        .collect();
+    reports.sort_by_key(|(name, _)| *name);
+    if reports.is_empty() {
+        println!("No test corpora were run");
+        return;
+    }
+    for (name, report) in reports {
+        let total = report.passed + report.failures.len();
+        println!(
+            "{}: {}/{} corpus tests passed, {} failed, {} skipped",
+            name,
+            report.passed,
+            total,
+            report.failures.len(),
+            report.skipped
+        );
+        for failure in &report.failures {
+            println!("  failed: {}", failure);
+        }
+    }
+}
 
-    let comment_types = extract_comment_types(node_types_json)?;
+// every library the artifacts point at, once each
+fn artifact_paths(artifacts: &[LanguageArtifact]) -> Vec<String> {
+    let mut paths: Vec<String> = artifacts
+        .iter()
+        .filter_map(|a| a.entry.get("path").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
 
-    // read the config file (existing known_languages data) or initialize a new structure
-    let mut known_languages = if let Ok(mut output_file) = File::open(config_path) {
-        let mut output_file_content = String::new();
-        output_file.read_to_string(&mut output_file_content)?;
-        let existing_json: Value = serde_json::from_str(&output_file_content)?;
-        existing_json
-            .get("known_languages")
-            .and_then(Value::as_object)
-            .cloned()
-            .unwrap_or_default()
+// the output directory holding the current set, latest with --versioned
+fn current_output(args: &Args) -> String {
+    if args.versioned {
+        Path::new(&args.output)
+            .join(versions::LATEST)
+            .to_string_lossy()
+            .to_string()
     } else {
-        Map::new() // Start fresh if the output file doesn't exist
+        args.output.clone()
+    }
+}
+
+// audit: verify checksums and any signatures, exiting non-zero on problems
+fn audit(output_dir: &str, verify_key: Option<&str>) {
+    let mut problems = match checksums::verify_sha256sums(output_dir) {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("Error auditing {}: {}", output_dir, e);
+            std::process::exit(1);
+        }
     };
 
-    if let Some(grammars) = tree_sitter_json.get("grammars").and_then(Value::as_array) {
-        for grammar in grammars {
-            if let Some(name) = grammar.get("name").and_then(Value::as_str) {
-                let extension = grammar
-                    .get("file-types")
-                    .and_then(Value::as_array)
-                    .and_then(|arr| arr.first())
-                    .and_then(Value::as_str)
-                    .unwrap_or(""); // Default to empty if no extension found
-
-                // Add or update the entry in known_languages
-                known_languages.insert(
-                    name.to_string(),
-                    json!({
-                        "language": name,
-                        "path": shared_object_path,
-                        "extension": extension,
-                        "comment_types": comment_types
-                    }),
-                );
-            }
+    let mut files = checksums::listed_files(output_dir);
+    let checked = files.len();
+    files.push(Path::new(output_dir).join(checksums::SHA256SUMS));
+    let mut verified = 0;
+    for file in &files {
+        match signing::verify(file, verify_key) {
+            Some(Ok(())) => verified += 1,
+            Some(Err(e)) => problems.push(e.to_string()),
+            None => log::info!("{} is not signed", file.display()),
         }
     }
 
-    let output_json = json!({ "known_languages": known_languages });
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    println!(
+        "{} files checked, {} signatures verified",
+        checked, verified
+    );
+    if !problems.is_empty() {
+        eprintln!("{} problems found in {}", problems.len(), output_dir);
+        std::process::exit(1);
+    }
+}
 
-    let mut output_file = File::create(config_path)?;
-    output_file.write_all(output_json.to_string().as_bytes())?;
+// config validate: print every problem found, exiting non-zero if there are any
+fn validate_config_file(path: &str, format: ConfigFormat) {
+    let document = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| format.parse(&content).map_err(|e| e.to_string()));
+    let document = match document {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Error reading config {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let problems = validate::validate_config(&document, path);
+    if problems.is_empty() {
+        println!("{} is valid", path);
+        return;
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    eprintln!("{} problems found in {}", problems.len(), path);
+    std::process::exit(1);
+}
 
-    Ok(())
+// the XDG user directories, or the ones below --prefix
+fn install_dirs(prefix: Option<&str>) -> InstallDirs {
+    let dirs = match prefix {
+        Some(prefix) => InstallDirs::prefix(Path::new(prefix)).map_err(|e| e.to_string()),
+        None => InstallDirs::user().ok_or_else(|| "HOME is not set, give a --prefix".to_string()),
+    };
+    dirs.unwrap_or_else(|e| {
+        eprintln!("Error finding where to install: {}", e);
+        std::process::exit(1);
+    })
 }
 
-fn find_file(dir: &str, filename: &str) -> Result<String, Box<dyn std::error::Error>> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
+// verify: the artifact checks of `config validate` without the schema, exiting non-zero on problems
+fn verify_config_file(path: &str, format: ConfigFormat) {
+    let document = match load_config(path, format) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Error reading config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let languages = document
+        .get("known_languages")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    let problems = validate::verify_entries(&languages, path);
+    if problems.is_empty() {
+        println!("{} entries in {} verified", languages.len(), path);
+        return;
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    eprintln!("{} problems found in {}", problems.len(), path);
+    std::process::exit(1);
+}
 
-        if path.is_file() && path.file_name().unwrap_or_default() == filename {
-            return Ok(path.to_string_lossy().to_string());
-        } else if path.is_dir() {
-            // Recursive search in the subdirectory
-            if let Ok(found_path) = find_file(&path.to_string_lossy(), filename) {
-                return Ok(found_path);
-            }
+// merge-config: fold every fragment into the config file, exiting on failure
+fn merge_config(fragments_dir: &str, config_destination: &str, config_format: ConfigFormat) {
+    let artifacts = match read_fragments(fragments_dir) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Error reading fragments: {}", e);
+            std::process::exit(1);
         }
+    };
+    if let Err(e) = write_config(config_destination, config_format, &artifacts, None) {
+        eprintln!("Error writing config: {}", e);
+        std::process::exit(1);
     }
-    Err(format!("File {} not found in {}", filename, dir).into())
+    log::info!(
+        "merged {} fragments from {} into {}",
+        artifacts.len(),
+        fragments_dir,
+        config_destination
+    );
+    println!(
+        "Merged {} fragments into {}",
+        artifacts.len(),
+        config_destination
+    );
 }
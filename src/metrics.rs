@@ -0,0 +1,235 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// seconds, from a fast clone to a huge grammar with --check-reproducible
+const DURATION_BUCKETS: [f64; 10] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+// bytes, most grammars are between a few hundred KiB and a few MiB
+const SIZE_BUCKETS: [f64; 7] = [
+    65536.0,
+    262144.0,
+    1048576.0,
+    4194304.0,
+    16777216.0,
+    67108864.0,
+    268435456.0,
+];
+
+struct Histogram {
+    bounds: &'static [f64],
+    // per bucket, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+struct Counters {
+    runs: u64,
+    attempted: u64,
+    succeeded: u64,
+    failed: u64,
+    skipped: u64,
+    clone: Histogram,
+    compile: Histogram,
+    artifact_size: Histogram,
+    last_run_finished: f64,
+    last_run_duration: f64,
+}
+
+// Counters and histograms of every run fed to them, in the Prometheus text
+// format. A daemon keeps one for its whole life, so they only go up.
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            counters: Mutex::new(Counters {
+                runs: 0,
+                attempted: 0,
+                succeeded: 0,
+                failed: 0,
+                skipped: 0,
+                clone: Histogram::new(&DURATION_BUCKETS),
+                compile: Histogram::new(&DURATION_BUCKETS),
+                artifact_size: Histogram::new(&SIZE_BUCKETS),
+                last_run_finished: 0.0,
+                last_run_duration: 0.0,
+            }),
+        }
+    }
+}
+
+// how a grammar's build ended, for the counters
+#[derive(Clone, Copy)]
+pub enum Ended {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+impl Metrics {
+    pub fn build_started(&self) {
+        self.counters.lock().unwrap().attempted += 1;
+    }
+
+    pub fn build_ended(&self, ended: Ended) {
+        let mut counters = self.counters.lock().unwrap();
+        match ended {
+            Ended::Succeeded => counters.succeeded += 1,
+            Ended::Failed => counters.failed += 1,
+            Ended::Skipped => counters.skipped += 1,
+        }
+    }
+
+    pub fn observe_clone(&self, took: Duration) {
+        self.counters
+            .lock()
+            .unwrap()
+            .clone
+            .observe(took.as_secs_f64());
+    }
+
+    pub fn observe_compile(&self, took: Duration) {
+        self.counters
+            .lock()
+            .unwrap()
+            .compile
+            .observe(took.as_secs_f64());
+    }
+
+    pub fn observe_artifact_size(&self, bytes: u64) {
+        self.counters
+            .lock()
+            .unwrap()
+            .artifact_size
+            .observe(bytes as f64);
+    }
+
+    pub fn run_finished(&self, took: Duration) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.runs += 1;
+        counters.last_run_duration = took.as_secs_f64();
+        counters.last_run_finished = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+    }
+
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric(
+            "parser_scraper_runs_total",
+            "counter",
+            "Runs that got through to writing their outputs.",
+            counters.runs as f64,
+        );
+        metric(
+            "parser_scraper_builds_attempted_total",
+            "counter",
+            "Grammars whose clone started.",
+            counters.attempted as f64,
+        );
+        metric(
+            "parser_scraper_builds_succeeded_total",
+            "counter",
+            "Grammars built and verified.",
+            counters.succeeded as f64,
+        );
+        metric(
+            "parser_scraper_builds_failed_total",
+            "counter",
+            "Grammars that failed to clone, build or verify.",
+            counters.failed as f64,
+        );
+        metric(
+            "parser_scraper_builds_skipped_total",
+            "counter",
+            "Grammars a build filter dropped.",
+            counters.skipped as f64,
+        );
+        metric(
+            "parser_scraper_last_run_timestamp_seconds",
+            "gauge",
+            "When the last run finished, as a Unix time.",
+            counters.last_run_finished,
+        );
+        metric(
+            "parser_scraper_last_run_duration_seconds",
+            "gauge",
+            "How long the last run took.",
+            counters.last_run_duration,
+        );
+        counters.clone.render(
+            &mut out,
+            "parser_scraper_clone_duration_seconds",
+            "Time to clone a grammar, hooks included.",
+        );
+        counters.compile.render(
+            &mut out,
+            "parser_scraper_compile_duration_seconds",
+            "Time to compile a grammar into a library.",
+        );
+        counters.artifact_size.render(
+            &mut out,
+            "parser_scraper_artifact_size_bytes",
+            "Size of each library built.",
+        );
+        out
+    }
+
+    // For node_exporter's textfile collector: written next to `path` and
+    // renamed over it, so the collector never reads half a file
+    pub fn write_textfile(&self, path: &str) -> std::io::Result<()> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, self.render())?;
+        fs::rename(&partial, path)
+    }
+}
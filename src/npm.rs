@@ -0,0 +1,219 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// The npm packages a grammar.js needs before `tree-sitter generate` can run
+// it, shared bases like tree-sitter-c for cpp
+pub struct Dependencies {
+    // where package.json is, npm runs there
+    pub package_dir: PathBuf,
+    // required by the grammar and listed in package.json, but not installed
+    pub missing: Vec<String>,
+}
+
+// None when everything grammar.js (or what it requires) needs is installed,
+// or isn't listed in package.json, which npm can't help with
+pub fn missing_dependencies(grammar_dir: &Path) -> Option<Dependencies> {
+    let package_dir = grammar_dir
+        .ancestors()
+        .find(|dir| dir.join("package.json").is_file())?
+        .to_path_buf();
+    let declared = declared_packages(&package_dir.join("package.json"));
+    let missing: Vec<String> = required_packages(&grammar_dir.join("grammar.js"))
+        .into_iter()
+        .filter(|package| declared.contains(package) && !installed(package, grammar_dir))
+        .collect();
+    if missing.is_empty() {
+        return None;
+    }
+    Some(Dependencies {
+        package_dir,
+        missing,
+    })
+}
+
+// `npm ci` with a lockfile, `npm install` without one. No install scripts,
+// they'd build the grammar's own node bindings, and no lockfile written into
+// the grammar's sources.
+pub fn install(package_dir: &Path) -> Result<String, String> {
+    let lockfile = ["package-lock.json", "npm-shrinkwrap.json"]
+        .iter()
+        .any(|name| package_dir.join(name).is_file());
+    let mut cmd = Command::new("npm");
+    if lockfile {
+        cmd.arg("ci");
+    } else {
+        cmd.args(["install", "--no-package-lock"]);
+    }
+    cmd.args(["--ignore-scripts", "--no-audit", "--no-fund"])
+        .current_dir(package_dir);
+    let description = if lockfile { "npm ci" } else { "npm install" };
+    let output = cmd
+        .output()
+        .map_err(|e| format!("failed to run npm, is it installed? {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} in {} failed: {}",
+            description,
+            package_dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(description.to_string())
+}
+
+fn declared_packages(package_json: &Path) -> BTreeSet<String> {
+    let Ok(content) = fs::read_to_string(package_json) else {
+        return BTreeSet::new();
+    };
+    let Ok(package) = serde_json::from_str::<Value>(&content) else {
+        log::warn!("{} isn't valid JSON", package_json.display());
+        return BTreeSet::new();
+    };
+    ["dependencies", "devDependencies", "peerDependencies"]
+        .iter()
+        .filter_map(|key| package.get(key).and_then(Value::as_object))
+        .flat_map(|deps| deps.keys().cloned())
+        .collect()
+}
+
+// node looks in node_modules/ of the directory and every one above it
+fn installed(package: &str, grammar_dir: &Path) -> bool {
+    grammar_dir.ancestors().any(|dir| {
+        dir.join("node_modules")
+            .join(package)
+            .join("package.json")
+            .is_file()
+    })
+}
+
+// The packages `entry` requires or imports, following its relative requires
+// (grammars split into several files) but not into the packages
+fn required_packages(entry: &Path) -> BTreeSet<String> {
+    let mut packages = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![entry.to_path_buf()];
+    while let Some(file) = pending.pop() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let Ok(source) = fs::read_to_string(&file) else {
+            continue;
+        };
+        for specifier in specifiers(&source) {
+            if specifier.starts_with('.') {
+                let base = file.parent().unwrap_or(Path::new(".")).join(&specifier);
+                if let Some(path) = resolve_relative(&base) {
+                    pending.push(path);
+                }
+            } else if let Some(package) = package_name(&specifier) {
+                packages.insert(package);
+            }
+        }
+    }
+    packages
+}
+
+// the quoted argument of every require(...), import(...) and `from '...'`
+fn specifiers(source: &str) -> Vec<String> {
+    let mut specifiers = Vec::new();
+    for keyword in ["require(", "import(", "from "] {
+        for (start, _) in source.match_indices(keyword) {
+            let rest = source[start + keyword.len()..].trim_start();
+            let Some(quote) = rest
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '\'' | '"' | '`'))
+            else {
+                continue;
+            };
+            if let Some(end) = rest[1..].find(quote) {
+                specifiers.push(rest[1..1 + end].to_string());
+            }
+        }
+    }
+    specifiers
+}
+
+// tree-sitter-c/grammar is tree-sitter-c, @scope/name/x is @scope/name.
+// None for node's own modules.
+fn package_name(specifier: &str) -> Option<String> {
+    if specifier.starts_with("node:") || specifier.starts_with('/') || specifier.is_empty() {
+        return None;
+    }
+    let mut parts = specifier.split('/');
+    let first = parts.next()?;
+    let name = if first.starts_with('@') {
+        format!("{}/{}", first, parts.next()?)
+    } else {
+        first.to_string()
+    };
+    if NODE_BUILTINS.contains(&name.as_str()) {
+        return None;
+    }
+    Some(name)
+}
+
+// the ones a grammar.js might plausibly use
+const NODE_BUILTINS: [&str; 6] = ["fs", "path", "os", "util", "assert", "process"];
+
+fn resolve_relative(base: &Path) -> Option<PathBuf> {
+    let mut candidates = vec![base.to_path_buf()];
+    for ext in ["js", "mjs", "cjs"] {
+        let mut name = base.as_os_str().to_owned();
+        name.push(format!(".{}", ext));
+        candidates.push(PathBuf::from(name));
+    }
+    candidates.push(base.join("index.js"));
+    candidates.into_iter().find(|path| {
+        path.is_file()
+            && matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("js" | "mjs" | "cjs")
+            )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specifiers_of_requires_and_imports() {
+        let source = r#"
+const C = require('tree-sitter-c/grammar');
+const { rules } = require("./common/rules");
+import js from `tree-sitter-javascript`;
+const lazy = await import( '@tree-sitter-grammars/tree-sitter-markdown' );
+// aside from this comment
+"#;
+        assert_eq!(
+            specifiers(source),
+            vec![
+                "tree-sitter-c/grammar",
+                "./common/rules",
+                "@tree-sitter-grammars/tree-sitter-markdown",
+                "tree-sitter-javascript",
+            ]
+        );
+    }
+
+    #[test]
+    fn package_names() {
+        assert_eq!(
+            package_name("tree-sitter-c/grammar"),
+            Some("tree-sitter-c".to_string())
+        );
+        assert_eq!(
+            package_name("@tree-sitter-grammars/tree-sitter-markdown/common"),
+            Some("@tree-sitter-grammars/tree-sitter-markdown".to_string())
+        );
+        assert_eq!(package_name("@scope"), None);
+        assert_eq!(package_name("node:path"), None);
+        assert_eq!(package_name("fs/promises"), None);
+        assert_eq!(package_name("/abs/grammar.js"), None);
+        assert_eq!(package_name(""), None);
+    }
+}
@@ -0,0 +1,433 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{ConfigFormat, FILE_MAPS};
+use crate::languages::safe_name;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PackageFormat {
+    TarGz,
+    Zip,
+    Deb,
+    Rpm,
+}
+
+impl PackageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            PackageFormat::TarGz => ".tar.gz",
+            PackageFormat::Zip => ".zip",
+            PackageFormat::Deb => ".deb",
+            PackageFormat::Rpm => ".rpm",
+        }
+    }
+}
+
+// fill in the bundle name template: {version}, {date} (YYYYMMDD) and {target} (os-arch)
+pub fn render_bundle_name(template: &str, version: &str, built: DateTime<Utc>) -> String {
+    let date = built.format("%Y%m%d").to_string();
+    let target = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+    template
+        .replace("{version}", version)
+        .replace("{date}", &date)
+        .replace("{target}", &target)
+}
+
+// (path inside the package, contents)
+type BundleFile = (String, Vec<u8>);
+
+// what to build and how to name it
+pub struct PackageSpec<'a> {
+    pub format: PackageFormat,
+    // archive file name without extension
+    pub name: &'a str,
+    // package name for deb/rpm, also their install directory
+    pub package_name: &'a str,
+    pub version: &'a str,
+    pub maintainer: &'a str,
+    pub dest_dir: &'a str,
+    pub built: DateTime<Utc>,
+}
+
+// Bundle everything in the output directory (libraries, queries, licenses,
+// checksums, ...) plus the config. Archives put it all under a top-level
+// `<name>/` directory with library paths relative to the bundle root, so it
+// can be unpacked anywhere; deb and rpm packages install it for the system.
+pub fn write_package(
+    output_dir: &str,
+    config_path: &str,
+    config_format: ConfigFormat,
+    spec: &PackageSpec,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let output_root = fs::canonicalize(output_dir)
+        .map_err(|e| format!("can't read output directory {}: {}", output_dir, e))?;
+    fs::create_dir_all(spec.dest_dir)?;
+    let archive_path =
+        Path::new(spec.dest_dir).join(format!("{}{}", spec.name, spec.format.extension()));
+
+    let files = match spec.format {
+        PackageFormat::TarGz | PackageFormat::Zip => {
+            bundle_files(&output_root, config_path, config_format, spec.name, "./")?
+        }
+        PackageFormat::Deb | PackageFormat::Rpm => {
+            system_files(&output_root, config_path, config_format, spec.package_name)?
+        }
+    };
+
+    let tmp_path = archive_path.with_extension("tmp");
+    match spec.format {
+        PackageFormat::TarGz => write_tar_gz(&tmp_path, &files, spec.built)?,
+        PackageFormat::Zip => write_zip(&tmp_path, &files, spec.built)?,
+        PackageFormat::Deb | PackageFormat::Rpm => write_system_package(&tmp_path, &files, spec)?,
+    }
+    fs::rename(&tmp_path, &archive_path)?;
+    Ok(archive_path)
+}
+
+// the output directory and the config, under `prefix`
+fn bundle_files(
+    output_root: &Path,
+    config_path: &str,
+    config_format: ConfigFormat,
+    prefix: &str,
+    base: &str,
+) -> Result<Vec<BundleFile>, Box<dyn std::error::Error>> {
+    let mut files: Vec<BundleFile> = Vec::new();
+    let config_file = fs::canonicalize(config_path).ok();
+    for path in walk(output_root)? {
+        // a config kept inside the output directory is added below, rewritten
+        if Some(&path) == config_file.as_ref() {
+            continue;
+        }
+        let relative = path
+            .strip_prefix(output_root)?
+            .to_string_lossy()
+            .to_string();
+        files.push((format!("{}/{}", prefix, relative), fs::read(&path)?));
+    }
+    let config_name = Path::new(config_path)
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file", config_path))?
+        .to_string_lossy()
+        .to_string();
+    let config = bundled_config(config_path, config_format, output_root, base)?;
+    files.push((
+        format!("{}/{}", prefix, config_name),
+        config_format.render(&config)?.into_bytes(),
+    ));
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+// Files of a system package: the output directory under /usr/lib/<package>/,
+// the config plus one fragment per language under /usr/share/<package>/ for
+// the install scripts to register
+fn system_files(
+    output_root: &Path,
+    config_path: &str,
+    config_format: ConfigFormat,
+    package_name: &str,
+) -> Result<Vec<BundleFile>, Box<dyn std::error::Error>> {
+    let base = format!("/usr/lib/{}/", package_name);
+    let mut files = Vec::new();
+    let config_file = fs::canonicalize(config_path).ok();
+    for path in walk(output_root)? {
+        if Some(&path) == config_file.as_ref() {
+            continue;
+        }
+        let relative = path
+            .strip_prefix(output_root)?
+            .to_string_lossy()
+            .to_string();
+        files.push((
+            format!("{}{}", base.trim_start_matches('/'), relative),
+            fs::read(&path)?,
+        ));
+    }
+    let share = format!("usr/share/{}", package_name);
+    let config = bundled_config(config_path, config_format, output_root, &base)?;
+    if let Some(languages) = config.get("known_languages").and_then(Value::as_object) {
+        for (name, entry) in languages {
+            files.push((
+                format!("{}/config.d/{}.json", share, safe_name(name)?),
+                ConfigFormat::Json.render(entry)?.into_bytes(),
+            ));
+        }
+    }
+    files.push((
+        format!("{}/config.json", share),
+        ConfigFormat::Json.render(&config)?.into_bytes(),
+    ));
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+fn write_tar_gz(
+    path: &Path,
+    files: &[BundleFile],
+    built: DateTime<Utc>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(path)?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(library_mode(path));
+        // fixed timestamps so the same inputs give the same archive
+        header.set_mtime(built.timestamp().max(0) as u64);
+        header.set_cksum();
+        archive.append_data(&mut header, path, contents.as_slice())?;
+    }
+    archive.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+fn write_zip(
+    path: &Path,
+    files: &[BundleFile],
+    built: DateTime<Utc>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipWriter::new(File::create(path)?);
+    let modified = zip::DateTime::from_date_and_time(
+        built.year().clamp(1980, 2107) as u16,
+        built.month() as u8,
+        built.day() as u8,
+        built.hour() as u8,
+        built.minute() as u8,
+        built.second() as u8,
+    )
+    .unwrap_or_default();
+    for (path, contents) in files {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .last_modified_time(modified)
+            .unix_permissions(library_mode(path));
+        archive.start_file(path.as_str(), options)?;
+        archive.write_all(contents)?;
+    }
+    archive.finish()?.sync_all()?;
+    Ok(())
+}
+
+// Register the packaged grammars in /etc/<package>/config.json after install:
+// merged with parser_scraper when it's installed, otherwise the packaged
+// config is put in place unless the admin already has one.
+fn postinst_script(package_name: &str) -> String {
+    format!(
+        "#!/bin/sh\n\
+         set -e\n\
+         mkdir -p /etc/{0}\n\
+         if command -v parser_scraper >/dev/null 2>&1; then\n    \
+             cd /var/tmp && parser_scraper merge-config --fragments-dir /usr/share/{0}/config.d -c /etc/{0}/config.json\n\
+         elif [ ! -e /etc/{0}/config.json ]; then\n    \
+             cp /usr/share/{0}/config.json /etc/{0}/config.json\n\
+         fi\n",
+        package_name
+    )
+}
+
+// debian and rpm spell architectures differently
+fn package_arch(format: PackageFormat) -> &'static str {
+    match (format, std::env::consts::ARCH) {
+        (PackageFormat::Deb, "x86_64") => "amd64",
+        (PackageFormat::Deb, "aarch64") => "arm64",
+        (PackageFormat::Deb, "x86") => "i386",
+        (PackageFormat::Deb, "arm") => "armhf",
+        (_, arch) => arch,
+    }
+}
+
+// Stage the files in a scratch tree and hand it to dpkg-deb or rpmbuild
+fn write_system_package(
+    path: &Path,
+    files: &[BundleFile],
+    spec: &PackageSpec,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stage = Path::new(spec.dest_dir).join(format!(".{}.stage", spec.name));
+    if stage.exists() {
+        fs::remove_dir_all(&stage)?;
+    }
+    let root = stage.join("root");
+    for (name, contents) in files {
+        let target = root.join(name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, contents)?;
+        set_mode(&target, library_mode(name))?;
+    }
+
+    let description = "tree-sitter grammars built by parser_scraper";
+    let result = match spec.format {
+        PackageFormat::Deb => {
+            let debian = root.join("DEBIAN");
+            fs::create_dir_all(&debian)?;
+            fs::write(
+                debian.join("control"),
+                format!(
+                    "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nSection: libs\nPriority: optional\nDescription: {}\n",
+                    spec.package_name,
+                    spec.version,
+                    package_arch(spec.format),
+                    spec.maintainer,
+                    description
+                ),
+            )?;
+            fs::write(debian.join("postinst"), postinst_script(spec.package_name))?;
+            set_mode(&debian.join("postinst"), 0o755)?;
+            let mut cmd = Command::new("dpkg-deb");
+            cmd.args(["--root-owner-group", "--build"])
+                .arg(&root)
+                .arg(path);
+            run(&mut cmd)
+        }
+        _ => {
+            // rpm versions can't contain dashes
+            let version = spec.version.replace('-', ".");
+            let spec_file = stage.join("package.spec");
+            fs::write(
+                &spec_file,
+                format!(
+                    "Name: {0}\nVersion: {1}\nRelease: 1\nSummary: {2}\nLicense: see /usr/lib/{0}/NOTICE\nPackager: {3}\nBuildArch: {4}\nAutoReqProv: no\n\n\
+                     %description\n{2}\n\n\
+                     %install\nmkdir -p %{{buildroot}}\ncp -a {5}/. %{{buildroot}}/\n\n\
+                     %post\n{6}\n\
+                     %files\n/usr/lib/{0}\n/usr/share/{0}\n",
+                    spec.package_name,
+                    version,
+                    description,
+                    spec.maintainer,
+                    package_arch(spec.format),
+                    fs::canonicalize(&root)?.display(),
+                    postinst_script(spec.package_name).trim_start_matches("#!/bin/sh\n"),
+                ),
+            )?;
+            let topdir = fs::canonicalize(&stage)?.join("rpmbuild");
+            let rpms = topdir.join("RPMS");
+            let mut cmd = Command::new("rpmbuild");
+            cmd.arg("-bb")
+                .arg("--define")
+                .arg(format!("_topdir {}", topdir.display()))
+                .arg("--define")
+                .arg(format!("_rpmdir {}", rpms.display()))
+                .arg("--define")
+                .arg("_build_name_fmt package.rpm")
+                .arg(&spec_file);
+            run(&mut cmd).and_then(|_| Ok(fs::rename(rpms.join("package.rpm"), path)?))
+        }
+    };
+    fs::remove_dir_all(&stage)?;
+    result
+}
+
+fn run(cmd: &mut Command) -> Result<(), Box<dyn std::error::Error>> {
+    let output = cmd
+        .output()
+        .map_err(|e| format!("failed to run {:?}: {}", cmd.get_program(), e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{:?} failed: {}",
+            cmd.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+// libraries are executable, like the linker leaves them
+pub(crate) fn library_mode(path: &str) -> u32 {
+    if is_library(path) {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+pub(crate) fn is_library(path: &str) -> bool {
+    [".so", ".dylib", ".dll"]
+        .iter()
+        .any(|ext| path.ends_with(ext))
+}
+
+// every file below `dir`, in a stable order
+fn walk(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            files.extend(walk(&path)?);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+// the config with library and query paths pointing below `base`
+fn bundled_config(
+    config_path: &str,
+    format: ConfigFormat,
+    output_root: &Path,
+    base: &str,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(config_path)
+        .map_err(|e| format!("can't read config {}: {}", config_path, e))?;
+    let mut document = format.parse(&content)?;
+    // relative paths are from the working directory, or from the config with --path-style relative-to-config
+    let config_dir = Path::new(config_path).parent().unwrap_or(Path::new("."));
+    let rebase = |value: &mut Value| {
+        let rebased = value
+            .as_str()
+            .and_then(|path| {
+                fs::canonicalize(path)
+                    .or_else(|_| fs::canonicalize(config_dir.join(path)))
+                    .ok()
+            })
+            .and_then(|path| {
+                path.strip_prefix(output_root)
+                    .map(|p| format!("{}{}", base, p.to_string_lossy()))
+                    .ok()
+            });
+        if let Some(rebased) = rebased {
+            *value = Value::String(rebased);
+        }
+    };
+    if let Some(languages) = document
+        .get_mut("known_languages")
+        .and_then(Value::as_object_mut)
+    {
+        for entry in languages.values_mut() {
+            if let Some(path) = entry.get_mut("path") {
+                rebase(path);
+            }
+            for map in FILE_MAPS {
+                if let Some(files) = entry.get_mut(*map).and_then(Value::as_object_mut) {
+                    files.values_mut().for_each(rebase);
+                }
+            }
+        }
+    }
+    Ok(document)
+}
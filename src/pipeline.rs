@@ -0,0 +1,1402 @@
+use crossbeam_channel::{self as channel, Sender};
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::cancel::CancelToken;
+use crate::config::{
+    resolve_extension_collisions, ConfigFormat, ExtensionCollision, LanguageArtifact, NativeConfig,
+    PathStyle,
+};
+use crate::disk::{self, DiskBudget, Reclaim};
+use crate::emit::{EmitContext, Emitter};
+use crate::error::{BuildError, PipelineError};
+use crate::failure::Phase;
+use crate::grammar::{remote_head_commit, BuildOptions, BuiltGrammar, Checkout, GrammarBuilder};
+use crate::languages::{self, canonical_name, own_name};
+use crate::metrics::{Ended, Metrics};
+use crate::progress::{NoProgress, ProgressSink};
+use crate::schedule::{self, History, Order};
+use crate::skiplist::{SkipEntry, Skiplist};
+use crate::sources::{ParserEntry, ParserSource, WikiSource};
+use crate::store::{InterruptedRun, LastBuild, RunRecorder, Store};
+use crate::trust::{self, TrustPolicy};
+
+// Clones wait on the network and the remote rather than the CPU, more of them
+// than there are cores keeps the link busy without hammering the hosts
+pub const NETWORK_JOBS: usize = 16;
+
+// a compile keeps a core busy, one per core the process may use
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+// Progress of a run. Everything but Scraped and Emitted comes from the
+// worker threads, so events of different languages interleave.
+#[derive(Debug, Clone)]
+pub enum Event {
+    // the parsers left after filtering by language, before any build starts
+    Scraped {
+        total: usize,
+        // what those of them built on earlier runs took on average, by language
+        expected: HashMap<String, Duration>,
+    },
+    Started {
+        language: String,
+    },
+    // a build step, e.g. "Compiling json"
+    Status {
+        language: String,
+        message: String,
+    },
+    Finished {
+        language: String,
+        artifacts: usize,
+    },
+    // shared with the report, which keeps the same error
+    Failed {
+        language: String,
+        error: Arc<BuildError>,
+    },
+    // a build filter dropped the grammar after it was compiled
+    Skipped {
+        language: String,
+    },
+    // the run was cancelled before the grammar got through, queued or not
+    Cancelled {
+        language: String,
+    },
+    // an output was written at the end of the run, e.g. the config
+    Emitted {
+        destination: String,
+    },
+    // about the run as a whole, e.g. too little disk space for it
+    Warning {
+        message: String,
+    },
+}
+
+type ParserFilter = Box<dyn Fn(&ParserEntry) -> bool + Send + Sync>;
+type BuildFilter = Box<dyn Fn(&BuiltGrammar) -> bool + Send + Sync>;
+
+// what a run produced
+pub struct PipelineReport {
+    // with extension collisions already resolved
+    pub artifacts: Vec<LanguageArtifact>,
+    // (language, error) of every failed build
+    pub failed: Vec<(String, Arc<BuildError>)>,
+    // the languages a build filter dropped
+    pub skipped: Vec<String>,
+    // the languages whose earlier build was kept by `resume` or `incremental`,
+    // their artifacts are in `artifacts` too
+    pub reused: Vec<String>,
+    pub extension_collisions: Vec<ExtensionCollision>,
+    // the name the source gave every language, by the safe_name the language
+    // goes by everywhere else
+    pub display_names: BTreeMap<String, String>,
+    // left out for being on the skiplist
+    pub skiplisted: Vec<SkipEntry>,
+    // (language, repository) of the ones the trust policy refused
+    pub untrusted: Vec<(String, String)>,
+    // canonical names of everything the source listed, before any filter
+    pub listed: BTreeSet<String>,
+    // the builder's C compiler (its --version), or the builder when there's no telling
+    pub compiler: String,
+}
+
+// The whole scrape, clone, build and config flow without any terminal output:
+// progress goes to the `progress` sink and the results come back from `run`.
+pub struct Pipeline {
+    source: Box<dyn ParserSource>,
+    languages: HashSet<String>,
+    revisions: HashMap<String, String>,
+    parser_filters: Vec<ParserFilter>,
+    build_filters: Vec<BuildFilter>,
+    threads: usize,
+    network_jobs: usize,
+    verify_jobs: usize,
+    emitters: Vec<Box<dyn Emitter>>,
+    store: Option<Arc<Store>>,
+    metrics: Option<Arc<Metrics>>,
+    resume: bool,
+    incremental: bool,
+    only_config: bool,
+    fail_fast: bool,
+    disk_budget: Option<Arc<DiskBudget>>,
+    order: Order,
+    requested: Vec<String>,
+    skiplist: Skiplist,
+    trust: TrustPolicy,
+    grammar_builder: Arc<GrammarBuilder>,
+    progress: Arc<dyn ProgressSink>,
+}
+
+pub struct PipelineBuilder {
+    source: Box<dyn ParserSource>,
+    languages: HashSet<String>,
+    revisions: HashMap<String, String>,
+    output: Option<String>,
+    options: BuildOptions,
+    parser_filters: Vec<ParserFilter>,
+    build_filters: Vec<BuildFilter>,
+    threads: usize,
+    network_jobs: usize,
+    verify_jobs: usize,
+    emitters: Vec<Box<dyn Emitter>>,
+    store: Option<Arc<Store>>,
+    metrics: Option<Arc<Metrics>>,
+    resume: bool,
+    incremental: bool,
+    only_config: bool,
+    fail_fast: bool,
+    max_disk: Option<u64>,
+    order: Order,
+    requested: Vec<String>,
+    skiplist: Skiplist,
+    trust: TrustPolicy,
+    cancel: Option<CancelToken>,
+    progress: Arc<dyn ProgressSink>,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder {
+            source: Box::new(WikiSource::default()),
+            languages: HashSet::new(),
+            revisions: HashMap::new(),
+            output: None,
+            options: BuildOptions::default(),
+            parser_filters: Vec::new(),
+            build_filters: Vec::new(),
+            threads: default_threads(),
+            network_jobs: NETWORK_JOBS,
+            verify_jobs: 4,
+            emitters: Vec::new(),
+            store: None,
+            metrics: None,
+            resume: false,
+            incremental: false,
+            only_config: false,
+            fail_fast: false,
+            max_disk: None,
+            order: Order::default(),
+            requested: Vec::new(),
+            skiplist: Skiplist::default(),
+            trust: TrustPolicy::default(),
+            cancel: None,
+            progress: Arc::new(NoProgress),
+        }
+    }
+
+    pub fn options(&self) -> &BuildOptions {
+        self.grammar_builder.options()
+    }
+
+    // Build every parser of the source, then write the outputs that were asked
+    // for. Only a failed fetch or output is an error, failed builds are
+    // reported as events and in the report.
+    //
+    // After the source is fetched, each stage has its own workers and hands its
+    // output to the next over a channel as soon as one grammar is through: up to
+    // `network_jobs` clones run on a tokio runtime, `threads` workers compile
+    // and `verify_jobs` workers run the checks that load the libraries.
+    pub fn run(&self) -> Result<PipelineReport, PipelineError> {
+        let report = self.run_stages();
+        self.progress.finished();
+        report
+    }
+
+    fn run_stages(&self) -> Result<PipelineReport, PipelineError> {
+        if self.only_config {
+            return self.describe_existing();
+        }
+        let run_started = Instant::now();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(PipelineError::Runtime)?;
+
+        let parsers = self
+            .source
+            .fetch()
+            .map_err(|source| PipelineError::Scrape {
+                origin: self.source.describe(),
+                source,
+            })?;
+        let listed: BTreeSet<String> = parsers.iter().map(|p| canonical_name(&p.name)).collect();
+        // match on canonical names so "c++" finds the wiki's "C++" as well as "cpp"
+        let parsers: Vec<ParserEntry> = parsers
+            .into_iter()
+            .filter(|p| {
+                self.languages.is_empty() || self.languages.contains(&canonical_name(&p.name))
+            })
+            .collect();
+        let (parsers, display_names) = self.sanitize(parsers);
+        // asking for one by name builds it anyway
+        let mut skiplisted = Vec::new();
+        let parsers: Vec<ParserEntry> = parsers
+            .into_iter()
+            .filter(|p| match self.skiplist.get(&p.name) {
+                Some(entry) if !self.requested.iter().any(|r| canonical_name(r) == p.name) => {
+                    log::info!(
+                        "skipping {}, it is on the skiplist{}",
+                        p.name,
+                        entry
+                            .reason
+                            .as_ref()
+                            .map(|reason| format!(": {}", reason))
+                            .unwrap_or_default()
+                    );
+                    skiplisted.push(entry.clone());
+                    false
+                }
+                _ => true,
+            })
+            .collect();
+        let mut untrusted = Vec::new();
+        let parsers: Vec<ParserEntry> = parsers
+            .into_iter()
+            .filter(|p| {
+                if self.trust.allows(&p.repo_url) {
+                    return true;
+                }
+                let owner = trust::owner_of(&p.repo_url)
+                    .map(|owner| format!("{} isn't a trusted owner", owner))
+                    .unwrap_or_else(|| "its owner can't be told".to_string());
+                let message = if self.trust.warn_only {
+                    format!("building {} from {} although {}", p.name, p.repo_url, owner)
+                } else {
+                    format!(
+                        "refusing to build {} from {}: {}",
+                        p.name, p.repo_url, owner
+                    )
+                };
+                log::warn!("{}", message);
+                self.progress.event(Event::Warning { message });
+                if self.trust.warn_only {
+                    return true;
+                }
+                untrusted.push((p.name.clone(), p.repo_url.clone()));
+                false
+            })
+            .filter(|p| self.parser_filters.iter().all(|keep| keep(p)))
+            .collect();
+        let cancel = &self.options().cancel;
+        if let Some(reason) = cancel.reason() {
+            return Err(PipelineError::Cancelled {
+                reason,
+                unfinished: parsers.len(),
+            });
+        }
+
+        let (recorder, interrupted) = self.begin_run();
+        let mut parsers = parsers;
+        let mut reused = Vec::new();
+        let mut reused_artifacts = Vec::new();
+        if let Some(interrupted) = interrupted {
+            // only what the interrupted run was going to build
+            if !interrupted.languages.is_empty() {
+                parsers.retain(|p| interrupted.languages.contains(&own_name(&p.name)));
+            }
+            reuse(
+                &mut parsers,
+                by_language(interrupted.built),
+                &mut reused,
+                &mut reused_artifacts,
+            );
+        }
+        if let (true, Some(store)) = (self.incremental, &self.store) {
+            let unchanged = runtime.block_on(self.unchanged_builds(store, &parsers));
+            reuse(&mut parsers, unchanged, &mut reused, &mut reused_artifacts);
+        }
+        reused.sort();
+        self.check_disk(&parsers);
+        self.schedule(&mut parsers);
+        if let Some(recorder) = &recorder {
+            let jobs: Vec<(String, String)> = parsers
+                .iter()
+                .map(|p| (p.name.clone(), p.repo_url.clone()))
+                .collect();
+            recorder.queued(&jobs);
+        }
+        self.progress.event(Event::Scraped {
+            total: parsers.len(),
+            expected: self.expected_durations(&parsers),
+        });
+
+        let (checkout_tx, checkout_rx) = channel::unbounded::<Checkout>();
+        let (built_tx, built_rx) = channel::unbounded::<BuiltGrammar>();
+        let (outcome_tx, outcome_rx) = channel::unbounded::<(String, Outcome)>();
+        thread::scope(|scope| {
+            for _ in 0..self.threads.max(1) {
+                let (checkouts, built_tx, outcome_tx) =
+                    (checkout_rx.clone(), built_tx.clone(), outcome_tx.clone());
+                let recorder = recorder.as_deref();
+                scope.spawn(move || {
+                    for checkout in checkouts {
+                        let lang = checkout.lang.clone();
+                        // empty the queue without building any of it
+                        if cancel.is_cancelled() {
+                            self.grammar_builder.discard(&lang);
+                            self.finish(recorder, &outcome_tx, lang, Outcome::Cancelled);
+                            continue;
+                        }
+                        if let Some(recorder) = recorder {
+                            contained(&lang, || recorder.building(&lang));
+                        }
+                        let status = self.status_for(lang.clone());
+                        let compile_started = Instant::now();
+                        let compiled = guarded(Phase::Compile, || {
+                            self.grammar_builder.compile(&checkout, &status)
+                        });
+                        if let Some(metrics) = &self.metrics {
+                            metrics.observe_compile(compile_started.elapsed());
+                        }
+                        match compiled {
+                            Ok(built) if self.build_filters.iter().all(|keep| keep(&built)) => {
+                                let _ = built_tx.send(built);
+                            }
+                            Ok(_) => self.finish(recorder, &outcome_tx, lang, Outcome::Skipped),
+                            Err(e) => {
+                                let outcome = self.failure(&lang, e);
+                                self.finish(recorder, &outcome_tx, lang, outcome)
+                            }
+                        }
+                    }
+                });
+            }
+            // the verify workers stop once every compile worker has
+            drop(built_tx);
+            for _ in 0..self.verify_jobs.max(1) {
+                let (built, outcome_tx) = (built_rx.clone(), outcome_tx.clone());
+                let recorder = recorder.as_deref();
+                scope.spawn(move || {
+                    for built in built {
+                        let lang = built.lang.clone();
+                        if cancel.is_cancelled() {
+                            self.grammar_builder.discard(&lang);
+                            self.finish(recorder, &outcome_tx, lang, Outcome::Cancelled);
+                            continue;
+                        }
+                        let status = self.status_for(lang.clone());
+                        let outcome = match guarded(Phase::Verify, || {
+                            self.grammar_builder.verify(built, &status)
+                        }) {
+                            Ok(artifacts) => Outcome::Built(artifacts),
+                            Err(e) => self.failure(&lang, e),
+                        };
+                        self.finish(recorder, &outcome_tx, lang, outcome);
+                    }
+                });
+            }
+
+            let network = Arc::new(Semaphore::new(self.network_jobs.max(1)));
+            runtime.block_on(async {
+                let mut clones = JoinSet::new();
+                // the language of every clone task, for the ones that panic
+                let mut tasks = HashMap::new();
+                for ParserEntry {
+                    name: lang,
+                    repo_url,
+                } in parsers
+                {
+                    let rev = self.revisions.get(&canonical_name(&lang)).cloned();
+                    let grammar_builder = Arc::clone(&self.grammar_builder);
+                    let progress = Arc::clone(&self.progress);
+                    let network = Arc::clone(&network);
+                    let checkout_tx = checkout_tx.clone();
+                    let recorder = recorder.clone();
+                    let metrics = self.metrics.clone();
+                    let cancel = cancel.clone();
+                    let store = self.store.clone();
+                    let disk_budget = self.disk_budget.clone();
+                    let task_lang = lang.clone();
+                    let task = clones.spawn(async move {
+                        let _permit = tokio::select! {
+                            permit = network.acquire_owned() => permit.expect("never closed"),
+                            _ = cancel.cancelled() => return (lang, None),
+                        };
+                        // until a grammar that's through makes room for the checkout
+                        if let Some(budget) = &disk_budget {
+                            loop {
+                                match budget.reserve(&lang) {
+                                    Ok(room) => {
+                                        reclaim(budget, room, &grammar_builder, &cancel);
+                                        break;
+                                    }
+                                    Err(room) => reclaim(budget, room, &grammar_builder, &cancel),
+                                }
+                                tokio::select! {
+                                    _ = tokio::time::sleep(DISK_POLL) => {}
+                                    _ = cancel.cancelled() => return (lang, None),
+                                }
+                            }
+                        }
+                        if let Some(recorder) = &recorder {
+                            contained(&lang, || recorder.started(&lang, &repo_url));
+                        }
+                        if let Some(metrics) = &metrics {
+                            metrics.build_started();
+                        }
+                        let clone_started = Instant::now();
+                        progress.event(Event::Started {
+                            language: lang.clone(),
+                        });
+                        progress.event(Event::Status {
+                            language: lang.clone(),
+                            message: format!("Cloning {}", repo_url),
+                        });
+                        let cloned = grammar_builder
+                            .clone_at_async(&lang, &repo_url, rev.as_deref())
+                            .await;
+                        if let Some(metrics) = &metrics {
+                            metrics.observe_clone(clone_started.elapsed());
+                        }
+                        if let (Ok(checkout), true) =
+                            (&cloned, store.is_some() || disk_budget.is_some())
+                        {
+                            let repo_dir = checkout.repo_dir.clone();
+                            let bytes = tokio::task::spawn_blocking(move || {
+                                disk::dir_size(Path::new(&repo_dir))
+                            })
+                            .await
+                            .unwrap_or(0);
+                            if let Some(store) = &store {
+                                if let Err(e) = store.record_checkout_size(&lang, bytes) {
+                                    log::warn!("failed to record the size of {} : {}", lang, e);
+                                }
+                            }
+                            if let Some(budget) = &disk_budget {
+                                reclaim(
+                                    budget,
+                                    budget.cloned(&lang, bytes),
+                                    &grammar_builder,
+                                    &cancel,
+                                );
+                            }
+                        }
+                        let cloned = cloned.map(|checkout| {
+                            progress.event(Event::Status {
+                                language: lang.clone(),
+                                message: format!("Cloned {}, waiting for a build worker", lang),
+                            });
+                            let _ = checkout_tx.send(checkout);
+                        });
+                        (lang, Some(cloned))
+                    });
+                    tasks.insert(task.id(), task_lang);
+                }
+                while let Some(cloned) = clones.join_next_with_id().await {
+                    match cloned {
+                        Ok((_, (_, Some(Ok(()))))) => {}
+                        Ok((_, (lang, Some(Err(e))))) => {
+                            let outcome = self.failure(&lang, e.into());
+                            self.finish(recorder.as_deref(), &outcome_tx, lang, outcome)
+                        }
+                        // never started
+                        Ok((_, (lang, None))) => {
+                            self.finish(recorder.as_deref(), &outcome_tx, lang, Outcome::Cancelled)
+                        }
+                        // the clone stage isn't `guarded`, it would have to be around the future
+                        Err(e) => {
+                            let Some(lang) = tasks.remove(&e.id()) else {
+                                log::error!("a clone task died: {}", e);
+                                continue;
+                            };
+                            let message = match e.try_into_panic() {
+                                Ok(payload) => panic_message(&payload),
+                                Err(e) => e.to_string(),
+                            };
+                            let outcome = self.failure(
+                                &lang,
+                                BuildError::Panicked {
+                                    phase: Phase::Clone,
+                                    message,
+                                },
+                            );
+                            self.finish(recorder.as_deref(), &outcome_tx, lang, outcome)
+                        }
+                    }
+                }
+            });
+            // the compile workers stop once the queue is empty
+            drop(checkout_tx);
+        });
+        drop(outcome_tx);
+
+        let mut artifacts = Vec::new();
+        let mut failed = Vec::new();
+        let mut skipped = Vec::new();
+        let mut unfinished = 0;
+        for (lang, outcome) in outcome_rx {
+            match outcome {
+                Outcome::Built(built) => artifacts.extend(built),
+                Outcome::Failed(e) => failed.push((lang, e)),
+                Outcome::Skipped => skipped.push(lang),
+                Outcome::Cancelled => unfinished += 1,
+            }
+        }
+        // nothing is written for a run that stopped short
+        if let Some(reason) = cancel.reason() {
+            return Err(PipelineError::Cancelled { reason, unfinished });
+        }
+        failed.sort_by(|a, b| a.0.cmp(&b.0));
+        skipped.sort();
+        artifacts.extend(reused_artifacts);
+
+        let extension_collisions = resolve_extension_collisions(
+            &mut artifacts,
+            &self.options().manifest.extension_priority,
+        );
+        for collision in &extension_collisions {
+            log::warn!(
+                "extension {} is claimed by {}, assigning it to {} {}",
+                collision.extension,
+                collision.languages.join(", "),
+                collision.winner,
+                collision.rule.describe()
+            );
+        }
+
+        let context = EmitContext {
+            hashes: &BTreeMap::new(),
+            compiler: self.options().builder.describe(),
+        };
+        for emitter in &self.emitters {
+            emitter.emit(&artifacts, &context)?;
+            self.progress.event(Event::Emitted {
+                destination: emitter.destination(),
+            });
+        }
+        // a run that stopped short of this can be resumed
+        if let Some(recorder) = &recorder {
+            recorder.finish();
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.run_finished(run_started.elapsed());
+        }
+
+        Ok(PipelineReport {
+            artifacts,
+            failed,
+            skipped,
+            reused,
+            extension_collisions,
+            display_names,
+            skiplisted,
+            untrusted,
+            listed,
+            compiler: self.compiler(),
+        })
+    }
+
+    fn compiler(&self) -> String {
+        let builder = &self.options().builder;
+        builder
+            .compiler_version()
+            .unwrap_or_else(|| builder.describe())
+    }
+
+    // The only_config run: every checkout on disk, as far as `languages` lets
+    // it, described instead of built. Nothing goes into the store, there was
+    // no build.
+    fn describe_existing(&self) -> Result<PipelineReport, PipelineError> {
+        let destination = &self.options().source_destination;
+        let checkouts = match self.grammar_builder.existing_checkouts() {
+            Ok(checkouts) => checkouts,
+            Err(e) => {
+                let message = format!("no checkouts to describe in {}: {}", destination, e);
+                log::warn!("{}", message);
+                self.progress.event(Event::Warning { message });
+                Vec::new()
+            }
+        };
+        let checkouts: Vec<Checkout> = checkouts
+            .into_iter()
+            .filter(|c| {
+                self.languages.is_empty() || self.languages.contains(&canonical_name(&c.lang))
+            })
+            .collect();
+        self.progress.event(Event::Scraped {
+            total: checkouts.len(),
+            expected: HashMap::new(),
+        });
+        let mut artifacts = Vec::new();
+        let mut failed = Vec::new();
+        for checkout in &checkouts {
+            let lang = checkout.lang.clone();
+            self.progress.event(Event::Started {
+                language: lang.clone(),
+            });
+            let status = |message: String| {
+                self.progress.event(Event::Status {
+                    language: lang.clone(),
+                    message,
+                })
+            };
+            let outcome = match self.grammar_builder.describe(checkout, &status) {
+                Ok(built) => Outcome::Built(built),
+                Err(e) => Outcome::Failed(Arc::new(e)),
+            };
+            self.announce(&lang, &outcome);
+            match outcome {
+                Outcome::Built(built) => artifacts.extend(built),
+                Outcome::Failed(e) => failed.push((lang, e)),
+                Outcome::Skipped | Outcome::Cancelled => {}
+            }
+        }
+        let extension_collisions = resolve_extension_collisions(
+            &mut artifacts,
+            &self.options().manifest.extension_priority,
+        );
+        let context = EmitContext {
+            hashes: &BTreeMap::new(),
+            compiler: self.options().builder.describe(),
+        };
+        for emitter in &self.emitters {
+            emitter.emit(&artifacts, &context)?;
+            self.progress.event(Event::Emitted {
+                destination: emitter.destination(),
+            });
+        }
+        Ok(PipelineReport {
+            listed: checkouts.iter().map(|c| canonical_name(&c.lang)).collect(),
+            artifacts,
+            failed,
+            skipped: Vec::new(),
+            reused: Vec::new(),
+            extension_collisions,
+            display_names: BTreeMap::new(),
+            skiplisted: Vec::new(),
+            untrusted: Vec::new(),
+            compiler: self.compiler(),
+        })
+    }
+
+    // Name every parser by its safe_name from here on, the scraped names go
+    // into the report. Entries without a usable name or repository, or named
+    // like one before them, are dropped with a warning.
+    fn sanitize(&self, parsers: Vec<ParserEntry>) -> (Vec<ParserEntry>, BTreeMap<String, String>) {
+        let mut display_names = BTreeMap::new();
+        let mut sanitized = Vec::new();
+        for parser in parsers {
+            // a URL git would take for an option, or that breaks up in a shell
+            let repo_url = &parser.repo_url;
+            let named = if repo_url.starts_with('-')
+                || repo_url
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control())
+            {
+                Err(format!("{:?} isn't a repository", repo_url))
+            } else {
+                languages::safe_name(&parser.name)
+            }
+            .and_then(|name| match display_names.get(&name) {
+                Some(first) => Err(format!("it is named {} like {:?}", name, first)),
+                None => Ok(name),
+            });
+            match named {
+                Ok(name) => {
+                    display_names.insert(name.clone(), parser.name);
+                    sanitized.push(ParserEntry {
+                        name,
+                        repo_url: parser.repo_url,
+                    });
+                }
+                Err(problem) => {
+                    let message = format!("skipping {}: {}", parser.name, problem);
+                    log::warn!("{}", message);
+                    self.progress.event(Event::Warning { message });
+                }
+            }
+        }
+        (sanitized, display_names)
+    }
+
+    // Warn when the checkouts the run is estimated to need, going by the sizes
+    // of the last ones, won't fit where they go
+    fn check_disk(&self, parsers: &[ParserEntry]) {
+        let sizes = match self.store.as_ref().map(|store| store.checkout_sizes()) {
+            Some(Ok(sizes)) => sizes,
+            Some(Err(e)) => {
+                log::warn!("can't estimate the disk usage : {}", e);
+                return;
+            }
+            None => HashMap::new(),
+        };
+        let languages: Vec<String> = parsers.iter().map(|p| p.name.clone()).collect();
+        let estimate = disk::estimate(&languages, &sizes);
+        if let Some(budget) = &self.disk_budget {
+            budget.expect(&sizes, disk::guess(&sizes));
+        }
+        let destination = &self.options().source_destination;
+        log::info!(
+            "the checkouts of {} grammars need about {} ({} never measured)",
+            languages.len(),
+            disk::format_size(estimate.bytes),
+            estimate.unmeasured
+        );
+        // at most the budget with one
+        let needed = match &self.disk_budget {
+            Some(budget) if estimate.bytes > budget.limit => {
+                log::info!(
+                    "that is over the disk budget of {}, clones wait for the checkouts of grammars that are through to be removed",
+                    disk::format_size(budget.limit)
+                );
+                budget.limit
+            }
+            _ => estimate.bytes,
+        };
+        let Some(available) = disk::available_space(Path::new(destination)) else {
+            return;
+        };
+        // some headroom for the libraries and everything else on that disk
+        if needed + needed / 10 > available {
+            let message = format!(
+                "the checkouts need about {} but only {} is free for {}",
+                disk::format_size(needed),
+                disk::format_size(available),
+                destination
+            );
+            log::warn!("{}", message);
+            self.progress.event(Event::Warning { message });
+        }
+    }
+
+    // the clones start in this order, and the builds follow them
+    fn schedule(&self, parsers: &mut [ParserEntry]) {
+        let mut history = History::default();
+        match (self.order, &self.store) {
+            (Order::Requested, _) => {}
+            (order, None) => {
+                log::info!(
+                    "no state database to order by {:?}, building in the sources' order",
+                    order
+                );
+            }
+            (Order::Smallest, Some(store)) => match store.checkout_sizes() {
+                Ok(sizes) => history.checkout_sizes = sizes,
+                Err(e) => log::warn!("can't order by the checkout sizes : {}", e),
+            },
+            (Order::Duration, Some(store)) => match store.language_stats() {
+                Ok(stats) => {
+                    history.durations = stats
+                        .into_iter()
+                        .map(|stats| (stats.language, stats.average_duration))
+                        .collect()
+                }
+                Err(e) => log::warn!("can't order by the build durations : {}", e),
+            },
+        }
+        schedule::sort(parsers, self.order, &self.requested, &history);
+        log::info!(
+            "building in this order: {}",
+            parsers
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // the average build durations the store has of `parsers`, for an ETA
+    fn expected_durations(&self, parsers: &[ParserEntry]) -> HashMap<String, Duration> {
+        let Some(store) = &self.store else {
+            return HashMap::new();
+        };
+        let stats: HashMap<String, Duration> = match store.language_stats() {
+            Ok(stats) => stats
+                .into_iter()
+                .map(|stats| (stats.language, stats.average_duration))
+                .collect(),
+            Err(e) => {
+                log::warn!("can't estimate how long the run takes : {}", e);
+                return HashMap::new();
+            }
+        };
+        parsers
+            .iter()
+            .filter_map(|p| {
+                let duration = stats.get(&canonical_name(&p.name))?;
+                Some((p.name.clone(), *duration))
+            })
+            .collect()
+    }
+
+    fn status_for(&self, lang: String) -> impl Fn(String) + '_ {
+        move |message| {
+            self.progress.event(Event::Status {
+                language: lang.clone(),
+                message,
+            })
+        }
+    }
+
+    // The run of the store this one is recorded in: the interrupted one when
+    // resuming, or a new one
+    fn begin_run(&self) -> (Option<Arc<RunRecorder>>, Option<InterruptedRun>) {
+        let Some(store) = &self.store else {
+            return (None, None);
+        };
+        let interrupted = match self.resume.then(|| store.interrupted_run()) {
+            Some(Ok(interrupted)) => interrupted,
+            Some(Err(e)) => {
+                log::warn!("can't resume, starting a new run : {}", e);
+                None
+            }
+            None => None,
+        };
+        let run = match &interrupted {
+            Some(interrupted) => {
+                log::info!(
+                    "resuming run {}, {} grammars already built",
+                    interrupted.id,
+                    interrupted.built.len()
+                );
+                Ok(interrupted.id)
+            }
+            None => {
+                if self.resume {
+                    log::info!("the last run finished, nothing to resume");
+                }
+                store.begin_run()
+            }
+        };
+        match run {
+            Ok(run) => (
+                Some(Arc::new(RunRecorder::new(Arc::clone(store), run))),
+                interrupted,
+            ),
+            Err(e) => {
+                log::warn!("not recording this run : {}", e);
+                (None, interrupted)
+            }
+        }
+    }
+
+    // The last builds of `parsers` that are of the commit their default branch
+    // is at now, asked of the remotes `network_jobs` at a time. Languages
+    // with a `revision` are always rebuilt.
+    async fn unchanged_builds(
+        &self,
+        store: &Store,
+        parsers: &[ParserEntry],
+    ) -> HashMap<String, Vec<LastBuild>> {
+        let mut last = match store.last_builds() {
+            Ok(builds) => by_language(builds),
+            Err(e) => {
+                log::warn!("can't tell what changed, rebuilding everything : {}", e);
+                return HashMap::new();
+            }
+        };
+        let network = Arc::new(Semaphore::new(self.network_jobs.max(1)));
+        let mut checks = JoinSet::new();
+        for parser in parsers {
+            // checked out at a --rev, which goes by any name canonical_name understands
+            if self.revisions.contains_key(&canonical_name(&parser.name)) {
+                continue;
+            }
+            let language = own_name(&parser.name);
+            let Some(builds) = last.remove(&language) else {
+                continue;
+            };
+            // built with other flags or another compiler, it has to be rebuilt
+            // whatever the remote says
+            let options_hash = self.options().options_hash(&language);
+            if builds
+                .iter()
+                .any(|b| b.options_hash.as_deref() != Some(options_hash.as_str()))
+            {
+                log::info!("{} was built with other options, rebuilding it", language);
+                continue;
+            }
+            let repo_url = parser.repo_url.clone();
+            let network = Arc::clone(&network);
+            checks.spawn(async move {
+                let _permit = network.acquire_owned().await.expect("never closed");
+                let head = remote_head_commit(&repo_url).await;
+                let unchanged = head.is_some()
+                    && builds
+                        .iter()
+                        .all(|b| b.commit == head && b.repo_url == repo_url);
+                (language, builds, unchanged)
+            });
+        }
+        let mut unchanged = HashMap::new();
+        while let Some(checked) = checks.join_next().await {
+            match checked {
+                Ok((language, builds, true)) => {
+                    unchanged.insert(language, builds);
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("a remote check died: {}", e),
+            }
+        }
+        unchanged
+    }
+
+    // A failed stage, unless the run was cancelled meanwhile: then it most
+    // likely failed because its process was killed, and is built again on resume
+    fn failure(&self, lang: &str, error: BuildError) -> Outcome {
+        let cancel = &self.options().cancel;
+        if cancel.is_cancelled() {
+            self.grammar_builder.discard(lang);
+            return Outcome::Cancelled;
+        }
+        if self.fail_fast {
+            cancel.cancel(format!("{} failed and fail-fast is on", lang));
+        }
+        Outcome::Failed(Arc::new(error))
+    }
+
+    // report the outcome of one language, from whichever stage it ended in
+    fn finish(
+        &self,
+        recorder: Option<&RunRecorder>,
+        outcomes: &Sender<(String, Outcome)>,
+        lang: String,
+        outcome: Outcome,
+    ) {
+        // whatever goes wrong in the bookkeeping, the outcome is still announced and sent
+        contained(&lang, || self.record(recorder, &lang, &outcome));
+        if let Some(budget) = &self.disk_budget {
+            let cancel = &self.options().cancel;
+            reclaim(
+                budget,
+                budget.finished(&lang),
+                &self.grammar_builder,
+                cancel,
+            );
+        }
+        self.announce(&lang, &outcome);
+        // the receiver lives until every stage is done
+        let _ = outcomes.send((lang, outcome));
+    }
+
+    // the outcome in the store and the metrics
+    fn record(&self, recorder: Option<&RunRecorder>, lang: &str, outcome: &Outcome) {
+        if let Some(recorder) = recorder {
+            match outcome {
+                Outcome::Built(artifacts) => {
+                    recorder.built(lang, artifacts, &self.options().options_hash(lang))
+                }
+                Outcome::Failed(e) => recorder.failed(lang, e),
+                Outcome::Skipped => recorder.skipped(lang),
+                // left as it was, for resume to pick up
+                Outcome::Cancelled => {}
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            match outcome {
+                Outcome::Built(artifacts) => {
+                    metrics.build_ended(Ended::Succeeded);
+                    for path in artifacts
+                        .iter()
+                        .filter_map(|a| a.entry.get("path").and_then(|p| p.as_str()))
+                    {
+                        if let Ok(metadata) = std::fs::metadata(path) {
+                            metrics.observe_artifact_size(metadata.len());
+                        }
+                    }
+                }
+                Outcome::Failed(_) => metrics.build_ended(Ended::Failed),
+                Outcome::Skipped => metrics.build_ended(Ended::Skipped),
+                Outcome::Cancelled => {}
+            }
+        }
+    }
+
+    // the outcome in the log and to the event handler
+    fn announce(&self, lang: &str, outcome: &Outcome) {
+        let lang = lang.to_string();
+        match outcome {
+            Outcome::Built(artifacts) => {
+                log::info!("Done with {}", lang);
+                self.progress.event(Event::Finished {
+                    language: lang.clone(),
+                    artifacts: artifacts.len(),
+                });
+            }
+            Outcome::Failed(e) => {
+                log::warn!("failed for {} : {}", lang, e);
+                self.progress.event(Event::Failed {
+                    language: lang.clone(),
+                    error: Arc::clone(e),
+                });
+            }
+            Outcome::Skipped => {
+                log::info!("skipped {}, a build filter dropped it", lang);
+                self.progress.event(Event::Skipped {
+                    language: lang.clone(),
+                });
+            }
+            Outcome::Cancelled => {
+                log::info!("abandoned {}, the run was cancelled", lang);
+                self.progress.event(Event::Cancelled { language: lang });
+            }
+        }
+    }
+}
+
+// how a language left the pipeline
+enum Outcome {
+    Built(Vec<LanguageArtifact>),
+    Failed(Arc<BuildError>),
+    Skipped,
+    Cancelled,
+}
+
+fn by_language(builds: Vec<LastBuild>) -> HashMap<String, Vec<LastBuild>> {
+    let mut grouped: HashMap<String, Vec<LastBuild>> = HashMap::new();
+    for build in builds {
+        grouped
+            .entry(build.language.clone())
+            .or_default()
+            .push(build);
+    }
+    grouped
+}
+
+// Take the parsers whose earlier build can stand in for a new one out of
+// `parsers`: every library it built is still there, unchanged.
+fn reuse(
+    parsers: &mut Vec<ParserEntry>,
+    mut builds: HashMap<String, Vec<LastBuild>>,
+    reused: &mut Vec<String>,
+    artifacts: &mut Vec<LanguageArtifact>,
+) {
+    parsers.retain(|parser| match builds.remove(&own_name(&parser.name)) {
+        Some(builds) if builds.iter().all(LastBuild::artifact_intact) => {
+            log::info!(
+                "keeping the build of {} from {}",
+                parser.name,
+                builds[0].finished_at
+            );
+            reused.push(parser.name.clone());
+            artifacts.extend(builds.iter().filter_map(LastBuild::to_artifact));
+            false
+        }
+        _ => true,
+    });
+}
+
+// Run one stage for one grammar. A panic still has to end the language, or
+// whoever listens for its events waits forever.
+fn guarded<T>(
+    phase: Phase,
+    stage: impl FnOnce() -> Result<T, BuildError>,
+) -> Result<T, BuildError> {
+    panic::catch_unwind(AssertUnwindSafe(stage)).unwrap_or_else(|payload| {
+        Err(BuildError::Panicked {
+            phase,
+            message: panic_message(&payload),
+        })
+    })
+}
+
+// a sink panicking on one event mustn't take a worker down with it
+struct Contained(Arc<dyn ProgressSink>);
+
+impl ProgressSink for Contained {
+    fn event(&self, event: Event) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.0.event(event))) {
+            log::error!("the progress sink panicked: {}", panic_message(&payload));
+        }
+    }
+
+    fn finished(&self) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.0.finished())) {
+            log::error!("the progress sink panicked: {}", panic_message(&payload));
+        }
+    }
+}
+
+// how often a clone waiting for disk space looks again
+const DISK_POLL: Duration = Duration::from_millis(200);
+
+// Remove the checkouts the disk budget gave up, and cancel the run for a
+// checkout that can't fit at all
+fn reclaim(budget: &DiskBudget, reclaim: Reclaim, builder: &GrammarBuilder, cancel: &CancelToken) {
+    for lang in &reclaim.remove {
+        log::info!(
+            "removing the checkout of {} to stay under the disk budget",
+            lang
+        );
+        builder.discard(lang);
+    }
+    if let Some(bytes) = reclaim.too_big {
+        if !cancel.is_cancelled() {
+            cancel.cancel(format!(
+                "a checkout takes {}, over the whole disk budget of {}",
+                disk::format_size(bytes),
+                disk::format_size(budget.limit)
+            ));
+        }
+    }
+}
+
+// Run bookkeeping for `lang` (the store, the metrics), logging a panic rather
+// than letting it take the worker down
+fn contained(lang: &str, bookkeeping: impl FnOnce()) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(bookkeeping)) {
+        log::error!(
+            "recording the progress of {} panicked: {}",
+            lang,
+            panic_message(&payload)
+        );
+    }
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl PipelineBuilder {
+    // where to find the parsers to build, the wiki by default
+    pub fn source(mut self, source: impl ParserSource + 'static) -> Self {
+        self.source = Box::new(source);
+        self
+    }
+
+    // only build these languages, by any name canonical_name understands
+    pub fn languages<I, S>(mut self, languages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.languages = languages
+            .into_iter()
+            .map(|l| canonical_name(l.as_ref()))
+            .collect();
+        self
+    }
+
+    // build `language` (by any name canonical_name understands) at a branch,
+    // tag or commit instead of its default branch
+    pub fn revision(mut self, language: &str, rev: impl Into<String>) -> Self {
+        self.revisions.insert(canonical_name(language), rev.into());
+        self
+    }
+
+    // directory the libraries go to, wins over the one in `options`
+    pub fn output(mut self, output_dir: impl Into<String>) -> Self {
+        self.output = Some(output_dir.into());
+        self
+    }
+
+    pub fn options(mut self, options: BuildOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // how many grammars compile at once, one per CPU by default
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    // how many clones talk to the network at once, NETWORK_JOBS by default
+    pub fn network_jobs(mut self, network_jobs: usize) -> Self {
+        self.network_jobs = network_jobs;
+        self
+    }
+
+    // how many grammars go through the checks that load them at once, 4 by default
+    pub fn verify_jobs(mut self, verify_jobs: usize) -> Self {
+        self.verify_jobs = verify_jobs;
+        self
+    }
+
+    // Only clone the parsers `keep` returns true for. Applied after `languages`,
+    // to the entries already named by their languages::safe_name, every filter
+    // has to agree.
+    pub fn filter_parsers(
+        mut self,
+        keep: impl Fn(&ParserEntry) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.parser_filters.push(Box::new(keep));
+        self
+    }
+
+    // Drop compiled grammars `keep` returns false for before they are verified
+    // and written out, e.g. by ABI. They end with a Skipped event.
+    pub fn filter_builds(
+        mut self,
+        keep: impl Fn(&BuiltGrammar) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.build_filters.push(Box::new(keep));
+        self
+    }
+
+    // write the built languages into this config at the end of the run
+    pub fn config(
+        self,
+        path: impl Into<String>,
+        format: ConfigFormat,
+        path_style: Option<PathStyle>,
+    ) -> Self {
+        self.emit(NativeConfig {
+            path: path.into(),
+            format,
+            path_style,
+        })
+    }
+
+    // Write another output at the end of the run, in the order they were added.
+    // The first failing one ends the run with an error.
+    pub fn emit(mut self, emitter: impl Emitter + 'static) -> Self {
+        self.emitters.push(Box::new(emitter));
+        self
+    }
+
+    // Record the run and every grammar's outcome in `store`, for the stats and
+    // outdated queries and for `resume` and `incremental` to build on
+    pub fn store(mut self, store: Arc<Store>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    // Carry on with the store's newest run if it never finished, keeping what
+    // it had already built. Needs a `store`.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    // Count attempts, outcomes, clone and compile times and library sizes in
+    // `metrics`, which can be shared across runs
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Keep the last build of every language still at the commit it was built
+    // from, with its libraries untouched, rather than cloning it again. Needs
+    // a `store`.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    // Don't scrape, clone or compile anything: write the outputs for the
+    // checkouts in the source destination and the libraries built from them
+    // that are already in the output directory
+    pub fn only_config(mut self, only_config: bool) -> Self {
+        self.only_config = only_config;
+        self
+    }
+
+    // Stop the run when `cancel` is: clones and compiles in flight are killed,
+    // queued grammars abandoned and `run` returns PipelineError::Cancelled.
+    // The run is left unfinished in the store, for `resume`.
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    // Cancel the run at the first grammar that fails
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    // Keep the run's checkouts under `bytes`: once they are over it, the
+    // checkouts of grammars that are through are removed, and if that isn't
+    // enough the run is cancelled
+    pub fn max_disk(mut self, bytes: u64) -> Self {
+        self.max_disk = Some(bytes);
+        self
+    }
+
+    // Leave the grammars on `skiplist` out of the run, except the ones
+    // `requested` names
+    pub fn skiplist(mut self, skiplist: Skiplist) -> Self {
+        self.skiplist = skiplist;
+        self
+    }
+
+    // Only clone repositories of the owners `trust` lists, the others are
+    // refused (or with warn_only built with a warning). Empty allows all.
+    pub fn trust(mut self, trust: TrustPolicy) -> Self {
+        self.trust = trust;
+        self
+    }
+
+    // which grammars are cloned and built first, Order::Requested by default
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    // The languages asked for by name, most wanted first. Order::Requested
+    // starts with these, before the rest of `languages`.
+    pub fn requested<I, S>(mut self, languages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.requested = languages
+            .into_iter()
+            .map(|l| l.as_ref().to_string())
+            .collect();
+        self
+    }
+
+    // Report the run's progress to `sink`: progress::Terminal, JsonLines,
+    // NoProgress (the default) or one of your own
+    pub fn progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Arc::new(Contained(sink));
+        self
+    }
+
+    // Called with every event of the run, from whichever thread it happens on.
+    // Forward to a channel to handle them elsewhere.
+    pub fn on_event(self, handler: impl Fn(Event) + Send + Sync + 'static) -> Self {
+        self.progress(Arc::new(handler))
+    }
+
+    pub fn build(mut self) -> Pipeline {
+        if let Some(output_dir) = self.output {
+            self.options.output_dir = output_dir;
+        }
+        if let Some(cancel) = self.cancel {
+            self.options.cancel = cancel;
+        }
+        Pipeline {
+            source: self.source,
+            languages: self.languages,
+            revisions: self.revisions,
+            parser_filters: self.parser_filters,
+            build_filters: self.build_filters,
+            threads: self.threads,
+            network_jobs: self.network_jobs,
+            verify_jobs: self.verify_jobs,
+            emitters: self.emitters,
+            store: self.store,
+            metrics: self.metrics,
+            resume: self.resume,
+            incremental: self.incremental,
+            only_config: self.only_config,
+            fail_fast: self.fail_fast,
+            disk_budget: self.max_disk.map(|limit| Arc::new(DiskBudget::new(limit))),
+            order: self.order,
+            requested: self.requested,
+            skiplist: self.skiplist,
+            trust: self.trust,
+            grammar_builder: Arc::new(GrammarBuilder::new(self.options)),
+            progress: self.progress,
+        }
+    }
+}
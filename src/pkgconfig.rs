@@ -0,0 +1,135 @@
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::LanguageArtifact;
+use crate::emit::{EmitContext, Emitter};
+use crate::error::EmitError;
+use crate::languages::{checked_symbol_name, safe_name};
+
+// tree-sitter-<name>, as the grammars' own Makefiles name their .pc files
+pub fn package_name(name: &str) -> String {
+    format!("tree-sitter-{}", name.replace('_', "-"))
+}
+
+// package_name of an artifact, whose name could have come from any config file
+fn artifact_package(artifact: &LanguageArtifact) -> io::Result<String> {
+    safe_name(&artifact.name)
+        .map(|name| package_name(&name))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// A `<name>.pc` per built grammar in <dir>/pkgconfig/, and the header
+// declaring its entry point in <dir>/include/tree_sitter/. The paths go from
+// ${pcfiledir}, so the output directory can be moved as a whole.
+pub fn write_pkg_config(
+    dir: &str,
+    artifacts: &[LanguageArtifact],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let dir = Path::new(dir);
+    let pc_dir = dir.join("pkgconfig");
+    let include_dir = dir.join("include").join("tree_sitter");
+    fs::create_dir_all(&pc_dir)?;
+    fs::create_dir_all(&include_dir)?;
+    for artifact in artifacts {
+        let Some(library) = artifact.entry.get("path").and_then(Value::as_str) else {
+            continue;
+        };
+        let library = Path::new(library);
+        let package = artifact_package(artifact)?;
+        let file_name = library
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // inside the output directory relative to the .pc file, else where it is
+        let libdir = match library.parent().map(|parent| parent.strip_prefix(dir)) {
+            Some(Ok(relative)) if relative.as_os_str().is_empty() => "${prefix}".to_string(),
+            Some(Ok(relative)) => format!("${{prefix}}/{}", relative.display()),
+            _ => fs::canonicalize(library.parent().unwrap_or(Path::new(".")))?
+                .display()
+                .to_string(),
+        };
+        // -l only finds lib<name>.so (or .dylib), any other name is linked by path
+        let libs = match file_name
+            .strip_prefix("lib")
+            .and_then(|rest| rest.strip_suffix(".so").or(rest.strip_suffix(".dylib")))
+        {
+            Some(name) => format!("-L${{libdir}} -l{}", name),
+            None => format!("${{libdir}}/{}", file_name),
+        };
+        let version = artifact
+            .entry
+            .get("version")
+            .and_then(Value::as_str)
+            .unwrap_or("0.0.0");
+        let pc = format!(
+            "# @generated by parser_scraper, do not edit\n\
+             prefix=${{pcfiledir}}/..\n\
+             libdir={}\n\
+             includedir=${{prefix}}/include\n\
+             \n\
+             Name: {}\n\
+             Description: {} grammar for tree-sitter\n\
+             URL: {}\n\
+             Version: {}\n\
+             Libs: {}\n\
+             Cflags: -I${{includedir}}\n",
+            libdir, package, artifact.name, artifact.repo_url, version, libs
+        );
+        fs::write(pc_dir.join(format!("{}.pc", package)), pc)?;
+        write_header(&include_dir, artifact)?;
+    }
+    Ok(())
+}
+
+// <include_dir>/tree-sitter-<name>.h declaring the grammar's entry point, like
+// the bindings/c header of the grammars that have one
+pub fn write_header(include_dir: &Path, artifact: &LanguageArtifact) -> io::Result<()> {
+    let language = artifact
+        .entry
+        .get("language")
+        .and_then(Value::as_str)
+        .unwrap_or(&artifact.name);
+    let symbol =
+        checked_symbol_name(language).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let guard = format!("{}_H_", symbol.to_uppercase());
+    let header = format!(
+        "/* @generated by parser_scraper, do not edit */\n\
+         #ifndef {guard}\n\
+         #define {guard}\n\
+         \n\
+         typedef struct TSLanguage TSLanguage;\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {{\n\
+         #endif\n\
+         \n\
+         const TSLanguage *{symbol}(void);\n\
+         \n\
+         #ifdef __cplusplus\n\
+         }}\n\
+         #endif\n\
+         \n\
+         #endif\n"
+    );
+    fs::write(
+        include_dir.join(format!("{}.h", artifact_package(artifact)?)),
+        header,
+    )
+}
+
+// --emit pkg-config, into `dir`
+pub struct PkgConfig {
+    pub dir: String,
+}
+
+impl Emitter for PkgConfig {
+    fn destination(&self) -> String {
+        Path::new(&self.dir).join("pkgconfig").display().to_string()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], _: &EmitContext) -> Result<(), EmitError> {
+        write_pkg_config(&self.dir, artifacts).map_err(EmitError::write(self.destination()))
+    }
+}
@@ -0,0 +1,373 @@
+use reqwest::blocking::Client;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::builder::{CompileJob, GrammarCompiler};
+use crate::error::CompileError;
+
+// --prefer-prebuilt: the library a grammar's GitHub release ships for this
+// target, when the checkout is at that release and the release has a
+// checksum for it. Anything else is compiled by `fallback` as usual.
+#[derive(Debug)]
+pub struct PrebuiltBuilder {
+    pub fallback: Arc<dyn GrammarCompiler>,
+    // GITHUB_API_URL, which Actions sets, or api.github.com
+    pub api_url: String,
+}
+
+impl PrebuiltBuilder {
+    pub fn new(fallback: Arc<dyn GrammarCompiler>) -> PrebuiltBuilder {
+        PrebuiltBuilder {
+            fallback,
+            api_url: std::env::var("GITHUB_API_URL")
+                .unwrap_or_else(|_| "https://api.github.com".to_string()),
+        }
+    }
+
+    // Ok(false) when there is no usable prebuilt library, why is logged
+    fn download(&self, job: &CompileJob) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(repo) = git(job.repo_dir, &["remote", "get-url", "origin"]) else {
+            return Ok(false);
+        };
+        let Some((owner, name)) = github_repo(&repo) else {
+            log::info!(
+                "{} isn't on GitHub, building {} from source",
+                repo,
+                job.lang
+            );
+            return Ok(false);
+        };
+        // a release's library only matches the sources it was tagged on
+        let Some(tag) = git(
+            job.repo_dir,
+            &["describe", "--tags", "--exact-match", "HEAD"],
+        ) else {
+            log::info!(
+                "{} isn't checked out at a release, building {} from source",
+                repo,
+                job.lang
+            );
+            return Ok(false);
+        };
+
+        let client = Client::builder().timeout(Duration::from_secs(60)).build()?;
+        let get = |url: &str| {
+            let request = client
+                .get(url)
+                .header("User-Agent", "parser_scraper")
+                .header("Accept", "application/vnd.github+json");
+            match std::env::var("GITHUB_TOKEN") {
+                Ok(token) => request.bearer_auth(token),
+                Err(_) => request,
+            }
+        };
+        let response = get(&format!(
+            "{}/repos/{}/{}/releases/tags/{}",
+            self.api_url, owner, name, tag
+        ))
+        .send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            log::info!("{} has no release for {}, building from source", repo, tag);
+            return Ok(false);
+        }
+        let release: Value = response.error_for_status()?.json()?;
+        let assets: Vec<(&str, &str)> = release["assets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|asset| {
+                Some((
+                    asset["name"].as_str()?,
+                    asset["browser_download_url"].as_str()?,
+                ))
+            })
+            .collect();
+
+        let Some((asset, url)) = assets
+            .iter()
+            .find(|(asset, _)| matches_target(asset, job.lang))
+        else {
+            log::info!(
+                "the {} release of {} has no library for {}-{}, building from source",
+                tag,
+                repo,
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            );
+            return Ok(false);
+        };
+        // an unchecked download could be anything
+        let Some(expected) = assets
+            .iter()
+            .filter(|(name, _)| is_checksum_file(name, asset))
+            .find_map(|(_, url)| {
+                let listing = get(url).send().ok()?.error_for_status().ok()?.text().ok()?;
+                checksum_of(&listing, asset)
+            })
+        else {
+            log::warn!(
+                "the {} release of {} has no checksum for {}, building {} from source",
+                tag,
+                repo,
+                asset,
+                job.lang
+            );
+            return Ok(false);
+        };
+
+        let library = get(url).send()?.error_for_status()?.bytes()?;
+        let actual: String = Sha256::digest(&library)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if actual != expected {
+            log::warn!(
+                "{} of the {} release of {} doesn't match its checksum, building {} from source",
+                asset,
+                tag,
+                repo,
+                job.lang
+            );
+            return Ok(false);
+        }
+        fs::write(job.output, &library)?;
+        log::info!("downloaded {} ({}) for {}", asset, tag, job.lang);
+        Ok(true)
+    }
+}
+
+impl GrammarCompiler for PrebuiltBuilder {
+    fn compile(&self, job: &CompileJob) -> Result<(), CompileError> {
+        match self.download(job) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => log::warn!(
+                "downloading a prebuilt {} failed: {}, building from source",
+                job.lang,
+                e
+            ),
+        }
+        self.fallback.compile(job)
+    }
+
+    fn describe(&self) -> String {
+        format!("prebuilt release assets, else {}", self.fallback.describe())
+    }
+
+    fn compiler_version(&self) -> Option<String> {
+        self.fallback.compiler_version()
+    }
+
+    fn takes_flags(&self) -> bool {
+        self.fallback.takes_flags()
+    }
+}
+
+fn git(repo_dir: &Path, args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|out| !out.is_empty())
+}
+
+// (owner, repo) of a GitHub clone URL, https or ssh
+fn github_repo(url: &str) -> Option<(String, String)> {
+    let path = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("git@github.com:"))?;
+    let (owner, repo) = path.trim_end_matches('/').split_once('/')?;
+    let repo = repo.trim_end_matches(".git");
+    (!owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+        .then(|| (owner.to_string(), repo.to_string()))
+}
+
+// A shared library for this OS and architecture named after the language,
+// however the release spells them: tree-sitter-rust-linux-x64.so,
+// libtree-sitter-rust.aarch64-apple-darwin.dylib, ...
+fn matches_target(asset: &str, lang: &str) -> bool {
+    matches_platform(asset, lang, std::env::consts::OS, std::env::consts::ARCH)
+}
+
+fn matches_platform(asset: &str, lang: &str, os: &str, arch: &str) -> bool {
+    let asset = asset.to_lowercase();
+    let (os_names, extension): (&[&str], &str) = match os {
+        "macos" => (&["macos", "darwin", "apple"], "dylib"),
+        "windows" => (&["windows", "win"], "dll"),
+        os => (&[os][..], "so"),
+    };
+    let arch_names: &[&str] = match arch {
+        "x86_64" => &["x86_64", "x64", "amd64"],
+        "aarch64" => &["aarch64", "arm64"],
+        arch => &[arch][..],
+    };
+    if !asset.ends_with(&format!(".{}", extension))
+        || !os_names.iter().any(|os| asset.contains(os))
+        || !arch_names.iter().any(|arch| asset.contains(arch))
+    {
+        return false;
+    }
+    // The language has to be the whole name: right after the lib and
+    // tree-sitter prefixes, and followed by the target or a version.
+    // markdown isn't tree-sitter-markdown-inline, c isn't tree-sitter-cpp.
+    let tokens = name_tokens(&asset);
+    let start = tokens
+        .iter()
+        .take_while(|t| matches!(t.as_str(), "lib" | "libtree" | "tree" | "sitter"))
+        .count();
+    let Some(after) = tokens[start..].strip_prefix(name_tokens(lang).as_slice()) else {
+        return false;
+    };
+    let Some(next) = after.first() else {
+        return false;
+    };
+    let is_target = |t: &str| {
+        t == extension
+            || os_names.contains(&t)
+            || arch_names
+                .iter()
+                .any(|arch| name_tokens(arch).first().is_some_and(|a| a == t))
+            || t.trim_start_matches('v')
+                .starts_with(|c: char| c.is_ascii_digit())
+    };
+    is_target(next)
+}
+
+// the parts of a name between -, _ and .
+fn name_tokens(name: &str) -> Vec<String> {
+    name.to_lowercase()
+        .split(['-', '_', '.'])
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// <asset>.sha256 or a release-wide SHA256SUMS, checksums.txt and the like
+fn is_checksum_file(name: &str, asset: &str) -> bool {
+    let name = name.to_lowercase();
+    match name.strip_suffix(".sha256") {
+        Some(of) => of == asset.to_lowercase(),
+        None => name.contains("sha256") || name.contains("checksums"),
+    }
+}
+
+// the hash `sha256sum` printed for `asset`, or the lone hash of a .sha256 file
+fn checksum_of(listing: &str, asset: &str) -> Option<String> {
+    let is_hash = |s: &str| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit());
+    let mut words = listing.split_whitespace();
+    if let (Some(hash), None) = (words.next(), words.next()) {
+        return is_hash(hash).then(|| hash.to_lowercase());
+    }
+    listing.lines().find_map(|line| {
+        let (hash, name) = line.split_once(char::is_whitespace)?;
+        let name = name.trim().trim_start_matches('*');
+        (is_hash(hash) && name == asset).then(|| hash.to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on_linux(asset: &str, lang: &str) -> bool {
+        matches_platform(asset, lang, "linux", "x86_64")
+    }
+
+    #[test]
+    fn assets_of_the_language_and_target() {
+        assert!(on_linux("tree-sitter-rust-linux-x64.so", "rust"));
+        assert!(on_linux(
+            "libtree-sitter-rust.x86_64-unknown-linux-gnu.so",
+            "rust"
+        ));
+        assert!(on_linux("tree-sitter-rust-v0.21.0-linux-amd64.so", "rust"));
+        assert!(on_linux("C_Sharp-linux-x86_64.so", "c_sharp"));
+        assert!(on_linux(
+            "tree-sitter-markdown-inline-linux-x64.so",
+            "markdown_inline"
+        ));
+        assert!(matches_platform(
+            "libtree-sitter-rust.aarch64-apple-darwin.dylib",
+            "rust",
+            "macos",
+            "aarch64"
+        ));
+        // other targets
+        assert!(!on_linux("tree-sitter-rust-linux-arm64.so", "rust"));
+        assert!(!on_linux("tree-sitter-rust-macos-x64.dylib", "rust"));
+        assert!(!on_linux("tree-sitter-rust-linux-x64.tar.gz", "rust"));
+    }
+
+    #[test]
+    fn assets_of_other_grammars() {
+        assert!(!on_linux(
+            "tree-sitter-markdown-inline-linux-x64.so",
+            "markdown"
+        ));
+        assert!(!on_linux("tree-sitter-php-only-linux-x64.so", "php"));
+        assert!(!on_linux("tree-sitter-cpp-linux-x64.so", "c"));
+        assert!(!on_linux("tree-sitter-c-sharp-linux-x64.so", "c"));
+        assert!(!on_linux("tree-sitter-typescript-linux-x64.so", "tsx"));
+        assert!(on_linux("tree-sitter-c-linux-x64.so", "c"));
+    }
+
+    #[test]
+    fn checksums() {
+        let hash = "a".repeat(64);
+        let other = "b".repeat(64);
+        let listing = format!(
+            "{}  tree-sitter-rust-linux-x64.so\n{} *tree-sitter-rust-macos-x64.dylib\n",
+            hash, other
+        );
+        assert_eq!(
+            checksum_of(&listing, "tree-sitter-rust-linux-x64.so"),
+            Some(hash.clone())
+        );
+        assert_eq!(
+            checksum_of(&listing, "tree-sitter-rust-macos-x64.dylib"),
+            Some(other)
+        );
+        assert_eq!(
+            checksum_of(&listing, "tree-sitter-rust-linux-arm64.so"),
+            None
+        );
+        // a .sha256 file holding only the hash
+        assert_eq!(
+            checksum_of(&format!("{}\n", hash.to_uppercase()), "any.so"),
+            Some(hash)
+        );
+        assert_eq!(checksum_of("not-a-hash", "any.so"), None);
+        assert!(is_checksum_file("rust.so.sha256", "rust.so"));
+        assert!(!is_checksum_file("json.so.sha256", "rust.so"));
+        assert!(is_checksum_file("SHA256SUMS", "rust.so"));
+    }
+
+    #[test]
+    fn github_repos() {
+        let repo = |owner: &str, name: &str| Some((owner.to_string(), name.to_string()));
+        assert_eq!(
+            github_repo("https://github.com/tree-sitter/tree-sitter-rust"),
+            repo("tree-sitter", "tree-sitter-rust")
+        );
+        assert_eq!(
+            github_repo("https://github.com/tree-sitter/tree-sitter-rust.git/"),
+            repo("tree-sitter", "tree-sitter-rust")
+        );
+        assert_eq!(
+            github_repo("git@github.com:tree-sitter/tree-sitter-rust.git"),
+            repo("tree-sitter", "tree-sitter-rust")
+        );
+        assert_eq!(github_repo("https://gitlab.com/a/b"), None);
+        assert_eq!(github_repo("https://github.com/a/b/tree/main"), None);
+        assert_eq!(github_repo("https://github.com/a"), None);
+    }
+}
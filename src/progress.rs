@@ -0,0 +1,360 @@
+use serde_json::{json, Value};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::failure;
+use crate::pipeline::Event;
+
+// Where a pipeline reports its progress. Events come from the worker threads
+// as they happen, `finished` once when the run is over (or stopped short).
+// A sink that panics only loses the event it panicked on.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: Event);
+
+    fn finished(&self) {}
+}
+
+// any `Fn(Event)` is a sink, which is what Pipeline::on_event takes
+impl<F: Fn(Event) + Send + Sync> ProgressSink for F {
+    fn event(&self, event: Event) {
+        self(event)
+    }
+}
+
+// reports nothing, the log still has everything
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn event(&self, _event: Event) {}
+}
+
+// One JSON object per event and line, for a supervisor to read
+pub struct JsonLines<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(out: W) -> Self {
+        JsonLines {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonLines<W> {
+    fn event(&self, event: Event) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // a reader that went away isn't a reason to stop the build
+        let _ = writeln!(out, "{}", event_json(&event)).and_then(|_| out.flush());
+    }
+}
+
+// the JSON of one event, as JsonLines writes it and the serve API streams it
+pub fn event_json(event: &Event) -> Value {
+    match event {
+        Event::Scraped { total, expected } => {
+            let expected: serde_json::Map<String, Value> = expected
+                .iter()
+                .map(|(lang, duration)| (lang.clone(), json!(duration.as_secs_f64())))
+                .collect();
+            json!({ "event": "scraped", "total": total, "expected_seconds": expected })
+        }
+        Event::Started { language } => json!({ "event": "started", "language": language }),
+        Event::Status { language, message } => {
+            json!({ "event": "status", "language": language, "message": message })
+        }
+        Event::Finished {
+            language,
+            artifacts,
+        } => json!({ "event": "finished", "language": language, "artifacts": artifacts }),
+        Event::Failed { language, error } => {
+            let failure = failure::classify(error);
+            json!({
+                "event": "failed",
+                "language": language,
+                "error": error.to_string(),
+                "phase": failure.phase.as_str(),
+                "category": failure.category.as_str(),
+            })
+        }
+        Event::Skipped { language } => json!({ "event": "skipped", "language": language }),
+        Event::Cancelled { language } => json!({ "event": "cancelled", "language": language }),
+        Event::Emitted { destination } => {
+            json!({ "event": "emitted", "destination": destination })
+        }
+        Event::Warning { message } => json!({ "event": "warning", "message": message }),
+    }
+}
+
+#[cfg(feature = "terminal")]
+pub use terminal::Terminal;
+
+#[cfg(feature = "terminal")]
+mod terminal {
+    use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    use super::ProgressSink;
+    use crate::failure;
+    use crate::pipeline::Event;
+
+    // The CLI's progress bars: an overall one and a spinner per running build
+    pub struct Terminal {
+        multi_progress: MultiProgress,
+        overall: ProgressBar,
+        // created when a build starts. The lock is taken poisoned or not, a
+        // panic on one event mustn't leave the spinners of every other build
+        // hanging
+        spinners: Arc<Mutex<HashMap<String, ProgressBar>>>,
+        failed: Arc<AtomicUsize>,
+        timing: Arc<Mutex<Timing>>,
+        ticking: Arc<AtomicBool>,
+        ticker: Mutex<Option<JoinHandle<()>>>,
+    }
+
+    impl Default for Terminal {
+        fn default() -> Self {
+            let multi_progress = MultiProgress::new();
+            let overall = multi_progress.add(ProgressBar::new(0));
+            overall.set_style(
+                ProgressStyle::default_bar()
+                    .template("[{elapsed_precise}] {pos}/{len} completed {msg}")
+                    .unwrap(),
+            );
+            let spinners: Arc<Mutex<HashMap<String, ProgressBar>>> = Arc::default();
+            let failed = Arc::new(AtomicUsize::new(0));
+            let timing: Arc<Mutex<Timing>> = Arc::default();
+            // a single thread animates all of them, rather than one ticker per
+            // bar, and keeps the ETA of the overall bar current
+            let ticking = Arc::new(AtomicBool::new(true));
+            let ticker = {
+                let spinners = Arc::clone(&spinners);
+                let ticking = Arc::clone(&ticking);
+                let (overall, failed, timing) =
+                    (overall.clone(), Arc::clone(&failed), Arc::clone(&timing));
+                thread::spawn(move || {
+                    let mut ticks = 0u32;
+                    while ticking.load(Ordering::Relaxed) {
+                        for pb in lock(&spinners).values() {
+                            pb.tick();
+                        }
+                        if ticks.is_multiple_of(10) {
+                            overall.set_message(overall_message(&failed, &lock(&timing)));
+                        }
+                        ticks = ticks.wrapping_add(1);
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                })
+            };
+            Terminal {
+                multi_progress,
+                overall,
+                spinners,
+                failed,
+                timing,
+                ticking,
+                ticker: Mutex::new(Some(ticker)),
+            }
+        }
+    }
+
+    impl ProgressSink for Terminal {
+        fn event(&self, event: Event) {
+            let (language, message) = match event {
+                Event::Scraped { total, expected } => {
+                    self.overall.set_length(total as u64);
+                    let mut timing = lock(&self.timing);
+                    timing.waiting = expected.keys().cloned().collect();
+                    timing.expected = expected;
+                    timing.total = total;
+                    return;
+                }
+                Event::Started { language } => {
+                    lock(&self.timing).started(&language);
+                    let pb = self.multi_progress.add(ProgressBar::new_spinner());
+                    pb.set_style(
+                        ProgressStyle::default_spinner()
+                            .template("{spinner:.green}[{elapsed_precise}] {msg}")
+                            .unwrap(),
+                    );
+                    pb.set_message(format!("Cloning {}", language));
+                    lock(&self.spinners).insert(language, pb);
+                    return;
+                }
+                Event::Status { language, message } => {
+                    if let Some(pb) = lock(&self.spinners).get(&language) {
+                        pb.set_message(message);
+                    }
+                    return;
+                }
+                Event::Finished { language, .. } => {
+                    let message = format!("Done with {}", language);
+                    (language, message)
+                }
+                Event::Failed { language, error } => {
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                    let message = format!(
+                        "Failed for {} ({}): {}",
+                        language,
+                        failure::classify(&error),
+                        error
+                    );
+                    (language, message)
+                }
+                Event::Skipped { language } => {
+                    let message = format!("Skipped {}", language);
+                    (language, message)
+                }
+                Event::Cancelled { language } => {
+                    let message = format!("Cancelled {}", language);
+                    (language, message)
+                }
+                Event::Emitted { .. } => return,
+                Event::Warning { message } => {
+                    self.multi_progress
+                        .suspend(|| eprintln!("Warning: {}", message));
+                    return;
+                }
+            };
+            // a build is over, either way
+            if let Some(pb) = lock(&self.spinners).remove(&language) {
+                pb.finish_with_message(message);
+                self.multi_progress.remove(&pb);
+            }
+            let mut timing = lock(&self.timing);
+            timing.ended(&language);
+            self.overall
+                .set_message(overall_message(&self.failed, &timing));
+            self.overall.inc(1);
+        }
+
+        fn finished(&self) {
+            self.ticking.store(false, Ordering::Relaxed);
+            if let Some(ticker) = lock(&self.ticker).take() {
+                let _ = ticker.join();
+            }
+            self.overall.finish_with_message(format!(
+                "All tasks completed. {} failed.",
+                self.failed.load(Ordering::Relaxed)
+            ));
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            self.ticking.store(false, Ordering::Relaxed);
+        }
+    }
+
+    // "2 failed, ETA 4 minutes, slowest: haskell (3 minutes), ..."
+    fn overall_message(failed: &AtomicUsize, timing: &Timing) -> String {
+        let mut message = format!("{} failed", failed.load(Ordering::Relaxed));
+        if let Some(eta) = timing.eta() {
+            message.push_str(&format!(", ETA {}", HumanDuration(eta)));
+        }
+        let slowest = timing.slowest(2);
+        if !slowest.is_empty() {
+            let slowest: Vec<String> = slowest
+                .iter()
+                .map(|(lang, elapsed)| format!("{} ({})", lang, HumanDuration(*elapsed)))
+                .collect();
+            message.push_str(&format!(", slowest: {}", slowest.join(", ")));
+        }
+        message
+    }
+
+    // What the ETA is worked out from: the durations of the earlier runs, and
+    // what the builds of this one took so far for the grammars without any
+    #[derive(Default)]
+    struct Timing {
+        expected: HashMap<String, Duration>,
+        // those of `expected` that haven't started yet
+        waiting: HashSet<String>,
+        total: usize,
+        // started, or cancelled before they were
+        started: usize,
+        running: HashMap<String, Instant>,
+        // sum and count of the builds over in this run
+        done: (Duration, u32),
+        // the most builds seen running at once, how many the run does in parallel
+        parallel: usize,
+    }
+
+    impl Timing {
+        fn started(&mut self, lang: &str) {
+            self.waiting.remove(lang);
+            self.started += 1;
+            self.running.insert(lang.to_string(), Instant::now());
+            self.parallel = self.parallel.max(self.running.len());
+        }
+
+        fn ended(&mut self, lang: &str) {
+            match self.running.remove(lang) {
+                Some(started) => {
+                    self.done.0 += started.elapsed();
+                    self.done.1 += 1;
+                }
+                None => {
+                    self.waiting.remove(lang);
+                    self.started += 1;
+                }
+            }
+        }
+
+        // a grammar with no record: like the ones built so far, or else the
+        // median of the recorded ones
+        fn guess(&self) -> Option<Duration> {
+            if self.done.1 > 0 {
+                return Some(self.done.0 / self.done.1);
+            }
+            let mut known: Vec<Duration> = self.expected.values().copied().collect();
+            known.sort_unstable();
+            known.get(known.len() / 2).copied()
+        }
+
+        // None until there is anything to go by
+        fn eta(&self) -> Option<Duration> {
+            let guess = self.guess()?;
+            let expected = |lang: &str| self.expected.get(lang).copied().unwrap_or(guess);
+            // what's left of the running builds, the longest of them is the least
+            // the run still takes
+            let mut longest = Duration::ZERO;
+            let mut left = Duration::ZERO;
+            for (lang, started) in &self.running {
+                let remaining = expected(lang).saturating_sub(started.elapsed());
+                longest = longest.max(remaining);
+                left += remaining;
+            }
+            let queued = self.total.saturating_sub(self.started);
+            left += self
+                .waiting
+                .iter()
+                .map(|lang| expected(lang))
+                .sum::<Duration>();
+            left += guess * queued.saturating_sub(self.waiting.len()) as u32;
+            Some((left / self.parallel.max(1) as u32).max(longest))
+        }
+
+        // the builds running the longest, with how long they have been; the
+        // ones a few seconds in aren't worth naming
+        fn slowest(&self, count: usize) -> Vec<(&str, Duration)> {
+            let mut running: Vec<(&str, Duration)> = self
+                .running
+                .iter()
+                .map(|(lang, started)| (lang.as_str(), started.elapsed()))
+                .filter(|&(_, elapsed)| elapsed >= Duration::from_secs(5))
+                .collect();
+            running.sort_by_key(|&(_, elapsed)| std::cmp::Reverse(elapsed));
+            running.truncate(count);
+            running
+        }
+    }
+
+    fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
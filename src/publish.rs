@@ -0,0 +1,454 @@
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::checksums::{self, sha256_file, SHA256SUMS};
+use crate::config::{load_config, resolve_entry_path, ConfigFormat, FILE_MAPS};
+
+// objects never change once uploaded, what points at them does
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const MUTABLE: &str = "no-cache";
+
+// the detached signatures `--sign-key` leaves next to a file
+const SIGNATURES: [&str; 2] = ["asc", "minisig"];
+
+// Somewhere to upload to, keys are relative to the --to prefix
+pub trait Remote: Send + Sync {
+    fn describe(&self) -> String;
+
+    fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>>;
+
+    // None when there is nothing under `key`
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+    fn put(&self, key: &str, body: &[u8], cache_control: &str) -> Result<(), Box<dyn Error>>;
+}
+
+// s3://bucket/prefix, through the aws CLI so every way it finds credentials
+// (profiles, SSO, instance roles) and AWS_ENDPOINT_URL for MinIO and the
+// like just work
+pub struct S3 {
+    bucket: String,
+    prefix: String,
+}
+
+impl S3 {
+    fn url(&self, key: &str) -> String {
+        format!("s3://{}/{}", self.bucket, self.object(key))
+    }
+
+    fn object(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", self.prefix, key)
+        }
+    }
+}
+
+impl Remote for S3 {
+    fn describe(&self) -> String {
+        self.url("").trim_end_matches('/').to_string()
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+        let status = Command::new("aws")
+            .args(["s3api", "head-object", "--bucket", &self.bucket, "--key"])
+            .arg(self.object(key))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("running aws failed: {}", e))?;
+        Ok(status.success())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let output = Command::new("aws")
+            .args(["s3", "cp", "--only-show-errors"])
+            .arg(self.url(key))
+            .arg("-")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("running aws failed: {}", e))?;
+        if output.status.success() {
+            return Ok(Some(output.stdout));
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("404") || stderr.contains("Not Found") {
+            return Ok(None);
+        }
+        Err(format!(
+            "downloading {} failed ({}): {}",
+            self.url(key),
+            output.status,
+            stderr.trim()
+        )
+        .into())
+    }
+
+    fn put(&self, key: &str, body: &[u8], cache_control: &str) -> Result<(), Box<dyn Error>> {
+        let mut child = Command::new("aws")
+            .args([
+                "s3",
+                "cp",
+                "--only-show-errors",
+                "--cache-control",
+                cache_control,
+            ])
+            .arg("-")
+            .arg(self.url(key))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("running aws failed: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(body)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "uploading {} failed ({}): {}",
+                self.url(key),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// an http(s):// base URL taking PUTs, like a WebDAV share, Artifactory or
+// a presigned bucket endpoint. PUBLISH_TOKEN is sent as a bearer token.
+pub struct Http {
+    base: String,
+    token: Option<String>,
+    client: Client,
+}
+
+impl Http {
+    fn request(&self, method: reqwest::Method, key: &str) -> reqwest::blocking::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}/{}", self.base, key));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+impl Remote for Http {
+    fn describe(&self) -> String {
+        self.base.clone()
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+        let response = self.request(reqwest::Method::HEAD, key).send()?;
+        Ok(response.status().is_success())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let response = self.request(reqwest::Method::GET, key).send()?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes()?.to_vec())),
+            status => Err(format!("{}/{}: {}", self.base, key, status).into()),
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], cache_control: &str) -> Result<(), Box<dyn Error>> {
+        let response = self
+            .request(reqwest::Method::PUT, key)
+            .header("Cache-Control", cache_control)
+            .body(body.to_vec())
+            .send()?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(format!(
+                "{}/{}: {}, is PUBLISH_TOKEN set?",
+                self.base,
+                key,
+                response.status()
+            )
+            .into()),
+            status => Err(format!("{}/{}: {}", self.base, key, status).into()),
+        }
+    }
+}
+
+// what `--to` names
+pub fn remote(to: &str) -> Result<Box<dyn Remote>, Box<dyn Error>> {
+    if let Some(rest) = to.strip_prefix("s3://") {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(format!("{} names no bucket", to).into());
+        }
+        return Ok(Box::new(S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        }));
+    }
+    if to.starts_with("http://") || to.starts_with("https://") {
+        return Ok(Box::new(Http {
+            base: to.trim_end_matches('/').to_string(),
+            token: std::env::var("PUBLISH_TOKEN").ok(),
+            client: Client::new(),
+        }));
+    }
+    Err(format!("can't use {}, give an s3:// or http(s):// URL", to).into())
+}
+
+pub struct Published {
+    pub uploaded: usize,
+    // objects already there from an earlier publish
+    pub present: usize,
+    pub config_key: String,
+}
+
+// Upload the libraries and queries of every config entry as
+// objects/<sha256>/<file name>, which never change and can be cached
+// forever, skipping the ones already there. Then SHA256SUMS and NOTICE, and
+// last the config with its paths pointing at the objects, so whoever reads
+// it never finds one that isn't there yet.
+pub fn publish(
+    output_dir: &str,
+    config_path: &str,
+    format: ConfigFormat,
+    remote: &dyn Remote,
+) -> Result<Published, Box<dyn Error>> {
+    // only what a run left intact goes out
+    let problems = checksums::verify_sha256sums(output_dir)?;
+    if !problems.is_empty() {
+        return Err(format!(
+            "{} doesn't match its {}: {}",
+            output_dir,
+            SHA256SUMS,
+            problems.join("; ")
+        )
+        .into());
+    }
+
+    let mut config = load_config(config_path, format)?;
+    let mut published = Published {
+        uploaded: 0,
+        present: 0,
+        config_key: Path::new(config_path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    let mut upload_object = |path: &str| -> Result<String, Box<dyn Error>> {
+        let file = resolve_entry_path(path, config_path);
+        let hash = sha256_file(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let key = format!("objects/{}/{}", hash, name);
+        if remote.exists(&key)? {
+            published.present += 1;
+        } else {
+            remote.put(&key, &std::fs::read(&file)?, IMMUTABLE)?;
+            published.uploaded += 1;
+        }
+        for (signature, extension) in signatures(&file) {
+            remote.put(
+                &format!("{}.{}", key, extension),
+                &std::fs::read(&signature)?,
+                IMMUTABLE,
+            )?;
+        }
+        Ok(key)
+    };
+
+    let entries = config
+        .get_mut("known_languages")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| format!("{} has no languages to publish", config_path))?;
+    for entry in entries.values_mut() {
+        if let Some(path) = entry
+            .get("path")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+        {
+            let file = resolve_entry_path(&path, config_path);
+            entry["sha256"] = json!(sha256_file(&file)?);
+            entry["path"] = json!(upload_object(&path)?);
+        }
+        for map in FILE_MAPS {
+            if let Some(files) = entry.get_mut(*map).and_then(Value::as_object_mut) {
+                for file in files.values_mut() {
+                    if let Some(path) = file.as_str() {
+                        *file = json!(upload_object(path)?);
+                    }
+                }
+            }
+        }
+    }
+
+    for name in [SHA256SUMS, "NOTICE"] {
+        let file = Path::new(output_dir).join(name);
+        if !file.is_file() {
+            continue;
+        }
+        remote.put(name, &std::fs::read(&file)?, MUTABLE)?;
+        for (signature, extension) in signatures(&file) {
+            remote.put(
+                &format!("{}.{}", name, extension),
+                &std::fs::read(&signature)?,
+                MUTABLE,
+            )?;
+        }
+    }
+    // the paths are relative to where the config is
+    let rendered = format.render(&config)?;
+    remote.put(&published.config_key, rendered.as_bytes(), MUTABLE)?;
+    Ok(published)
+}
+
+fn signatures(file: &Path) -> Vec<(PathBuf, &'static str)> {
+    SIGNATURES
+        .iter()
+        .map(|extension| {
+            let mut signature = file.as_os_str().to_owned();
+            signature.push(".");
+            signature.push(extension);
+            (PathBuf::from(signature), *extension)
+        })
+        .filter(|(signature, _)| signature.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::sync::Mutex;
+
+    // key -> (body, cache control)
+    #[derive(Default)]
+    pub(crate) struct MemoryRemote {
+        pub objects: Mutex<BTreeMap<String, (Vec<u8>, String)>>,
+    }
+
+    impl Remote for MemoryRemote {
+        fn describe(&self) -> String {
+            "memory".to_string()
+        }
+
+        fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+            Ok(self.objects.lock().unwrap().contains_key(key))
+        }
+
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+            Ok(self
+                .objects
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|(body, _)| body.clone()))
+        }
+
+        fn put(&self, key: &str, body: &[u8], cache_control: &str) -> Result<(), Box<dyn Error>> {
+            self.objects
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), (body.to_vec(), cache_control.to_string()));
+            Ok(())
+        }
+    }
+
+    fn output(name: &str) -> (PathBuf, String) {
+        let root = std::env::temp_dir().join(format!("publish-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let library = root.join("libjson.so");
+        fs::write(&library, b"a library").unwrap();
+        checksums::write_sha256sums(
+            &root.to_string_lossy(),
+            &[library.to_string_lossy().to_string()],
+        )
+        .unwrap();
+        let config = root.join("config.json");
+        fs::write(
+            &config,
+            json!({ "known_languages": { "json": { "path": "libjson.so" } } }).to_string(),
+        )
+        .unwrap();
+        (root, config.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn publishes_content_addressed_objects_then_the_config() {
+        let (root, config) = output("objects");
+        let remote = MemoryRemote::default();
+        let published = publish(
+            &root.to_string_lossy(),
+            &config,
+            ConfigFormat::Json,
+            &remote,
+        )
+        .unwrap();
+        assert_eq!((published.uploaded, published.present), (1, 0));
+
+        let hash = sha256_file(&root.join("libjson.so")).unwrap();
+        let key = format!("objects/{}/libjson.so", hash);
+        let objects = remote.objects.lock().unwrap();
+        assert_eq!(
+            objects[&key],
+            (b"a library".to_vec(), IMMUTABLE.to_string())
+        );
+        assert_eq!(objects[SHA256SUMS].1, MUTABLE);
+        let written: Value = serde_json::from_slice(&objects["config.json"].0).unwrap();
+        assert_eq!(written["known_languages"]["json"]["path"], json!(key));
+        assert_eq!(written["known_languages"]["json"]["sha256"], json!(hash));
+        drop(objects);
+
+        // the second time the object is already there
+        let again = publish(
+            &root.to_string_lossy(),
+            &config,
+            ConfigFormat::Json,
+            &remote,
+        )
+        .unwrap();
+        assert_eq!((again.uploaded, again.present), (0, 1));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_changed_output() {
+        let (root, config) = output("changed");
+        fs::write(root.join("libjson.so"), b"changed since").unwrap();
+        let remote = MemoryRemote::default();
+        assert!(publish(
+            &root.to_string_lossy(),
+            &config,
+            ConfigFormat::Json,
+            &remote
+        )
+        .is_err());
+        assert!(remote.objects.lock().unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn remotes() {
+        assert_eq!(
+            remote("s3://bucket/some/prefix/").unwrap().describe(),
+            "s3://bucket/some/prefix"
+        );
+        assert_eq!(remote("s3://bucket").unwrap().describe(), "s3://bucket");
+        assert_eq!(
+            remote("https://dav.example/grammars/").unwrap().describe(),
+            "https://dav.example/grammars"
+        );
+        assert!(remote("s3:///prefix").is_err());
+        assert!(remote("ftp://example/").is_err());
+    }
+}
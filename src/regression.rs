@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::Path;
+use tree_sitter::Parser;
+
+use crate::smoke::load_grammar;
+
+// how the trees of the new build compare to the previous one
+#[derive(Debug, Default, Clone)]
+pub struct TreeDiff {
+    pub compared: usize,
+    // names of the inputs whose tree changed
+    pub changed: Vec<String>,
+}
+
+// Parse every input with the previous and the new library and compare the
+// s-expressions. The old and new tree of every changed input go to `report`.
+pub fn diff_trees(
+    previous: &Path,
+    current: &Path,
+    language: &str,
+    inputs: &[(String, Vec<u8>)],
+    report: &Path,
+) -> Result<TreeDiff, Box<dyn std::error::Error>> {
+    let old_grammar = load_grammar(previous, language)?;
+    let new_grammar = load_grammar(current, language)?;
+    let mut old_parser = Parser::new();
+    old_parser
+        .set_language(&old_grammar.language)
+        .map_err(|e| format!("previous build unusable: {}", e))?;
+    let mut new_parser = Parser::new();
+    new_parser
+        .set_language(&new_grammar.language)
+        .map_err(|e| format!("new build unusable: {}", e))?;
+
+    let mut diff = TreeDiff::default();
+    let mut details = String::new();
+    for (name, source) in inputs {
+        let old = old_parser
+            .parse(source, None)
+            .map(|t| t.root_node().to_sexp());
+        let new = new_parser
+            .parse(source, None)
+            .map(|t| t.root_node().to_sexp());
+        diff.compared += 1;
+        if old != new {
+            let none = || "(no tree)".to_string();
+            details.push_str(&format!(
+                "=== {}\n- {}\n+ {}\n",
+                name,
+                old.unwrap_or_else(none),
+                new.unwrap_or_else(none)
+            ));
+            diff.changed.push(name.clone());
+        }
+    }
+
+    if diff.changed.is_empty() {
+        // don't leave the details of an earlier run around
+        let _ = fs::remove_file(report);
+    } else {
+        if let Some(parent) = report.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(report, details)?;
+    }
+    Ok(diff)
+}
@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::limits::ResourceLimits;
+
+// Isolation backends for running the compiler over untrusted grammar sources.
+// Every backend gets no network, a read-only view of the checkout and write
+// access to the output directory only.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Sandbox {
+    Bwrap,
+    Nsjail,
+    Docker,
+}
+
+// image used by the docker sandbox, it only needs a C/C++ toolchain
+pub const DOCKER_IMAGE: &str = "gcc:latest";
+
+// system directories the toolchain needs, mounted read-only when they exist
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc/alternatives",
+];
+
+// Build the command running `program args` inside `repo_dir`, wrapped in the
+// selected sandbox (or directly when there is none), under `limits`: rlimits
+// inherited through the sandbox, or the container's own for docker. Set on
+// the docker CLI they'd never reach the compiler.
+pub fn command(
+    sandbox: Option<Sandbox>,
+    program: &str,
+    args: &[String],
+    env: &[(String, String)],
+    limits: &ResourceLimits,
+    repo_dir: &Path,
+    output_dir: &Path,
+) -> Command {
+    let repo = repo_dir.display().to_string();
+    let output = output_dir.display().to_string();
+
+    match sandbox {
+        None => {
+            let mut cmd = Command::new(program);
+            cmd.current_dir(repo_dir)
+                .args(args)
+                .envs(env.iter().cloned());
+            limits.apply(&mut cmd);
+            cmd
+        }
+        Some(Sandbox::Bwrap) => {
+            let mut cmd = Command::new("bwrap");
+            for dir in SYSTEM_DIRS {
+                cmd.arg("--ro-bind-try").arg(dir).arg(dir);
+            }
+            // a program from elsewhere, e.g. ~/.cargo/bin, is mounted on its own
+            if let Some(program) = outside_system_dirs(program) {
+                cmd.arg("--ro-bind").arg(program).arg(program);
+            }
+            cmd.args(["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"])
+                .args(["--ro-bind", &repo, &repo])
+                .args(["--bind", &output, &output])
+                .args(["--chdir", &repo])
+                .args(["--unshare-all", "--die-with-parent", "--clearenv"])
+                .args(["--setenv", "PATH", "/usr/bin:/bin"]);
+            for (key, value) in env {
+                cmd.arg("--setenv").arg(key).arg(value);
+            }
+            cmd.arg(program).args(args);
+            limits.apply(&mut cmd);
+            cmd
+        }
+        Some(Sandbox::Nsjail) => {
+            let mut cmd = Command::new("nsjail");
+            // no wall clock limit, and ours rather than nsjail's default
+            // rlimits, its 1MiB file size is less than many a parser.o
+            cmd.args(["--mode", "o", "--quiet", "-t", "0"])
+                .args(limits.nsjail_args());
+            for dir in SYSTEM_DIRS.iter().filter(|d| Path::new(d).exists()) {
+                cmd.arg("-R").arg(dir);
+            }
+            if let Some(program) = outside_system_dirs(program) {
+                cmd.arg("-R").arg(program);
+            }
+            cmd.args(["-R", &repo, "-B", &output, "-T", "/tmp", "--cwd", &repo])
+                .args(["-E", "PATH=/usr/bin:/bin"]);
+            for (key, value) in env {
+                cmd.arg("-E").arg(format!("{}={}", key, value));
+            }
+            let program = if Path::new(program).is_absolute() {
+                program.to_string()
+            } else {
+                format!("/usr/bin/{}", program)
+            };
+            cmd.arg("--").arg(program).args(args);
+            cmd
+        }
+        Some(Sandbox::Docker) => docker_command(
+            DOCKER_IMAGE,
+            &limits.docker_args(),
+            program,
+            args,
+            env,
+            repo_dir,
+            output_dir,
+        ),
+    }
+}
+
+// Where `program` is on our PATH, for running one that's outside the system
+// directories in a sandbox, whose PATH is only /usr/bin:/bin
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+        .and_then(|found| std::fs::canonicalize(found).ok())
+}
+
+// an absolute `program` the sandbox doesn't mount with the system directories
+fn outside_system_dirs(program: &str) -> Option<&str> {
+    let path = Path::new(program);
+    let mounted = SYSTEM_DIRS.iter().any(|dir| path.starts_with(dir));
+    (path.is_absolute() && !mounted).then_some(program)
+}
+
+// `docker run` with the checkout mounted read-only at the same path and the
+// output directory writable, running as the owner of the output directory
+pub fn docker_command(
+    image: &str,
+    run_args: &[String],
+    program: &str,
+    args: &[String],
+    env: &[(String, String)],
+    repo_dir: &Path,
+    output_dir: &Path,
+) -> Command {
+    let repo = repo_dir.display().to_string();
+    let output = output_dir.display().to_string();
+
+    let mut cmd = Command::new("docker");
+    cmd.args(["run", "--rm", "--network", "none"])
+        .arg("-v")
+        .arg(format!("{}:{}:ro", repo, repo))
+        .arg("-v")
+        .arg(format!("{}:{}", output, output))
+        .args(["-w", &repo])
+        .args(run_args);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(meta) = std::fs::metadata(output_dir) {
+            cmd.arg("--user")
+                .arg(format!("{}:{}", meta.uid(), meta.gid()));
+        }
+    }
+    for (key, value) in env {
+        cmd.arg("-e").arg(format!("{}={}", key, value));
+    }
+    cmd.arg(image).arg(program).args(args);
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mounts_programs_from_elsewhere() {
+        let limits = ResourceLimits::default();
+        let args: Vec<String> = vec!["build".to_string()];
+        let cmd = command(
+            Some(Sandbox::Bwrap),
+            "/home/me/.cargo/bin/tree-sitter",
+            &args,
+            &[],
+            &limits,
+            Path::new("/src/tree-sitter-json"),
+            Path::new("/out"),
+        );
+        let cmd_args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        let bind = cmd_args
+            .windows(3)
+            .any(|w| w[0] == "--ro-bind" && w[1] == "/home/me/.cargo/bin/tree-sitter");
+        assert!(bind, "{:?}", cmd_args);
+        assert_eq!(outside_system_dirs("/usr/bin/gcc"), None);
+        assert_eq!(outside_system_dirs("gcc"), None);
+    }
+}
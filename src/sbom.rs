@@ -0,0 +1,221 @@
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::config::LanguageArtifact;
+use crate::emit::{EmitContext, Emitter};
+use crate::error::EmitError;
+use crate::grammar::build_timestamp;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SbomFormat {
+    Spdx,
+    Cyclonedx,
+}
+
+// everything about a run that goes into its bill of materials
+pub struct BuildRun<'a> {
+    pub artifacts: &'a [LanguageArtifact],
+    // sha256 of each library, keyed by the path in its entry
+    pub hashes: &'a BTreeMap<String, String>,
+    pub compiler: String,
+    pub created: String,
+}
+
+pub fn write_sbom(
+    path: &str,
+    format: SbomFormat,
+    run: &BuildRun,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let document = match format {
+        SbomFormat::Spdx => spdx(run),
+        SbomFormat::Cyclonedx => cyclonedx(run),
+    };
+    fs::write(path, serde_json::to_string_pretty(&document)? + "\n")?;
+    Ok(())
+}
+
+fn tool_version() -> String {
+    format!("parser_scraper-{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn str_field<'a>(artifact: &'a LanguageArtifact, key: &str) -> Option<&'a str> {
+    artifact.entry.get(key).and_then(Value::as_str)
+}
+
+// SPDX ids only allow letters, digits, `.` and `-`
+fn spdx_id(kind: &str, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{}-{}", kind, name)
+}
+
+// SPDX 2.3: a package per upstream repository, a file per library, and a
+// GENERATED_FROM relationship between them
+fn spdx(run: &BuildRun) -> Value {
+    let mut packages = Vec::new();
+    let mut files = Vec::new();
+    let mut relationships = Vec::new();
+    let mut sorted: Vec<&LanguageArtifact> = run.artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for artifact in sorted {
+        let package_id = spdx_id("Source", &artifact.name);
+        let file_id = spdx_id("Artifact", &artifact.name);
+        let commit = str_field(artifact, "commit");
+        let download = match commit {
+            Some(commit) => format!("git+{}@{}", artifact.repo_url, commit),
+            None => format!("git+{}", artifact.repo_url),
+        };
+        let license = artifact.license.as_deref().unwrap_or("NOASSERTION");
+        packages.push(json!({
+            "SPDXID": package_id,
+            "name": artifact.name,
+            "versionInfo": str_field(artifact, "version").or(commit).unwrap_or("NOASSERTION"),
+            "downloadLocation": download,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": license,
+            "copyrightText": "NOASSERTION",
+            "filesAnalyzed": false
+        }));
+
+        let path = str_field(artifact, "path").unwrap_or_default();
+        let mut file = json!({
+            "SPDXID": file_id,
+            "fileName": path,
+            "licenseConcluded": license,
+            "copyrightText": "NOASSERTION",
+            "comment": format!("built with {}", run.compiler)
+        });
+        if let Some(hash) = run.hashes.get(path) {
+            file["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": hash }]);
+        }
+        files.push(file);
+
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": file_id
+        }));
+        relationships.push(json!({
+            "spdxElementId": file_id,
+            "relationshipType": "GENERATED_FROM",
+            "relatedSpdxElement": package_id
+        }));
+    }
+
+    // the namespace has to be unique per document, derive it from what was built
+    let mut hasher = Sha256::new();
+    hasher.update(run.created.as_bytes());
+    for hash in run.hashes.values() {
+        hasher.update(hash.as_bytes());
+    }
+    let digest: String = hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": "parser_scraper build",
+        "documentNamespace": format!("https://spdx.org/spdxdocs/parser_scraper-{}", digest),
+        "creationInfo": {
+            "created": run.created,
+            "creators": [format!("Tool: {}", tool_version())],
+            "comment": format!("compiler: {}", run.compiler)
+        },
+        "packages": packages,
+        "files": files,
+        "relationships": relationships
+    })
+}
+
+// CycloneDX 1.5: a library component per built language, pointing at its repository
+fn cyclonedx(run: &BuildRun) -> Value {
+    let mut sorted: Vec<&LanguageArtifact> = run.artifacts.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let components: Vec<Value> = sorted
+        .into_iter()
+        .map(|artifact| {
+            let path = str_field(artifact, "path").unwrap_or_default();
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": format!("grammar-{}", artifact.name),
+                "name": artifact.name,
+                "version": str_field(artifact, "version")
+                    .or(str_field(artifact, "commit"))
+                    .unwrap_or("unknown"),
+                "externalReferences": [{ "type": "vcs", "url": artifact.repo_url }],
+                "properties": [
+                    { "name": "parser_scraper:path", "value": path },
+                    { "name": "parser_scraper:compiler", "value": run.compiler }
+                ]
+            });
+            if let Some(commit) = str_field(artifact, "commit") {
+                component["properties"]
+                    .as_array_mut()
+                    .unwrap()
+                    .push(json!({ "name": "parser_scraper:commit", "value": commit }));
+            }
+            if let Some(hash) = run.hashes.get(path) {
+                component["hashes"] = json!([{ "alg": "SHA-256", "content": hash }]);
+            }
+            if let Some(license) = &artifact.license {
+                component["licenses"] = json!([{ "expression": license }]);
+            }
+            component
+        })
+        .collect();
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": run.created,
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "parser_scraper",
+                    "version": env!("CARGO_PKG_VERSION")
+                }]
+            },
+            "properties": [{ "name": "parser_scraper:compiler", "value": run.compiler }]
+        },
+        "components": components
+    })
+}
+
+// --emit spdx and --emit cyclonedx
+pub struct Sbom {
+    pub path: String,
+    pub format: SbomFormat,
+}
+
+impl Emitter for Sbom {
+    fn destination(&self) -> String {
+        self.path.clone()
+    }
+
+    fn emit(&self, artifacts: &[LanguageArtifact], context: &EmitContext) -> Result<(), EmitError> {
+        let run = BuildRun {
+            artifacts,
+            hashes: context.hashes,
+            compiler: context.compiler.clone(),
+            created: build_timestamp(),
+        };
+        write_sbom(&self.path, self.format, &run).map_err(EmitError::write(&self.path))
+    }
+}
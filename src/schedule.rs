@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::disk;
+use crate::languages::canonical_name;
+use crate::sources::ParserEntry;
+
+// Which grammars are cloned, and so built, first
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Order {
+    // the languages asked for by name in the order they were given, then the
+    // rest as the sources list them
+    #[default]
+    Requested,
+    // the smallest checkouts of the last runs first, the most grammars are
+    // usable soonest
+    Smallest,
+    // the longest builds of the last runs first, so the slowest aren't what
+    // the end of the run waits on
+    Duration,
+}
+
+// What the last runs recorded, what an order needs of it
+#[derive(Default)]
+pub struct History {
+    pub checkout_sizes: HashMap<String, u64>,
+    pub durations: HashMap<String, Duration>,
+}
+
+// Sort `parsers` for `order`. The sort is stable, what ties stays as the
+// sources list it. A grammar without a record counts as the median of the
+// recorded ones.
+pub fn sort(parsers: &mut [ParserEntry], order: Order, requested: &[String], history: &History) {
+    match order {
+        Order::Requested => {
+            let position: HashMap<String, usize> = requested
+                .iter()
+                .enumerate()
+                .map(|(i, lang)| (canonical_name(lang), i))
+                .rev()
+                .collect();
+            parsers.sort_by_key(|p| {
+                position
+                    .get(&canonical_name(&p.name))
+                    .copied()
+                    .unwrap_or(usize::MAX)
+            });
+        }
+        Order::Smallest => {
+            let guess = disk::guess(&history.checkout_sizes);
+            parsers.sort_by_key(|p| {
+                history
+                    .checkout_sizes
+                    .get(&canonical_name(&p.name))
+                    .copied()
+                    .unwrap_or(guess)
+            });
+        }
+        Order::Duration => {
+            let mut known: Vec<Duration> = history.durations.values().copied().collect();
+            known.sort_unstable();
+            let guess = known.get(known.len() / 2).copied().unwrap_or_default();
+            parsers.sort_by_key(|p| {
+                std::cmp::Reverse(
+                    history
+                        .durations
+                        .get(&canonical_name(&p.name))
+                        .copied()
+                        .unwrap_or(guess),
+                )
+            });
+        }
+    }
+}
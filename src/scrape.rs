@@ -0,0 +1,42 @@
+use reqwest::blocking::Client;
+use scraper::{Html, Selector};
+use std::collections::BTreeSet;
+
+use crate::error::ScrapeError;
+use crate::sources::ParserEntry;
+
+// the wiki page listing every known tree-sitter parser
+pub const PARSER_LIST_URL: &str = "https://github.com/tree-sitter/tree-sitter/wiki/List-of-parsers";
+
+// Scrape parsers from the Tree-sitter wiki, sorted by name
+pub fn scrape_parsers(url: &str) -> Result<Vec<ParserEntry>, ScrapeError> {
+    let client = Client::new();
+    let res = client.get(url).send()?.text()?;
+    Ok(parse_parser_list(&res))
+}
+
+// `scrape_parsers` for callers already running on a tokio runtime
+pub async fn scrape_parsers_async(url: &str) -> Result<Vec<ParserEntry>, ScrapeError> {
+    let res = reqwest::get(url).await?.text().await?;
+    Ok(parse_parser_list(&res))
+}
+
+fn parse_parser_list(page: &str) -> Vec<ParserEntry> {
+    let document = Html::parse_document(page);
+    let container_selector = Selector::parse("div.markdown-body li").unwrap();
+    let link_selector = Selector::parse("a").unwrap();
+
+    let mut parsers = BTreeSet::new();
+    for li_element in document.select(&container_selector) {
+        if let Some(a_element) = li_element.select(&link_selector).next() {
+            if let Some(href) = a_element.value().attr("href") {
+                parsers.insert(ParserEntry {
+                    name: a_element.text().next().unwrap().to_string(),
+                    repo_url: href.to_string(),
+                });
+            }
+        }
+    }
+
+    parsers.into_iter().collect()
+}
@@ -0,0 +1,475 @@
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config::{load_config, resolve_entry_path, ConfigFormat};
+use crate::languages::own_name;
+use crate::service::{BuildJob, BuildQueue, BuildRequest};
+
+// one config whose libraries are served for one target
+pub struct ServedTarget {
+    // os-arch, like the {target} of bundle names
+    pub target: String,
+    pub config_path: String,
+    pub format: ConfigFormat,
+}
+
+// the target of the libraries this machine builds
+pub fn host_target() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+// Serve the built grammars over HTTP until the process is killed:
+//
+//   GET /targets                    the targets there are configs for
+//   GET /grammars?target=T          the grammars built for T
+//   GET /grammars/<name>?target=T   the library of one of them
+//   GET /config?target=T            the config file as written
+//
+// and with a build queue, for the first target only:
+//
+//   POST /builds                    {"languages": [...], "refs": {lang: ref}}, queues a build
+//   GET /builds                     every build since the server started
+//   GET /builds/<id>                its state, and the result once it's over
+//   GET /builds/<id>/events         its events as JSON lines, streamed until it ends
+//
+// `target` defaults to the first one. Configs are read on every request, so a
+// rebuild shows up without restarting the server.
+pub fn serve(
+    listen: &str,
+    targets: Vec<ServedTarget>,
+    workers: usize,
+    builds: Option<Arc<BuildQueue>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = Arc::new(Server::http(listen)?);
+    let targets = Arc::new(targets);
+    log::info!("serving grammars on {}", listen);
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let targets = Arc::clone(&targets);
+            let builds = builds.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &targets, builds.as_deref());
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, targets: &[ServedTarget], builds: Option<&BuildQueue>) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let target_name = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "target")
+        .map(|(_, value)| value);
+    let target = match target_name {
+        Some(name) => targets.iter().find(|t| t.target == name),
+        None => targets.first(),
+    };
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let response = match (request.method(), segments.as_slice(), target) {
+        (_, ["builds", ..], _) if builds.is_none() => {
+            error_response(404, "builds are off, start the server with --allow-builds")
+        }
+        (Method::Post, ["builds"], _) => {
+            let mut body = String::new();
+            match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => submit_build(builds.unwrap(), &body),
+                Err(e) => error_response(400, &e.to_string()),
+            }
+        }
+        (Method::Get, ["builds"], _) => {
+            let summaries: Vec<Value> =
+                builds.unwrap().list().iter().map(|b| b.summary()).collect();
+            json_response(200, &json!(summaries))
+        }
+        (Method::Get, ["builds", id, rest @ ..], _) if rest.is_empty() || rest == ["events"] => {
+            match id.parse().ok().and_then(|id| builds.unwrap().get(id)) {
+                None => error_response(404, &format!("no build {}", id)),
+                Some(job) if rest.is_empty() => json_response(200, &job.summary()),
+                Some(job) => {
+                    log::info!("{} {} -> streaming", request.method(), url);
+                    stream_events(request, &job);
+                    return;
+                }
+            }
+        }
+        (Method::Get, ["targets"], _) => {
+            let names: Vec<&str> = targets.iter().map(|t| t.target.as_str()).collect();
+            json_response(200, &json!(names))
+        }
+        (Method::Get, _, None) => error_response(
+            404,
+            &format!("no grammars for target {}", target_name.unwrap_or("")),
+        ),
+        (Method::Get, ["grammars"], Some(target)) => list_grammars(target),
+        (Method::Get, ["grammars", name], Some(target)) => download_grammar(target, name),
+        (Method::Get, ["config"], Some(target)) => match File::open(&target.config_path) {
+            Ok(file) => Response::from_file(file)
+                .with_header(header("Content-Type", content_type(target.format)))
+                .boxed(),
+            Err(e) => error_response(500, &format!("{}: {}", target.config_path, e)),
+        },
+        (Method::Get, _, _) => error_response(404, "not found"),
+        _ => error_response(405, "method not allowed"),
+    };
+    log::info!(
+        "{} {} {} -> {}",
+        request
+            .remote_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default(),
+        request.method(),
+        url,
+        response.status_code().0
+    );
+    if let Err(e) = request.respond(response) {
+        log::warn!("failed to respond to {} : {}", url, e);
+    }
+}
+
+fn submit_build(builds: &BuildQueue, body: &str) -> Response<Box<dyn std::io::Read + Send>> {
+    let body: Value = match serde_json::from_str(body) {
+        Ok(body) => body,
+        Err(e) => return error_response(400, &format!("invalid JSON: {}", e)),
+    };
+    let languages: Vec<String> = body
+        .get("languages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect();
+    // an empty list would build every parser there is
+    if languages.is_empty() {
+        return error_response(400, "\"languages\" must list at least one language");
+    }
+    let revisions = body
+        .get("refs")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter_map(|(lang, rev)| Some((lang.clone(), rev.as_str()?.to_string())))
+        .collect();
+    let job = builds.submit(BuildRequest {
+        languages,
+        revisions,
+    });
+    json_response(202, &job.summary())
+}
+
+// Chunked by hand rather than through a Response, which would hold events
+// back until a whole chunk's worth of them is buffered
+fn stream_events(request: Request, job: &BuildJob) {
+    let mut writer = request.into_writer();
+    let mut seen = 0;
+    let mut write = || -> std::io::Result<()> {
+        writer.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n\r\n",
+        )?;
+        loop {
+            let events = job.events_after(seen);
+            if events.is_empty() {
+                break;
+            }
+            seen += events.len();
+            let mut chunk = String::new();
+            for event in events {
+                chunk.push_str(&event.to_string());
+                chunk.push('\n');
+            }
+            write!(writer, "{:x}\r\n{}\r\n", chunk.len(), chunk)?;
+            writer.flush()?;
+        }
+        writer.write_all(b"0\r\n\r\n")?;
+        writer.flush()
+    };
+    // the client going away ends the stream, not the build
+    if let Err(e) = write() {
+        log::info!("stopped streaming build {} : {}", job.id, e);
+    }
+}
+
+fn list_grammars(target: &ServedTarget) -> Response<Box<dyn std::io::Read + Send>> {
+    let languages = match known_languages(target) {
+        Ok(languages) => languages,
+        Err(e) => return error_response(500, &e),
+    };
+    let grammars: Vec<Value> = languages
+        .iter()
+        .map(|(name, entry)| {
+            json!({
+                "name": name,
+                "language": entry.get("language"),
+                "version": entry.get("version"),
+                "abi_version": entry.get("abi_version"),
+                "commit": entry.get("commit"),
+                "sha256": entry.get("sha256"),
+                "url": format!("/grammars/{}?target={}", name, target.target),
+            })
+        })
+        .collect();
+    json_response(
+        200,
+        &json!({ "target": target.target, "grammars": grammars }),
+    )
+}
+
+fn download_grammar(target: &ServedTarget, name: &str) -> Response<Box<dyn std::io::Read + Send>> {
+    let languages = match known_languages(target) {
+        Ok(languages) => languages,
+        Err(e) => return error_response(500, &e),
+    };
+    let wanted = own_name(name);
+    let path = languages
+        .iter()
+        .find(|(n, _)| own_name(n) == wanted)
+        .and_then(|(_, entry)| entry.get("path"))
+        .and_then(Value::as_str);
+    let Some(path) = path else {
+        return error_response(404, &format!("no grammar {} for {}", name, target.target));
+    };
+    let library = resolve_entry_path(path, &target.config_path);
+    let file_name = Path::new(&library)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match File::open(&library) {
+        Ok(file) => Response::from_file(file)
+            .with_header(header("Content-Type", "application/octet-stream"))
+            .with_header(header(
+                "Content-Disposition",
+                &format!("attachment; filename=\"{}\"", file_name),
+            ))
+            .boxed(),
+        Err(e) => error_response(500, &format!("{}: {}", library.display(), e)),
+    }
+}
+
+fn known_languages(target: &ServedTarget) -> Result<serde_json::Map<String, Value>, String> {
+    let config = load_config(&target.config_path, target.format).map_err(|e| e.to_string())?;
+    Ok(config
+        .get("known_languages")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default())
+}
+
+fn content_type(format: ConfigFormat) -> &'static str {
+    match format {
+        ConfigFormat::Json => "application/json",
+        ConfigFormat::Toml => "application/toml",
+        ConfigFormat::Yaml => "application/yaml",
+    }
+}
+
+fn json_response(status: u16, body: &Value) -> Response<Box<dyn std::io::Read + Send>> {
+    Response::from_string(body.to_string() + "\n")
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .boxed()
+}
+
+fn error_response(status: u16, message: &str) -> Response<Box<dyn std::io::Read + Send>> {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::BuildOptions;
+    use crate::service::BuildService;
+    use crate::sources::ParserEntry;
+    use crate::trust::TrustPolicy;
+    use std::fs;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    // a server on a free port, left running until the tests exit
+    fn start(targets: Vec<ServedTarget>, builds: Option<Arc<BuildQueue>>) -> String {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listen = format!("127.0.0.1:{}", port);
+        let address = listen.clone();
+        thread::spawn(move || serve(&address, targets, 2, builds).unwrap());
+        listen
+    }
+
+    // (status, body)
+    fn request(listen: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = (0..100)
+            .find_map(|_| {
+                TcpStream::connect(listen)
+                    .map_err(|_| thread::sleep(Duration::from_millis(20)))
+                    .ok()
+            })
+            .unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.0\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, body.to_string())
+    }
+
+    fn json_body(body: &str) -> Value {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn serves_grammars_per_target() {
+        let root = std::env::temp_dir().join(format!("serve-{}", std::process::id()));
+        fs::create_dir_all(root.join("arm")).unwrap();
+        fs::write(root.join("libtree-sitter-bash.so"), b"bash").unwrap();
+        fs::write(root.join("libtree-sitter-zsh.so"), b"zsh").unwrap();
+        let config = json!({ "known_languages": {
+            "bash": { "language": "bash", "path": "libtree-sitter-bash.so", "commit": "abc" },
+            "zsh": { "language": "zsh", "path": root.join("libtree-sitter-zsh.so") },
+        }});
+        fs::write(root.join("config.json"), config.to_string()).unwrap();
+        fs::write(
+            root.join("arm/config.json"),
+            json!({ "known_languages": {} }).to_string(),
+        )
+        .unwrap();
+        let target = |target: &str, config: &str| ServedTarget {
+            target: target.to_string(),
+            config_path: root.join(config).to_string_lossy().to_string(),
+            format: ConfigFormat::Json,
+        };
+        let listen = start(
+            vec![
+                target("linux-x86_64", "config.json"),
+                target("linux-aarch64", "arm/config.json"),
+            ],
+            None,
+        );
+
+        let (status, body) = request(&listen, "GET", "/targets", "");
+        assert_eq!(status, 200);
+        assert_eq!(json_body(&body), json!(["linux-x86_64", "linux-aarch64"]));
+
+        let (status, body) = request(&listen, "GET", "/grammars", "");
+        assert_eq!(status, 200);
+        let listed = json_body(&body);
+        assert_eq!(listed["target"], "linux-x86_64");
+        assert_eq!(listed["grammars"][0]["name"], "bash");
+        assert_eq!(listed["grammars"][0]["commit"], "abc");
+        assert_eq!(
+            listed["grammars"][0]["url"],
+            "/grammars/bash?target=linux-x86_64"
+        );
+        let (_, body) = request(&listen, "GET", "/grammars?target=linux-aarch64", "");
+        assert_eq!(json_body(&body)["grammars"], json!([]));
+        let (status, _) = request(&listen, "GET", "/grammars?target=windows-x86", "");
+        assert_eq!(status, 404);
+
+        // relative to the config, and zsh isn't bash
+        assert_eq!(
+            request(&listen, "GET", "/grammars/bash", ""),
+            (200, "bash".to_string())
+        );
+        assert_eq!(
+            request(&listen, "GET", "/grammars/zsh", ""),
+            (200, "zsh".to_string())
+        );
+        assert_eq!(request(&listen, "GET", "/grammars/toml", "").0, 404);
+        assert_eq!(
+            request(&listen, "GET", "/config", ""),
+            (200, config.to_string())
+        );
+
+        assert_eq!(request(&listen, "POST", "/grammars", "").0, 405);
+        assert_eq!(request(&listen, "GET", "/builds", "").0, 404);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn queues_builds_and_streams_their_events() {
+        let root = std::env::temp_dir().join(format!("serve-builds-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let config_path = root.join("config.json").to_string_lossy().to_string();
+        let source: Vec<ParserEntry> = Vec::new();
+        let builds = BuildQueue::start(BuildService {
+            options: BuildOptions {
+                output_dir: format!("{}/libs/", root.display()),
+                source_destination: format!("{}/src/", root.display()),
+                ..BuildOptions::default()
+            },
+            source: Arc::new(source),
+            config_path: config_path.clone(),
+            config_format: ConfigFormat::Json,
+            path_style: None,
+            threads: 1,
+            network_jobs: 1,
+            verify_jobs: 1,
+            trust: TrustPolicy::default(),
+            store: None,
+        });
+        let listen = start(
+            vec![ServedTarget {
+                target: host_target(),
+                config_path,
+                format: ConfigFormat::Json,
+            }],
+            Some(builds),
+        );
+
+        assert_eq!(request(&listen, "POST", "/builds", "{").0, 400);
+        assert_eq!(
+            request(&listen, "POST", "/builds", r#"{"languages": []}"#).0,
+            400
+        );
+        let (status, body) = request(
+            &listen,
+            "POST",
+            "/builds",
+            r#"{"languages": ["json"], "refs": {"json": "v1.0.0"}}"#,
+        );
+        assert_eq!(status, 202);
+        let queued = json_body(&body);
+        assert_eq!(queued["url"], "/builds/1");
+        assert_eq!(queued["refs"]["json"], "v1.0.0");
+
+        // the stream ends with the build
+        let (status, events) = request(&listen, "GET", "/builds/1/events", "");
+        assert_eq!(status, 200);
+        assert!(events.contains('{'));
+        let (_, body) = request(&listen, "GET", "/builds/1", "");
+        assert_eq!(json_body(&body)["state"], "finished");
+        let (_, body) = request(&listen, "GET", "/builds", "");
+        assert_eq!(json_body(&body).as_array().unwrap().len(), 1);
+        assert_eq!(request(&listen, "GET", "/builds/2", "").0, 404);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
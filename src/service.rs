@@ -0,0 +1,326 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::config::{ConfigFormat, PathStyle};
+use crate::failure;
+use crate::grammar::BuildOptions;
+use crate::pipeline::Pipeline;
+use crate::progress::event_json;
+use crate::sources::ParserSource;
+use crate::store::Store;
+use crate::trust::TrustPolicy;
+
+// How `serve --allow-builds` runs the builds it is asked for: the same options
+// as a CLI run, writing into the config that is being served.
+pub struct BuildService {
+    pub options: BuildOptions,
+    pub source: Arc<dyn ParserSource>,
+    pub config_path: String,
+    pub config_format: ConfigFormat,
+    pub path_style: Option<PathStyle>,
+    pub threads: usize,
+    pub network_jobs: usize,
+    pub verify_jobs: usize,
+    // whose repositories requests may build
+    pub trust: TrustPolicy,
+    // where the builds are recorded
+    pub store: Option<Arc<Store>>,
+}
+
+// what a client asks for
+pub struct BuildRequest {
+    pub languages: Vec<String>,
+    // language -> branch, tag or commit
+    pub revisions: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BuildState {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+}
+
+impl BuildState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BuildState::Queued => "queued",
+            BuildState::Running => "running",
+            BuildState::Finished => "finished",
+            BuildState::Failed => "failed",
+        }
+    }
+}
+
+struct Progress {
+    state: BuildState,
+    events: Vec<Value>,
+    // the report once the run is over, or why it couldn't run
+    result: Option<Value>,
+}
+
+// one requested build, shared by the worker and everyone watching it
+pub struct BuildJob {
+    pub id: u64,
+    request: BuildRequest,
+    progress: Mutex<Progress>,
+    changed: Condvar,
+}
+
+impl BuildJob {
+    fn push(&self, event: Value) {
+        self.progress.lock().unwrap().events.push(event);
+        self.changed.notify_all();
+    }
+
+    fn end(&self, state: BuildState, result: Value) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.state = state;
+        progress.result = Some(result);
+        drop(progress);
+        self.changed.notify_all();
+    }
+
+    // the state, the request and the result so far
+    pub fn summary(&self) -> Value {
+        let progress = self.progress.lock().unwrap();
+        json!({
+            "id": self.id,
+            "state": progress.state.as_str(),
+            "languages": self.request.languages,
+            "refs": self.request.revisions,
+            "events": progress.events.len(),
+            "result": progress.result,
+            "url": format!("/builds/{}", self.id),
+        })
+    }
+
+    // The events after the first `seen`, waiting for more while the build
+    // runs. Empty once it's over and they have all been seen.
+    pub fn events_after(&self, seen: usize) -> Vec<Value> {
+        let mut progress = self.progress.lock().unwrap();
+        while seen >= progress.events.len() && progress.result.is_none() {
+            progress = self.changed.wait(progress).unwrap();
+        }
+        progress.events.get(seen..).unwrap_or_default().to_vec()
+    }
+}
+
+// Builds run one at a time in the order they were asked for, since they all
+// write into the same output directory and config.
+pub struct BuildQueue {
+    jobs: Mutex<BTreeMap<u64, Arc<BuildJob>>>,
+    next_id: AtomicU64,
+    queue: crossbeam_channel::Sender<Arc<BuildJob>>,
+}
+
+impl BuildQueue {
+    pub fn start(service: BuildService) -> Arc<BuildQueue> {
+        let (queue, jobs) = crossbeam_channel::unbounded::<Arc<BuildJob>>();
+        thread::spawn(move || {
+            for job in jobs {
+                run_build(&service, &job);
+            }
+        });
+        Arc::new(BuildQueue {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            queue,
+        })
+    }
+
+    pub fn submit(&self, request: BuildRequest) -> Arc<BuildJob> {
+        let job = Arc::new(BuildJob {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            request,
+            progress: Mutex::new(Progress {
+                state: BuildState::Queued,
+                events: Vec::new(),
+                result: None,
+            }),
+            changed: Condvar::new(),
+        });
+        self.jobs.lock().unwrap().insert(job.id, Arc::clone(&job));
+        // the worker lives as long as the queue
+        let _ = self.queue.send(Arc::clone(&job));
+        job
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<BuildJob>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Arc<BuildJob>> {
+        self.jobs.lock().unwrap().values().cloned().collect()
+    }
+}
+
+// Every build clones into its own directory under the source destination,
+// removed afterwards, so asking for a language twice doesn't trip over the
+// first checkout.
+fn run_build(service: &BuildService, job: &Arc<BuildJob>) {
+    job.progress.lock().unwrap().state = BuildState::Running;
+    job.changed.notify_all();
+    log::info!(
+        "build {} started: {}",
+        job.id,
+        job.request.languages.join(", ")
+    );
+
+    let mut options = service.options.clone();
+    options.source_destination = format!("{}build-{}/", options.source_destination, job.id);
+    let source_dir = options.source_destination.clone();
+    let mut pipeline = Pipeline::builder()
+        .source(Arc::clone(&service.source))
+        .languages(&job.request.languages)
+        .options(options)
+        .threads(service.threads)
+        .network_jobs(service.network_jobs)
+        .verify_jobs(service.verify_jobs)
+        .trust(service.trust.clone())
+        .config(
+            service.config_path.clone(),
+            service.config_format,
+            service.path_style,
+        );
+    if let Some(store) = &service.store {
+        pipeline = pipeline.store(Arc::clone(store));
+    }
+    for (language, rev) in &job.request.revisions {
+        pipeline = pipeline.revision(language, rev.clone());
+    }
+    let watcher = Arc::clone(job);
+    let report = pipeline
+        .on_event(move |event| watcher.push(event_json(&event)))
+        .build()
+        .run();
+    if Path::new(&source_dir).exists() {
+        if let Err(e) = fs::remove_dir_all(&source_dir) {
+            log::warn!("failed to remove {} : {}", source_dir, e);
+        }
+    }
+
+    match report {
+        Ok(report) => {
+            let built: Vec<&str> = report.artifacts.iter().map(|a| a.name.as_str()).collect();
+            let failed: BTreeMap<&str, String> = report
+                .failed
+                .iter()
+                .map(|(lang, e)| (lang.as_str(), e.to_string()))
+                .collect();
+            // the same failures by phase and category, for triage
+            let failures: BTreeMap<&str, Value> = report
+                .failed
+                .iter()
+                .map(|(lang, e)| {
+                    let failure = failure::classify(e);
+                    (
+                        lang.as_str(),
+                        json!({ "phase": failure.phase.as_str(), "category": failure.category.as_str() }),
+                    )
+                })
+                .collect();
+            // language -> repository the trust policy refused
+            let untrusted: BTreeMap<&str, &str> = report
+                .untrusted
+                .iter()
+                .map(|(lang, repo_url)| (lang.as_str(), repo_url.as_str()))
+                .collect();
+            log::info!(
+                "build {} finished: {} built, {} failed",
+                job.id,
+                built.len(),
+                failed.len()
+            );
+            job.end(
+                BuildState::Finished,
+                json!({
+                    "built": built,
+                    "failed": failed,
+                    "failures": failures,
+                    "skipped": report.skipped,
+                    "untrusted": untrusted,
+                    "compiler": report.compiler,
+                }),
+            );
+        }
+        Err(e) => {
+            log::error!("build {} failed : {}", job.id, e);
+            job.end(BuildState::Failed, json!({ "error": e.to_string() }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ParserEntry;
+
+    #[test]
+    fn runs_queued_builds_in_order() {
+        let root = std::env::temp_dir().join(format!("service-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let options = BuildOptions {
+            output_dir: format!("{}/libs/", root.display()),
+            source_destination: format!("{}/src/", root.display()),
+            ..BuildOptions::default()
+        };
+        let source: Vec<ParserEntry> = vec![ParserEntry {
+            name: "json".to_string(),
+            repo_url: "https://github.com/someone/tree-sitter-json".to_string(),
+        }];
+        // refused by the trust policy, so nothing is cloned
+        let queue = BuildQueue::start(BuildService {
+            options,
+            source: Arc::new(source),
+            config_path: format!("{}/config.json", root.display()),
+            config_format: ConfigFormat::Json,
+            path_style: None,
+            threads: 1,
+            network_jobs: 1,
+            verify_jobs: 1,
+            trust: TrustPolicy::new(["tree-sitter"]),
+            store: None,
+        });
+        let request = |lang: &str| BuildRequest {
+            languages: vec![lang.to_string()],
+            revisions: BTreeMap::from([(lang.to_string(), "v1.0.0".to_string())]),
+        };
+        let first = queue.submit(request("json"));
+        let second = queue.submit(request("toml"));
+        assert_eq!((first.id, second.id), (1, 2));
+        assert_eq!(queue.list().len(), 2);
+        assert!(queue.get(3).is_none());
+
+        // the events come until the build is over, then nothing more
+        let mut seen = 0;
+        loop {
+            let events = second.events_after(seen);
+            if events.is_empty() {
+                break;
+            }
+            seen += events.len();
+        }
+        assert!(seen > 0);
+        let second = queue.get(2).unwrap().summary();
+        assert_eq!(second["state"], "finished");
+        assert_eq!(second["refs"]["toml"], "v1.0.0");
+        assert_eq!(second["result"]["built"], json!([]));
+
+        // builds run one at a time, the first was over before the second
+        let first = first.summary();
+        assert_eq!(first["state"], "finished");
+        assert_eq!(
+            first["result"]["untrusted"]["json"],
+            "https://github.com/someone/tree-sitter-json"
+        );
+        assert!(!root.join("src/build-1").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
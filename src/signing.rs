@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// external tool producing detached signatures
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Signer {
+    // `gpg --detach-sign --armor`, the key is a key id or user id
+    Gpg,
+    // `minisign -S`, the key is a secret key file
+    Minisign,
+}
+
+impl Signer {
+    pub fn signature_path(&self, path: &Path) -> PathBuf {
+        let extension = match self {
+            Signer::Gpg => "asc",
+            Signer::Minisign => "minisig",
+        };
+        let mut signature = path.as_os_str().to_owned();
+        signature.push(".");
+        signature.push(extension);
+        PathBuf::from(signature)
+    }
+
+    // write a detached signature next to `path`, returning where it went
+    pub fn sign(&self, key: &str, path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let signature = self.signature_path(path);
+        let mut cmd = match self {
+            Signer::Gpg => {
+                let mut cmd = Command::new("gpg");
+                cmd.args(["--batch", "--yes", "--armor", "--local-user", key])
+                    .arg("--output")
+                    .arg(&signature)
+                    .arg("--detach-sign")
+                    .arg(path);
+                cmd
+            }
+            Signer::Minisign => {
+                let mut cmd = Command::new("minisign");
+                cmd.args(["-S", "-s", key])
+                    .arg("-m")
+                    .arg(path)
+                    .arg("-x")
+                    .arg(&signature);
+                cmd
+            }
+        };
+        run(&mut cmd, "sign", path)?;
+        Ok(signature)
+    }
+}
+
+// Check whichever detached signature sits next to `path`. None if it isn't
+// signed at all; minisign signatures need the public key file.
+pub fn verify(
+    path: &Path,
+    minisign_public_key: Option<&str>,
+) -> Option<Result<(), Box<dyn std::error::Error>>> {
+    let gpg_signature = Signer::Gpg.signature_path(path);
+    if gpg_signature.exists() {
+        let mut cmd = Command::new("gpg");
+        cmd.args(["--batch", "--verify"])
+            .arg(&gpg_signature)
+            .arg(path);
+        return Some(run(&mut cmd, "verify", path));
+    }
+    let minisign_signature = Signer::Minisign.signature_path(path);
+    if minisign_signature.exists() {
+        let public_key = match minisign_public_key {
+            Some(key) => key,
+            None => {
+                return Some(Err(
+                    "minisign signature found but no --verify-key given".into()
+                ))
+            }
+        };
+        let mut cmd = Command::new("minisign");
+        cmd.args(["-V", "-p", public_key])
+            .arg("-m")
+            .arg(path)
+            .arg("-x")
+            .arg(&minisign_signature);
+        return Some(run(&mut cmd, "verify", path));
+    }
+    None
+}
+
+fn run(cmd: &mut Command, action: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let output = cmd
+        .output()
+        .map_err(|e| format!("failed to run {:?}: {}", cmd.get_program(), e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "failed to {} {}: {}",
+            action,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+use crate::languages::canonical_name;
+
+// where the CLI looks for it unless told otherwise
+pub const SKIPLIST: &str = "./skiplist.txt";
+
+// a grammar left out of every run
+#[derive(Debug, Clone)]
+pub struct SkipEntry {
+    pub language: String,
+    pub reason: Option<String>,
+    // built again from this day on
+    pub until: Option<NaiveDate>,
+}
+
+// Grammars that never build here, one per line: the name, an optional
+// `until=YYYY-MM-DD` and an optional reason. `#` at the start of a word
+// starts a comment, C# is a name.
+//
+//     haskell  needs more memory than this machine has
+//     swift    until=2026-11-01  waiting for the scanner fix
+#[derive(Debug, Clone, Default)]
+pub struct Skiplist {
+    // by canonical name
+    entries: BTreeMap<String, SkipEntry>,
+}
+
+impl Skiplist {
+    // No file is an empty list. Entries whose day has come are left out.
+    pub fn load(path: &str, today: NaiveDate) -> Result<Skiplist, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => parse(&text, today).map_err(|e| format!("{}: {}", path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Skiplist::default()),
+            Err(e) => Err(format!("{}: {}", path, e)),
+        }
+    }
+
+    pub fn get(&self, language: &str) -> Option<&SkipEntry> {
+        self.entries.get(&canonical_name(language))
+    }
+}
+
+pub fn parse(text: &str, today: NaiveDate) -> Result<Skiplist, String> {
+    let mut entries = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let Some((language, rest)) = line
+            .split_once(char::is_whitespace)
+            .or((!line.is_empty()).then_some((line, "")))
+        else {
+            continue;
+        };
+        let mut rest = rest.trim_start();
+        let mut until = None;
+        if let Some(date) = rest.strip_prefix("until=") {
+            let (date, reason) = date.split_once(char::is_whitespace).unwrap_or((date, ""));
+            until = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+                format!(
+                    "line {}: until={} isn't a date like 2026-11-01",
+                    number + 1,
+                    date
+                )
+            })?);
+            rest = reason.trim_start();
+        }
+        if until.is_some_and(|until| until <= today) {
+            log::info!(
+                "the skiplist entry of {} expired, building it again",
+                language
+            );
+            continue;
+        }
+        entries.insert(
+            canonical_name(language),
+            SkipEntry {
+                language: language.to_string(),
+                reason: (!rest.is_empty()).then(|| rest.to_string()),
+                until,
+            },
+        );
+    }
+    Ok(Skiplist { entries })
+}
+
+fn strip_comment(line: &str) -> &str {
+    let comment = line.char_indices().find(|&(i, c)| {
+        c == '#'
+            && line[..i]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+    });
+    match comment {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn parses_entries() {
+        let text = "# grammars that don't build here
+haskell  needs more memory than this machine has
+swift    until=2026-11-01  waiting for the scanner fix
+C#       # the scanner needs a newer compiler
+perl until=2026-01-01 fixed upstream by now
+
+";
+        let list = parse(text, day("2026-10-14")).unwrap();
+        let haskell = list.get("haskell").unwrap();
+        assert_eq!(
+            haskell.reason.as_deref(),
+            Some("needs more memory than this machine has")
+        );
+        assert_eq!(haskell.until, None);
+        let swift = list.get("Swift").unwrap();
+        assert_eq!(swift.until, Some(day("2026-11-01")));
+        assert_eq!(swift.reason.as_deref(), Some("waiting for the scanner fix"));
+        let c_sharp = list.get("c_sharp").unwrap();
+        assert_eq!(c_sharp.language, "C#");
+        assert_eq!(c_sharp.reason, None);
+        // expired
+        assert!(list.get("perl").is_none());
+        assert!(list.get("c").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_dates() {
+        let e = parse("swift until=next-week\n", day("2026-10-14")).unwrap_err();
+        assert!(e.starts_with("line 1:"), "{}", e);
+    }
+}
@@ -0,0 +1,129 @@
+use libloading::{Library, Symbol};
+use std::path::Path;
+use tree_sitter::{Language, Parser};
+use tree_sitter_language::LanguageFn;
+
+use crate::languages::symbol_name;
+use crate::symbols::needs_asan_runtime;
+
+// A grammar loaded from a built library. The library stays open for as long
+// as the language is around, fields drop in order.
+pub struct LoadedGrammar {
+    pub language: Language,
+    _library: Library,
+}
+
+// Load a freshly built library into this process and construct its language
+pub fn load_grammar(
+    library: &Path,
+    language: &str,
+) -> Result<LoadedGrammar, Box<dyn std::error::Error>> {
+    if needs_asan_runtime(library) {
+        return Err(format!(
+            "{} is built with AddressSanitizer and can't be loaded here",
+            library.display()
+        )
+        .into());
+    }
+    let symbol = symbol_name(language);
+    // SAFETY: loading runs the library's initializers; this is a grammar we just
+    // compiled, and the entry point has the signature every grammar exports
+    let library = unsafe { Library::new(library) }
+        .map_err(|e| format!("can't load {}: {}", library.display(), e))?;
+    let entry_point: Symbol<unsafe extern "C" fn() -> *const ()> =
+        unsafe { library.get(symbol.as_bytes()) }.map_err(|_| format!("no symbol {}", symbol))?;
+    if unsafe { entry_point() }.is_null() {
+        return Err(format!("{} returned no language", symbol).into());
+    }
+    let grammar = Language::new(unsafe { LanguageFn::from_raw(*entry_point) });
+    Ok(LoadedGrammar {
+        language: grammar,
+        _library: library,
+    })
+}
+
+// Parse `sample` with a freshly built library and check the result is a real
+// tree. Catches grammars that compile but export the wrong symbol, target an
+// ABI we can't load, or can't parse at all.
+pub fn smoke_test(
+    library: &Path,
+    language: &str,
+    sample: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let grammar = load_grammar(library, language)?;
+
+    let mut parser = Parser::new();
+    parser
+        .set_language(&grammar.language)
+        .map_err(|e| format!("unusable language: {}", e))?;
+    let tree = parser
+        .parse(sample, None)
+        .ok_or("parsing the sample failed")?;
+    let root = tree.root_node();
+    if root.is_error() || root.is_missing() || (root.end_byte() == 0 && !sample.trim().is_empty()) {
+        return Err(format!("bogus root node {} for the sample", root.kind()).into());
+    }
+    if root.has_error() {
+        // samples are tiny and generic, a grammar may well disagree with one
+        log::warn!(
+            "sample for {} parsed with errors: {}",
+            language,
+            root.to_sexp()
+        );
+    }
+    Ok(())
+}
+
+// how a user supplied test file parsed with its grammar
+#[derive(Debug, Clone)]
+pub struct TestFileParse {
+    pub file: String,
+    // ERROR and MISSING nodes in the tree
+    pub errors: usize,
+    // 1-based line and column of the first of them
+    pub first_error: Option<(usize, usize)>,
+}
+
+// Parse `file` with a freshly built library and count the errors in the tree
+pub fn parse_test_file(
+    library: &Path,
+    language: &str,
+    file: &Path,
+) -> Result<TestFileParse, Box<dyn std::error::Error>> {
+    let grammar = load_grammar(library, language)?;
+    let source = std::fs::read(file)?;
+    let mut parser = Parser::new();
+    parser
+        .set_language(&grammar.language)
+        .map_err(|e| format!("unusable language: {}", e))?;
+    let tree = parser
+        .parse(&source, None)
+        .ok_or_else(|| format!("parsing {} failed", file.display()))?;
+
+    let mut errors = 0;
+    let mut first_error = None;
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            errors += 1;
+            if first_error.is_none() {
+                let at = node.start_position();
+                first_error = Some((at.row + 1, at.column + 1));
+            }
+        }
+        // only subtrees with errors in them are worth walking into
+        if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return Ok(TestFileParse {
+                    file: file.to_string_lossy().to_string(),
+                    errors,
+                    first_error,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Arc;
+
+use crate::error::ScrapeError;
+use crate::grammar::load_manifest;
+use crate::languages::own_name;
+use crate::scrape::{scrape_parsers, PARSER_LIST_URL};
+
+// one parser to clone and build
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParserEntry {
+    // the language as the source names it, e.g. "C++" on the wiki
+    pub name: String,
+    pub repo_url: String,
+}
+
+// Somewhere to discover parsers. The pipeline only sees the entries, so a
+// new backend is just another implementation of this.
+pub trait ParserSource: Send + Sync {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError>;
+
+    // what errors name the source as, e.g. its URL
+    fn describe(&self) -> String {
+        "a custom parser source".to_string()
+    }
+}
+
+// the tree-sitter wiki's list of parsers
+pub struct WikiSource {
+    pub url: String,
+}
+
+impl Default for WikiSource {
+    fn default() -> Self {
+        WikiSource {
+            url: PARSER_LIST_URL.to_string(),
+        }
+    }
+}
+
+impl ParserSource for WikiSource {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        scrape_parsers(&self.url)
+    }
+
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+}
+
+// the `parsers` of a manifest, name -> repository
+pub struct ManifestSource {
+    pub path: String,
+}
+
+impl ParserSource for ManifestSource {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        let manifest = load_manifest(&self.path).map_err(|e| ScrapeError::Invalid {
+            origin: self.path.clone(),
+            message: e.to_string(),
+        })?;
+        Ok(manifest
+            .parsers
+            .into_iter()
+            .map(|(name, repo_url)| ParserEntry { name, repo_url })
+            .collect())
+    }
+
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+}
+
+pub const NVIM_TREESITTER_PARSERS_URL: &str =
+    "https://raw.githubusercontent.com/nvim-treesitter/nvim-treesitter/main/lua/nvim-treesitter/parsers.lua";
+
+// nvim-treesitter's registry, its parsers.lua read as text. `url` may also be
+// a local path, e.g. of a checkout.
+pub struct NvimTreesitterSource {
+    pub url: String,
+}
+
+impl Default for NvimTreesitterSource {
+    fn default() -> Self {
+        NvimTreesitterSource {
+            url: NVIM_TREESITTER_PARSERS_URL.to_string(),
+        }
+    }
+}
+
+impl ParserSource for NvimTreesitterSource {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        let registry = if self.url.starts_with("http://") || self.url.starts_with("https://") {
+            Client::new()
+                .get(&self.url)
+                .send()?
+                .error_for_status()?
+                .text()?
+        } else {
+            fs::read_to_string(&self.url).map_err(|source| ScrapeError::Io {
+                path: self.url.clone(),
+                source,
+            })?
+        };
+        let parsers = parse_nvim_registry(&registry);
+        if parsers.is_empty() {
+            return Err(ScrapeError::Invalid {
+                origin: self.url.clone(),
+                message: "no parsers with an install_info url found".to_string(),
+            });
+        }
+        Ok(parsers)
+    }
+
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+}
+
+// The <lang> = { install_info = { url = ... } } tables of parsers.lua, both
+// the `list.<lang> = {` layout of the master branch and the `return { <lang> = {`
+// one of main. Languages without a url (queries only) are left out.
+fn parse_nvim_registry(registry: &str) -> Vec<ParserEntry> {
+    let mut parsers = Vec::new();
+    let mut returns_table = false;
+    let mut current: Option<String> = None;
+    for line in registry.lines() {
+        let trimmed = line.trim();
+        if line.starts_with("return {") {
+            returns_table = true;
+        } else if let Some(rest) = line.strip_prefix("list.") {
+            current = rest
+                .split_once('=')
+                .map(|(name, _)| name.trim().to_string());
+        } else if returns_table
+            && line.starts_with("  ")
+            && !line.starts_with("   ")
+            && trimmed.ends_with('{')
+        {
+            current = trimmed
+                .split_once('=')
+                .map(|(name, _)| name.trim().trim_matches(['[', ']', '"', '\'']).to_string());
+        } else if let Some(value) = trimmed.strip_prefix("url") {
+            let url = value
+                .trim_start()
+                .strip_prefix('=')
+                .map(|v| v.trim().trim_end_matches(',').trim_matches(['"', '\'']));
+            if let (Some(url), Some(name)) = (url, current.take()) {
+                parsers.push(ParserEntry {
+                    name,
+                    repo_url: url.to_string(),
+                });
+            }
+        }
+    }
+    parsers
+}
+
+pub const GITHUB_SEARCH_QUERY: &str = "tree-sitter in:name topic:tree-sitter";
+
+// Repositories named tree-sitter-<lang> found by GitHub's repository search,
+// most starred first. GITHUB_TOKEN is used when set, unauthenticated search
+// is limited to a few requests a minute.
+pub struct GithubSearchSource {
+    pub query: String,
+    // the search API stops at 1000 anyway
+    pub max_results: usize,
+    pub api_url: String,
+}
+
+impl Default for GithubSearchSource {
+    fn default() -> Self {
+        GithubSearchSource {
+            query: GITHUB_SEARCH_QUERY.to_string(),
+            max_results: 100,
+            api_url: "https://api.github.com".to_string(),
+        }
+    }
+}
+
+impl ParserSource for GithubSearchSource {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        let client = Client::new();
+        let token = std::env::var("GITHUB_TOKEN").ok();
+        let mut parsers = Vec::new();
+        let mut seen = HashSet::new();
+        let per_page = self.max_results.clamp(1, 100);
+        let mut page = 1;
+        let mut fetched = 0;
+        while fetched < self.max_results {
+            let mut request = client
+                .get(format!("{}/search/repositories", self.api_url))
+                .query(&[
+                    ("q", self.query.as_str()),
+                    ("sort", "stars"),
+                    ("order", "desc"),
+                    ("per_page", &per_page.to_string()),
+                    ("page", &page.to_string()),
+                ])
+                // the API refuses requests without one
+                .header("User-Agent", "parser_scraper")
+                .header("Accept", "application/vnd.github+json");
+            if let Some(token) = &token {
+                request = request.bearer_auth(token);
+            }
+            let results: Value = request.send()?.error_for_status()?.json()?;
+            let items = results["items"]
+                .as_array()
+                .ok_or_else(|| ScrapeError::Invalid {
+                    origin: self.describe(),
+                    message: "search results without items".to_string(),
+                })?;
+            for item in items {
+                let name = item["name"].as_str().unwrap_or_default();
+                let url = item["html_url"].as_str().unwrap_or_default();
+                let Some(lang) = name.strip_prefix("tree-sitter-") else {
+                    continue;
+                };
+                // forks and mirrors lose against the most starred repository
+                if !lang.is_empty() && !url.is_empty() && seen.insert(own_name(lang)) {
+                    parsers.push(ParserEntry {
+                        name: lang.to_string(),
+                        repo_url: url.to_string(),
+                    });
+                }
+            }
+            fetched += items.len();
+            if items.len() < per_page {
+                break;
+            }
+            page += 1;
+        }
+        parsers.sort();
+        Ok(parsers)
+    }
+
+    fn describe(&self) -> String {
+        format!("GitHub search \"{}\"", self.query)
+    }
+}
+
+// shared with something else, e.g. every build of the service
+impl ParserSource for Arc<dyn ParserSource> {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        (**self).fetch()
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+}
+
+// a fixed list, nothing is fetched
+impl ParserSource for Vec<ParserEntry> {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        Ok(self.clone())
+    }
+
+    fn describe(&self) -> String {
+        "the given parsers".to_string()
+    }
+}
+
+// Several sources merged. When two list the same language, by canonical name,
+// the earlier source wins.
+impl ParserSource for Vec<Box<dyn ParserSource>> {
+    fn fetch(&self) -> Result<Vec<ParserEntry>, ScrapeError> {
+        let mut parsers = Vec::new();
+        let mut seen = HashSet::new();
+        for source in self {
+            for parser in source.fetch()? {
+                if seen.insert(own_name(&parser.name)) {
+                    parsers.push(parser);
+                }
+            }
+        }
+        Ok(parsers)
+    }
+
+    fn describe(&self) -> String {
+        self.iter()
+            .map(|s| s.describe())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
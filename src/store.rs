@@ -0,0 +1,864 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::checksums;
+use crate::config::LanguageArtifact;
+use crate::error::{BuildError, StoreError};
+use crate::grammar::remote_head_commit;
+use crate::languages::own_name;
+use crate::serve::host_target;
+
+pub const STATE_DB: &str = "./parser_scraper.db";
+
+// bumped with every change to the tables below
+const SCHEMA_VERSION: i32 = 5;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    -- NULL while running, and forever for a run that was killed
+    finished_at TEXT
+);
+CREATE TABLE IF NOT EXISTS builds (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    -- own_name of the parser the source listed
+    language TEXT NOT NULL,
+    -- the config entry, which differs for repos holding several grammars
+    name TEXT NOT NULL,
+    repo_url TEXT NOT NULL,
+    commit_sha TEXT,
+    artifact TEXT,
+    sha256 TEXT,
+    -- what a later run needs to write the config and other outputs without
+    -- rebuilding: the config entry as JSON, and the upstream details
+    entry TEXT,
+    scope TEXT,
+    license TEXT,
+    license_files TEXT,
+    duration_ms INTEGER NOT NULL,
+    -- built, failed or skipped
+    outcome TEXT NOT NULL,
+    error TEXT,
+    finished_at TEXT NOT NULL,
+    -- BuildOptions::options_hash of a built one, --incremental reuses it only
+    -- with the same options
+    options_hash TEXT
+);
+CREATE INDEX IF NOT EXISTS builds_by_language ON builds(language, id);
+-- every grammar a run is going to build, and how far it got
+CREATE TABLE IF NOT EXISTS jobs (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    language TEXT NOT NULL,
+    -- os-arch the grammar is built for
+    target TEXT NOT NULL,
+    repo_url TEXT NOT NULL,
+    state TEXT NOT NULL,
+    error TEXT,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (run_id, language, target)
+);
+-- every --versioned output directory that became latest, and the config of
+-- that moment, for rollback
+CREATE TABLE IF NOT EXISTS versions (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER REFERENCES runs(id),
+    -- the canonical output directory the version is in
+    output TEXT NOT NULL,
+    name TEXT NOT NULL,
+    config TEXT,
+    promoted_at TEXT NOT NULL,
+    -- set when rolled back from, it isn't known-good anymore
+    rolled_back_at TEXT
+);
+-- how big the last checkout of every language was, for the disk estimate
+CREATE TABLE IF NOT EXISTS checkouts (
+    language TEXT PRIMARY KEY,
+    bytes INTEGER NOT NULL,
+    measured_at TEXT NOT NULL
+);
+";
+
+// Every run and every grammar it built or failed, in one SQLite file. The
+// stats and outdated subcommands, --resume and --incremental all query it.
+pub struct Store {
+    path: String,
+    conn: Mutex<Connection>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildOutcome {
+    Built,
+    Failed,
+    // a build filter dropped it
+    Skipped,
+}
+
+impl BuildOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BuildOutcome::Built => "built",
+            BuildOutcome::Failed => "failed",
+            BuildOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+// Where one grammar of a run is. A killed run leaves its in-flight jobs in
+// cloning or building, and resuming it builds everything that isn't done.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobState {
+    Queued,
+    Cloning,
+    // compiling and the checks that load the library
+    Building,
+    Done,
+    Failed,
+    // a build filter dropped it
+    Skipped,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Cloning => "cloning",
+            JobState::Building => "building",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Skipped => "skipped",
+        }
+    }
+}
+
+// one row of `jobs`
+pub struct Job {
+    pub language: String,
+    pub target: String,
+    pub repo_url: String,
+    pub state: String,
+    pub error: Option<String>,
+    pub updated_at: String,
+}
+
+// the newest run, when it never finished
+pub struct InterruptedRun {
+    pub id: i64,
+    // the languages it was asked to build, empty if it didn't record its jobs
+    pub languages: HashSet<String>,
+    // what it had already built
+    pub built: Vec<LastBuild>,
+}
+
+// one row of `builds`
+pub struct BuildRecord<'a> {
+    pub language: &'a str,
+    pub name: &'a str,
+    pub repo_url: &'a str,
+    pub commit: Option<&'a str>,
+    pub artifact: Option<&'a str>,
+    pub sha256: Option<&'a str>,
+    pub details: Option<&'a LanguageArtifact>,
+    pub duration: Duration,
+    pub outcome: BuildOutcome,
+    pub error: Option<&'a str>,
+    pub options_hash: Option<&'a str>,
+}
+
+// a successful build of a config entry
+#[derive(Debug, Clone)]
+pub struct LastBuild {
+    pub language: String,
+    pub name: String,
+    pub repo_url: String,
+    pub commit: Option<String>,
+    pub artifact: Option<String>,
+    pub sha256: Option<String>,
+    pub entry: Option<Value>,
+    pub scope: Option<String>,
+    pub license: Option<String>,
+    pub license_files: Vec<String>,
+    pub finished_at: String,
+    // None for builds recorded before the hash was
+    pub options_hash: Option<String>,
+}
+
+const LAST_BUILD_COLUMNS: &str =
+    "language, name, repo_url, commit_sha, artifact, sha256, entry, scope, license, license_files, finished_at, options_hash";
+
+impl LastBuild {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<LastBuild> {
+        Ok(LastBuild {
+            language: row.get(0)?,
+            name: row.get(1)?,
+            repo_url: row.get(2)?,
+            commit: row.get(3)?,
+            artifact: row.get(4)?,
+            sha256: row.get(5)?,
+            entry: row
+                .get::<_, Option<String>>(6)?
+                .and_then(|e| serde_json::from_str(&e).ok()),
+            scope: row.get(7)?,
+            license: row.get(8)?,
+            license_files: row
+                .get::<_, Option<String>>(9)?
+                .and_then(|f| serde_json::from_str(&f).ok())
+                .unwrap_or_default(),
+            finished_at: row.get(10)?,
+            options_hash: row.get(11)?,
+        })
+    }
+
+    // The artifact again, to write into outputs as if it was just built. The
+    // results of the checks that load the library are not kept.
+    pub fn to_artifact(&self) -> Option<LanguageArtifact> {
+        Some(LanguageArtifact {
+            name: self.name.clone(),
+            repo_url: self.repo_url.clone(),
+            scope: self.scope.clone(),
+            license: self.license.clone(),
+            license_files: self.license_files.clone(),
+            corpus: None,
+            test_files: Vec::new(),
+            tree_diff: None,
+            entry: self.entry.clone()?,
+        })
+    }
+
+    // the library is still there, and still the one that was built
+    pub fn artifact_intact(&self) -> bool {
+        match (&self.artifact, &self.sha256) {
+            (Some(artifact), Some(sha256)) => {
+                checksums::sha256_file(Path::new(artifact)).is_ok_and(|hash| &hash == sha256)
+            }
+            _ => false,
+        }
+    }
+}
+
+// a --versioned output directory that became latest
+pub struct Version {
+    pub name: String,
+    pub run: Option<i64>,
+    // the config as it was written by that run
+    pub config: Option<String>,
+    pub promoted_at: String,
+}
+
+pub struct LanguageStats {
+    pub language: String,
+    // runs that tried to build it
+    pub attempts: i64,
+    pub failures: i64,
+    pub last_outcome: String,
+    pub last_commit: Option<String>,
+    pub average_duration: Duration,
+    pub last_attempt: String,
+}
+
+impl Store {
+    // open the database, creating it and its tables if needed
+    pub fn open(path: &str) -> Result<Store, StoreError> {
+        let error = |source| StoreError::Sqlite {
+            path: path.to_string(),
+            source,
+        };
+        let conn = Connection::open(path).map_err(error)?;
+        // a serve build and a CLI run may share the file
+        conn.busy_timeout(Duration::from_secs(10)).map_err(error)?;
+        let version: i32 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(error)?;
+        if version > SCHEMA_VERSION {
+            return Err(StoreError::NewerSchema {
+                path: path.to_string(),
+                version,
+            });
+        }
+        conn.execute_batch(SCHEMA).map_err(error)?;
+        // CREATE TABLE IF NOT EXISTS leaves the builds of version 4 as they were
+        let has_options_hash: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('builds') WHERE name = 'options_hash'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(error)?;
+        if !has_options_hash {
+            conn.execute("ALTER TABLE builds ADD COLUMN options_hash TEXT", [])
+                .map_err(error)?;
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)
+            .map_err(error)?;
+        Ok(Store {
+            path: path.to_string(),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn error(&self, source: rusqlite::Error) -> StoreError {
+        StoreError::Sqlite {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    pub fn begin_run(&self) -> Result<i64, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT INTO runs (started_at) VALUES (?1)", [now()])
+            .map_err(|e| self.error(e))?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn finish_run(&self, run: i64) -> Result<(), StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE runs SET finished_at = ?1 WHERE id = ?2",
+                params![now(), run],
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    pub fn record_build(&self, run: i64, record: &BuildRecord) -> Result<(), StoreError> {
+        let details = record.details;
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO builds (run_id, language, name, repo_url, commit_sha, artifact, sha256,
+                                     entry, scope, license, license_files,
+                                     duration_ms, outcome, error, finished_at, options_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                params![
+                    run,
+                    own_name(record.language),
+                    record.name,
+                    record.repo_url,
+                    record.commit,
+                    record.artifact,
+                    record.sha256,
+                    details.map(|a| a.entry.to_string()),
+                    details.and_then(|a| a.scope.clone()),
+                    details.and_then(|a| a.license.clone()),
+                    details.map(|a| serde_json::json!(a.license_files).to_string()),
+                    record.duration.as_millis() as i64,
+                    record.outcome.as_str(),
+                    record.error,
+                    now(),
+                    record.options_hash
+                ],
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    pub fn interrupted_run(&self) -> Result<Option<InterruptedRun>, StoreError> {
+        let (run, finished) = match self.latest_run()? {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+        if finished {
+            return Ok(None);
+        }
+        let languages = self
+            .jobs(run)?
+            .into_iter()
+            .map(|job| job.language)
+            .collect();
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {} FROM builds WHERE run_id = ?1 AND outcome = 'built' ORDER BY id",
+                LAST_BUILD_COLUMNS
+            ))
+            .map_err(|e| self.error(e))?;
+        let built = statement
+            .query_map([run], LastBuild::from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| self.error(e))?;
+        Ok(Some(InterruptedRun {
+            id: run,
+            languages,
+            built,
+        }))
+    }
+
+    // (id, whether it finished) of the newest run
+    pub fn latest_run(&self) -> Result<Option<(i64, bool)>, StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id, finished_at IS NOT NULL FROM runs ORDER BY id DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| self.error(e))
+    }
+
+    // Add jobs to a run as queued. Ones it already has, when resuming, keep
+    // their state until they are started again.
+    pub fn queue_jobs(
+        &self,
+        run: i64,
+        target: &str,
+        jobs: &[(String, String)],
+    ) -> Result<(), StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction().map_err(|e| self.error(e))?;
+        for (language, repo_url) in jobs {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO jobs (run_id, language, target, repo_url, state, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        run,
+                        own_name(language),
+                        target,
+                        repo_url,
+                        JobState::Queued.as_str(),
+                        now()
+                    ],
+                )
+                .map_err(|e| self.error(e))?;
+        }
+        transaction.commit().map_err(|e| self.error(e))
+    }
+
+    pub fn set_job_state(
+        &self,
+        run: i64,
+        language: &str,
+        target: &str,
+        state: JobState,
+        error: Option<&str>,
+    ) -> Result<(), StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE jobs SET state = ?1, error = ?2, updated_at = ?3
+                 WHERE run_id = ?4 AND language = ?5 AND target = ?6",
+                params![
+                    state.as_str(),
+                    error,
+                    now(),
+                    run,
+                    own_name(language),
+                    target
+                ],
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    // the jobs of a run that haven't ended yet, either way
+    pub fn unfinished_jobs(&self, run: i64) -> Result<i64, StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM jobs WHERE run_id = ?1 AND state IN (?2, ?3, ?4)",
+                params![
+                    run,
+                    JobState::Queued.as_str(),
+                    JobState::Cloning.as_str(),
+                    JobState::Building.as_str()
+                ],
+                |row| row.get(0),
+            )
+            .map_err(|e| self.error(e))
+    }
+
+    pub fn jobs(&self, run: i64) -> Result<Vec<Job>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT language, target, repo_url, state, error, updated_at FROM jobs
+                 WHERE run_id = ?1 ORDER BY language, target",
+            )
+            .map_err(|e| self.error(e))?;
+        let jobs = statement
+            .query_map([run], |row| {
+                Ok(Job {
+                    language: row.get(0)?,
+                    target: row.get(1)?,
+                    repo_url: row.get(2)?,
+                    state: row.get(3)?,
+                    error: row.get(4)?,
+                    updated_at: row.get(5)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| self.error(e))?;
+        Ok(jobs)
+    }
+
+    // the newest successful build of every config entry, by name
+    pub fn last_builds(&self) -> Result<Vec<LastBuild>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {} FROM builds
+                 WHERE id IN (SELECT MAX(id) FROM builds WHERE outcome = 'built' GROUP BY name)
+                 ORDER BY name",
+                LAST_BUILD_COLUMNS
+            ))
+            .map_err(|e| self.error(e))?;
+        let builds = statement
+            .query_map([], LastBuild::from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(|e| self.error(e))?;
+        Ok(builds)
+    }
+
+    pub fn language_stats(&self) -> Result<Vec<LanguageStats>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT language,
+                        COUNT(DISTINCT run_id),
+                        COUNT(DISTINCT CASE WHEN outcome = 'failed' THEN run_id END),
+                        (SELECT outcome FROM builds l WHERE l.language = b.language
+                         ORDER BY id DESC LIMIT 1),
+                        (SELECT commit_sha FROM builds l WHERE l.language = b.language
+                         AND outcome = 'built' ORDER BY id DESC LIMIT 1),
+                        AVG(duration_ms),
+                        MAX(finished_at)
+                 FROM builds b
+                 GROUP BY language
+                 ORDER BY language",
+            )
+            .map_err(|e| self.error(e))?;
+        let stats = statement
+            .query_map([], |row| {
+                Ok(LanguageStats {
+                    language: row.get(0)?,
+                    attempts: row.get(1)?,
+                    failures: row.get(2)?,
+                    last_outcome: row.get(3)?,
+                    last_commit: row.get(4)?,
+                    average_duration: Duration::from_millis(row.get::<_, f64>(5)? as u64),
+                    last_attempt: row.get(6)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| self.error(e))?;
+        Ok(stats)
+    }
+
+    pub fn record_checkout_size(&self, language: &str, bytes: u64) -> Result<(), StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO checkouts (language, bytes, measured_at)
+                 VALUES (?1, ?2, ?3)",
+                params![own_name(language), bytes as i64, now()],
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    // the last measured checkout size of every language, by own_name
+    pub fn checkout_sizes(&self) -> Result<HashMap<String, u64>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare("SELECT language, bytes FROM checkouts")
+            .map_err(|e| self.error(e))?;
+        let sizes = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))
+            .and_then(|rows| rows.collect())
+            .map_err(|e| self.error(e))?;
+        Ok(sizes)
+    }
+
+    // a --versioned run went through and latest points at it now
+    pub fn record_version(
+        &self,
+        run: Option<i64>,
+        output: &str,
+        name: &str,
+        config: Option<&str>,
+    ) -> Result<(), StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO versions (run_id, output, name, config, promoted_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![run, output, name, config, now()],
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    // the versions of `output` that became latest and weren't rolled back
+    // from since, newest first
+    pub fn good_versions(&self, output: &str) -> Result<Vec<Version>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT name, run_id, config, promoted_at FROM versions
+                 WHERE output = ?1 AND id IN (SELECT MAX(id) FROM versions GROUP BY output, name)
+                 AND rolled_back_at IS NULL
+                 ORDER BY id DESC",
+            )
+            .map_err(|e| self.error(e))?;
+        let versions = statement
+            .query_map([output], |row| {
+                Ok(Version {
+                    name: row.get(0)?,
+                    run: row.get(1)?,
+                    config: row.get(2)?,
+                    promoted_at: row.get(3)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| self.error(e))?;
+        Ok(versions)
+    }
+
+    pub fn rolled_back(&self, output: &str, name: &str) -> Result<(), StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE versions SET rolled_back_at = ?1
+                 WHERE output = ?2 AND name = ?3 AND rolled_back_at IS NULL",
+                params![now(), output, name],
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    // (runs, runs that finished)
+    pub fn run_counts(&self) -> Result<(i64, i64), StoreError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*), COUNT(finished_at) FROM runs", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(|e| self.error(e))
+    }
+}
+
+// What a pipeline run writes into the store as its grammars move through the
+// stages, so a killed run still has everything it got through recorded
+pub(crate) struct RunRecorder {
+    store: Arc<Store>,
+    pub run: i64,
+    // the jobs are all for the host
+    target: String,
+    // language -> (repo url, when its clone started)
+    started: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl RunRecorder {
+    pub fn new(store: Arc<Store>, run: i64) -> RunRecorder {
+        RunRecorder {
+            store,
+            run,
+            target: host_target(),
+            started: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // the grammars the run is going to build, before any of them starts
+    pub fn queued(&self, parsers: &[(String, String)]) {
+        if let Err(e) = self.store.queue_jobs(self.run, &self.target, parsers) {
+            log::warn!("failed to record the jobs of run {} : {}", self.run, e);
+        }
+    }
+
+    pub fn started(&self, lang: &str, repo_url: &str) {
+        self.started
+            .lock()
+            .unwrap()
+            .insert(lang.to_string(), (repo_url.to_string(), Instant::now()));
+        self.set_state(lang, JobState::Cloning, None);
+    }
+
+    pub fn building(&self, lang: &str) {
+        self.set_state(lang, JobState::Building, None);
+    }
+
+    pub fn built(&self, lang: &str, artifacts: &[LanguageArtifact], options_hash: &str) {
+        let (repo_url, duration) = self.elapsed(lang);
+        for artifact in artifacts {
+            let path = artifact.entry.get("path").and_then(|p| p.as_str());
+            let sha256 = path.and_then(|p| checksums::sha256_file(Path::new(p)).ok());
+            self.record(&BuildRecord {
+                language: lang,
+                name: &artifact.name,
+                repo_url: &artifact.repo_url,
+                commit: artifact.entry.get("commit").and_then(|c| c.as_str()),
+                artifact: path,
+                sha256: sha256.as_deref(),
+                details: Some(artifact),
+                duration,
+                outcome: BuildOutcome::Built,
+                error: None,
+                options_hash: Some(options_hash),
+            });
+        }
+        if artifacts.is_empty() {
+            self.ended(lang, &repo_url, duration, BuildOutcome::Built, None);
+        }
+        self.set_state(lang, JobState::Done, None);
+    }
+
+    pub fn failed(&self, lang: &str, error: &BuildError) {
+        let (repo_url, duration) = self.elapsed(lang);
+        let error = error.to_string();
+        self.ended(
+            lang,
+            &repo_url,
+            duration,
+            BuildOutcome::Failed,
+            Some(&error),
+        );
+        self.set_state(lang, JobState::Failed, Some(&error));
+    }
+
+    pub fn skipped(&self, lang: &str) {
+        let (repo_url, duration) = self.elapsed(lang);
+        self.ended(lang, &repo_url, duration, BuildOutcome::Skipped, None);
+        self.set_state(lang, JobState::Skipped, None);
+    }
+
+    fn set_state(&self, lang: &str, state: JobState, error: Option<&str>) {
+        if let Err(e) = self
+            .store
+            .set_job_state(self.run, lang, &self.target, state, error)
+        {
+            log::warn!("failed to record {} as {} : {}", lang, state.as_str(), e);
+        }
+    }
+
+    // End the run, unless some of its jobs were left out, e.g. resuming it
+    // for only some languages
+    pub fn finish(&self) {
+        match self.store.unfinished_jobs(self.run) {
+            Ok(0) => {}
+            Ok(left) => {
+                log::info!(
+                    "run {} still has {} grammars to build, leaving it to --resume",
+                    self.run,
+                    left
+                );
+                return;
+            }
+            Err(e) => log::warn!("failed to check the jobs of run {} : {}", self.run, e),
+        }
+        if let Err(e) = self.store.finish_run(self.run) {
+            log::warn!("failed to record the end of run {} : {}", self.run, e);
+        }
+    }
+
+    fn ended(
+        &self,
+        lang: &str,
+        repo_url: &str,
+        duration: Duration,
+        outcome: BuildOutcome,
+        error: Option<&str>,
+    ) {
+        self.record(&BuildRecord {
+            language: lang,
+            name: lang,
+            repo_url,
+            commit: None,
+            artifact: None,
+            sha256: None,
+            details: None,
+            duration,
+            outcome,
+            error,
+            options_hash: None,
+        });
+    }
+
+    fn elapsed(&self, lang: &str) -> (String, Duration) {
+        match self.started.lock().unwrap().remove(lang) {
+            Some((repo_url, started)) => (repo_url, started.elapsed()),
+            None => (String::new(), Duration::ZERO),
+        }
+    }
+
+    fn record(&self, record: &BuildRecord) {
+        if let Err(e) = self.store.record_build(self.run, record) {
+            log::warn!("failed to record the build of {} : {}", record.name, e);
+        }
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+// a built grammar whose upstream moved on since, or couldn't be asked
+pub struct Outdated {
+    pub build: LastBuild,
+    // where the default branch is now, None when the remote can't be reached
+    pub head: Option<String>,
+}
+
+// Ask the remote of every build where its default branch is now, `jobs` at a
+// time, and return the builds that aren't of that commit
+pub fn find_outdated(builds: Vec<LastBuild>, jobs: usize) -> std::io::Result<Vec<Outdated>> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    // one question per repository, however many grammars it holds
+    let mut by_repo: HashMap<String, Vec<LastBuild>> = HashMap::new();
+    for build in builds {
+        by_repo
+            .entry(build.repo_url.clone())
+            .or_default()
+            .push(build);
+    }
+    let mut outdated = runtime.block_on(async {
+        let network = Arc::new(tokio::sync::Semaphore::new(jobs.max(1)));
+        let mut checks = tokio::task::JoinSet::new();
+        for (repo_url, builds) in by_repo {
+            let network = Arc::clone(&network);
+            checks.spawn(async move {
+                let _permit = network.acquire_owned().await.expect("never closed");
+                (remote_head_commit(&repo_url).await, builds)
+            });
+        }
+        let mut outdated = Vec::new();
+        while let Some(checked) = checks.join_next().await {
+            match checked {
+                Ok((head, builds)) => outdated.extend(
+                    builds
+                        .into_iter()
+                        .filter(|build| head.is_none() || build.commit != head)
+                        .map(|build| Outdated {
+                            build,
+                            head: head.clone(),
+                        }),
+                ),
+                Err(e) => log::error!("a remote check died: {}", e),
+            }
+        }
+        outdated
+    });
+    outdated.sort_by(|a, b| a.build.name.cmp(&b.build.name));
+    Ok(outdated)
+}
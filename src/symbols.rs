@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// file the export list is written to, at the top of the checkout
+pub const EXPORTS_FILE: &str = ".parser_scraper-exports";
+
+// symbols every shared object defines, which never clash in practice
+const LINKER_SYMBOLS: &[&str] = &["_init", "_fini", "_edata", "_end", "__bss_start"];
+
+// (type letter, name) of the dynamic symbols nm lists with `flags`
+fn nm(library: &str, flags: &[&str]) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let output = Command::new("nm").args(flags).arg(library).output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "nm failed for {}: {}",
+            library,
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+    let symbols = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().rev();
+            let name = fields.next()?;
+            // undefined symbols come without an address, and without a type on macOS
+            let kind = fields.next().unwrap_or("U");
+            // Mach-O prefixes C symbols with an underscore
+            let name = if cfg!(target_os = "macos") {
+                name.strip_prefix('_').unwrap_or(name)
+            } else {
+                name
+            };
+            Some((kind.to_string(), name.to_string()))
+        })
+        .collect();
+    Ok(symbols)
+}
+
+// The strong symbols a library exports. Weak ones (template instances from
+// C++ scanners) are left out, the loader happily picks one of those.
+pub fn exported_symbols(library: &str) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let flags: &[&str] = if cfg!(target_os = "macos") {
+        &["-gU"]
+    } else {
+        &["-D", "--defined-only"]
+    };
+    let symbols = nm(library, flags)?
+        .into_iter()
+        .filter(|(kind, name)| {
+            let strong = kind.len() == 1 && kind.chars().all(|c| c.is_ascii_uppercase());
+            strong
+                && !matches!(kind.as_str(), "W" | "V" | "U")
+                && !LINKER_SYMBOLS.contains(&name.as_str())
+        })
+        .map(|(_, name)| name)
+        .collect();
+    Ok(symbols)
+}
+
+// the symbols a library expects some other object to provide at load time
+pub fn undefined_symbols(library: &str) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let flags: &[&str] = if cfg!(target_os = "macos") {
+        &["-gu"]
+    } else {
+        &["-D", "--undefined-only"]
+    };
+    Ok(nm(library, flags)?
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect())
+}
+
+// the tree_sitter_<lang>_external_scanner_* functions parser.c calls
+pub fn external_scanner_references(parser_c: &str) -> BTreeSet<String> {
+    parser_c
+        .match_indices("tree_sitter_")
+        .filter_map(|(start, _)| {
+            let rest = &parser_c[start..];
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            name.contains("_external_scanner_")
+                .then(|| name.to_string())
+        })
+        .collect()
+}
+
+// Whether `library` was built with -fsanitize=address. Those only load into
+// processes that start with the ASan runtime, anything else is aborted.
+pub fn needs_asan_runtime(library: &Path) -> bool {
+    let content = fs::read(library).unwrap_or_default();
+    content.windows(11).any(|w| w == b"__asan_init")
+}
+
+// every symbol exported by more than one of `libraries`, with the libraries
+pub fn find_collisions(libraries: &[String]) -> BTreeMap<String, Vec<String>> {
+    let mut owners: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for library in libraries {
+        match exported_symbols(library) {
+            Ok(symbols) => {
+                for symbol in symbols {
+                    owners.entry(symbol).or_default().push(library.clone());
+                }
+            }
+            Err(e) => log::warn!("can't read the symbols of {} : {}", library, e),
+        }
+    }
+    owners.retain(|_, libraries| libraries.len() > 1);
+    owners
+}
+
+// the tree_sitter_<lang> functions parser.c defines
+fn entry_points(parser_c: &str) -> Vec<String> {
+    let mut names: Vec<String> = parser_c
+        .match_indices("TSLanguage *tree_sitter_")
+        .filter_map(|(start, matched)| {
+            let rest = &parser_c[start + matched.len() - "tree_sitter_".len()..];
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+            rest[end..]
+                .starts_with("(void)")
+                .then(|| rest[..end].to_string())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Write an export list holding only the entry points of `parser_c` into the
+// checkout and return the extra cflags and ldflags that apply it
+pub fn export_only_entry_points(
+    repo_dir: &Path,
+    parser_c: &Path,
+) -> Result<(Vec<String>, Vec<String>), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(parser_c)?;
+    let entry_points = entry_points(&content);
+    if entry_points.is_empty() {
+        return Err(format!("no entry point found in {} to export", parser_c.display()).into());
+    }
+    // parser.c from before TS_PUBLIC doesn't mark its entry point visible, hiding
+    // everything would hide that too. The export list alone still keeps the rest local.
+    let cflags = if content.contains("TS_PUBLIC") {
+        vec!["-fvisibility=hidden".to_string()]
+    } else {
+        Vec::new()
+    };
+    let ldflags = if cfg!(target_os = "macos") {
+        let list: String = entry_points.iter().map(|e| format!("_{}\n", e)).collect();
+        fs::write(repo_dir.join(EXPORTS_FILE), list)?;
+        vec![format!("-Wl,-exported_symbols_list,{}", EXPORTS_FILE)]
+    } else {
+        let globals: String = entry_points.iter().map(|e| format!(" {};", e)).collect();
+        fs::write(
+            repo_dir.join(EXPORTS_FILE),
+            format!("{{\n  global:{}\n  local: *;\n}};\n", globals),
+        )?;
+        vec![format!("-Wl,--version-script={}", EXPORTS_FILE)]
+    };
+    Ok((cflags, ldflags))
+}
@@ -0,0 +1,152 @@
+use std::fs;
+
+use crate::languages::parse_language_list;
+
+// --trusted-orgs: the owners whose repositories may be cloned and built.
+// An owner is `host/name`, or just the name for one on github.com.
+#[derive(Debug, Clone, Default)]
+pub struct TrustPolicy {
+    owners: Vec<String>,
+    // --allow-untrusted: build the others too, with a warning
+    pub warn_only: bool,
+}
+
+impl TrustPolicy {
+    pub fn new<I, S>(owners: I) -> TrustPolicy
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        TrustPolicy {
+            owners: owners
+                .into_iter()
+                .map(|owner| qualified(owner.as_ref()))
+                .filter(|owner| !owner.is_empty())
+                .collect(),
+            warn_only: false,
+        }
+    }
+
+    // a policy file: owners separated by commas or whitespace, `#` comments
+    pub fn load(path: &str) -> Result<TrustPolicy, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(TrustPolicy::new(parse_language_list(&text)))
+    }
+
+    pub fn extend(&mut self, other: TrustPolicy) {
+        self.owners.extend(other.owners);
+    }
+
+    // No policy trusts everything. A repository whose owner can't be told
+    // isn't trusted by one.
+    pub fn allows(&self, repo_url: &str) -> bool {
+        self.owners.is_empty()
+            || owner_of(repo_url).is_some_and(|owner| self.owners.contains(&owner))
+    }
+}
+
+// github.com/<name> for a bare name, lowercase like the hosts compare them
+fn qualified(owner: &str) -> String {
+    let owner = owner.trim().trim_matches('/').to_lowercase();
+    if owner.contains('/') || owner.is_empty() {
+        owner
+    } else {
+        format!("github.com/{}", owner)
+    }
+}
+
+// `host/owner` of a clone URL: https://host/owner/repo, ssh://git@host/owner/repo
+// or git@host:owner/repo. None for local paths, the unauthenticated http://
+// and git://, and paths git would normalize to another owner (`..`, `%2e%2e`).
+pub fn owner_of(repo_url: &str) -> Option<String> {
+    let (host, path) = match repo_url.split_once("://") {
+        Some((scheme, rest)) => {
+            if !["https", "ssh"].contains(&scheme.to_lowercase().as_str()) {
+                return None;
+            }
+            rest.split_once('/')?
+        }
+        // scp-like, a colon before any slash
+        None => {
+            let (host, path) = repo_url.split_once(':')?;
+            if host.contains('/') {
+                return None;
+            }
+            (host, path)
+        }
+    };
+    let host = host.rsplit('@').next()?.split(':').next()?.to_lowercase();
+    let mut segments = path.strip_suffix('/').unwrap_or(path).split('/');
+    if segments
+        .clone()
+        .any(|s| s.is_empty() || s == "." || s == ".." || s.contains('%'))
+    {
+        return None;
+    }
+    let owner = segments.next()?.to_lowercase();
+    (!host.is_empty()).then(|| format!("{}/{}", host, owner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owner_of_clone_urls() {
+        let owner = Some("github.com/tree-sitter".to_string());
+        assert_eq!(
+            owner_of("https://github.com/tree-sitter/tree-sitter-c"),
+            owner
+        );
+        assert_eq!(
+            owner_of("https://github.com/Tree-Sitter/tree-sitter-c/"),
+            owner
+        );
+        assert_eq!(
+            owner_of("ssh://git@github.com/tree-sitter/tree-sitter-c.git"),
+            owner
+        );
+        assert_eq!(
+            owner_of("git@github.com:tree-sitter/tree-sitter-c.git"),
+            owner
+        );
+        assert_eq!(
+            owner_of("https://gitlab.com/owner/repo"),
+            Some("gitlab.com/owner".to_string())
+        );
+    }
+
+    #[test]
+    fn owner_of_rejects_dot_segments() {
+        assert_eq!(owner_of("https://github.com/tree-sitter/../evil/x"), None);
+        assert_eq!(
+            owner_of("https://github.com/tree-sitter/%2e%2e/evil/x"),
+            None
+        );
+        assert_eq!(owner_of("https://github.com/tree-sitter/./x"), None);
+        assert_eq!(owner_of("https://github.com//evil/x"), None);
+        assert_eq!(owner_of("git@github.com:tree-sitter/../evil/x"), None);
+    }
+
+    #[test]
+    fn owner_of_rejects_unauthenticated_and_local() {
+        assert_eq!(
+            owner_of("http://github.com/tree-sitter/tree-sitter-c"),
+            None
+        );
+        assert_eq!(owner_of("git://github.com/tree-sitter/tree-sitter-c"), None);
+        assert_eq!(owner_of("file:///src/tree-sitter-c"), None);
+        assert_eq!(owner_of("/src/tree-sitter-c"), None);
+        assert_eq!(owner_of("./tree-sitter-c"), None);
+    }
+
+    #[test]
+    fn policy_allows_its_owners() {
+        let policy = TrustPolicy::new(["tree-sitter", "gitlab.com/other"]);
+        assert!(policy.allows("https://github.com/tree-sitter/tree-sitter-c"));
+        assert!(policy.allows("https://gitlab.com/other/x"));
+        assert!(!policy.allows("https://github.com/tree-sitter/../evil/x"));
+        assert!(!policy.allows("https://github.com/evil/x"));
+        assert!(TrustPolicy::default().allows("http://anything/x"));
+    }
+}
@@ -0,0 +1,359 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::checksums::sha256_file;
+use crate::config::{entry_extensions, resolve_entry_path};
+use crate::languages::symbol_name;
+use crate::symbols::{exported_symbols, needs_asan_runtime};
+
+// the published schema for emitted configs, also at schema/config.schema.json
+pub const CONFIG_SCHEMA: &str = include_str!("../schema/config.schema.json");
+
+// Check a config document against the schema, then check its entries against
+// the filesystem and each other. Returns one line per problem found.
+pub fn validate_config(document: &Value, config_path: &str) -> Vec<String> {
+    let schema: Value = serde_json::from_str(CONFIG_SCHEMA).expect("bundled schema is valid JSON");
+    let mut problems = Vec::new();
+    check_schema(document, &schema, &schema, "", &mut problems);
+
+    let languages = match document.get("known_languages").and_then(Value::as_object) {
+        Some(languages) => languages,
+        None => return problems,
+    };
+    problems.extend(verify_entries(languages, config_path));
+
+    let mut libraries: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut extensions: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for (name, entry) in languages {
+        for extension in entry_extensions(entry) {
+            extensions.entry(extension).or_default().push(name);
+        }
+        if let Some(path) = entry.get("path").and_then(Value::as_str) {
+            libraries.entry(path).or_default().push(name);
+        }
+    }
+
+    for (path, names) in libraries.iter().filter(|(_, names)| names.len() > 1) {
+        // the same library under several keys is fine as long as it's the same language
+        let symbols: Vec<&str> = names
+            .iter()
+            .filter_map(|n| languages[*n].get("language").and_then(Value::as_str))
+            .collect();
+        let kind = if symbols.windows(2).all(|w| w[0] == w[1]) {
+            "duplicate"
+        } else {
+            "conflicting"
+        };
+        problems.push(format!(
+            "{} entries {} share library {}",
+            kind,
+            names.join(", "),
+            path
+        ));
+    }
+    for (extension, names) in extensions.iter().filter(|(_, names)| names.len() > 1) {
+        problems.push(format!(
+            "extension {} is claimed by {}",
+            extension,
+            names.join(", ")
+        ));
+    }
+    problems
+}
+
+// Check every entry against the library it points at: the file exists, is a
+// shared object for this platform, exports the entry point and matches its
+// sha256, and the entry claims some files
+pub fn verify_entries(languages: &Map<String, Value>, config_path: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, entry) in languages {
+        let extensions = entry_extensions(entry);
+        let has_filenames = entry
+            .get("filenames")
+            .and_then(Value::as_array)
+            .is_some_and(|f| !f.is_empty());
+        if extensions.iter().any(String::is_empty) {
+            problems.push(format!("{}: has an empty extension", name));
+        } else if extensions.is_empty() && !has_filenames {
+            problems.push(format!("{}: claims no extensions or filenames", name));
+        }
+
+        let path = match entry.get("path").and_then(Value::as_str) {
+            Some(path) => path,
+            None => continue,
+        };
+        // dlopen searches the library path for names without a slash, so hand it an absolute one
+        let resolved = match resolve_entry_path(path, config_path).canonicalize() {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                problems.push(format!("{}: library {} does not exist", name, path));
+                continue;
+            }
+        };
+        if let Err(e) = check_object_format(&resolved) {
+            problems.push(format!("{}: library {} {}", name, path, e));
+            continue;
+        }
+        let language = entry
+            .get("language")
+            .and_then(Value::as_str)
+            .unwrap_or(name);
+        let loadable = if needs_asan_runtime(&resolved) {
+            check_exports(&resolved, language)
+        } else {
+            check_loadable(&resolved, language)
+        };
+        if let Err(e) = loadable {
+            problems.push(format!("{}: {}", name, e));
+        }
+        if let Some(expected) = entry.get("sha256").and_then(Value::as_str) {
+            match sha256_file(&resolved) {
+                Ok(actual) if actual == expected => {}
+                Ok(_) => problems.push(format!(
+                    "{}: library {} does not match its sha256",
+                    name, path
+                )),
+                Err(e) => problems.push(format!("{}: can't hash {}: {}", name, path, e)),
+            }
+        }
+    }
+    problems
+}
+
+// the ELF machine this binary was built for
+fn elf_machine() -> Option<u16> {
+    match std::env::consts::ARCH {
+        "x86" => Some(3),
+        "arm" => Some(40),
+        "x86_64" => Some(62),
+        "aarch64" => Some(183),
+        "powerpc64" => Some(21),
+        "s390x" => Some(22),
+        "riscv64" => Some(243),
+        "loongarch64" => Some(258),
+        _ => None,
+    }
+}
+
+// Look at the header of `path`: the object format this platform loads, and
+// for ELF the word size and architecture this process has
+fn check_object_format(path: &Path) -> Result<(), String> {
+    let mut header = [0u8; 20];
+    let read = File::open(path)
+        .and_then(|mut f| f.read(&mut header))
+        .map_err(|e| format!("can't be read: {}", e))?;
+    let header = &header[..read];
+
+    if cfg!(target_os = "windows") {
+        return match header.starts_with(b"MZ") {
+            true => Ok(()),
+            false => Err("is not a DLL".to_string()),
+        };
+    }
+    if cfg!(target_os = "macos") {
+        let magic = header
+            .get(..4)
+            .map(|m| u32::from_le_bytes([m[0], m[1], m[2], m[3]]));
+        return match magic {
+            // 64-bit Mach-O, or a universal binary (big endian magic)
+            Some(0xfeedfacf) | Some(0xbebafeca) => Ok(()),
+            _ => Err("is not a Mach-O library".to_string()),
+        };
+    }
+
+    if !header.starts_with(b"\x7fELF") || header.len() < 20 {
+        return Err("is not an ELF shared object".to_string());
+    }
+    let class = if cfg!(target_pointer_width = "64") {
+        2
+    } else {
+        1
+    };
+    if header[4] != class {
+        return Err(format!(
+            "is a {}-bit object, this platform is {}-bit",
+            if header[4] == 2 { 64 } else { 32 },
+            usize::BITS
+        ));
+    }
+    let little_endian = header[5] == 1;
+    let read_u16 = |at: usize| {
+        let bytes = [header[at], header[at + 1]];
+        if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    };
+    // ET_DYN
+    if read_u16(16) != 3 {
+        return Err("is an ELF file but not a shared object".to_string());
+    }
+    match elf_machine() {
+        Some(machine) if read_u16(18) != machine => Err(format!(
+            "is built for ELF machine {}, not {}",
+            read_u16(18),
+            std::env::consts::ARCH
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Enough of JSON Schema for the bundled schema: type, required, properties,
+// additionalProperties, items, minimum and local $refs.
+fn check_schema(value: &Value, schema: &Value, root: &Value, at: &str, problems: &mut Vec<String>) {
+    let location = if at.is_empty() { "config" } else { at };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer))
+        {
+            Some(target) => check_schema(value, target, root, at, problems),
+            None => problems.push(format!("schema: unresolved reference {}", reference)),
+        }
+        return;
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.iter().any(|t| has_type(value, t)) {
+            problems.push(format!(
+                "{}: expected {}, found {}",
+                location,
+                allowed.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(Value::as_f64),
+        value.as_f64(),
+    ) {
+        if number < minimum {
+            problems.push(format!("{}: {} is below {}", location, number, minimum));
+        }
+    }
+
+    if let Value::Object(map) = value {
+        for required in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !map.contains_key(required) {
+                problems.push(format!("{}: missing `{}`", location, required));
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, child) in map {
+            let child_at = format!("{}/{}", at, key);
+            match properties.and_then(|p| p.get(key)) {
+                Some(child_schema) => check_schema(child, child_schema, root, &child_at, problems),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        problems.push(format!("{}: unexpected field", child_at))
+                    }
+                    Some(child_schema @ Value::Object(_)) => {
+                        check_schema(child, child_schema, root, &child_at, problems)
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            check_schema(item, item_schema, root, &format!("{}/{}", at, i), problems);
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// look the entry point up in the symbol table, for libraries we can't dlopen
+fn check_exports(path: &Path, language: &str) -> Result<(), String> {
+    let symbol = symbol_name(language);
+    let exported = exported_symbols(&path.to_string_lossy()).map_err(|e| e.to_string())?;
+    if exported.contains(&symbol) {
+        Ok(())
+    } else {
+        Err(format!(
+            "library {} has no symbol {}",
+            path.display(),
+            symbol
+        ))
+    }
+}
+
+// dlopen the library and look up its tree_sitter_<language> entry point
+#[cfg(unix)]
+fn check_loadable(path: &Path, language: &str) -> Result<(), String> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    let symbol = symbol_name(language);
+    let c_symbol = CString::new(symbol.as_str()).map_err(|e| e.to_string())?;
+    // SAFETY: the library is only inspected, the symbol is never called
+    unsafe {
+        let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if handle.is_null() {
+            let error = libc::dlerror();
+            let reason = if error.is_null() {
+                "unknown error".to_string()
+            } else {
+                CStr::from_ptr(error).to_string_lossy().to_string()
+            };
+            return Err(format!(
+                "library {} can't be loaded: {}",
+                path.display(),
+                reason
+            ));
+        }
+        let found = !libc::dlsym(handle, c_symbol.as_ptr()).is_null();
+        libc::dlclose(handle);
+        if !found {
+            return Err(format!(
+                "library {} has no symbol {}",
+                path.display(),
+                symbol
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_loadable(_path: &Path, _language: &str) -> Result<(), String> {
+    Ok(())
+}
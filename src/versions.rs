@@ -0,0 +1,395 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::{LanguageArtifact, FILE_MAPS};
+use crate::package::is_library;
+use crate::store::{Store, Version};
+
+// the symlink in the output directory naming the version in use
+pub const LATEST: &str = "latest";
+
+// --versioned: every run writes into <output>/<timestamp>/, and only once it
+// went through is <output>/latest pointed at it. The config goes through
+// latest, so whatever has the old libraries loaded keeps them until the
+// flip, and rolling back is pointing latest somewhere else.
+
+// A fresh directory for one run, named after when it started
+pub fn create_version(root: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(root)?;
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut name = stamp.clone();
+    // two runs in the same second
+    for n in 2.. {
+        match fs::create_dir(root.join(&name)) {
+            Ok(()) => break,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => name = format!("{}-{}", stamp, n),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(root.join(name))
+}
+
+// the version latest points at, if any
+pub fn current(root: &Path) -> Option<PathBuf> {
+    let target = fs::read_link(root.join(LATEST)).ok()?;
+    Some(root.join(target))
+}
+
+// the versions under `root`, oldest first
+pub fn list(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut versions: Vec<PathBuf> = fs::read_dir(root)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name() != LATEST)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.path())
+        .collect();
+    // the names are timestamps
+    versions.sort();
+    Ok(versions)
+}
+
+// Make `version` a whole set. Artifacts a run didn't build itself (reused by
+// --resume or --incremental) are linked in from the version they were built
+// in, and so is everything latest has that the run didn't write, e.g. the
+// last good build of a grammar that failed this time. Their paths are
+// rewritten into `version`. Returns how many files were brought over.
+pub fn complete(
+    root: &Path,
+    version: &Path,
+    artifacts: &mut [LanguageArtifact],
+) -> io::Result<usize> {
+    let mut linked = 0;
+    for artifact in artifacts.iter_mut() {
+        rewrite_paths(artifact, |path| {
+            let Some(inner) = inside_version(root, Path::new(path)) else {
+                return Ok(path.to_string());
+            };
+            let dest = version.join(inner);
+            if !dest.exists() && Path::new(path).is_file() {
+                link_or_copy(Path::new(path), &dest)?;
+                linked += 1;
+            }
+            Ok(dest.to_string_lossy().to_string())
+        })?;
+    }
+    if let Some(latest) = current(root).filter(|latest| latest != version) {
+        linked += link_missing(&latest, version)?;
+    }
+    Ok(linked)
+}
+
+// rewrite the paths of artifacts in `version` to go through latest, for the
+// config and everything else written after the flip
+pub fn through_latest(root: &Path, artifacts: &mut [LanguageArtifact]) {
+    for artifact in artifacts.iter_mut() {
+        let _ = rewrite_paths(artifact, |path| Ok(latest_path(root, path)));
+    }
+}
+
+// a path inside one of the versions, through latest instead
+pub fn latest_path(root: &Path, path: &str) -> String {
+    match inside_version(root, Path::new(path)) {
+        Some(inner) => root.join(LATEST).join(inner).to_string_lossy().to_string(),
+        None => path.to_string(),
+    }
+}
+
+// Point latest at `version`: a new link renamed over the old one, so there is
+// never a moment without one
+pub fn point_latest(root: &Path, version: &Path) -> io::Result<()> {
+    let name = version.file_name().unwrap_or_default();
+    let partial = root.join(format!("{}.partial", LATEST));
+    let _ = fs::remove_file(&partial);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(name, &partial)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_dir(name, &partial)?;
+    fs::rename(&partial, root.join(LATEST))
+}
+
+// how the store knows an output directory, however it was spelled
+pub fn output_key(root: &Path) -> io::Result<String> {
+    Ok(fs::canonicalize(root)?.to_string_lossy().to_string())
+}
+
+// the name of the version latest points at
+pub fn current_name(root: &Path) -> Option<String> {
+    let current = current(root)?;
+    Some(current.file_name()?.to_string_lossy().to_string())
+}
+
+pub struct Rollback {
+    pub from: Option<String>,
+    pub to: Version,
+    pub config_restored: bool,
+}
+
+// Point latest back at the newest known-good version other than the current
+// one, or at `to`, and put back the config that version was written with.
+// The version rolled back from isn't known-good anymore, so rolling back
+// again goes further back.
+pub fn rollback(
+    root: &Path,
+    store: &Store,
+    to: Option<&str>,
+    config_path: &str,
+) -> Result<Rollback, Box<dyn Error>> {
+    let output = output_key(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    let from = current_name(root);
+    let mut good = store.good_versions(&output)?.into_iter();
+    let target = match to {
+        Some(name) => good
+            .find(|version| version.name == name)
+            .ok_or_else(|| format!("{} isn't a known-good version of {}", name, root.display()))?,
+        None => good
+            .filter(|version| Some(&version.name) != from.as_ref())
+            // pruned ones can't be gone back to
+            .find(|version| root.join(&version.name).is_dir())
+            .ok_or_else(|| {
+                format!(
+                    "{} has no earlier known-good version to roll back to",
+                    root.display()
+                )
+            })?,
+    };
+    let dir = root.join(&target.name);
+    if !dir.is_dir() {
+        return Err(format!("{} was removed", dir.display()).into());
+    }
+    point_latest(root, &dir).map_err(|e| format!("{}: {}", root.join(LATEST).display(), e))?;
+    if let Some(from) = from.as_ref().filter(|from| **from != target.name) {
+        store.rolled_back(&output, from)?;
+    }
+    let mut config_restored = false;
+    if let Some(config) = &target.config {
+        let tmp_path = format!("{}.tmp", config_path);
+        fs::write(&tmp_path, config)
+            .and_then(|_| fs::rename(&tmp_path, config_path))
+            .map_err(|e| format!("{}: {}", config_path, e))?;
+        config_restored = true;
+    }
+    Ok(Rollback {
+        from,
+        to: target,
+        config_restored,
+    })
+}
+
+// Remove all but the newest `keep` versions, never the one latest points at.
+// Returns the ones removed.
+pub fn prune(root: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let latest = current(root);
+    let versions = list(root)?;
+    let mut removed = Vec::new();
+    for version in &versions[..versions.len().saturating_sub(keep)] {
+        if Some(version) == latest.as_ref() {
+            continue;
+        }
+        fs::remove_dir_all(version)?;
+        removed.push(version.clone());
+    }
+    Ok(removed)
+}
+
+// `path` relative to the version directory it is in, if it is in one
+fn inside_version(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut rest = path.strip_prefix(root).ok()?.components();
+    match rest.next()? {
+        Component::Normal(name) if name != LATEST => {}
+        _ => return None,
+    }
+    let inner = rest.as_path().to_path_buf();
+    (!inner.as_os_str().is_empty()).then_some(inner)
+}
+
+// replace the library, query, grammar and license file paths of an artifact
+// with what `rewrite` makes of them
+fn rewrite_paths(
+    artifact: &mut LanguageArtifact,
+    mut rewrite: impl FnMut(&str) -> io::Result<String>,
+) -> io::Result<()> {
+    if let Some(path) = artifact.entry.get("path").and_then(Value::as_str) {
+        let path = path.to_string();
+        artifact.entry["path"] = json!(rewrite(&path)?);
+    }
+    for map in FILE_MAPS {
+        if let Some(files) = artifact.entry.get_mut(*map).and_then(Value::as_object_mut) {
+            for file in files.values_mut() {
+                if let Some(path) = file.as_str() {
+                    *file = json!(rewrite(path)?);
+                }
+            }
+        }
+    }
+    for file in artifact.license_files.iter_mut() {
+        *file = rewrite(file)?;
+    }
+    Ok(())
+}
+
+// every file below `from` that `to` doesn't have yet
+fn link_missing(from: &Path, to: &Path) -> io::Result<usize> {
+    let mut linked = 0;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&dest)?;
+            linked += link_missing(&entry.path(), &dest)?;
+        } else if !dest.exists() {
+            link_or_copy(&entry.path(), &dest)?;
+            linked += 1;
+        }
+    }
+    Ok(linked)
+}
+
+// Libraries are hard linked, nothing writes into one once it's built, the
+// compiler only ever writes into a fresh version. Everything else is small
+// and copied, SHA256SUMS, NOTICE and the like are rewritten in place by
+// every run.
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if !is_library(&from.to_string_lossy()) || fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifact(path: &Path) -> LanguageArtifact {
+        LanguageArtifact {
+            name: "json".to_string(),
+            repo_url: String::new(),
+            scope: None,
+            license: None,
+            license_files: Vec::new(),
+            corpus: None,
+            test_files: Vec::new(),
+            tree_diff: None,
+            entry: json!({ "language": "json", "path": path }),
+        }
+    }
+
+    #[test]
+    fn paths_inside_versions() {
+        let root = Path::new("/out");
+        assert_eq!(
+            inside_version(root, Path::new("/out/20240101T000000Z/lib/json.so")),
+            Some(PathBuf::from("lib/json.so"))
+        );
+        assert_eq!(
+            inside_version(root, Path::new("/out/latest/lib/json.so")),
+            None
+        );
+        assert_eq!(
+            inside_version(root, Path::new("/out/20240101T000000Z")),
+            None
+        );
+        assert_eq!(inside_version(root, Path::new("/elsewhere/json.so")), None);
+        assert_eq!(
+            latest_path(root, "/out/20240101T000000Z/lib/json.so"),
+            "/out/latest/lib/json.so"
+        );
+        assert_eq!(
+            latest_path(root, "/elsewhere/json.so"),
+            "/elsewhere/json.so"
+        );
+    }
+
+    #[test]
+    fn versions_and_latest() {
+        let root = std::env::temp_dir().join(format!("versions-{}", std::process::id()));
+        let first = create_version(&root).unwrap();
+        let second = create_version(&root).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(list(&root).unwrap(), vec![first.clone(), second.clone()]);
+        assert_eq!(current(&root), None);
+
+        point_latest(&root, &first).unwrap();
+        assert_eq!(current(&root), Some(first.clone()));
+        point_latest(&root, &second).unwrap();
+        assert_eq!(
+            current_name(&root).as_deref(),
+            second.file_name().unwrap().to_str()
+        );
+        // latest itself isn't a version
+        assert_eq!(list(&root).unwrap().len(), 2);
+
+        // never the one latest points at, even when it's the oldest
+        let third = create_version(&root).unwrap();
+        point_latest(&root, &first).unwrap();
+        assert_eq!(prune(&root, 1).unwrap(), vec![second]);
+        assert_eq!(list(&root).unwrap(), vec![first, third]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn completes_a_version_from_earlier_ones() {
+        let root = std::env::temp_dir().join(format!("versions-complete-{}", std::process::id()));
+        let old = create_version(&root).unwrap();
+        fs::create_dir_all(old.join("lib")).unwrap();
+        fs::write(old.join("lib/libtree-sitter-json.so"), b"json").unwrap();
+        fs::write(old.join("lib/libtree-sitter-toml.so"), b"toml").unwrap();
+        point_latest(&root, &old).unwrap();
+
+        // json was reused from the old version, toml failed this time
+        let new = create_version(&root).unwrap();
+        fs::write(new.join("SHA256SUMS"), b"").unwrap();
+        let mut artifacts = [artifact(&old.join("lib/libtree-sitter-json.so"))];
+        assert_eq!(complete(&root, &new, &mut artifacts).unwrap(), 2);
+        let json = new.join("lib/libtree-sitter-json.so");
+        assert_eq!(artifacts[0].entry["path"], json!(json));
+        assert_eq!(fs::read(&json).unwrap(), b"json");
+        assert_eq!(
+            fs::read(new.join("lib/libtree-sitter-toml.so")).unwrap(),
+            b"toml"
+        );
+
+        through_latest(&root, &mut artifacts);
+        assert_eq!(
+            artifacts[0].entry["path"],
+            json!(root.join("latest/lib/libtree-sitter-json.so"))
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rolls_back_to_the_last_good_version() {
+        let root = std::env::temp_dir().join(format!("versions-rollback-{}", std::process::id()));
+        let first = create_version(&root).unwrap();
+        let second = create_version(&root).unwrap();
+        let store = Store::open(&root.join("store.db").to_string_lossy()).unwrap();
+        let output = output_key(&root).unwrap();
+        let name = |version: &Path| version.file_name().unwrap().to_string_lossy().to_string();
+        store
+            .record_version(None, &output, &name(&first), Some("first config"))
+            .unwrap();
+        store
+            .record_version(None, &output, &name(&second), Some("second config"))
+            .unwrap();
+        point_latest(&root, &second).unwrap();
+
+        let config = root.join("config.json").to_string_lossy().to_string();
+        let done = rollback(&root, &store, None, &config).unwrap();
+        assert_eq!(done.from, Some(name(&second)));
+        assert_eq!(done.to.name, name(&first));
+        assert!(done.config_restored);
+        assert_eq!(fs::read_to_string(&config).unwrap(), "first config");
+        assert_eq!(current(&root), Some(first.clone()));
+
+        // the one rolled back from isn't good anymore
+        assert!(rollback(&root, &store, None, &config).is_err());
+        assert!(rollback(&root, &store, Some(&name(&second)), &config).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
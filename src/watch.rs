@@ -0,0 +1,297 @@
+use notify::event::ModifyKind;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::emit::{EmitContext, Emitter};
+use crate::grammar::{BuildOptions, Checkout, GrammarBuilder};
+use crate::npm;
+
+// files `tree-sitter generate` writes, which mustn't set off another rebuild
+const GENERATED: [&str; 3] = ["parser.c", "grammar.json", "node-types.json"];
+
+// directories whose changes never matter to the build
+const IGNORED_DIRS: [&str; 5] = [".git", "node_modules", "target", "build", "bindings"];
+
+// A grammar under development, rebuilt whenever its sources change
+pub struct WatchOptions {
+    pub dir: PathBuf,
+    pub lang: String,
+    // how long the sources have to stay quiet before a rebuild starts, so an
+    // editor saving several files is one rebuild
+    pub debounce: Duration,
+    // written after every successful build, e.g. the config
+    pub emitters: Vec<Box<dyn Emitter>>,
+    // run npm in the grammar when grammar.js requires a package that isn't installed
+    pub allow_npm: bool,
+}
+
+// The language a grammar directory builds when not given one: its name
+// without the tree-sitter- prefix
+pub fn lang_of_dir(dir: &Path) -> String {
+    let name = std::path::absolute(dir)
+        .ok()
+        .and_then(|d| d.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    name.strip_prefix("tree-sitter-")
+        .unwrap_or(&name)
+        .replace('-', "_")
+}
+
+// Build the grammar once, then again every time grammar.js, the scanner or
+// another source changes, until the process is killed. A changed grammar.js
+// is regenerated with `tree-sitter generate` first when the CLI is installed,
+// after `npm ci` when allowed and it requires packages that aren't installed.
+// Every build clears the terminal and shows how it went, errors included.
+pub fn watch(
+    options: &WatchOptions,
+    build_options: BuildOptions,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let dir = std::path::absolute(&options.dir)?;
+    let output_dir = std::path::absolute(&build_options.output_dir)?;
+    let builder = GrammarBuilder::new(build_options);
+    let generates = tree_sitter_cli_installed();
+    let (changes_tx, changes) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = changes_tx.send(event);
+    })?;
+    watcher.watch(&dir, RecursiveMode::Recursive)?;
+    log::info!("watching {} for changes to {}", dir.display(), options.lang);
+
+    // the first build regenerates only when parser.c is missing
+    let mut grammar_changed = !dir.join("src").join("parser.c").exists();
+    loop {
+        rebuild(options, &builder, &dir, grammar_changed, generates);
+        let mut changed = Vec::new();
+        // block for the first change, then wait for the rest of the burst
+        let mut timeout = None;
+        loop {
+            let event = match timeout {
+                None => changes
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                Some(timeout) => changes.recv_timeout(timeout),
+            };
+            match event {
+                // the build reading the sources shows up as access events
+                Ok(Ok(event)) if !is_write(&event.kind) => {}
+                Ok(Ok(event)) => {
+                    let relevant: Vec<PathBuf> = event
+                        .paths
+                        .into_iter()
+                        .filter(|p| is_source(p, &dir, &output_dir, generates))
+                        .collect();
+                    if !relevant.is_empty() {
+                        changed.extend(relevant);
+                        timeout = Some(options.debounce);
+                    }
+                }
+                Ok(Err(e)) => log::warn!("watching {} : {}", dir.display(), e),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        for path in &changed {
+            log::info!("changed: {}", path.display());
+        }
+        grammar_changed = changed.iter().any(|p| is_grammar_definition(p, &dir));
+    }
+}
+
+fn rebuild(
+    options: &WatchOptions,
+    builder: &GrammarBuilder,
+    dir: &Path,
+    grammar_changed: bool,
+    generates: bool,
+) {
+    let started = Instant::now();
+    let lang = &options.lang;
+    let mut notes = Vec::new();
+    if grammar_changed {
+        // without the CLI nothing runs grammar.js, it needs no packages
+        let dependencies = generates.then(|| npm::missing_dependencies(dir)).flatten();
+        if let Some(dependencies) = dependencies {
+            let missing = dependencies.missing.join(", ");
+            if !options.allow_npm {
+                return show_error(
+                    options,
+                    "tree-sitter generate",
+                    &format!(
+                        "grammar.js requires {}, which isn't in node_modules/: run `npm ci` in {} \
+                         or watch with --allow-npm",
+                        missing,
+                        dependencies.package_dir.display()
+                    ),
+                );
+            }
+            match npm::install(&dependencies.package_dir) {
+                Ok(command) => notes.push(format!("installed {} with {}", missing, command)),
+                Err(message) => return show_error(options, "npm", &message),
+            }
+        }
+        match generate(dir) {
+            Ok(()) => notes.push("regenerated src/parser.c".to_string()),
+            Err(Generate::Missing) => notes.push(
+                "grammar.js changed, but the tree-sitter CLI isn't installed to regenerate \
+                 src/parser.c, building the one there is"
+                    .to_string(),
+            ),
+            Err(Generate::Failed(stderr)) => {
+                return show_error(options, "tree-sitter generate", &stderr);
+            }
+        }
+    }
+    let checkout = Checkout {
+        lang: lang.clone(),
+        repo_url: dir.display().to_string(),
+        repo_dir: dir.display().to_string(),
+        rev: None,
+    };
+    let status = |message: String| log::info!("{}", message);
+    let artifacts = builder
+        .compile(&checkout, &status)
+        .and_then(|built| builder.verify(built, &status));
+    let artifacts = match artifacts {
+        Ok(artifacts) => artifacts,
+        Err(e) => return show_error(options, "build", &e.to_string()),
+    };
+    let context = EmitContext {
+        hashes: &Default::default(),
+        compiler: builder.options().builder.describe(),
+    };
+    for emitter in &options.emitters {
+        if let Err(e) = emitter.emit(&artifacts, &context) {
+            notes.push(format!("failed to write {}", e));
+        }
+    }
+
+    clear_screen();
+    let library = artifacts
+        .first()
+        .and_then(|a| a.entry.get("path"))
+        .and_then(|p| p.as_str())
+        .unwrap_or("");
+    println!(
+        "\x1b[1;32m✔ built {}\x1b[0m in {:.1}s: {}",
+        lang,
+        started.elapsed().as_secs_f64(),
+        library
+    );
+    if builder.options().smoke_test {
+        println!("  smoke test passed");
+    }
+    for note in &notes {
+        println!("  {}", note);
+    }
+    log::info!("rebuilt {} in {:?}", lang, started.elapsed());
+    print_footer(options);
+}
+
+// the failure in a red banner, so it can't be missed in the scrollback
+fn show_error(options: &WatchOptions, stage: &str, message: &str) {
+    log::error!("{} of {} failed : {}", stage, options.lang, message);
+    clear_screen();
+    println!(
+        "\x1b[1;97;41m {} of {} failed \x1b[0m\n",
+        stage, options.lang
+    );
+    for line in message.trim().lines() {
+        println!("\x1b[31m│\x1b[0m {}", line);
+    }
+    println!();
+    print_footer(options);
+}
+
+fn print_footer(options: &WatchOptions) {
+    println!(
+        "\x1b[2mwatching {} for changes, Ctrl-C to stop\x1b[0m",
+        options.dir.display()
+    );
+}
+
+fn clear_screen() {
+    print!("\x1b[2J\x1b[H");
+}
+
+enum Generate {
+    Missing,
+    Failed(String),
+}
+
+fn generate(dir: &Path) -> Result<(), Generate> {
+    let output = Command::new("tree-sitter")
+        .arg("generate")
+        .current_dir(dir)
+        .output()
+        .map_err(|_| Generate::Missing)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Generate::Failed(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ))
+    }
+}
+
+// Sources of the build: the grammar definition and whatever it requires,
+// the scanner and headers, parser.c when it is regenerated by hand. Nothing
+// in the output directory, which the build itself writes to.
+fn is_source(path: &Path, dir: &Path, output_dir: &Path, generates: bool) -> bool {
+    if path.starts_with(output_dir) {
+        return false;
+    }
+    let Ok(relative) = path.strip_prefix(dir) else {
+        return false;
+    };
+    if relative
+        .components()
+        .any(|c| IGNORED_DIRS.iter().any(|d| c.as_os_str() == *d))
+    {
+        return false;
+    }
+    let file_name = relative
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // what generate wrote, or the tree_sitter/ headers it copies
+    if generates
+        && relative.starts_with("src")
+        && (GENERATED.contains(&file_name.as_str()) || relative.starts_with("src/tree_sitter"))
+    {
+        return false;
+    }
+    matches!(
+        relative.extension().and_then(|e| e.to_str()),
+        Some("js" | "mjs" | "cjs" | "ts" | "c" | "cc" | "cpp" | "h" | "hpp" | "json")
+    )
+}
+
+fn is_write(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(modify) => !matches!(modify, ModifyKind::Metadata(_)),
+        _ => false,
+    }
+}
+
+// a file `tree-sitter generate` reads rather than one the compiler does
+fn is_grammar_definition(path: &Path, dir: &Path) -> bool {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    !relative.starts_with("src")
+        && matches!(
+            relative.extension().and_then(|e| e.to_str()),
+            Some("js" | "mjs" | "cjs" | "ts")
+        )
+}
+
+fn tree_sitter_cli_installed() -> bool {
+    Command::new("tree-sitter")
+        .arg("--version")
+        .output()
+        .is_ok_and(|o| o.status.success())
+}